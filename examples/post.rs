@@ -1,10 +1,12 @@
 use nucleus_http::{
+    form::Form,
     http,
-    request::{FormTypes, Request},
+    request::Request,
     response::Response,
     routes::{Route, Router},
     Server,
 };
+use serde::Deserialize;
 use std::{convert::Infallible, sync::Arc};
 use tokio::sync::RwLock;
 
@@ -17,6 +19,12 @@ struct AppState {
     last_name: Arc<RwLock<String>>,
 }
 
+#[derive(Debug, Deserialize)]
+struct NameForm {
+    fname: String,
+    lname: String,
+}
+
 #[tokio::main]
 async fn main() -> tokio::io::Result<()> {
     pretty_env_logger::init();
@@ -73,41 +81,24 @@ async fn success(state: AppState, _: Request) -> Result<String, Infallible> {
     Ok(format!("Hello {} {}", *first, *last))
 }
 
-async fn post(state: AppState, req: Request) -> Result<Response, Infallible> {
+async fn post(state: AppState, req: Request) -> Result<Response, Response> {
+    let Form(name, _) = Form::<NameForm>::from_request(&req)?;
+    *state.first_name.write().await = name.fname;
+    *state.last_name.write().await = name.lname;
     let mut res = Response::new(
         http::StatusCode::FOUND,
         "".into(),
         http::MimeType::PlainText,
     );
-    let body_string = String::from_utf8_lossy(req.body());
-    let params: Vec<_> = body_string.split('&').collect();
-    for param in params {
-        let split: Vec<_> = param.split('=').collect();
-        if split[0] == "fname" {
-            let name = state.first_name.clone();
-            let mut name_locked = name.write().await;
-            *name_locked = split[1].to_string();
-        } else if split[0] == "lname" {
-            let name = state.last_name.clone();
-            let mut name_locked = name.write().await;
-            *name_locked = split[1].to_string();
-        }
-    }
     res.add_header(("Location", "/success"));
     Ok(res)
 }
 
-async fn multipart(_: AppState, req: Request) -> Result<Response, Infallible> {
-    match req.form_data() {
-        FormTypes::MultiPart(f) => {
-            if let Some(file_entry) = f.get("cover_image") {
-                return Ok(format!("Got File: {}", file_entry.file_name().unwrap()).into());
-            }
-        }
-        _ => {
-            return Ok("Something went wrong".into());
-        }
-    }
-
-    Ok("Something went wrong".into())
+async fn multipart(_: AppState, req: Request) -> Result<String, Response> {
+    let Form((), files) = Form::<()>::from_request(&req)?;
+    let file_name = files
+        .get("cover_image")
+        .and_then(|file| file.file_name().cloned())
+        .unwrap_or_default();
+    Ok(format!("Got File: {file_name}"))
 }
@@ -0,0 +1,764 @@
+use crate::{
+    http::{self, MimeType},
+    http2,
+    request::Request,
+    response::Response,
+};
+use anyhow::Context;
+use base64::{engine::general_purpose, Engine as _};
+use futures::StreamExt;
+use ring::signature::{EcdsaKeyPair, KeyPair};
+use rustls_acme::{
+    acme::{
+        Account, AuthStatus, ChallengeType as AcmeChallengeType, Directory, Identifier, Order,
+        OrderStatus, ACME_TLS_ALPN_NAME, LETS_ENCRYPT_PRODUCTION_DIRECTORY,
+        LETS_ENCRYPT_STAGING_DIRECTORY,
+    },
+    caches::DirCache,
+    AccountCache, CertCache, EventError, EventOk,
+};
+use sha2::{Digest, Sha256};
+use std::{
+    collections::HashMap,
+    fmt::Debug,
+    future::Future,
+    path::PathBuf,
+    sync::Arc,
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+use tokio::sync::RwLock;
+use tokio_rustls::rustls::{
+    self,
+    server::{ClientHello, ResolvesServerCert},
+    sign::CertifiedKey,
+    Certificate, ClientConfig, OwnedTrustAnchor, PrivateKey, RootCertStore,
+};
+use tokio_util::sync::CancellationToken;
+
+/// Path prefix the ACME server requests HTTP-01 key authorizations from
+pub const HTTP01_CHALLENGE_PREFIX: &str = "/.well-known/acme-challenge/";
+
+/// Times a pending or processing order is fetched again before giving up on it
+const ORDER_POLLS: u32 = 10;
+
+/// How the ACME server should validate that we control the domains
+#[derive(Debug, PartialEq, Eq, Clone, Copy, Default)]
+pub enum ChallengeType {
+    /// Answered during the TLS handshake using the `acme-tls/1` ALPN protocol
+    #[default]
+    TlsAlpn01,
+    /// Answered over plain HTTP on port 80 by a router with
+    /// [`crate::routes::Router::add_acme_challenges`]
+    Http01,
+}
+
+/// Certificate life cycle events, passed to the handler set with [`AcmeConfig::on_event`]
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub enum AcmeEvent {
+    DeployedCachedCert,
+    DeployedNewCert,
+    CertCacheStore,
+    AccountCacheStore,
+    CacheError(String),
+    CertParseError(String),
+    /// Ordering or renewing a certificate failed, it will be retried with backoff
+    OrderFailed(String),
+}
+
+pub type AcmeEventHandler = Arc<dyn Fn(&AcmeEvent) + Send + Sync>;
+
+/// Pending HTTP-01 challenges, token -> key authorization
+/// cloned into routers so they can answer the ACME server
+#[derive(Debug, Clone, Default)]
+pub struct Http01Challenges(Arc<RwLock<HashMap<String, String>>>);
+
+/// Settings for certificates managed through ACME
+/// Defaults to the Let's Encrypt staging directory, TLS-ALPN-01 and a cache in
+/// ./rustls_acme_cache
+#[derive(Clone)]
+pub struct AcmeConfig {
+    domains: Vec<String>,
+    contact: Vec<String>,
+    directory_url: String,
+    cache_dir: Option<PathBuf>,
+    challenge: ChallengeType,
+    client_tls_config: Option<Arc<ClientConfig>>,
+    on_event: Option<AcmeEventHandler>,
+    http01_challenges: Http01Challenges,
+}
+
+/// Cert resolver used for HTTP-01, rustls-acme's resolver can only be filled by its own state
+#[derive(Default)]
+struct CertResolver {
+    cert: std::sync::RwLock<Option<Arc<CertifiedKey>>>,
+}
+
+impl AcmeEvent {
+    pub fn is_error(&self) -> bool {
+        matches!(
+            self,
+            Self::CacheError(_) | Self::CertParseError(_) | Self::OrderFailed(_)
+        )
+    }
+}
+
+impl<EC: std::fmt::Display + Debug, EA: std::fmt::Display + Debug>
+    From<Result<EventOk, EventError<EC, EA>>> for AcmeEvent
+{
+    fn from(event: Result<EventOk, EventError<EC, EA>>) -> Self {
+        match event {
+            Ok(EventOk::DeployedCachedCert) => Self::DeployedCachedCert,
+            Ok(EventOk::DeployedNewCert) => Self::DeployedNewCert,
+            Ok(EventOk::CertCacheStore) => Self::CertCacheStore,
+            Ok(EventOk::AccountCacheStore) => Self::AccountCacheStore,
+            Err(
+                err @ (EventError::CertCacheLoad(_)
+                | EventError::AccountCacheLoad(_)
+                | EventError::CertCacheStore(_)
+                | EventError::AccountCacheStore(_)),
+            ) => Self::CacheError(err.to_string()),
+            Err(err @ (EventError::CachedCertParse(_) | EventError::NewCertParse(_))) => {
+                Self::CertParseError(err.to_string())
+            }
+            Err(err @ EventError::Order(_)) => Self::OrderFailed(err.to_string()),
+        }
+    }
+}
+
+impl Http01Challenges {
+    pub async fn insert(&self, token: &str, key_authorization: &str) {
+        let mut locked = self.0.write().await;
+        locked.insert(token.to_string(), key_authorization.to_string());
+    }
+
+    pub async fn remove(&self, token: &str) {
+        let mut locked = self.0.write().await;
+        locked.remove(token);
+    }
+
+    pub async fn get(&self, token: &str) -> Option<String> {
+        self.0.read().await.get(token).cloned()
+    }
+
+    /// Answer request if it is for an ACME challenge, None for any other path
+    pub async fn respond(&self, request: &Request) -> Option<Response> {
+        let token = request.path().strip_prefix(HTTP01_CHALLENGE_PREFIX)?;
+        let response = match self.get(token).await {
+            Some(key_authorization) => Response::new(
+                http::StatusCode::OK,
+                key_authorization.into_bytes(),
+                MimeType::PlainText,
+            ),
+            None => {
+                tracing::warn!("ACME challenge requested for unknown token: {token}");
+                Response::error(http::StatusCode::NOT_FOUND, "Unknown Challenge".into())
+            }
+        };
+        Some(response)
+    }
+}
+
+impl ResolvesServerCert for CertResolver {
+    fn resolve(&self, _client_hello: ClientHello) -> Option<Arc<CertifiedKey>> {
+        self.cert.read().ok()?.clone()
+    }
+}
+
+impl AcmeConfig {
+    pub fn new(domains: impl IntoIterator<Item = impl AsRef<str>>, email: &str) -> Self {
+        AcmeConfig {
+            domains: domains
+                .into_iter()
+                .map(|d| d.as_ref().to_string())
+                .collect(),
+            contact: vec![format!("mailto:{email}")],
+            directory_url: LETS_ENCRYPT_STAGING_DIRECTORY.to_string(),
+            cache_dir: Some(PathBuf::from("./rustls_acme_cache")),
+            challenge: ChallengeType::default(),
+            client_tls_config: None,
+            on_event: None,
+            http01_challenges: Http01Challenges::default(),
+        }
+    }
+
+    pub fn domains(&self) -> &[String] {
+        &self.domains
+    }
+
+    pub fn directory(&self) -> &str {
+        &self.directory_url
+    }
+
+    /// Use any RFC 8555 directory, eg. a local Pebble instance for tests
+    pub fn set_directory(&mut self, directory_url: &str) {
+        self.directory_url = directory_url.to_string();
+    }
+
+    pub fn set_directory_lets_encrypt(&mut self, production: bool) {
+        let url = if production {
+            LETS_ENCRYPT_PRODUCTION_DIRECTORY
+        } else {
+            LETS_ENCRYPT_STAGING_DIRECTORY
+        };
+        self.set_directory(url);
+    }
+
+    pub fn cache_dir(&self) -> Option<&PathBuf> {
+        self.cache_dir.as_ref()
+    }
+
+    /// Where account keys and certificates are stored, None disables caching which means a new
+    /// certificate is ordered on every start
+    pub fn set_cache_dir(&mut self, cache_dir: Option<PathBuf>) {
+        self.cache_dir = cache_dir;
+    }
+
+    pub fn challenge(&self) -> ChallengeType {
+        self.challenge
+    }
+
+    pub fn set_challenge(&mut self, challenge: ChallengeType) {
+        self.challenge = challenge;
+    }
+
+    /// TLS settings for talking to the ACME directory, needed to trust a test CA like Pebble's
+    pub fn set_client_tls_config(&mut self, client_config: Arc<ClientConfig>) {
+        self.client_tls_config = Some(client_config);
+    }
+
+    /// Called for every certificate event, including renewal failures
+    pub fn on_event<F>(&mut self, handler: F)
+    where
+        F: Fn(&AcmeEvent) + Send + Sync + 'static,
+    {
+        self.on_event = Some(Arc::new(handler));
+    }
+
+    /// Handle to the pending HTTP-01 challenges, add it to the router serving port 80
+    pub fn http01_challenges(&self) -> Http01Challenges {
+        self.http01_challenges.clone()
+    }
+
+    /// Build rustls config and spawn the task that keeps the certificate up to date
+    /// task exits when cancel is triggered
+    pub(crate) fn start(self, cancel: CancellationToken) -> rustls::ServerConfig {
+        match self.challenge {
+            ChallengeType::TlsAlpn01 => self.start_tls_alpn(cancel),
+            ChallengeType::Http01 => self.start_http01(cancel),
+        }
+    }
+
+    fn emit(&self, event: AcmeEvent) {
+        if event.is_error() {
+            tracing::error!("acme: {:?}", event);
+        } else {
+            tracing::info!("acme: {:?}", event);
+        }
+        if let Some(handler) = &self.on_event {
+            handler(&event);
+        }
+    }
+
+    fn start_tls_alpn(self, cancel: CancellationToken) -> rustls::ServerConfig {
+        let mut acme = rustls_acme::AcmeConfig::new(&self.domains)
+            .contact(&self.contact)
+            .directory(&self.directory_url)
+            .cache_option(self.cache_dir.clone().map(DirCache::new));
+        if let Some(client_config) = &self.client_tls_config {
+            acme = acme.client_tls_config(client_config.clone());
+        }
+        let mut state = acme.state();
+        let mut config = rustls::ServerConfig::builder()
+            .with_safe_defaults()
+            .with_no_client_auth()
+            .with_cert_resolver(state.resolver());
+        config.alpn_protocols = vec![
+            http2::ALPN_H2.to_vec(),
+            b"http/1.1".to_vec(),
+            ACME_TLS_ALPN_NAME.to_vec(),
+        ];
+        tokio::spawn(async move {
+            let events = async {
+                while let Some(event) = state.next().await {
+                    self.emit(event.into());
+                }
+            };
+            tokio::select! {
+                _ = events => {}
+                _ = cancel.cancelled() => {
+                    tracing::debug!("shutting down acme task");
+                }
+            }
+        });
+        config
+    }
+
+    fn start_http01(self, cancel: CancellationToken) -> rustls::ServerConfig {
+        let resolver = Arc::new(CertResolver::default());
+        let mut config = rustls::ServerConfig::builder()
+            .with_safe_defaults()
+            .with_no_client_auth()
+            .with_cert_resolver(resolver.clone());
+        config.alpn_protocols = vec![http2::ALPN_H2.to_vec(), b"http/1.1".to_vec()];
+        tokio::spawn(async move {
+            tokio::select! {
+                _ = self.run_http01(resolver) => {}
+                _ = cancel.cancelled() => {
+                    tracing::debug!("shutting down acme task");
+                }
+            }
+        });
+        config
+    }
+
+    /// Deploy cached cert if there is one, then order new certs whenever the current one is 2/3
+    /// through its lifetime
+    async fn run_http01(&self, resolver: Arc<CertResolver>) {
+        let client_config = self
+            .client_tls_config
+            .clone()
+            .unwrap_or_else(default_client_config);
+        let mut pem = match self.load_cert().await {
+            Ok(pem) => pem,
+            Err(err) => {
+                self.emit(AcmeEvent::CacheError(err.to_string()));
+                None
+            }
+        };
+        let mut cached = pem.is_some();
+        let mut backoff = 0;
+        loop {
+            let new_pem = match pem.take() {
+                Some(pem) => pem,
+                None => match self.order_http01(&client_config).await {
+                    Ok(pem) => {
+                        cached = false;
+                        pem
+                    }
+                    Err(err) => {
+                        self.emit(AcmeEvent::OrderFailed(format!("{:#}", err)));
+                        tokio::time::sleep(Duration::from_secs(1 << backoff)).await;
+                        backoff = (backoff + 1).min(16);
+                        continue;
+                    }
+                },
+            };
+            match parse_cert(&new_pem) {
+                Ok((cert, renew_in)) => {
+                    if let Ok(mut locked) = resolver.cert.write() {
+                        *locked = Some(Arc::new(cert));
+                    }
+                    backoff = 0;
+                    if cached {
+                        self.emit(AcmeEvent::DeployedCachedCert);
+                    } else {
+                        self.emit(AcmeEvent::DeployedNewCert);
+                        match self.store_cert(&new_pem).await {
+                            Ok(true) => self.emit(AcmeEvent::CertCacheStore),
+                            Ok(false) => {}
+                            Err(err) => self.emit(AcmeEvent::CacheError(err.to_string())),
+                        }
+                    }
+                    tokio::time::sleep(renew_in).await;
+                }
+                Err(err) => {
+                    self.emit(AcmeEvent::CertParseError(format!("{:#}", err)));
+                    // ordering again right away would hammer the CA with the same failure
+                    tokio::time::sleep(Duration::from_secs(1 << backoff)).await;
+                    backoff = (backoff + 1).min(16);
+                }
+            }
+        }
+    }
+
+    async fn order_http01(&self, client_config: &Arc<ClientConfig>) -> anyhow::Result<Vec<u8>> {
+        let key_pair = self.account_key().await?;
+        let directory = Directory::discover(client_config, &self.directory_url)
+            .await
+            .context("Discovering ACME directory")?;
+        let account =
+            Account::create_with_keypair(client_config, directory, &self.contact, &key_pair)
+                .await
+                .context("Creating ACME account")?;
+
+        let mut params = rcgen::CertificateParams::new(self.domains.clone());
+        params.distinguished_name = rcgen::DistinguishedName::new();
+        params.alg = &rcgen::PKCS_ECDSA_P256_SHA256;
+        let cert = rcgen::Certificate::from_params(params)?;
+
+        let (order_url, mut order) = account
+            .new_order(client_config, self.domains.clone())
+            .await?;
+        loop {
+            match order.status {
+                OrderStatus::Pending => {
+                    for auth_url in &order.authorizations {
+                        self.authorize_http01(client_config, &account, auth_url)
+                            .await?;
+                    }
+                    // the order can lag behind its authorizations turning valid
+                    order = wait_for_order(OrderStatus::Pending, Duration::from_secs(1), || {
+                        account.order(client_config, &order_url)
+                    })
+                    .await?;
+                }
+                OrderStatus::Processing => {
+                    order = wait_for_order(OrderStatus::Processing, Duration::from_secs(1), || {
+                        account.order(client_config, &order_url)
+                    })
+                    .await?;
+                }
+                OrderStatus::Ready => {
+                    let csr = cert.serialize_request_der()?;
+                    order = account
+                        .finalize(client_config, &order.finalize, csr)
+                        .await?;
+                }
+                OrderStatus::Valid { certificate } => {
+                    let chain = account.certificate(client_config, certificate).await?;
+                    let pem = [&cert.serialize_private_key_pem(), "\n", &chain].concat();
+                    return Ok(pem.into_bytes());
+                }
+                OrderStatus::Invalid => {
+                    return Err(anyhow::Error::msg(format!(
+                        "Invalid order: {:?}",
+                        order.error
+                    )));
+                }
+            }
+        }
+    }
+
+    async fn authorize_http01(
+        &self,
+        client_config: &Arc<ClientConfig>,
+        account: &Account,
+        auth_url: &str,
+    ) -> anyhow::Result<()> {
+        let auth = account.auth(client_config, auth_url).await?;
+        let Identifier::Dns(domain) = auth.identifier;
+        match auth.status {
+            AuthStatus::Pending => {}
+            AuthStatus::Valid => return Ok(()),
+            status => {
+                return Err(anyhow::Error::msg(format!(
+                    "Authorization for {domain} is {:?}",
+                    status
+                )))
+            }
+        }
+        let challenge = auth
+            .challenges
+            .iter()
+            .find(|c| c.typ == AcmeChallengeType::Http01)
+            .ok_or_else(|| anyhow::Error::msg(format!("No http-01 challenge for {domain}")))?;
+        let key_authorization =
+            format!("{}.{}", challenge.token, jwk_thumbprint(&account.key_pair));
+        tracing::info!("acme: triggering http-01 challenge for {domain}");
+        self.http01_challenges
+            .insert(&challenge.token, &key_authorization)
+            .await;
+        let result = async {
+            account.challenge(client_config, &challenge.url).await?;
+            for i in 0u64..5 {
+                tokio::time::sleep(Duration::from_secs(1 << i)).await;
+                let auth = account.auth(client_config, auth_url).await?;
+                match auth.status {
+                    AuthStatus::Pending => {
+                        tracing::info!("acme: authorization for {domain} still pending");
+                    }
+                    AuthStatus::Valid => return Ok(()),
+                    status => {
+                        return Err(anyhow::Error::msg(format!(
+                            "Authorization for {domain} is {:?}",
+                            status
+                        )))
+                    }
+                }
+            }
+            Err(anyhow::Error::msg(format!(
+                "Authorization for {domain} failed too many times"
+            )))
+        }
+        .await;
+        self.http01_challenges.remove(&challenge.token).await;
+        result
+    }
+
+    async fn account_key(&self) -> anyhow::Result<Vec<u8>> {
+        if let Some(dir) = &self.cache_dir {
+            let cache = DirCache::new(dir);
+            let cached = cache
+                .load_account(&self.contact, &self.directory_url)
+                .await
+                .context("Loading ACME account")?;
+            if let Some(key_pair) = cached {
+                return Ok(key_pair);
+            }
+            let key_pair = Account::generate_key_pair();
+            match cache
+                .store_account(&self.contact, &self.directory_url, &key_pair)
+                .await
+            {
+                Ok(()) => self.emit(AcmeEvent::AccountCacheStore),
+                Err(err) => self.emit(AcmeEvent::CacheError(err.to_string())),
+            }
+            Ok(key_pair)
+        } else {
+            Ok(Account::generate_key_pair())
+        }
+    }
+
+    async fn load_cert(&self) -> std::io::Result<Option<Vec<u8>>> {
+        match &self.cache_dir {
+            Some(dir) => {
+                DirCache::new(dir)
+                    .load_cert(&self.domains, &self.directory_url)
+                    .await
+            }
+            None => Ok(None),
+        }
+    }
+
+    /// returns false if there is no cache configured
+    async fn store_cert(&self, pem: &[u8]) -> std::io::Result<bool> {
+        match &self.cache_dir {
+            Some(dir) => {
+                DirCache::new(dir)
+                    .store_cert(&self.domains, &self.directory_url, pem)
+                    .await?;
+                Ok(true)
+            }
+            None => Ok(false),
+        }
+    }
+}
+
+impl Debug for AcmeConfig {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("AcmeConfig")
+            .field("domains", &self.domains)
+            .field("contact", &self.contact)
+            .field("directory_url", &self.directory_url)
+            .field("cache_dir", &self.cache_dir)
+            .field("challenge", &self.challenge)
+            .finish()
+    }
+}
+
+/// Same trust roots rustls-acme uses by default
+fn default_client_config() -> Arc<ClientConfig> {
+    let mut root_store = RootCertStore::empty();
+    root_store.add_trust_anchors(webpki_roots::TLS_SERVER_ROOTS.iter().map(|ta| {
+        OwnedTrustAnchor::from_subject_spki_name_constraints(
+            ta.subject,
+            ta.spki,
+            ta.name_constraints,
+        )
+    }));
+    Arc::new(
+        ClientConfig::builder()
+            .with_safe_defaults()
+            .with_root_certificates(root_store)
+            .with_no_client_auth(),
+    )
+}
+
+/// Fetch the order with doubling delays starting at delay until it leaves status, errors if it
+/// is still there after ORDER_POLLS tries
+async fn wait_for_order<F, Fut, E>(
+    status: OrderStatus,
+    delay: Duration,
+    mut fetch: F,
+) -> anyhow::Result<Order>
+where
+    F: FnMut() -> Fut,
+    Fut: Future<Output = Result<Order, E>>,
+    E: std::error::Error + Send + Sync + 'static,
+{
+    for i in 0..ORDER_POLLS {
+        tokio::time::sleep(delay * (1 << i)).await;
+        let order = fetch().await?;
+        if order.status != status {
+            return Ok(order);
+        }
+    }
+    Err(anyhow::Error::msg(format!(
+        "Order stayed {:?} too long",
+        status
+    )))
+}
+
+/// RFC 7638 thumbprint of the account key, second half of an HTTP-01 key authorization
+fn jwk_thumbprint(key_pair: &EcdsaKeyPair) -> String {
+    let (x, y) = key_pair.public_key().as_ref()[1..].split_at(32);
+    let jwk = format!(
+        r#"{{"crv":"P-256","kty":"EC","x":"{}","y":"{}"}}"#,
+        general_purpose::URL_SAFE_NO_PAD.encode(x),
+        general_purpose::URL_SAFE_NO_PAD.encode(y)
+    );
+    general_purpose::URL_SAFE_NO_PAD.encode(Sha256::digest(jwk.as_bytes()))
+}
+
+/// Parse private key + chain in the format rustls-acme caches, returns how long until renewal
+fn parse_cert(pem: &[u8]) -> anyhow::Result<(CertifiedKey, Duration)> {
+    let mut key = None;
+    let mut chain = vec![];
+    for item in rustls_pemfile::read_all(&mut std::io::BufReader::new(pem))? {
+        match item {
+            rustls_pemfile::Item::PKCS8Key(k) | rustls_pemfile::Item::ECKey(k) => {
+                key = Some(PrivateKey(k))
+            }
+            rustls_pemfile::Item::X509Certificate(cert) => chain.push(Certificate(cert)),
+            _ => {}
+        }
+    }
+    let key = key.ok_or_else(|| anyhow::Error::msg("Missing private key"))?;
+    let leaf = chain
+        .first()
+        .ok_or_else(|| anyhow::Error::msg("Missing certificate"))?;
+    let (_, x509) = x509_parser::parse_x509_certificate(&leaf.0)
+        .map_err(|e| anyhow::Error::msg(format!("Invalid certificate: {e}")))?;
+    let validity = x509.validity();
+    let not_before = validity.not_before.timestamp();
+    let not_after = validity.not_after.timestamp();
+    let renew_at = not_after - (not_after - not_before) / 3;
+    let now = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs() as i64;
+    let renew_in = Duration::from_secs((renew_at - now).max(0) as u64);
+    let signing_key = rustls::sign::any_ecdsa_type(&key)
+        .map_err(|_| anyhow::Error::msg("Unsupported private key type"))?;
+    Ok((CertifiedKey::new(chain, signing_key), renew_in))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn http01_challenge_response() {
+        let challenges = Http01Challenges::default();
+        challenges.insert("token123", "token123.thumb").await;
+
+        let request = Request::from_string(
+            "GET /.well-known/acme-challenge/token123 HTTP/1.1\r\nHost: localhost\r\n\r\n"
+                .to_owned(),
+        )
+        .unwrap();
+        let expected = Response::new(
+            http::StatusCode::OK,
+            "token123.thumb".into(),
+            MimeType::PlainText,
+        );
+        assert_eq!(Some(expected), challenges.respond(&request).await);
+
+        let request = Request::from_string(
+            "GET /.well-known/acme-challenge/other HTTP/1.1\r\nHost: localhost\r\n\r\n".to_owned(),
+        )
+        .unwrap();
+        let response = challenges.respond(&request).await.expect("no response");
+        assert_eq!(http::StatusCode::NOT_FOUND, response.status());
+
+        let request =
+            Request::from_string("GET /index.html HTTP/1.1\r\nHost: localhost\r\n\r\n".to_owned())
+                .unwrap();
+        assert_eq!(None, challenges.respond(&request).await);
+    }
+
+    #[test]
+    fn event_errors() {
+        let event: AcmeEvent = Err::<EventOk, EventError<std::io::Error, std::io::Error>>(
+            EventError::CertCacheLoad(std::io::Error::new(std::io::ErrorKind::NotFound, "gone")),
+        )
+        .into();
+        assert!(matches!(event, AcmeEvent::CacheError(_)));
+        assert!(event.is_error());
+        let event: AcmeEvent =
+            Ok::<EventOk, EventError<std::io::Error, std::io::Error>>(EventOk::DeployedNewCert)
+                .into();
+        assert_eq!(AcmeEvent::DeployedNewCert, event);
+        assert!(!event.is_error());
+    }
+
+    fn order(status: OrderStatus) -> Order {
+        Order {
+            status,
+            authorizations: vec![],
+            finalize: "https://acme.test/finalize".to_string(),
+            error: None,
+        }
+    }
+
+    #[tokio::test]
+    async fn order_polling() {
+        // leaves pending once its authorizations catch up
+        let mut fetched = 0;
+        let ready = wait_for_order(OrderStatus::Pending, Duration::ZERO, || {
+            fetched += 1;
+            let status = if fetched < 3 {
+                OrderStatus::Pending
+            } else {
+                OrderStatus::Ready
+            };
+            async move { Ok::<_, std::io::Error>(order(status)) }
+        })
+        .await
+        .unwrap();
+        assert_eq!(OrderStatus::Ready, ready.status);
+        assert_eq!(3, fetched);
+
+        // a stuck order is given up on instead of fetched forever
+        let mut fetched = 0;
+        let stuck = wait_for_order(OrderStatus::Processing, Duration::ZERO, || {
+            fetched += 1;
+            async { Ok::<_, std::io::Error>(order(OrderStatus::Processing)) }
+        })
+        .await;
+        assert!(stuck.is_err());
+        assert_eq!(ORDER_POLLS, fetched);
+
+        // fetch errors end the wait
+        let failed = wait_for_order(OrderStatus::Pending, Duration::ZERO, || async {
+            Err(std::io::Error::other("down"))
+        })
+        .await;
+        assert!(failed.is_err());
+    }
+
+    /// Key and chain the way order_http01 stores them
+    fn cert_pem(not_before: (i32, u8, u8), not_after: (i32, u8, u8)) -> Vec<u8> {
+        let mut params = rcgen::CertificateParams::new(vec!["localhost".to_string()]);
+        params.alg = &rcgen::PKCS_ECDSA_P256_SHA256;
+        params.not_before = rcgen::date_time_ymd(not_before.0, not_before.1, not_before.2);
+        params.not_after = rcgen::date_time_ymd(not_after.0, not_after.1, not_after.2);
+        let cert = rcgen::Certificate::from_params(params).unwrap();
+        [
+            cert.serialize_private_key_pem(),
+            "\n".to_string(),
+            cert.serialize_pem().unwrap(),
+        ]
+        .concat()
+        .into_bytes()
+    }
+
+    #[test]
+    fn renewal_schedule() {
+        // renewed 2/3 through its lifetime, around 2080 for 2020 to 2110
+        let (_, renew_in) = parse_cert(&cert_pem((2020, 1, 1), (2110, 1, 1))).unwrap();
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap()
+            .as_secs();
+        let renew_at = now + renew_in.as_secs();
+        // 2079-07-01 and 2080-07-01
+        assert!((3455395200..3487017600).contains(&renew_at), "{renew_at}");
+
+        // past its renewal point it's renewed right away
+        let (_, renew_in) = parse_cert(&cert_pem((2000, 1, 1), (2001, 1, 1))).unwrap();
+        assert_eq!(Duration::ZERO, renew_in);
+
+        assert!(parse_cert(b"not a cert").is_err());
+    }
+}
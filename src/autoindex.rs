@@ -0,0 +1,220 @@
+//! Directory listings for static directories without an index file
+use crate::{
+    headers::Accept,
+    http::{MimeType, StatusCode},
+    request::Request,
+    response::Response,
+    static_files::{AutoIndex, DotFiles, StaticConfig, Symlinks},
+    utils,
+};
+use serde::Serialize;
+use std::{
+    path::Path,
+    time::{Duration, UNIX_EPOCH},
+};
+
+/// File or directory in a listing
+#[derive(Debug, PartialEq, Eq, Clone, Serialize)]
+pub struct Entry {
+    pub name: String,
+    pub dir: bool,
+    /// bytes, 0 for directories
+    pub size: u64,
+    /// seconds since the unix epoch
+    pub modified: Option<u64>,
+}
+
+#[derive(Serialize)]
+struct Listing<'a> {
+    path: &'a str,
+    entries: &'a [Entry],
+}
+
+/// Entries of dir that could be served with config, directories first then by name
+/// hidden files and symlinks that would be refused are left out
+pub async fn entries(
+    root: &Path,
+    dir: &Path,
+    config: &StaticConfig,
+) -> Result<Vec<Entry>, std::io::Error> {
+    let canonical_root = tokio::fs::canonicalize(root).await?;
+    let mut read_dir = tokio::fs::read_dir(dir).await?;
+    let mut entries = vec![];
+    while let Some(dir_entry) = read_dir.next_entry().await? {
+        let Ok(name) = dir_entry.file_name().into_string() else {
+            continue;
+        };
+        if name.starts_with('.') && config.dot_files() != DotFiles::Allow {
+            continue;
+        }
+        if dir_entry.file_type().await?.is_symlink() {
+            match config.symlinks() {
+                Symlinks::Deny => continue,
+                Symlinks::WithinRoot => match tokio::fs::canonicalize(dir_entry.path()).await {
+                    Ok(target) if target.starts_with(&canonical_root) => {}
+                    _ => continue,
+                },
+                Symlinks::Follow => {}
+            }
+        }
+        // follows symlinks, skips broken ones
+        let Ok(metadata) = tokio::fs::metadata(dir_entry.path()).await else {
+            continue;
+        };
+        entries.push(Entry {
+            name,
+            dir: metadata.is_dir(),
+            size: if metadata.is_dir() { 0 } else { metadata.len() },
+            modified: metadata
+                .modified()
+                .ok()
+                .and_then(|m| m.duration_since(UNIX_EPOCH).ok())
+                .map(|d| d.as_secs()),
+        });
+    }
+    entries.sort_by(|a, b| b.dir.cmp(&a.dir).then_with(|| a.name.cmp(&b.name)));
+    Ok(entries)
+}
+
+/// Listing of dir as html or json depending on config, request_path is the directory's url
+pub async fn listing(
+    root: &Path,
+    dir: &Path,
+    request_path: &str,
+    config: &StaticConfig,
+    request: &Request,
+) -> Result<Response, std::io::Error> {
+    let entries = entries(root, dir, config).await?;
+    let json = match config.autoindex() {
+        AutoIndex::Json => true,
+        AutoIndex::Negotiate => request
+            .typed_header::<Accept>()
+            .map(|accept| accept.quality("application/json") > accept.quality("text/html"))
+            .unwrap_or(false),
+        AutoIndex::Html | AutoIndex::Off => false,
+    };
+    if json {
+        let listing = Listing {
+            path: request_path,
+            entries: &entries,
+        };
+        let body = serde_json::to_vec(&listing)?;
+        Ok(Response::new(StatusCode::OK, body, MimeType::Json))
+    } else {
+        let body = html(request_path, &entries);
+        Ok(Response::new(StatusCode::OK, body.into(), MimeType::HTML))
+    }
+}
+
+fn html(request_path: &str, entries: &[Entry]) -> String {
+    let title = escape(&format!("Index of {request_path}"));
+    let mut html = format!(
+        "<!DOCTYPE html>\n<html>\n<head><meta charset=\"utf-8\"><title>{title}</title></head>\n\
+        <body>\n<h1>{title}</h1>\n<table>\n\
+        <tr><th>Name</th><th>Last Modified</th><th>Size</th></tr>\n"
+    );
+    if request_path != "/" {
+        html.push_str("<tr><td><a href=\"../\">../</a></td><td></td><td></td></tr>\n");
+    }
+    for entry in entries {
+        let slash = if entry.dir { "/" } else { "" };
+        let href = utils::percent_encode_path(&entry.name);
+        let name = escape(&entry.name);
+        let modified = entry
+            .modified
+            .map(|secs| httpdate::fmt_http_date(UNIX_EPOCH + Duration::from_secs(secs)))
+            .unwrap_or_default();
+        let size = if entry.dir {
+            "-".to_string()
+        } else {
+            entry.size.to_string()
+        };
+        html.push_str(&format!(
+            "<tr><td><a href=\"{href}{slash}\">{name}{slash}</a></td><td>{modified}</td><td>{size}</td></tr>\n"
+        ));
+    }
+    html.push_str("</table>\n</body>\n</html>\n");
+    html
+}
+
+fn escape(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            '\'' => escaped.push_str("&#39;"),
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::TempDir;
+
+    fn test_dir() -> TempDir {
+        let root = TempDir::new("autoindex");
+        std::fs::create_dir_all(root.join("sub")).unwrap();
+        std::fs::write(root.join("b <&>.txt"), "12345").unwrap();
+        std::fs::write(root.join("a.txt"), "1").unwrap();
+        std::fs::write(root.join(".hidden"), "").unwrap();
+        std::os::unix::fs::symlink(std::env::temp_dir(), root.join("escape")).unwrap();
+        root
+    }
+
+    fn request(accept: &str) -> Request {
+        Request::from_string(format!(
+            "GET / HTTP/1.1\r\nHost: test\r\nAccept: {accept}\r\n\r\n"
+        ))
+        .unwrap()
+    }
+
+    #[tokio::test]
+    async fn listings() {
+        let root = test_dir();
+        let mut config = StaticConfig::default();
+        let names =
+            |entries: Vec<Entry>| -> Vec<String> { entries.into_iter().map(|e| e.name).collect() };
+        let listed = entries(&root, &root, &config).await.unwrap();
+        assert_eq!(vec!["sub", "a.txt", "b <&>.txt"], names(listed.clone()));
+        assert!(listed[0].dir);
+        assert_eq!(5, listed[2].size);
+        assert!(listed[2].modified.is_some());
+
+        config.set_dot_files(DotFiles::Allow);
+        let listed = entries(&root, &root, &config).await.unwrap();
+        assert_eq!(vec!["sub", ".hidden", "a.txt", "b <&>.txt"], names(listed));
+
+        config.set_autoindex(AutoIndex::Negotiate);
+        let html = listing(&root, &root, "/", &config, &request("text/html"))
+            .await
+            .unwrap();
+        assert_eq!(MimeType::HTML, html.mime());
+        let body = String::from_utf8(html.body().to_vec()).unwrap();
+        assert!(body.contains("<a href=\"sub/\">sub/</a>"), "{body}");
+        assert!(
+            body.contains("<a href=\"b%20%3C%26%3E.txt\">b &lt;&amp;&gt;.txt</a>"),
+            "{body}"
+        );
+        assert!(!body.contains("../"));
+
+        let json = listing(
+            &root,
+            &root.join("sub"),
+            "/sub/",
+            &config,
+            &request("application/json"),
+        )
+        .await
+        .unwrap();
+        assert_eq!(MimeType::Json, json.mime());
+        let value: serde_json::Value = serde_json::from_slice(json.body()).unwrap();
+        assert_eq!("/sub/", value["path"]);
+        assert_eq!(0, value["entries"].as_array().unwrap().len());
+    }
+}
@@ -0,0 +1,446 @@
+//! Response compression negotiated with Accept-Encoding
+use crate::{
+    headers::{AcceptEncoding, ETag},
+    http::{MimeType, StatusCode},
+    request::Request,
+    response::{BodyStream, Response},
+};
+use flate2::write::{GzEncoder, ZlibEncoder};
+use futures::StreamExt;
+use std::io::Write;
+
+/// Bodies larger than this are compressed on the blocking pool, it would hold up a runtime thread
+const BLOCKING_SIZE: usize = 64 * 1024;
+
+/// Content codings the server can produce
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum Encoding {
+    Brotli,
+    Gzip,
+    /// zlib format (RFC 1950), which is what deflate means in HTTP
+    Deflate,
+}
+
+impl Encoding {
+    /// Content-Encoding value
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Encoding::Brotli => "br",
+            Encoding::Gzip => "gzip",
+            Encoding::Deflate => "deflate",
+        }
+    }
+
+    /// Extension of a precompressed copy of a file, eg. app.js.br
+    pub fn extension(&self) -> &'static str {
+        match self {
+            Encoding::Brotli => "br",
+            Encoding::Gzip => "gz",
+            Encoding::Deflate => "zz",
+        }
+    }
+}
+
+/// Settings for compressing responses, set on a [`crate::routes::Router`]
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub struct CompressionConfig {
+    encodings: Vec<Encoding>,
+    min_size: usize,
+    max_size: Option<usize>,
+    mime_overrides: Vec<(MimeType, bool)>,
+}
+
+impl Default for CompressionConfig {
+    fn default() -> Self {
+        CompressionConfig {
+            encodings: vec![Encoding::Brotli, Encoding::Gzip, Encoding::Deflate],
+            min_size: 1024,
+            max_size: None,
+            mime_overrides: vec![],
+        }
+    }
+}
+
+impl CompressionConfig {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Encodings the server will use, when the client weights several the same the first one in
+    /// this list wins
+    pub fn encodings(&self) -> &[Encoding] {
+        &self.encodings
+    }
+
+    pub fn set_encodings(&mut self, encodings: Vec<Encoding>) {
+        self.encodings = encodings;
+    }
+
+    /// Bodies smaller than this are sent as is
+    pub fn min_size(&self) -> usize {
+        self.min_size
+    }
+
+    pub fn set_min_size(&mut self, min_size: usize) {
+        self.min_size = min_size;
+    }
+
+    /// Bodies larger than this are sent as is, no limit unless set
+    pub fn max_size(&self) -> Option<usize> {
+        self.max_size
+    }
+
+    pub fn set_max_size(&mut self, max_size: usize) {
+        self.max_size = Some(max_size);
+    }
+
+    /// Force compression on or off for a mime type
+    pub fn set_mime(&mut self, mime: MimeType, compress: bool) {
+        self.mime_overrides.retain(|(m, _)| m != &mime);
+        self.mime_overrides.push((mime, compress));
+    }
+
+    /// Text types are compressed by default, images, audio, video and archives are not
+    pub fn compresses(&self, mime: MimeType) -> bool {
+        if let Some((_, compress)) = self.mime_overrides.iter().find(|(m, _)| m == &mime) {
+            return *compress;
+        }
+        let media_type = mime.media_type();
+        media_type.starts_with("text/")
+            || media_type == "image/svg+xml"
+            || media_type == "image/vnd.microsoft.icon"
+            || media_type == "application/javascript"
+            || media_type == "application/json"
+            || media_type == "application/xml"
+            || media_type == "application/wasm"
+            || media_type.ends_with("+json")
+            || media_type.ends_with("+xml")
+    }
+
+    /// Best encoding the client accepts, None to send the body as is
+    pub fn negotiate(&self, accept: &AcceptEncoding) -> Option<Encoding> {
+        negotiate(&self.encodings, accept)
+    }
+
+    /// Compress response body if the client accepts it
+    /// only complete 2xx bodies are compressed, never partial content or bodies that already
+    /// have a Content-Encoding. Large bodies are compressed on the blocking pool
+    pub async fn compress(&self, request: &Request, mut response: Response) -> Response {
+        let status = response.status();
+        if !status.is_success()
+            || status == StatusCode::PARTIAL_CONTENT
+            || status == StatusCode::NO_CONTENT
+            || response.headers().contains("content-encoding")
+            || !self.compresses(response.mime())
+        {
+            return response;
+        }
+        // the body depends on Accept-Encoding even if this client doesn't get it compressed
+        response.add_vary("Accept-Encoding");
+        let size = response.body().len();
+        if !response.is_streamed()
+            && (size < self.min_size || self.max_size.is_some_and(|max| size > max))
+        {
+            return response;
+        }
+        let Some(accept) = request.typed_header::<AcceptEncoding>() else {
+            return response;
+        };
+        let Some(encoding) = self.negotiate(&accept) else {
+            return response;
+        };
+        if let Some(stream) = response.take_stream() {
+            response.set_stream(encode_stream(stream, encoding));
+            response.set_header(("Content-Encoding", encoding.as_str()));
+            return response;
+        }
+        let compressed = if size > BLOCKING_SIZE {
+            let body = response.take_body();
+            let compressed = tokio::task::spawn_blocking(move || {
+                let compressed = Encoder::new(encoding).encode_all(&body);
+                (body, compressed)
+            })
+            .await;
+            match compressed {
+                Ok((body, compressed)) => {
+                    response.set_body(body);
+                    compressed
+                }
+                Err(error) => {
+                    tracing::error!("error compressing response: {error}");
+                    return Response::error(
+                        StatusCode::INTERNAL_SERVER_ERROR,
+                        "Internal Server Error".into(),
+                    );
+                }
+            }
+        } else {
+            Encoder::new(encoding).encode_all(response.body())
+        };
+        let compressed = match compressed {
+            Ok(compressed) => compressed,
+            Err(error) => {
+                tracing::error!("error compressing response: {error}");
+                return response;
+            }
+        };
+        if compressed.len() >= response.body().len() {
+            return response;
+        }
+        response.set_body(compressed);
+        response.set_header(("Content-Encoding", encoding.as_str()));
+        // compressed bytes differ so a strong tag would be wrong, weak still allows 304s
+        if let Some(etag) = response.typed_header::<ETag>() {
+            response.set_typed(ETag::weak(etag.tag()));
+        }
+        response
+    }
+}
+
+/// Encoding from encodings with the highest weight in accept, ties go to the earlier one
+pub fn negotiate(encodings: &[Encoding], accept: &AcceptEncoding) -> Option<Encoding> {
+    let mut best: Option<(Encoding, u16)> = None;
+    for encoding in encodings {
+        let quality = accept.quality(encoding.as_str());
+        if quality > 0 && best.map(|(_, q)| quality > q).unwrap_or(true) {
+            best = Some((*encoding, quality));
+        }
+    }
+    best.map(|(encoding, _)| encoding)
+}
+
+/// Compress each chunk of stream as it's produced so the client doesn't wait for more
+fn encode_stream(stream: BodyStream, encoding: Encoding) -> BodyStream {
+    let state = Some((stream, Encoder::new(encoding)));
+    BodyStream::new(futures::stream::unfold(state, |state| async move {
+        let (mut stream, mut encoder) = state?;
+        let encoded = match stream.next().await {
+            Some(chunk) => encoder
+                .encode_chunk(&chunk)
+                .map(|c| (c, Some((stream, encoder)))),
+            None => encoder.finish().map(|rest| (rest, None)),
+        };
+        encoded
+            .map_err(|error| tracing::error!("error compressing stream: {error}"))
+            .ok()
+    }))
+}
+
+/// Incremental compressor, chunks can be encoded and flushed one at a time for streamed bodies
+pub struct Encoder(EncoderKind);
+
+enum EncoderKind {
+    Brotli(Box<brotli::CompressorWriter<Vec<u8>>>),
+    Gzip(GzEncoder<Vec<u8>>),
+    Deflate(ZlibEncoder<Vec<u8>>),
+}
+
+impl Encoder {
+    pub fn new(encoding: Encoding) -> Self {
+        let level = flate2::Compression::default();
+        let kind = match encoding {
+            // quality 5 is a lot faster than the max of 11 and still beats gzip
+            Encoding::Brotli => {
+                EncoderKind::Brotli(Box::new(brotli::CompressorWriter::new(vec![], 4096, 5, 22)))
+            }
+            Encoding::Gzip => EncoderKind::Gzip(GzEncoder::new(vec![], level)),
+            Encoding::Deflate => EncoderKind::Deflate(ZlibEncoder::new(vec![], level)),
+        };
+        Encoder(kind)
+    }
+
+    fn writer(&mut self) -> &mut dyn Write {
+        match &mut self.0 {
+            EncoderKind::Brotli(w) => w.as_mut(),
+            EncoderKind::Gzip(w) => w,
+            EncoderKind::Deflate(w) => w,
+        }
+    }
+
+    fn take_output(&mut self) -> Vec<u8> {
+        let output = match &mut self.0 {
+            EncoderKind::Brotli(w) => w.get_mut(),
+            EncoderKind::Gzip(w) => w.get_mut(),
+            EncoderKind::Deflate(w) => w.get_mut(),
+        };
+        std::mem::take(output)
+    }
+
+    /// Compress chunk and flush, the returned bytes can be sent to the client right away
+    pub fn encode_chunk(&mut self, chunk: &[u8]) -> Result<Vec<u8>, std::io::Error> {
+        let writer = self.writer();
+        writer.write_all(chunk)?;
+        writer.flush()?;
+        Ok(self.take_output())
+    }
+
+    /// End the stream, returns any bytes that haven't been taken yet
+    pub fn finish(mut self) -> Result<Vec<u8>, std::io::Error> {
+        let mut output = self.take_output();
+        let rest = match self.0 {
+            EncoderKind::Brotli(w) => w.into_inner(),
+            EncoderKind::Gzip(w) => w.finish()?,
+            EncoderKind::Deflate(w) => w.finish()?,
+        };
+        output.extend_from_slice(&rest);
+        Ok(output)
+    }
+
+    /// Compress a whole body
+    pub fn encode_all(mut self, body: &[u8]) -> Result<Vec<u8>, std::io::Error> {
+        self.writer().write_all(body)?;
+        self.finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use flate2::read::{GzDecoder, ZlibDecoder};
+    use std::io::Read;
+
+    fn decode(encoding: Encoding, body: &[u8]) -> Vec<u8> {
+        let mut decoded = vec![];
+        match encoding {
+            Encoding::Brotli => {
+                brotli::Decompressor::new(body, 4096)
+                    .read_to_end(&mut decoded)
+                    .unwrap();
+            }
+            Encoding::Gzip => {
+                GzDecoder::new(body).read_to_end(&mut decoded).unwrap();
+            }
+            Encoding::Deflate => {
+                ZlibDecoder::new(body).read_to_end(&mut decoded).unwrap();
+            }
+        };
+        decoded
+    }
+
+    fn request(accept_encoding: &str) -> Request {
+        Request::from_string(format!(
+            "GET / HTTP/1.1\r\nHost: test\r\nAccept-Encoding: {accept_encoding}\r\n\r\n"
+        ))
+        .unwrap()
+    }
+
+    #[test]
+    fn encoders() {
+        let body = "hello compression ".repeat(100);
+        for encoding in [Encoding::Brotli, Encoding::Gzip, Encoding::Deflate] {
+            let compressed = Encoder::new(encoding).encode_all(body.as_bytes()).unwrap();
+            assert!(compressed.len() < body.len());
+            assert_eq!(body.as_bytes(), decode(encoding, &compressed));
+
+            // streamed chunks decode to the same thing
+            let mut encoder = Encoder::new(encoding);
+            let mut streamed = vec![];
+            for chunk in body.as_bytes().chunks(100) {
+                let encoded = encoder.encode_chunk(chunk).unwrap();
+                assert!(!encoded.is_empty(), "{encoding:?} chunk wasn't flushed");
+                streamed.extend(encoded);
+            }
+            streamed.extend(encoder.finish().unwrap());
+            assert_eq!(body.as_bytes(), decode(encoding, &streamed));
+        }
+    }
+
+    #[test]
+    fn negotiate() {
+        let config = CompressionConfig::default();
+        let accept = |value| request(value).typed_header::<AcceptEncoding>().unwrap();
+        assert_eq!(
+            Some(Encoding::Brotli),
+            config.negotiate(&accept("gzip, deflate, br"))
+        );
+        assert_eq!(
+            Some(Encoding::Gzip),
+            config.negotiate(&accept("gzip, br;q=0.5"))
+        );
+        assert_eq!(Some(Encoding::Brotli), config.negotiate(&accept("*")));
+        assert_eq!(None, config.negotiate(&accept("identity")));
+        assert_eq!(None, config.negotiate(&accept("br;q=0, gzip;q=0, *;q=0")));
+    }
+
+    #[tokio::test]
+    async fn compress_response() {
+        let config = CompressionConfig::default();
+        let body = "<p>hello</p>".repeat(200);
+        let mut response = Response::from(body.clone());
+        response.set_typed(ETag::strong("abc"));
+
+        let compressed = config.compress(&request("gzip"), response).await;
+        assert_eq!(Some("gzip"), compressed.headers().get("content-encoding"));
+        assert_eq!(Some("Accept-Encoding"), compressed.headers().get("vary"));
+        assert_eq!(Some(ETag::weak("abc")), compressed.typed_header::<ETag>());
+        assert_eq!(body.as_bytes(), decode(Encoding::Gzip, compressed.body()));
+
+        // client without gzip still gets Vary
+        let plain = config
+            .compress(&request("identity"), Response::from(body.clone()))
+            .await;
+        assert_eq!(None, plain.headers().get("content-encoding"));
+        assert_eq!(Some("Accept-Encoding"), plain.headers().get("vary"));
+
+        // too small
+        let small = config
+            .compress(&request("gzip"), Response::from("<p>hi</p>"))
+            .await;
+        assert_eq!(None, small.headers().get("content-encoding"));
+
+        // already compressed types are skipped unless overriden
+        let image = Response::new(StatusCode::OK, vec![0; 4096], MimeType::PNG);
+        let image = config.compress(&request("gzip"), image).await;
+        assert_eq!(None, image.headers().get("content-encoding"));
+        assert_eq!(None, image.headers().get("vary"));
+
+        let mut config = CompressionConfig::default();
+        config.set_mime(MimeType::PNG, true);
+        config.set_mime(MimeType::HTML, false);
+        let image = Response::new(StatusCode::OK, vec![0; 4096], MimeType::PNG);
+        let image = config.compress(&request("gzip"), image).await;
+        assert_eq!(Some("gzip"), image.headers().get("content-encoding"));
+        let html = config
+            .compress(&request("gzip"), Response::from(body))
+            .await;
+        assert_eq!(None, html.headers().get("content-encoding"));
+    }
+
+    #[tokio::test]
+    async fn compress_large() {
+        let body = "<p>hello</p>".repeat(20_000);
+        assert!(body.len() > BLOCKING_SIZE);
+        // compressed on the blocking pool
+        let mut config = CompressionConfig::default();
+        let compressed = config
+            .compress(&request("br"), Response::from(body.clone()))
+            .await;
+        assert_eq!(Some("br"), compressed.headers().get("content-encoding"));
+        assert_eq!(body.as_bytes(), decode(Encoding::Brotli, compressed.body()));
+
+        config.set_max_size(BLOCKING_SIZE);
+        let plain = config
+            .compress(&request("br"), Response::from(body.clone()))
+            .await;
+        assert_eq!(None, plain.headers().get("content-encoding"));
+        assert_eq!(body.as_bytes(), plain.body());
+    }
+
+    #[tokio::test]
+    async fn compress_stream() {
+        let config = CompressionConfig::default();
+        let chunks = vec![b"data: one\n\n".to_vec(), b"data: two\n\n".to_vec()];
+        let mut response = Response::from("");
+        response.set_stream(BodyStream::new(futures::stream::iter(chunks)));
+        // small streams are still compressed, their size isn't known up front
+        let mut compressed = config.compress(&request("gzip"), response).await;
+        assert_eq!(Some("gzip"), compressed.headers().get("content-encoding"));
+        let encoded: Vec<Vec<u8>> = compressed.take_stream().unwrap().collect().await;
+        // every event is flushed plus the trailer
+        assert_eq!(3, encoded.len());
+        assert_eq!(
+            b"data: one\n\ndata: two\n\n".to_vec(),
+            decode(Encoding::Gzip, &encoded.concat())
+        );
+    }
+}
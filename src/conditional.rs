@@ -0,0 +1,212 @@
+//! Conditional requests (RFC 7232), If-Match, If-None-Match, If-Modified-Since and
+//! If-Unmodified-Since evaluated against a response's ETag and Last-Modified
+use crate::{
+    headers::{ETag, IfMatch, IfModifiedSince, IfNoneMatch, IfUnmodifiedSince, LastModified},
+    http::{Method, StatusCode},
+    request::Request,
+    response::Response,
+};
+use sha2::{Digest, Sha256};
+use std::{fs::Metadata, time::SystemTime};
+
+/// Outcome of evaluating request preconditions
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum Precondition {
+    /// send the full response
+    Passed,
+    /// 304, the client's cached copy is still valid
+    NotModified,
+    /// 412
+    Failed,
+}
+
+/// Evaluate preconditions in the order from RFC 7232 6
+pub fn evaluate(
+    request: &Request,
+    etag: Option<&ETag>,
+    last_modified: Option<SystemTime>,
+) -> Precondition {
+    let last_modified = last_modified.map(|t| LastModified::new(t).time());
+    if let Some(if_match) = request.typed_header::<IfMatch>() {
+        if !etag.map(|e| if_match.matches(e)).unwrap_or(false) {
+            return Precondition::Failed;
+        }
+    } else if let Some(since) = request.typed_header::<IfUnmodifiedSince>() {
+        if last_modified.map(|m| m > since.time()).unwrap_or(false) {
+            return Precondition::Failed;
+        }
+    }
+
+    if let Some(if_none_match) = request.typed_header::<IfNoneMatch>() {
+        let matched = match etag {
+            Some(etag) => if_none_match.matches(etag),
+            None => if_none_match == IfNoneMatch::Any,
+        };
+        if matched {
+            return match request.method() {
+                Method::GET => Precondition::NotModified,
+                _ => Precondition::Failed,
+            };
+        }
+    } else if let Some(since) = request.typed_header::<IfModifiedSince>() {
+        if request.method() == &Method::GET
+            && last_modified.map(|m| m <= since.time()).unwrap_or(false)
+        {
+            return Precondition::NotModified;
+        }
+    }
+    Precondition::Passed
+}
+
+/// Turn a 2xx response into a 304 or 412 using its ETag and Last-Modified headers
+pub fn apply(request: &Request, response: Response) -> Response {
+    if !response.status().is_success() {
+        return response;
+    }
+    let etag = response.typed_header::<ETag>();
+    let last_modified = response.typed_header::<LastModified>().map(|l| l.time());
+    if etag.is_none() && last_modified.is_none() {
+        return response;
+    }
+    match evaluate(request, etag.as_ref(), last_modified) {
+        Precondition::Passed => response,
+        Precondition::NotModified => not_modified(&response),
+        Precondition::Failed => Response::error(
+            StatusCode::PRECONDITION_FAILED,
+            "Precondition Failed".into(),
+        ),
+    }
+}
+
+/// 304 keeping only the headers RFC 7232 4.1 says to send
+pub fn not_modified(response: &Response) -> Response {
+    const KEEP: [&str; 7] = [
+        "cache-control",
+        "content-location",
+        "date",
+        "etag",
+        "expires",
+        "last-modified",
+        "vary",
+    ];
+    let mut not_modified = Response::new(StatusCode::NOT_MODIFIED, vec![], response.mime());
+    for header in response.headers() {
+        if KEEP.contains(&header.key.as_str()) {
+            not_modified.add_header(header);
+        }
+    }
+    not_modified
+}
+
+/// Strong ETag from a hash of body, used for embedded files
+pub fn content_etag(body: &[u8]) -> ETag {
+    let hash = Sha256::digest(body);
+    let hex: String = hash[..16].iter().map(|b| format!("{b:02x}")).collect();
+    ETag::strong(&hex)
+}
+
+/// Strong ETag from modified time and size, used for files on disk
+pub fn file_etag(metadata: &Metadata) -> ETag {
+    let modified = metadata
+        .modified()
+        .ok()
+        .and_then(|m| m.duration_since(SystemTime::UNIX_EPOCH).ok())
+        .unwrap_or_default();
+    ETag::strong(&format!(
+        "{:x}.{:x}-{:x}",
+        modified.as_secs(),
+        modified.subsec_nanos(),
+        metadata.len()
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    fn request(headers: &str) -> Request {
+        Request::from_string(format!("GET / HTTP/1.1\r\nHost: test\r\n{headers}\r\n"))
+            .expect("Error Parsing")
+    }
+
+    #[test]
+    fn if_none_match() {
+        let etag = ETag::strong("abc");
+        let check = |headers| evaluate(&request(headers), Some(&etag), None);
+        assert_eq!(Precondition::Passed, check(""));
+        assert_eq!(
+            Precondition::NotModified,
+            check("If-None-Match: \"x\", W/\"abc\"\r\n")
+        );
+        assert_eq!(Precondition::NotModified, check("If-None-Match: *\r\n"));
+        assert_eq!(Precondition::Passed, check("If-None-Match: \"x\"\r\n"));
+
+        let post = Request::from_string(
+            "POST / HTTP/1.1\r\nHost: test\r\nIf-None-Match: \"abc\"\r\nContent-Length: 0\r\n\r\n"
+                .to_owned(),
+        )
+        .unwrap();
+        assert_eq!(Precondition::Failed, evaluate(&post, Some(&etag), None));
+    }
+
+    #[test]
+    fn if_match() {
+        let etag = ETag::strong("abc");
+        let check = |headers| evaluate(&request(headers), Some(&etag), None);
+        assert_eq!(Precondition::Passed, check("If-Match: \"abc\"\r\n"));
+        assert_eq!(Precondition::Passed, check("If-Match: *\r\n"));
+        assert_eq!(Precondition::Failed, check("If-Match: W/\"abc\"\r\n"));
+        assert_eq!(Precondition::Failed, check("If-Match: \"x\"\r\n"));
+        assert_eq!(
+            Precondition::Failed,
+            evaluate(&request("If-Match: *\r\n"), None, None)
+        );
+    }
+
+    #[test]
+    fn dates() {
+        // Sun, 06 Nov 1994 08:49:37 GMT
+        let date = SystemTime::UNIX_EPOCH + Duration::from_secs(784111777);
+        let check = |headers, modified| evaluate(&request(headers), None, Some(modified));
+        let since = "If-Modified-Since: Sun, 06 Nov 1994 08:49:37 GMT\r\n";
+        let unmodified = "If-Unmodified-Since: Sun, 06 Nov 1994 08:49:37 GMT\r\n";
+        let later = date + Duration::from_secs(1);
+
+        assert_eq!(Precondition::NotModified, check(since, date));
+        assert_eq!(
+            Precondition::NotModified,
+            check(since, date + Duration::from_millis(500))
+        );
+        assert_eq!(Precondition::Passed, check(since, later));
+        assert_eq!(Precondition::Passed, check(unmodified, date));
+        assert_eq!(Precondition::Failed, check(unmodified, later));
+
+        // If-None-Match wins over If-Modified-Since
+        let etag = ETag::strong("abc");
+        let headers = format!("If-None-Match: \"x\"\r\n{since}");
+        assert_eq!(
+            Precondition::Passed,
+            evaluate(&request(&headers), Some(&etag), Some(date))
+        );
+    }
+
+    #[test]
+    fn apply_response() {
+        let mut response = Response::from("hello");
+        response.set_typed(content_etag(b"hello"));
+        response.add_header(("Cache-Control", "max-age=60"));
+        response.add_header(("X-Other", "dropped"));
+        let etag = content_etag(b"hello").to_string();
+
+        let request = request(&format!("If-None-Match: {etag}\r\n"));
+        let not_modified = apply(&request, response);
+        assert_eq!(StatusCode::NOT_MODIFIED, not_modified.status());
+        assert_eq!(Some(etag.as_str()), not_modified.headers().get("etag"));
+        assert!(not_modified.headers().contains("cache-control"));
+        assert!(!not_modified.headers().contains("x-other"));
+        let buffer = String::from_utf8(not_modified.to_send_buffer()).unwrap();
+        assert!(!buffer.contains("Content-Length"), "{buffer}");
+        assert!(buffer.ends_with("\r\n\r\n"));
+    }
+}
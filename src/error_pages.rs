@@ -0,0 +1,200 @@
+//! Custom responses for error status codes, set on a router or virtual host
+use crate::{
+    http::{MimeType, StatusCode},
+    request::Request,
+    response::{IntoResponse, Response},
+    static_files::StaticConfig,
+    utils,
+};
+use async_trait::async_trait;
+use futures::FutureExt;
+use std::{collections::HashMap, future::Future, panic::AssertUnwindSafe, path::Path, sync::Arc};
+
+/// Async function that builds the response for an error
+/// request is None when the request couldn't be parsed, error is the response that would have
+/// been sent
+#[async_trait]
+pub trait ErrorResolver: Send + Sync + 'static {
+    async fn resolve(&self, request: Option<Request>, error: Response) -> Response;
+}
+
+#[async_trait]
+impl<F, O, Fut> ErrorResolver for F
+where
+    O: IntoResponse,
+    Fut: Future<Output = O> + Send + 'static,
+    F: Fn(Option<Request>, Response) -> Fut + Send + Sync + 'static,
+{
+    async fn resolve(&self, request: Option<Request>, error: Response) -> Response {
+        (self)(request, error).await.into_response()
+    }
+}
+
+#[derive(Clone)]
+pub enum ErrorHandler {
+    /// file relative to the doc root, eg. 404.html
+    Static {
+        file_path: String,
+    },
+    Embed(&'static [u8], MimeType),
+    Function(Arc<Box<dyn ErrorResolver>>),
+}
+
+impl ErrorHandler {
+    pub fn file(file_path: &str) -> Self {
+        ErrorHandler::Static {
+            file_path: file_path.to_string(),
+        }
+    }
+
+    /// use include_bytes! to load the page
+    pub fn embed(body: &'static [u8], mime: MimeType) -> Self {
+        ErrorHandler::Embed(body, mime)
+    }
+
+    pub fn function<R: ErrorResolver>(func: R) -> Self {
+        ErrorHandler::Function(Arc::new(Box::new(func)))
+    }
+}
+
+/// Error handlers keyed by status code
+#[derive(Clone, Default)]
+pub struct ErrorPages {
+    handlers: HashMap<StatusCode, ErrorHandler>,
+}
+
+impl ErrorPages {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Handle responses with status, replaces any handler already set for it
+    pub fn add(&mut self, status: StatusCode, handler: ErrorHandler) {
+        self.handlers.insert(status, handler);
+    }
+
+    pub fn get(&self, status: StatusCode) -> Option<&ErrorHandler> {
+        self.handlers.get(&status)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.handlers.is_empty()
+    }
+
+    /// Replace the body of a 4xx or 5xx response if there is a handler for its status
+    /// pages from files and embeds keep the status and headers of the original response, if the
+    /// file can't be read the original response is sent
+    pub async fn apply(
+        &self,
+        request: Option<&Request>,
+        doc_root: &Path,
+        static_config: &StaticConfig,
+        response: Response,
+    ) -> Response {
+        let status = response.status();
+        if !(status.is_client_error() || status.is_server_error()) {
+            return response;
+        }
+        let Some(handler) = self.handlers.get(&status) else {
+            return response;
+        };
+        match handler {
+            ErrorHandler::Static { file_path } => {
+                let page = async {
+                    let path = static_config
+                        .resolve(doc_root, file_path)
+                        .await
+                        .map_err(|status| anyhow::Error::msg(status.to_string()))?;
+                    let body = tokio::fs::read(&path).await?;
+                    let mime = static_config.file_mime_type(&path).await;
+                    Ok::<_, anyhow::Error>((body, mime))
+                };
+                match page.await {
+                    Ok((body, mime)) => replace_body(response, body, mime),
+                    Err(error) => {
+                        tracing::warn!("error page {file_path} for {status} failed: {error}");
+                        response
+                    }
+                }
+            }
+            ErrorHandler::Embed(body, mime) => replace_body(response, body.to_vec(), mime.clone()),
+            ErrorHandler::Function(resolver) => {
+                let resolved = AssertUnwindSafe(resolver.resolve(request.cloned(), response))
+                    .catch_unwind()
+                    .await;
+                resolved.unwrap_or_else(|panic| {
+                    tracing::error!(
+                        "error handler for {status} panicked: {}",
+                        utils::panic_message(panic.as_ref())
+                    );
+                    Response::from(status)
+                })
+            }
+        }
+    }
+}
+
+fn replace_body(mut response: Response, body: Vec<u8>, mime: MimeType) -> Response {
+    response.headers_mut().remove("content-type");
+    response.set_body(body);
+    response.set_mime(mime);
+    response
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    async fn teapot(request: Option<Request>, error: Response) -> Response {
+        let path = request.map(|r| r.path().to_string()).unwrap_or_default();
+        let body = format!("{} at {path}", error.status().as_u16());
+        Response::new(StatusCode::IM_A_TEAPOT, body.into(), MimeType::PlainText)
+    }
+
+    #[tokio::test]
+    async fn apply_handlers() {
+        let mut pages = ErrorPages::new();
+        pages.add(StatusCode::NOT_FOUND, ErrorHandler::file("404.html"));
+        pages.add(
+            StatusCode::FORBIDDEN,
+            ErrorHandler::embed(b"{\"error\":\"forbidden\"}", MimeType::Json),
+        );
+        pages.add(StatusCode::BAD_REQUEST, ErrorHandler::function(teapot));
+        pages.add(StatusCode::GONE, ErrorHandler::file("missing.html"));
+        pages.add(StatusCode::OK, ErrorHandler::file("404.html"));
+        let config = StaticConfig::default();
+        let root = Path::new("./");
+        let request =
+            Request::from_string("GET /x HTTP/1.1\r\nHost: test\r\n\r\n".to_owned()).unwrap();
+
+        let mut not_found = Response::error(StatusCode::NOT_FOUND, "Not Found".into());
+        not_found.add_header(("X-Kept", "yes"));
+        let page = pages.apply(Some(&request), root, &config, not_found).await;
+        assert_eq!(StatusCode::NOT_FOUND, page.status());
+        assert_eq!(MimeType::HTML, page.mime());
+        assert_eq!(std::fs::read("./404.html").unwrap(), page.body());
+        assert_eq!(Some("yes"), page.headers().get("x-kept"));
+
+        let forbidden = Response::error(StatusCode::FORBIDDEN, "Forbidden".into());
+        let page = pages.apply(Some(&request), root, &config, forbidden).await;
+        assert_eq!(MimeType::Json, page.mime());
+        assert_eq!(b"{\"error\":\"forbidden\"}", page.body());
+
+        let bad = Response::error(StatusCode::BAD_REQUEST, "Bad".into());
+        let page = pages.apply(Some(&request), root, &config, bad).await;
+        assert_eq!(StatusCode::IM_A_TEAPOT, page.status());
+        assert_eq!(b"400 at /x", page.body());
+        let bad = Response::error(StatusCode::BAD_REQUEST, "Bad".into());
+        let page = pages.apply(None, root, &config, bad).await;
+        assert_eq!(b"400 at ", page.body());
+
+        // missing file keeps the original, success is never replaced
+        let gone = Response::error(StatusCode::GONE, "Gone".into());
+        let page = pages.apply(Some(&request), root, &config, gone).await;
+        assert_eq!(b"Gone", page.body());
+        let ok = pages
+            .apply(Some(&request), root, &config, Response::from("ok"))
+            .await;
+        assert_eq!(b"ok", ok.body());
+    }
+}
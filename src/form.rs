@@ -0,0 +1,313 @@
+//! Typed forms, urlencoded and multipart/form-data bodies deserialized with serde
+use crate::{
+    http::StatusCode,
+    query::{self, QueryMap},
+    request::{FormTypes, MultiPartFormEntry, Request},
+    response::Response,
+};
+use core::fmt;
+use serde::de::{self, DeserializeOwned};
+use std::ops::Deref;
+
+/// Form body deserialized into T, with the file parts of a multipart form kept aside
+/// fields are filled like [`crate::query::QueryMap::deserialize`] does, so repeated names and
+/// names ending in [] (eg. checkboxes) fill Vec fields. Multipart parts with a file name never
+/// reach T, they're in the [`UploadedFiles`] of this request under their field name
+///
+/// ```ignore
+/// #[derive(Deserialize)]
+/// struct Profile {
+///     name: String,
+///     tags: Vec<String>,
+/// }
+///
+/// async fn profile(_: (), request: Request) -> Result<String, Response> {
+///     let Form(profile, files) = Form::<Profile>::from_request(&request)?;
+///     let avatar = files.get("avatar").and_then(|file| file.file_name());
+///     Ok(format!("Hello {} {avatar:?}", profile.name))
+/// }
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub struct Form<T>(pub T, pub UploadedFiles);
+
+impl<T: DeserializeOwned> Form<T> {
+    pub fn from_request(request: &Request) -> Result<Self, FormError> {
+        match request.form_data() {
+            FormTypes::XUrlEncoded(form) => Ok(Form(form.deserialize()?, UploadedFiles::new())),
+            FormTypes::MultiPart(form) => {
+                let mut fields = QueryMap::new();
+                let mut files = UploadedFiles::new();
+                for entry in form {
+                    if entry.file_name().is_some() {
+                        let file = UploadedFile(entry.clone());
+                        files.files.push((entry.name().to_string(), file));
+                        continue;
+                    }
+                    // only file parts are spooled, text is always in memory
+                    let value = entry.value().unwrap_or_default();
+                    let value = std::str::from_utf8(value).map_err(|_| {
+                        FormError::Invalid(de::Error::custom(format!(
+                            "{} isn't valid UTF-8",
+                            entry.name()
+                        )))
+                    })?;
+                    fields.push(entry.name(), value);
+                }
+                Ok(Form(fields.deserialize()?, files))
+            }
+            FormTypes::None => Err(FormError::UnsupportedMediaType),
+        }
+    }
+}
+
+impl<T> Form<T> {
+    pub fn into_inner(self) -> T {
+        self.0
+    }
+
+    pub fn files(&self) -> &UploadedFiles {
+        &self.1
+    }
+}
+
+impl<T> Deref for Form<T> {
+    type Target = T;
+    fn deref(&self) -> &Self::Target {
+        &self.0
+    }
+}
+
+/// Why a [`Form`] couldn't be built, answered with its [`FormError::status`]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum FormError {
+    /// the body isn't urlencoded or multipart/form-data
+    UnsupportedMediaType,
+    /// fields don't fit the type, eg. a missing field or a number that doesn't parse
+    Invalid(query::Error),
+}
+
+impl FormError {
+    pub fn status(&self) -> StatusCode {
+        match self {
+            FormError::UnsupportedMediaType => StatusCode::UNSUPPORTED_MEDIA_TYPE,
+            FormError::Invalid(_) => StatusCode::UNPROCESSABLE_ENTITY,
+        }
+    }
+}
+
+impl fmt::Display for FormError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            FormError::UnsupportedMediaType => write!(f, "Expected a form body"),
+            FormError::Invalid(error) => write!(f, "Invalid form: {error}"),
+        }
+    }
+}
+
+impl std::error::Error for FormError {}
+
+impl From<query::Error> for FormError {
+    fn from(error: query::Error) -> Self {
+        FormError::Invalid(error)
+    }
+}
+
+impl From<FormError> for Response {
+    fn from(error: FormError) -> Self {
+        Response::error(error.status(), error.to_string().into())
+    }
+}
+
+/// File part of a multipart form, taken from the [`UploadedFiles`] of a [`Form`]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct UploadedFile(MultiPartFormEntry);
+
+impl UploadedFile {
+    pub fn into_entry(self) -> MultiPartFormEntry {
+        self.0
+    }
+}
+
+impl Deref for UploadedFile {
+    type Target = MultiPartFormEntry;
+    fn deref(&self) -> &Self::Target {
+        &self.0
+    }
+}
+
+/// File parts of a multipart form by field name, in the order they were sent
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub struct UploadedFiles {
+    files: Vec<(String, UploadedFile)>,
+}
+
+impl UploadedFiles {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// First file sent as name
+    pub fn get(&self, name: &str) -> Option<&UploadedFile> {
+        self.files.iter().find(|(n, _)| n == name).map(|(_, f)| f)
+    }
+
+    /// Every file sent as name, eg. from an input with multiple set
+    pub fn get_all<'a>(&'a self, name: &'a str) -> impl Iterator<Item = &'a UploadedFile> + 'a {
+        self.files
+            .iter()
+            .filter(move |(n, _)| n == name)
+            .map(|(_, f)| f)
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (&str, &UploadedFile)> {
+        self.files.iter().map(|(n, f)| (n.as_str(), f))
+    }
+
+    pub fn len(&self) -> usize {
+        self.files.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.files.is_empty()
+    }
+}
+
+impl IntoIterator for UploadedFiles {
+    type Item = (String, UploadedFile);
+    type IntoIter = std::vec::IntoIter<(String, UploadedFile)>;
+    fn into_iter(self) -> Self::IntoIter {
+        self.files.into_iter()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use bytes::Bytes;
+    use serde::Deserialize;
+
+    #[derive(Debug, Deserialize)]
+    struct Signup {
+        name: String,
+        age: u8,
+        #[serde(default)]
+        colors: Vec<String>,
+        newsletter: Option<bool>,
+    }
+
+    #[derive(Debug, Deserialize)]
+    struct Upload {
+        title: String,
+    }
+
+    fn urlencoded(body: &str) -> Request {
+        Request::from_string(format!(
+            "POST / HTTP/1.1\r\nHost: test\r\n\
+            Content-Type: application/x-www-form-urlencoded\r\nContent-Length: {}\r\n\r\n{body}",
+            body.len()
+        ))
+        .unwrap()
+    }
+
+    #[test]
+    fn urlencoded_form() {
+        let request = urlencoded("name=Ada+L&age=36&colors=red&colors=blue&newsletter=on");
+        let Form(signup, files) = Form::<Signup>::from_request(&request).unwrap();
+        assert_eq!("Ada L", signup.name);
+        assert_eq!(36, signup.age);
+        assert_eq!(vec!["red", "blue"], signup.colors);
+        assert_eq!(Some(true), signup.newsletter);
+        assert!(files.is_empty());
+
+        let request = urlencoded("name=Ada&age=old");
+        let error = Form::<Signup>::from_request(&request).unwrap_err();
+        assert_eq!(StatusCode::UNPROCESSABLE_ENTITY, error.status());
+        let request = urlencoded("age=1");
+        assert!(Form::<Signup>::from_request(&request).is_err());
+    }
+
+    fn upload() -> Request {
+        Request::from_bytes(Bytes::from_static(
+            b"POST / HTTP/1.1\r\n\
+            Host: test\r\n\
+            Content-Type: multipart/form-data; boundary=b\r\n\
+            \r\n\
+            --b\r\n\
+            Content-Disposition: form-data; name=\"title\"\r\n\
+            \r\n\
+            holiday\r\n\
+            --b\r\n\
+            Content-Disposition: form-data; name=\"files\"; filename=\"a.jpg\"\r\n\
+            \r\n\
+            aaa\r\n\
+            --b\r\n\
+            Content-Disposition: form-data; name=\"files\"; filename=\"b.jpg\"\r\n\
+            \r\n\
+            bbb\r\n\
+            --b--\r\n",
+        ))
+        .unwrap()
+    }
+
+    #[test]
+    fn multipart_form() {
+        let Form(upload, files) = Form::<Upload>::from_request(&upload()).unwrap();
+        assert_eq!("holiday", upload.title);
+        let names: Vec<_> = files
+            .get_all("files")
+            .map(|f| f.file_name().unwrap())
+            .collect();
+        assert_eq!(vec!["a.jpg", "b.jpg"], names);
+        assert_eq!(
+            Some("a.jpg"),
+            files.get("files").unwrap().file_name().map(|n| n.as_str())
+        );
+        assert_eq!(Some(&b"bbb"[..]), files.iter().nth(1).unwrap().1.value());
+        assert!(files.get("cover").is_none());
+
+        // a file can't stand in for a text field, and text isn't a file
+        let request = Request::from_bytes(Bytes::from_static(
+            b"POST / HTTP/1.1\r\n\
+            Host: test\r\n\
+            Content-Type: multipart/form-data; boundary=b\r\n\
+            \r\n\
+            --b\r\n\
+            Content-Disposition: form-data; name=\"title\"; filename=\"t.txt\"\r\n\
+            \r\n\
+            holiday\r\n\
+            --b--\r\n",
+        ))
+        .unwrap();
+        let error = Form::<Upload>::from_request(&request).unwrap_err();
+        assert_eq!(StatusCode::UNPROCESSABLE_ENTITY, error.status());
+        let request = Request::from_bytes(Bytes::from_static(
+            b"POST / HTTP/1.1\r\n\
+            Host: test\r\n\
+            Content-Type: multipart/form-data; boundary=b\r\n\
+            \r\n\
+            --b\r\n\
+            Content-Disposition: form-data; name=\"title\"\r\n\
+            \r\n\
+            holiday\r\n\
+            --b\r\n\
+            Content-Disposition: form-data; name=\"files\"\r\n\
+            \r\n\
+            0\r\n\
+            --b--\r\n",
+        ))
+        .unwrap();
+        let Form(_, files) = Form::<Upload>::from_request(&request).unwrap();
+        assert!(files.is_empty());
+    }
+
+    #[test]
+    fn not_a_form() {
+        let request = Request::from_string("GET / HTTP/1.1\r\nHost: test\r\n\r\n".into()).unwrap();
+        let error = Form::<Signup>::from_request(&request).unwrap_err();
+        assert_eq!(StatusCode::UNSUPPORTED_MEDIA_TYPE, error.status());
+        assert_eq!(
+            StatusCode::UNSUPPORTED_MEDIA_TYPE,
+            Response::from(error).status()
+        );
+    }
+}
@@ -0,0 +1,1387 @@
+//! Typed headers, parse from and serialize to a [`HeaderMap`]
+use crate::{
+    http::{Header, HeaderMap, MimeType},
+    utils,
+};
+use base64::{engine::general_purpose, Engine as _};
+use core::fmt;
+use std::{
+    marker::PhantomData,
+    str::FromStr,
+    time::{Duration, SystemTime},
+};
+
+/// A header with a known name that can be parsed into a struct
+pub trait TypedHeader: Sized {
+    /// Header name, lowercase
+    const NAME: &'static str;
+
+    /// Parse the header value, if the header was sent more than once value has every value joined
+    /// with ", "
+    fn decode(value: &str) -> Result<Self, anyhow::Error>;
+
+    fn encode(&self) -> String;
+
+    fn to_header(&self) -> Header {
+        Header::new(Self::NAME, &self.encode())
+    }
+}
+
+impl HeaderMap {
+    /// Parse header T, missing and malformed headers are both None
+    pub fn typed<T: TypedHeader>(&self) -> Option<T> {
+        let value = self.get_joined(T::NAME)?;
+        match T::decode(&value) {
+            Ok(header) => Some(header),
+            Err(error) => {
+                tracing::debug!("ignoring invalid {} header {value:?}: {error}", T::NAME);
+                None
+            }
+        }
+    }
+
+    /// Set header T replacing any existing values
+    pub fn set_typed<T: TypedHeader>(&mut self, header: T) {
+        self.insert(header.to_header());
+    }
+}
+
+/// Split a list header on sep, ignoring seperators inside quoted strings
+fn split_list(value: &str, sep: char) -> Vec<&str> {
+    let mut items = vec![];
+    let mut quoted = false;
+    let mut escaped = false;
+    let mut start = 0;
+    for (i, c) in value.char_indices() {
+        if escaped {
+            escaped = false;
+            continue;
+        }
+        match c {
+            '\\' if quoted => escaped = true,
+            '"' => quoted = !quoted,
+            c if c == sep && !quoted => {
+                items.push(value[start..i].trim());
+                start = i + 1;
+            }
+            _ => {}
+        }
+    }
+    items.push(value[start..].trim());
+    items.retain(|item| !item.is_empty());
+    items
+}
+
+fn unquote(value: &str) -> String {
+    match value.strip_prefix('"').and_then(|v| v.strip_suffix('"')) {
+        Some(inner) => inner.replace("\\\"", "\"").replace("\\\\", "\\"),
+        None => value.to_string(),
+    }
+}
+
+fn is_token(value: &str) -> bool {
+    !value.is_empty()
+        && value
+            .bytes()
+            .all(|b| b.is_ascii_alphanumeric() || b"!#$%&'*+-.^_`|~".contains(&b))
+}
+
+fn quote_if_needed(value: &str) -> String {
+    if is_token(value) {
+        value.to_string()
+    } else {
+        format!("\"{}\"", value.replace('\\', "\\\\").replace('"', "\\\""))
+    }
+}
+
+/// Weight from a q parameter in thousandths, 1000 is q=1
+fn parse_quality(value: &str) -> Result<u16, anyhow::Error> {
+    let invalid = || anyhow::Error::msg(format!("invalid quality value {value:?}"));
+    let (int, frac) = value.split_once('.').unwrap_or((value, ""));
+    if frac.len() > 3 || !frac.bytes().all(|b| b.is_ascii_digit()) {
+        return Err(invalid());
+    }
+    let frac = format!("{frac:0<3}")
+        .parse::<u16>()
+        .map_err(|_| invalid())?;
+    match int {
+        "0" => Ok(frac),
+        "1" if frac == 0 => Ok(1000),
+        _ => Err(invalid()),
+    }
+}
+
+fn format_quality(quality: u16) -> String {
+    match quality {
+        1000 => "1".to_string(),
+        0 => "0".to_string(),
+        q => format!("0.{q:03}").trim_end_matches('0').to_string(),
+    }
+}
+
+/// Item from a list header with a weight, eg. text/html;q=0.8
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct QualityItem<T> {
+    pub item: T,
+    /// weight in thousandths, q=1 is 1000 and q=0 means not acceptable
+    pub quality: u16,
+}
+
+impl<T> QualityItem<T> {
+    pub fn new(item: T, quality: u16) -> Self {
+        QualityItem {
+            item,
+            quality: quality.min(1000),
+        }
+    }
+}
+
+/// Parse a comma seperated list where each item can have a ;q= weight
+pub fn parse_quality_list<T: FromStr>(value: &str) -> Result<Vec<QualityItem<T>>, anyhow::Error>
+where
+    T::Err: fmt::Display,
+{
+    let mut items = vec![];
+    for entry in split_list(value, ',') {
+        let mut params = split_list(entry, ';');
+        let mut quality = 1000;
+        if let Some(i) = params.iter().position(|p| {
+            p.split_once('=')
+                .map(|(k, _)| k.trim().eq_ignore_ascii_case("q"))
+                .unwrap_or(false)
+        }) {
+            let (_, q) = params[i].split_once('=').unwrap_or_default();
+            quality = parse_quality(q.trim())?;
+            // anything after q is an accept-ext, not a media type param
+            params.truncate(i);
+        }
+        let item = params
+            .join(";")
+            .parse::<T>()
+            .map_err(|e| anyhow::Error::msg(e.to_string()))?;
+        items.push(QualityItem { item, quality });
+    }
+    Ok(items)
+}
+
+fn format_quality_list<T: fmt::Display>(items: &[QualityItem<T>]) -> String {
+    items
+        .iter()
+        .map(|i| match i.quality {
+            1000 => i.item.to_string(),
+            q => format!("{};q={}", i.item, format_quality(q)),
+        })
+        .collect::<Vec<_>>()
+        .join(", ")
+}
+
+/// type/subtype with optional parameters, type, subtype and parameter names are lowercase
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MediaType {
+    essence: String,
+    params: Vec<(String, String)>,
+}
+
+impl MediaType {
+    pub fn new(essence: &str) -> Self {
+        MediaType {
+            essence: essence.to_lowercase(),
+            params: vec![],
+        }
+    }
+
+    pub fn with_param(mut self, name: &str, value: &str) -> Self {
+        let name = name.to_lowercase();
+        self.params.retain(|(k, _)| k != &name);
+        self.params.push((name, value.to_string()));
+        self
+    }
+
+    /// type/subtype without parameters
+    pub fn essence(&self) -> &str {
+        &self.essence
+    }
+
+    pub fn type_(&self) -> &str {
+        self.essence.split('/').next().unwrap_or_default()
+    }
+
+    pub fn subtype(&self) -> &str {
+        self.essence.split('/').nth(1).unwrap_or_default()
+    }
+
+    pub fn param(&self, name: &str) -> Option<&str> {
+        self.params
+            .iter()
+            .find(|(k, _)| k.eq_ignore_ascii_case(name))
+            .map(|(_, v)| v.as_str())
+    }
+
+    pub fn params(&self) -> impl Iterator<Item = (&str, &str)> {
+        self.params.iter().map(|(k, v)| (k.as_str(), v.as_str()))
+    }
+
+    /// How specific a match self (which can have wildcards) is for essence
+    /// None if it doesn't match, 0 for */*, 1 for type/*, 2 for an exact match
+    pub fn matches(&self, essence: &str) -> Option<u8> {
+        let (type_, subtype) = essence.split_once('/').unwrap_or((essence, ""));
+        match (self.type_(), self.subtype()) {
+            ("*", "*") => Some(0),
+            (t, "*") if t.eq_ignore_ascii_case(type_) => Some(1),
+            (t, s) if t.eq_ignore_ascii_case(type_) && s.eq_ignore_ascii_case(subtype) => Some(2),
+            _ => None,
+        }
+    }
+}
+
+impl FromStr for MediaType {
+    type Err = anyhow::Error;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let parts = split_list(s, ';');
+        let essence = parts.first().copied().unwrap_or_default();
+        match essence.split_once('/') {
+            Some((t, s)) if is_token(t) && is_token(s) => {}
+            _ => {
+                return Err(anyhow::Error::msg(format!(
+                    "invalid media type {essence:?}"
+                )))
+            }
+        }
+        let mut media_type = MediaType::new(essence);
+        for param in &parts[1..] {
+            let (name, value) = param
+                .split_once('=')
+                .ok_or_else(|| anyhow::Error::msg(format!("invalid parameter {param:?}")))?;
+            let name = name.trim();
+            if !is_token(name) {
+                return Err(anyhow::Error::msg(format!("invalid parameter {param:?}")));
+            }
+            media_type
+                .params
+                .push((name.to_lowercase(), unquote(value.trim())));
+        }
+        Ok(media_type)
+    }
+}
+
+impl fmt::Display for MediaType {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.essence)?;
+        for (name, value) in &self.params {
+            write!(f, "; {}={}", name, quote_if_needed(value))?;
+        }
+        Ok(())
+    }
+}
+
+/// Accept: text/html, application/json;q=0.9, */*;q=0.1
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Accept(pub Vec<QualityItem<MediaType>>);
+
+impl Accept {
+    /// Weight for a media type using the most specific matching range, 0 if not acceptable
+    pub fn quality(&self, essence: &str) -> u16 {
+        self.0
+            .iter()
+            .filter_map(|i| i.item.matches(essence).map(|s| (s, i.quality)))
+            .max_by_key(|(specificity, _)| *specificity)
+            .map(|(_, quality)| quality)
+            .unwrap_or(0)
+    }
+
+    /// An empty Accept header accepts anything
+    pub fn accepts(&self, essence: &str) -> bool {
+        self.0.is_empty() || self.quality(essence) > 0
+    }
+}
+
+impl TypedHeader for Accept {
+    const NAME: &'static str = "accept";
+    fn decode(value: &str) -> Result<Self, anyhow::Error> {
+        Ok(Accept(parse_quality_list(value)?))
+    }
+
+    fn encode(&self) -> String {
+        format_quality_list(&self.0)
+    }
+}
+
+/// Accept-Encoding: br, gzip;q=0.8, *;q=0
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AcceptEncoding(pub Vec<QualityItem<String>>);
+
+impl AcceptEncoding {
+    /// Weight for a content coding, * covers anything not listed and identity is acceptable
+    /// unless it's excluded (RFC 7231 5.3.4)
+    pub fn quality(&self, coding: &str) -> u16 {
+        let find = |name: &str| {
+            self.0
+                .iter()
+                .find(|i| i.item.eq_ignore_ascii_case(name))
+                .map(|i| i.quality)
+        };
+        find(coding)
+            .or_else(|| find("*"))
+            .unwrap_or(if coding.eq_ignore_ascii_case("identity") {
+                1000
+            } else {
+                0
+            })
+    }
+}
+
+impl TypedHeader for AcceptEncoding {
+    const NAME: &'static str = "accept-encoding";
+    fn decode(value: &str) -> Result<Self, anyhow::Error> {
+        let mut items: Vec<QualityItem<String>> = parse_quality_list(value)?;
+        for item in &mut items {
+            if !is_token(&item.item) {
+                return Err(anyhow::Error::msg(format!(
+                    "invalid coding {:?}",
+                    item.item
+                )));
+            }
+            item.item.make_ascii_lowercase();
+        }
+        Ok(AcceptEncoding(items))
+    }
+
+    fn encode(&self) -> String {
+        format_quality_list(&self.0)
+    }
+}
+
+/// Content-Type: text/html; charset=utf-8
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ContentType(pub MediaType);
+
+impl ContentType {
+    pub fn new(essence: &str) -> Self {
+        ContentType(MediaType::new(essence))
+    }
+
+    pub fn html() -> Self {
+        ContentType(MediaType::new("text/html").with_param("charset", "utf-8"))
+    }
+
+    pub fn text() -> Self {
+        ContentType(MediaType::new("text/plain").with_param("charset", "utf-8"))
+    }
+
+    pub fn json() -> Self {
+        ContentType::new("application/json")
+    }
+
+    pub fn form_url_encoded() -> Self {
+        ContentType::new("application/x-www-form-urlencoded")
+    }
+
+    pub fn octet_stream() -> Self {
+        ContentType::new("application/octet-stream")
+    }
+
+    pub fn media_type(&self) -> &MediaType {
+        &self.0
+    }
+
+    pub fn essence(&self) -> &str {
+        self.0.essence()
+    }
+
+    pub fn charset(&self) -> Option<&str> {
+        self.0.param("charset")
+    }
+
+    pub fn boundary(&self) -> Option<&str> {
+        self.0.param("boundary")
+    }
+}
+
+impl From<&MimeType> for ContentType {
+    fn from(mime: &MimeType) -> Self {
+        if let MimeType::Other(media_type) = mime {
+            return ContentType(media_type.clone());
+        }
+        let mut media_type = MediaType::new(mime.media_type());
+        if let Some(charset) = mime.charset() {
+            media_type = media_type.with_param("charset", charset);
+        }
+        ContentType(media_type)
+    }
+}
+
+impl From<MimeType> for ContentType {
+    fn from(mime: MimeType) -> Self {
+        ContentType::from(&mime)
+    }
+}
+
+impl TypedHeader for ContentType {
+    const NAME: &'static str = "content-type";
+    fn decode(value: &str) -> Result<Self, anyhow::Error> {
+        Ok(ContentType(value.parse()?))
+    }
+
+    fn encode(&self) -> String {
+        self.0.to_string()
+    }
+}
+
+/// Content-Disposition: form-data; name="upload"; filename="a.txt"
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ContentDisposition {
+    disposition: String,
+    params: Vec<(String, String)>,
+}
+
+impl ContentDisposition {
+    pub fn new(disposition: &str) -> Self {
+        ContentDisposition {
+            disposition: disposition.to_lowercase(),
+            params: vec![],
+        }
+    }
+
+    pub fn with_param(mut self, name: &str, value: &str) -> Self {
+        let name = name.to_lowercase();
+        self.params.retain(|(k, _)| k != &name);
+        self.params.push((name, value.to_string()));
+        self
+    }
+
+    /// inline, attachment or form-data, lowercase
+    pub fn disposition(&self) -> &str {
+        &self.disposition
+    }
+
+    pub fn param(&self, name: &str) -> Option<&str> {
+        self.params
+            .iter()
+            .find(|(k, _)| k.eq_ignore_ascii_case(name))
+            .map(|(_, v)| v.as_str())
+    }
+
+    /// Field name of a form-data part
+    pub fn name(&self) -> Option<&str> {
+        self.param("name")
+    }
+
+    /// File name of the part, filename* is preferred since it isn't limited to ASCII
+    pub fn filename(&self) -> Option<String> {
+        self.param("filename*")
+            .and_then(decode_ext_value)
+            .or_else(|| self.param("filename").map(str::to_string))
+    }
+}
+
+/// Extended parameter value, charset'language'percent-encoded (RFC 5987 3.2)
+/// None for charsets other than UTF-8 and ISO-8859-1
+fn decode_ext_value(value: &str) -> Option<String> {
+    let mut parts = value.splitn(3, '\'');
+    let (charset, _language, encoded) = (parts.next()?, parts.next()?, parts.next()?);
+    let decoded = utils::percent_decode(encoded.as_bytes())?;
+    if charset.eq_ignore_ascii_case("utf-8") {
+        String::from_utf8(decoded).ok()
+    } else if charset.eq_ignore_ascii_case("iso-8859-1") {
+        Some(decoded.into_iter().map(char::from).collect())
+    } else {
+        None
+    }
+}
+
+impl TypedHeader for ContentDisposition {
+    const NAME: &'static str = "content-disposition";
+    fn decode(value: &str) -> Result<Self, anyhow::Error> {
+        let parts = split_list(value, ';');
+        let disposition = parts.first().copied().unwrap_or_default();
+        if !is_token(disposition) {
+            return Err(anyhow::Error::msg(format!(
+                "invalid disposition {disposition:?}"
+            )));
+        }
+        let mut content_disposition = ContentDisposition::new(disposition);
+        for param in &parts[1..] {
+            let (name, value) = param
+                .split_once('=')
+                .ok_or_else(|| anyhow::Error::msg(format!("invalid parameter {param:?}")))?;
+            let name = name.trim();
+            if !is_token(name) {
+                return Err(anyhow::Error::msg(format!("invalid parameter {param:?}")));
+            }
+            content_disposition
+                .params
+                .push((name.to_lowercase(), unquote(value.trim())));
+        }
+        Ok(content_disposition)
+    }
+
+    fn encode(&self) -> String {
+        let mut value = self.disposition.clone();
+        for (name, param) in &self.params {
+            value.push_str(&format!("; {}={}", name, quote_if_needed(param)));
+        }
+        value
+    }
+}
+
+/// Credentials for an auth scheme used in [`Authorization`]
+pub trait Credentials: Sized {
+    /// Scheme name, compared case insensitively
+    const SCHEME: &'static str;
+
+    /// Parse everything after the scheme
+    fn decode(value: &str) -> Result<Self, anyhow::Error>;
+
+    fn encode(&self) -> String;
+}
+
+/// Authorization: Bearer abc123
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Authorization<C: Credentials>(pub C);
+
+impl<C: Credentials> Authorization<C> {
+    pub fn credentials(&self) -> &C {
+        &self.0
+    }
+}
+
+impl Authorization<Bearer> {
+    pub fn bearer(token: &str) -> Self {
+        Authorization(Bearer(token.to_string()))
+    }
+}
+
+impl Authorization<Basic> {
+    pub fn basic(username: &str, password: &str) -> Self {
+        Authorization(Basic {
+            username: username.to_string(),
+            password: password.to_string(),
+        })
+    }
+}
+
+impl<C: Credentials> TypedHeader for Authorization<C> {
+    const NAME: &'static str = "authorization";
+    fn decode(value: &str) -> Result<Self, anyhow::Error> {
+        let (scheme, rest) = value.trim().split_once(' ').unwrap_or((value, ""));
+        if !scheme.eq_ignore_ascii_case(C::SCHEME) {
+            return Err(anyhow::Error::msg(format!(
+                "expected {} scheme, found {scheme:?}",
+                C::SCHEME
+            )));
+        }
+        Ok(Authorization(C::decode(rest.trim())?))
+    }
+
+    fn encode(&self) -> String {
+        format!("{} {}", C::SCHEME, self.0.encode())
+    }
+}
+
+/// Bearer token (RFC 6750)
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Bearer(String);
+
+impl Bearer {
+    pub fn token(&self) -> &str {
+        &self.0
+    }
+}
+
+impl Credentials for Bearer {
+    const SCHEME: &'static str = "Bearer";
+    fn decode(value: &str) -> Result<Self, anyhow::Error> {
+        let valid = |b: u8| b.is_ascii_alphanumeric() || b"-._~+/=".contains(&b);
+        if value.is_empty() || !value.bytes().all(valid) {
+            return Err(anyhow::Error::msg("invalid bearer token"));
+        }
+        Ok(Bearer(value.to_string()))
+    }
+
+    fn encode(&self) -> String {
+        self.0.clone()
+    }
+}
+
+/// Basic username and password (RFC 7617)
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Basic {
+    username: String,
+    password: String,
+}
+
+impl Basic {
+    pub fn username(&self) -> &str {
+        &self.username
+    }
+
+    pub fn password(&self) -> &str {
+        &self.password
+    }
+}
+
+impl Credentials for Basic {
+    const SCHEME: &'static str = "Basic";
+    fn decode(value: &str) -> Result<Self, anyhow::Error> {
+        let decoded = String::from_utf8(general_purpose::STANDARD.decode(value)?)?;
+        let (username, password) = decoded
+            .split_once(':')
+            .ok_or_else(|| anyhow::Error::msg("missing ':' in basic credentials"))?;
+        Ok(Basic {
+            username: username.to_string(),
+            password: password.to_string(),
+        })
+    }
+
+    fn encode(&self) -> String {
+        general_purpose::STANDARD.encode(format!("{}:{}", self.username, self.password))
+    }
+}
+
+/// Cache-Control directives, unknown directives are ignored
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub struct CacheControl {
+    pub no_cache: bool,
+    pub no_store: bool,
+    pub no_transform: bool,
+    pub only_if_cached: bool,
+    pub must_revalidate: bool,
+    pub proxy_revalidate: bool,
+    pub public: bool,
+    pub private: bool,
+    pub immutable: bool,
+    pub max_age: Option<Duration>,
+    pub s_maxage: Option<Duration>,
+    pub max_stale: Option<Duration>,
+    pub min_fresh: Option<Duration>,
+}
+
+impl CacheControl {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn max_age(max_age: Duration) -> Self {
+        CacheControl {
+            max_age: Some(max_age),
+            ..Default::default()
+        }
+    }
+
+    pub fn no_cache() -> Self {
+        CacheControl {
+            no_cache: true,
+            ..Default::default()
+        }
+    }
+
+    pub fn no_store() -> Self {
+        CacheControl {
+            no_store: true,
+            ..Default::default()
+        }
+    }
+
+    pub fn with_public(mut self) -> Self {
+        self.public = true;
+        self
+    }
+
+    pub fn with_private(mut self) -> Self {
+        self.private = true;
+        self
+    }
+
+    pub fn with_immutable(mut self) -> Self {
+        self.immutable = true;
+        self
+    }
+
+    pub fn with_must_revalidate(mut self) -> Self {
+        self.must_revalidate = true;
+        self
+    }
+}
+
+impl TypedHeader for CacheControl {
+    const NAME: &'static str = "cache-control";
+    fn decode(value: &str) -> Result<Self, anyhow::Error> {
+        let mut cache_control = CacheControl::default();
+        for directive in split_list(value, ',') {
+            let (name, arg) = match directive.split_once('=') {
+                Some((name, arg)) => (name.trim(), Some(unquote(arg.trim()))),
+                None => (directive, None),
+            };
+            let seconds = || -> Result<Option<Duration>, anyhow::Error> {
+                let arg = arg.as_deref().unwrap_or_default();
+                Ok(Some(Duration::from_secs(arg.parse().map_err(|_| {
+                    anyhow::Error::msg(format!("invalid {name} {arg:?}"))
+                })?)))
+            };
+            match name.to_lowercase().as_str() {
+                "no-cache" => cache_control.no_cache = true,
+                "no-store" => cache_control.no_store = true,
+                "no-transform" => cache_control.no_transform = true,
+                "only-if-cached" => cache_control.only_if_cached = true,
+                "must-revalidate" => cache_control.must_revalidate = true,
+                "proxy-revalidate" => cache_control.proxy_revalidate = true,
+                "public" => cache_control.public = true,
+                "private" => cache_control.private = true,
+                "immutable" => cache_control.immutable = true,
+                "max-age" => cache_control.max_age = seconds()?,
+                "s-maxage" => cache_control.s_maxage = seconds()?,
+                "max-stale" if arg.is_none() => cache_control.max_stale = Some(Duration::MAX),
+                "max-stale" => cache_control.max_stale = seconds()?,
+                "min-fresh" => cache_control.min_fresh = seconds()?,
+                _ => {}
+            }
+        }
+        Ok(cache_control)
+    }
+
+    fn encode(&self) -> String {
+        let flags = [
+            (self.public, "public"),
+            (self.private, "private"),
+            (self.no_cache, "no-cache"),
+            (self.no_store, "no-store"),
+            (self.no_transform, "no-transform"),
+            (self.only_if_cached, "only-if-cached"),
+            (self.must_revalidate, "must-revalidate"),
+            (self.proxy_revalidate, "proxy-revalidate"),
+            (self.immutable, "immutable"),
+        ];
+        let mut directives: Vec<String> = flags
+            .iter()
+            .filter(|(set, _)| *set)
+            .map(|(_, name)| name.to_string())
+            .collect();
+        let durations = [
+            (self.max_age, "max-age"),
+            (self.s_maxage, "s-maxage"),
+            (self.max_stale, "max-stale"),
+            (self.min_fresh, "min-fresh"),
+        ];
+        for (duration, name) in durations {
+            match duration {
+                Some(Duration::MAX) => directives.push(name.to_string()),
+                Some(duration) => directives.push(format!("{name}={}", duration.as_secs())),
+                None => {}
+            }
+        }
+        directives.join(", ")
+    }
+}
+
+/// Entity tag, ETag: "abc" or W/"abc"
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct ETag {
+    tag: String,
+    weak: bool,
+}
+
+impl ETag {
+    /// tag is the opaque value without quotes
+    pub fn strong(tag: &str) -> Self {
+        ETag {
+            tag: tag.to_string(),
+            weak: false,
+        }
+    }
+
+    pub fn weak(tag: &str) -> Self {
+        ETag {
+            tag: tag.to_string(),
+            weak: true,
+        }
+    }
+
+    pub fn tag(&self) -> &str {
+        &self.tag
+    }
+
+    pub fn is_weak(&self) -> bool {
+        self.weak
+    }
+
+    /// Both tags are strong and the same (RFC 7232 2.3.2)
+    pub fn strong_eq(&self, other: &ETag) -> bool {
+        !self.weak && !other.weak && self.tag == other.tag
+    }
+
+    /// Tags are the same ignoring weakness
+    pub fn weak_eq(&self, other: &ETag) -> bool {
+        self.tag == other.tag
+    }
+}
+
+impl FromStr for ETag {
+    type Err = anyhow::Error;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let s = s.trim();
+        let (weak, quoted) = match s.strip_prefix("W/") {
+            Some(rest) => (true, rest),
+            None => (false, s),
+        };
+        let tag = quoted
+            .strip_prefix('"')
+            .and_then(|t| t.strip_suffix('"'))
+            .filter(|t| !t.contains('"'))
+            .ok_or_else(|| anyhow::Error::msg(format!("invalid entity tag {s:?}")))?;
+        Ok(ETag {
+            tag: tag.to_string(),
+            weak,
+        })
+    }
+}
+
+impl fmt::Display for ETag {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.weak {
+            write!(f, "W/\"{}\"", self.tag)
+        } else {
+            write!(f, "\"{}\"", self.tag)
+        }
+    }
+}
+
+impl TypedHeader for ETag {
+    const NAME: &'static str = "etag";
+    fn decode(value: &str) -> Result<Self, anyhow::Error> {
+        value.parse()
+    }
+
+    fn encode(&self) -> String {
+        self.to_string()
+    }
+}
+
+/// If-None-Match: "a", W/"b" or *
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum IfNoneMatch {
+    Any,
+    Tags(Vec<ETag>),
+}
+
+impl IfNoneMatch {
+    /// Uses weak comparison (RFC 7232 3.2)
+    pub fn matches(&self, etag: &ETag) -> bool {
+        match self {
+            IfNoneMatch::Any => true,
+            IfNoneMatch::Tags(tags) => tags.iter().any(|t| t.weak_eq(etag)),
+        }
+    }
+}
+
+impl TypedHeader for IfNoneMatch {
+    const NAME: &'static str = "if-none-match";
+    fn decode(value: &str) -> Result<Self, anyhow::Error> {
+        Ok(match decode_etag_list(value)? {
+            Some(tags) => IfNoneMatch::Tags(tags),
+            None => IfNoneMatch::Any,
+        })
+    }
+
+    fn encode(&self) -> String {
+        match self {
+            IfNoneMatch::Any => "*".to_string(),
+            IfNoneMatch::Tags(tags) => encode_etag_list(tags),
+        }
+    }
+}
+
+/// If-Match: "a", "b" or *
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum IfMatch {
+    Any,
+    Tags(Vec<ETag>),
+}
+
+impl IfMatch {
+    /// Uses strong comparison (RFC 7232 3.1)
+    pub fn matches(&self, etag: &ETag) -> bool {
+        match self {
+            IfMatch::Any => true,
+            IfMatch::Tags(tags) => tags.iter().any(|t| t.strong_eq(etag)),
+        }
+    }
+}
+
+impl TypedHeader for IfMatch {
+    const NAME: &'static str = "if-match";
+    fn decode(value: &str) -> Result<Self, anyhow::Error> {
+        Ok(match decode_etag_list(value)? {
+            Some(tags) => IfMatch::Tags(tags),
+            None => IfMatch::Any,
+        })
+    }
+
+    fn encode(&self) -> String {
+        match self {
+            IfMatch::Any => "*".to_string(),
+            IfMatch::Tags(tags) => encode_etag_list(tags),
+        }
+    }
+}
+
+/// None for *
+fn decode_etag_list(value: &str) -> Result<Option<Vec<ETag>>, anyhow::Error> {
+    if value.trim() == "*" {
+        return Ok(None);
+    }
+    let tags = split_list(value, ',')
+        .into_iter()
+        .map(ETag::from_str)
+        .collect::<Result<Vec<_>, _>>()?;
+    Ok(Some(tags))
+}
+
+fn encode_etag_list(tags: &[ETag]) -> String {
+    tags.iter()
+        .map(ToString::to_string)
+        .collect::<Vec<_>>()
+        .join(", ")
+}
+
+/// One range from a Range header
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ByteRange {
+    /// bytes=start-end, end is inclusive
+    FromTo(u64, u64),
+    /// bytes=start-
+    From(u64),
+    /// bytes=-len, the last len bytes
+    Last(u64),
+}
+
+impl ByteRange {
+    /// First and last (inclusive) byte offsets for a body of len bytes
+    /// None if the range is unsatisfiable
+    pub fn resolve(&self, len: u64) -> Option<(u64, u64)> {
+        match *self {
+            ByteRange::FromTo(start, end) if start < len => Some((start, end.min(len - 1))),
+            ByteRange::From(start) if start < len => Some((start, len - 1)),
+            ByteRange::Last(last) if last > 0 && len > 0 => {
+                Some((len.saturating_sub(last), len - 1))
+            }
+            _ => None,
+        }
+    }
+}
+
+impl fmt::Display for ByteRange {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ByteRange::FromTo(start, end) => write!(f, "{start}-{end}"),
+            ByteRange::From(start) => write!(f, "{start}-"),
+            ByteRange::Last(last) => write!(f, "-{last}"),
+        }
+    }
+}
+
+/// Range: bytes=0-499, -500, only the bytes unit is supported
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Range(pub Vec<ByteRange>);
+
+impl Range {
+    pub fn bytes(ranges: Vec<ByteRange>) -> Self {
+        Range(ranges)
+    }
+
+    pub fn ranges(&self) -> &[ByteRange] {
+        &self.0
+    }
+}
+
+impl TypedHeader for Range {
+    const NAME: &'static str = "range";
+    fn decode(value: &str) -> Result<Self, anyhow::Error> {
+        let invalid = || anyhow::Error::msg(format!("invalid range {value:?}"));
+        let (unit, ranges) = value.split_once('=').ok_or_else(invalid)?;
+        if !unit.trim().eq_ignore_ascii_case("bytes") {
+            return Err(anyhow::Error::msg(format!(
+                "unsupported range unit {unit:?}"
+            )));
+        }
+        let parse = |n: &str| -> Result<u64, anyhow::Error> {
+            if n.is_empty() || !n.bytes().all(|b| b.is_ascii_digit()) {
+                return Err(invalid());
+            }
+            n.parse().map_err(|_| invalid())
+        };
+        let mut byte_ranges = vec![];
+        for range in split_list(ranges, ',') {
+            let (start, end) = range.split_once('-').ok_or_else(invalid)?;
+            let (start, end) = (start.trim(), end.trim());
+            let byte_range = match (start.is_empty(), end.is_empty()) {
+                (true, _) => ByteRange::Last(parse(end)?),
+                (false, true) => ByteRange::From(parse(start)?),
+                (false, false) => {
+                    let (start, end) = (parse(start)?, parse(end)?);
+                    if end < start {
+                        return Err(invalid());
+                    }
+                    ByteRange::FromTo(start, end)
+                }
+            };
+            byte_ranges.push(byte_range);
+        }
+        if byte_ranges.is_empty() {
+            return Err(invalid());
+        }
+        Ok(Range(byte_ranges))
+    }
+
+    fn encode(&self) -> String {
+        let ranges: Vec<String> = self.0.iter().map(ToString::to_string).collect();
+        format!("bytes={}", ranges.join(", "))
+    }
+}
+
+/// If-Range: "etag" or an HTTP date
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum IfRange {
+    ETag(ETag),
+    Date(SystemTime),
+}
+
+impl IfRange {
+    /// Range should only be honoured if the representation is unchanged (RFC 7233 3.2)
+    /// tags use strong comparison and dates have to match Last-Modified exactly
+    pub fn matches(&self, etag: Option<&ETag>, last_modified: Option<SystemTime>) -> bool {
+        match self {
+            IfRange::ETag(tag) => etag.map(|e| tag.strong_eq(e)).unwrap_or(false),
+            IfRange::Date(date) => last_modified
+                .map(|m| LastModified::new(m).time() == *date)
+                .unwrap_or(false),
+        }
+    }
+}
+
+impl TypedHeader for IfRange {
+    const NAME: &'static str = "if-range";
+    fn decode(value: &str) -> Result<Self, anyhow::Error> {
+        let value = value.trim();
+        if value.starts_with('"') || value.starts_with("W/") {
+            Ok(IfRange::ETag(value.parse()?))
+        } else {
+            Ok(IfRange::Date(httpdate::parse_http_date(value)?))
+        }
+    }
+
+    fn encode(&self) -> String {
+        match self {
+            IfRange::ETag(etag) => etag.to_string(),
+            IfRange::Date(date) => httpdate::fmt_http_date(*date),
+        }
+    }
+}
+
+/// Content-Range: bytes 0-499/1234 or bytes */1234 for unsatisfiable ranges
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ContentRange {
+    /// first and last byte, inclusive
+    pub range: Option<(u64, u64)>,
+    pub complete_length: u64,
+}
+
+impl ContentRange {
+    pub fn bytes(first: u64, last: u64, complete_length: u64) -> Self {
+        ContentRange {
+            range: Some((first, last)),
+            complete_length,
+        }
+    }
+
+    pub fn unsatisfied(complete_length: u64) -> Self {
+        ContentRange {
+            range: None,
+            complete_length,
+        }
+    }
+}
+
+impl TypedHeader for ContentRange {
+    const NAME: &'static str = "content-range";
+    fn decode(value: &str) -> Result<Self, anyhow::Error> {
+        let invalid = || anyhow::Error::msg(format!("invalid content range {value:?}"));
+        let rest = value.trim().strip_prefix("bytes ").ok_or_else(invalid)?;
+        let (range, length) = rest.split_once('/').ok_or_else(invalid)?;
+        let complete_length = length.parse().map_err(|_| invalid())?;
+        let range = match range.split_once('-') {
+            _ if range == "*" => None,
+            Some((first, last)) => Some((
+                first.parse().map_err(|_| invalid())?,
+                last.parse().map_err(|_| invalid())?,
+            )),
+            None => return Err(invalid()),
+        };
+        Ok(ContentRange {
+            range,
+            complete_length,
+        })
+    }
+
+    fn encode(&self) -> String {
+        match self.range {
+            Some((first, last)) => format!("bytes {first}-{last}/{}", self.complete_length),
+            None => format!("bytes */{}", self.complete_length),
+        }
+    }
+}
+
+/// HTTP date header, eg. Date: Sun, 06 Nov 1994 08:49:37 GMT
+/// N is only used to pick the header name, see [`Date`] and [`LastModified`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct HttpDate<N> {
+    time: SystemTime,
+    name: PhantomData<N>,
+}
+
+impl<N> HttpDate<N> {
+    /// time is truncated to whole seconds since that is all an HTTP date can hold
+    pub fn new(time: SystemTime) -> Self {
+        let seconds = time
+            .duration_since(SystemTime::UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_secs();
+        HttpDate {
+            time: SystemTime::UNIX_EPOCH + Duration::from_secs(seconds),
+            name: PhantomData,
+        }
+    }
+
+    pub fn now() -> Self {
+        Self::new(SystemTime::now())
+    }
+
+    pub fn time(&self) -> SystemTime {
+        self.time
+    }
+}
+
+/// Header name for an [`HttpDate`]
+pub trait DateHeaderName {
+    const NAME: &'static str;
+}
+
+macro_rules! date_header {
+    ($name:ident, $alias:ident, $header:literal) => {
+        #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+        pub struct $name;
+
+        impl DateHeaderName for $name {
+            const NAME: &'static str = $header;
+        }
+
+        pub type $alias = HttpDate<$name>;
+    };
+}
+
+date_header!(DateName, Date, "date");
+date_header!(LastModifiedName, LastModified, "last-modified");
+date_header!(IfModifiedSinceName, IfModifiedSince, "if-modified-since");
+date_header!(
+    IfUnmodifiedSinceName,
+    IfUnmodifiedSince,
+    "if-unmodified-since"
+);
+
+impl<N: DateHeaderName> TypedHeader for HttpDate<N> {
+    const NAME: &'static str = N::NAME;
+    fn decode(value: &str) -> Result<Self, anyhow::Error> {
+        Ok(HttpDate::new(httpdate::parse_http_date(value.trim())?))
+    }
+
+    fn encode(&self) -> String {
+        httpdate::fmt_http_date(self.time)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn accept() {
+        let accept =
+            Accept::decode("text/html, application/xhtml+xml, application/xml;q=0.9, */*;q=0.1")
+                .unwrap();
+        assert_eq!(4, accept.0.len());
+        assert_eq!(1000, accept.quality("text/html"));
+        assert_eq!(900, accept.quality("application/xml"));
+        assert_eq!(100, accept.quality("image/png"));
+
+        let accept =
+            Accept::decode("text/*;q=0.5, text/plain; charset=utf-8, image/png;q=0").unwrap();
+        assert_eq!(1000, accept.quality("text/plain"));
+        assert_eq!(Some("utf-8"), accept.0[1].item.param("charset"));
+        assert_eq!(500, accept.quality("text/css"));
+        assert!(!accept.accepts("image/png"));
+        assert!(!accept.accepts("application/json"));
+        assert_eq!(
+            "text/*;q=0.5, text/plain; charset=utf-8, image/png;q=0",
+            accept.encode()
+        );
+
+        assert!(Accept::decode("text/html;q=2").is_err());
+        assert!(Accept::decode("text").is_err());
+    }
+
+    #[test]
+    fn accept_encoding() {
+        let accept = AcceptEncoding::decode("GZIP;q=0.5, br, *;q=0.1").unwrap();
+        assert_eq!(500, accept.quality("gzip"));
+        assert_eq!(1000, accept.quality("br"));
+        assert_eq!(100, accept.quality("deflate"));
+
+        let accept = AcceptEncoding::decode("gzip").unwrap();
+        assert_eq!(0, accept.quality("br"));
+        assert_eq!(1000, accept.quality("identity"));
+        let accept = AcceptEncoding::decode("gzip, identity;q=0").unwrap();
+        assert_eq!(0, accept.quality("identity"));
+        assert!(AcceptEncoding::decode("gzip;q=x").is_err());
+    }
+
+    #[test]
+    fn content_disposition() {
+        let disposition =
+            ContentDisposition::decode("form-data; Name=\"upload\"; filename=\"a; b.txt\"")
+                .unwrap();
+        assert_eq!("form-data", disposition.disposition());
+        assert_eq!(Some("upload"), disposition.name());
+        assert_eq!(Some("a; b.txt".to_string()), disposition.filename());
+        assert_eq!(
+            "form-data; name=upload; filename=\"a; b.txt\"",
+            disposition.encode()
+        );
+        assert!(ContentDisposition::decode("; name=x").is_err());
+        assert!(ContentDisposition::decode("form-data; name").is_err());
+
+        let extended = ContentDisposition::decode(
+            "attachment; filename=\"naive.txt\"; filename*=UTF-8''na%C3%AFve%20%E2%82%AC.txt",
+        )
+        .unwrap();
+        assert_eq!(Some("naïve €.txt".to_string()), extended.filename());
+        let latin1 = ContentDisposition::decode("file; filename*=iso-8859-1'en'%E9t%E9").unwrap();
+        assert_eq!(Some("été".to_string()), latin1.filename());
+        // unknown charsets fall back to filename
+        let unknown =
+            ContentDisposition::decode("file; filename*=koi8-r''%C1; filename=a.txt").unwrap();
+        assert_eq!(Some("a.txt".to_string()), unknown.filename());
+    }
+
+    #[test]
+    fn content_type() {
+        let content_type =
+            ContentType::decode("multipart/form-data; boundary=\"a b\"; Charset=UTF-8").unwrap();
+        assert_eq!("multipart/form-data", content_type.essence());
+        assert_eq!(Some("a b"), content_type.boundary());
+        assert_eq!(Some("UTF-8"), content_type.charset());
+        assert_eq!(
+            "multipart/form-data; boundary=\"a b\"; charset=UTF-8",
+            content_type.encode()
+        );
+        assert_eq!(ContentType::html(), ContentType::from(MimeType::HTML));
+        assert_eq!("application/json", ContentType::json().encode());
+    }
+
+    #[test]
+    fn authorization() {
+        let mut headers = HeaderMap::new();
+        headers.append(("Authorization", "bearer abc.def-123"));
+        let auth = headers.typed::<Authorization<Bearer>>().unwrap();
+        assert_eq!("abc.def-123", auth.credentials().token());
+        assert!(headers.typed::<Authorization<Basic>>().is_none());
+
+        headers.set_typed(Authorization::basic("aladdin", "open sesame"));
+        assert_eq!(
+            Some("Basic YWxhZGRpbjpvcGVuIHNlc2FtZQ=="),
+            headers.get("authorization")
+        );
+        let auth = headers.typed::<Authorization<Basic>>().unwrap();
+        assert_eq!("aladdin", auth.credentials().username());
+        assert_eq!("open sesame", auth.credentials().password());
+    }
+
+    #[test]
+    fn cache_control() {
+        let cache_control =
+            CacheControl::decode("public, max-age=\"3600\", must-revalidate, x-ext=1").unwrap();
+        assert!(cache_control.public);
+        assert!(cache_control.must_revalidate);
+        assert_eq!(Some(Duration::from_secs(3600)), cache_control.max_age);
+
+        let cache_control = CacheControl::max_age(Duration::from_secs(60))
+            .with_public()
+            .with_immutable();
+        assert_eq!("public, immutable, max-age=60", cache_control.encode());
+        assert!(CacheControl::decode("max-age=soon").is_err());
+    }
+
+    #[test]
+    fn etags() {
+        let strong = ETag::decode("\"abc\"").unwrap();
+        let weak = ETag::decode("W/\"abc\"").unwrap();
+        assert!(weak.is_weak());
+        assert!(strong.weak_eq(&weak));
+        assert!(!strong.strong_eq(&weak));
+        assert_eq!("W/\"abc\"", weak.encode());
+        assert!(ETag::decode("abc").is_err());
+
+        let if_none_match = IfNoneMatch::decode("\"x\", W/\"abc\"").unwrap();
+        assert!(if_none_match.matches(&strong));
+        assert!(!if_none_match.matches(&ETag::strong("y")));
+        assert!(IfNoneMatch::decode("*").unwrap().matches(&strong));
+
+        let if_match = IfMatch::decode("\"abc\", \"x\"").unwrap();
+        assert!(if_match.matches(&strong));
+        assert!(!if_match.matches(&weak));
+        assert_eq!("\"abc\", \"x\"", if_match.encode());
+    }
+
+    #[test]
+    fn range() {
+        let range = Range::decode("bytes=0-499, 500-, -200").unwrap();
+        assert_eq!(
+            vec![
+                ByteRange::FromTo(0, 499),
+                ByteRange::From(500),
+                ByteRange::Last(200)
+            ],
+            range.0
+        );
+        assert_eq!(Some((0, 99)), range.0[0].resolve(100));
+        assert_eq!(None, range.0[1].resolve(100));
+        assert_eq!(Some((0, 99)), range.0[2].resolve(100));
+        assert_eq!("bytes=0-499, 500-, -200", range.encode());
+
+        assert!(Range::decode("bytes=5-1").is_err());
+        assert!(Range::decode("items=0-1").is_err());
+        assert!(Range::decode("bytes=a-b").is_err());
+
+        let content_range = ContentRange::decode("bytes 0-99/1000").unwrap();
+        assert_eq!(ContentRange::bytes(0, 99, 1000), content_range);
+        assert_eq!("bytes */1000", ContentRange::unsatisfied(1000).encode());
+
+        let etag = ETag::strong("abc");
+        let date = SystemTime::UNIX_EPOCH + Duration::from_secs(784111777);
+        let if_range = IfRange::decode("\"abc\"").unwrap();
+        assert!(if_range.matches(Some(&etag), None));
+        assert!(!IfRange::decode("W/\"abc\"")
+            .unwrap()
+            .matches(Some(&etag), None));
+        let if_range = IfRange::decode("Sun, 06 Nov 1994 08:49:37 GMT").unwrap();
+        assert!(if_range.matches(None, Some(date + Duration::from_millis(10))));
+        assert!(!if_range.matches(None, Some(date + Duration::from_secs(1))));
+    }
+
+    #[test]
+    fn date() {
+        let date = Date::decode("Sun, 06 Nov 1994 08:49:37 GMT").unwrap();
+        let expected = SystemTime::UNIX_EPOCH + Duration::from_secs(784111777);
+        assert_eq!(expected, date.time());
+        assert_eq!("Sun, 06 Nov 1994 08:49:37 GMT", date.encode());
+        assert!(Date::decode("yesterday").is_err());
+    }
+}
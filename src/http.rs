@@ -1,7 +1,8 @@
+use crate::{headers::MediaType, mime};
 use core::fmt;
 use enum_map::Enum;
-use memchr::memmem;
-use std::path::PathBuf;
+use memchr::memchr;
+use std::{path::PathBuf, str::FromStr};
 
 #[derive(PartialEq, Debug, Clone, Copy, Enum)]
 pub enum Method {
@@ -9,7 +10,8 @@ pub enum Method {
     POST,
 }
 
-#[derive(PartialEq, Debug, Clone, Copy)]
+/// Ordered oldest to newest
+#[derive(PartialEq, Eq, PartialOrd, Ord, Debug, Clone, Copy)]
 pub enum Version {
     V0_9,
     V1_0,
@@ -19,7 +21,7 @@ pub enum Version {
 
 pub type StatusCode = http::StatusCode;
 
-#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+#[derive(Debug, PartialEq, Eq, Clone)]
 pub enum MimeType {
     HTML,
     PlainText,
@@ -31,7 +33,10 @@ pub enum MimeType {
     Binary,
     JPEG,
     PNG,
+    /// media type without parameters, eg. from the extension table
     Custom(&'static str),
+    /// anything parsed from a string, keeps its parameters
+    Other(MediaType),
 }
 
 impl TryFrom<&[u8]> for Method {
@@ -51,14 +56,14 @@ impl TryFrom<&[u8]> for Version {
         match value {
             b"HTTP/1.0" => Ok(Version::V1_0),
             b"HTTP/1.1" => Ok(Version::V1_1),
-            b"HTTP/2.2" => Ok(Version::V2_0),
+            b"HTTP/2" | b"HTTP/2.0" => Ok(Version::V2_0),
             _ => Err("invalid version".to_owned()),
         }
     }
 }
 
 /// HTTP headers are simple key value pairs both strings
-#[derive(Debug, PartialEq, Clone)]
+#[derive(Debug, PartialEq, Eq, Clone)]
 pub struct Header {
     pub key: String,
     pub value: String,
@@ -104,15 +109,12 @@ impl fmt::Display for Method {
 }
 impl From<&MimeType> for String {
     fn from(mime: &MimeType) -> String {
-        let media_type = mime.media_type();
-        let charset = mime.charset();
-        let boundary = mime.boundary();
-        if let (Some(boundary), Some(charset)) = (boundary, charset) {
-            format!("{}; charset={}; boundary={}", media_type, charset, boundary)
-        } else if let (None, Some(charset)) = (boundary, charset) {
-            format!("{}; charset={}", media_type, charset)
-        } else {
-            format!("{};", media_type)
+        match mime {
+            MimeType::Other(media_type) => media_type.to_string(),
+            mime => match mime.charset() {
+                Some(charset) => format!("{}; charset={}", mime.media_type(), charset),
+                None => mime.media_type().to_string(),
+            },
         }
     }
 }
@@ -133,6 +135,27 @@ impl From<PathBuf> for MimeType {
     }
 }
 
+/// Parse `type/subtype; params`, known types without parameters become their variant
+impl FromStr for MimeType {
+    type Err = anyhow::Error;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let media_type: MediaType = s.parse()?;
+        Ok(MimeType::from(media_type))
+    }
+}
+
+impl From<MediaType> for MimeType {
+    fn from(media_type: MediaType) -> Self {
+        if media_type.params().next().is_some() {
+            return MimeType::Other(media_type);
+        }
+        match MimeType::from_essence(media_type.essence()) {
+            Some(mime) => mime,
+            None => MimeType::Other(media_type),
+        }
+    }
+}
+
 impl MimeType {
     pub fn media_type(&self) -> &str {
         match self {
@@ -147,99 +170,115 @@ impl MimeType {
             Self::JPEG => "image/jpeg",
             Self::PNG => "image/png",
             Self::Custom(str) => str,
+            Self::Other(media_type) => media_type.essence(),
         }
     }
 
+    /// utf-8 for text types, parsed types only have the charset they were given
     pub fn charset(&self) -> Option<&str> {
         match self {
-            Self::SVG | Self::Icon | Self::Binary | Self::JPEG => None,
-            _ => Some("utf-8"),
+            Self::Other(media_type) => media_type.param("charset"),
+            mime if mime::is_text(mime.media_type()) => Some("utf-8"),
+            _ => None,
         }
     }
 
     pub fn boundary(&self) -> Option<&str> {
-        None
+        match self {
+            Self::Other(media_type) => media_type.param("boundary"),
+            _ => None,
+        }
     }
 
+    /// Type for a file extension from [`crate::mime`]'s table, plain text if it isn't known
     pub fn from_extension(extension: &str) -> Self {
-        match extension {
-            "json" => Self::Json,
-            "js" => Self::JavaScript,
-            "css" => Self::CSS,
-            "svg" => Self::SVG,
-            "ico" => Self::Icon,
-            "bin" => Self::Binary,
-            "html" => Self::HTML,
-            "jpeg" | "jpg" => Self::JPEG,
-            "png" => Self::PNG,
-            _ => Self::PlainText,
-        }
+        mime::from_extension(extension).unwrap_or(Self::PlainText)
+    }
+
+    /// Variant for a static media type, Custom if there isn't one
+    pub fn from_static(media_type: &'static str) -> Self {
+        Self::from_essence(media_type).unwrap_or(Self::Custom(media_type))
+    }
+
+    fn from_essence(essence: &str) -> Option<Self> {
+        let known = [
+            Self::HTML,
+            Self::PlainText,
+            Self::JavaScript,
+            Self::Json,
+            Self::CSS,
+            Self::SVG,
+            Self::Icon,
+            Self::Binary,
+            Self::JPEG,
+            Self::PNG,
+        ];
+        known
+            .into_iter()
+            .find(|mime| mime.media_type().eq_ignore_ascii_case(essence))
     }
 }
 
 impl TryFrom<String> for Header {
     type Error = &'static str;
     fn try_from(string: String) -> Result<Self, Self::Error> {
-        let split: Vec<&str> = string.split(": ").collect();
-        match split.len().cmp(&2) {
-            std::cmp::Ordering::Equal => {
-                let key = split[0].to_lowercase();
-                let value = split[1].to_string();
-                Ok(Self { key, value })
-            }
-            std::cmp::Ordering::Greater => Err("Too many ': '"),
-            std::cmp::Ordering::Less => Err("Invalid Key Value Pair"),
-        }
+        Header::try_from(string.as_bytes())
     }
 }
 
 impl TryFrom<&String> for Header {
     type Error = &'static str;
     fn try_from(string: &String) -> Result<Self, Self::Error> {
-        let split: Vec<&str> = string.split(": ").collect();
-        match split.len().cmp(&2) {
-            std::cmp::Ordering::Equal => {
-                let key = split[0].to_lowercase();
-                let value = split[1].to_string();
-                Ok(Self { key, value })
-            }
-            std::cmp::Ordering::Greater => Err("Too many ': '"),
-            std::cmp::Ordering::Less => Err("Invalid Key Value Pair"),
-        }
+        Header::try_from(string.as_bytes())
     }
 }
 
 impl TryFrom<&str> for Header {
     type Error = &'static str;
     fn try_from(string: &str) -> Result<Self, Self::Error> {
-        let split: Vec<&str> = string.split(": ").collect();
-        match split.len().cmp(&2) {
-            std::cmp::Ordering::Equal => {
-                let key = split[0].to_lowercase();
-                let value = split[1].to_string();
-                Ok(Self { key, value })
-            }
-            std::cmp::Ordering::Greater => Err("Too many ': '"),
-            std::cmp::Ordering::Less => Err("Invalid Key Value Pair"),
-        }
+        Header::try_from(string.as_bytes())
     }
 }
 
+/// Parse a single header line without the CRLF (RFC 7230 3.2)
+/// field-name ":" OWS field-value OWS, obsolete line folding is rejected
 impl TryFrom<&[u8]> for Header {
     type Error = &'static str;
     fn try_from(h_str: &[u8]) -> Result<Self, Self::Error> {
-        let sep = b": ";
-        let key_end = memmem::find(h_str, sep).ok_or("missing ': '")?;
-        let value_start = key_end + sep.len();
+        if matches!(h_str.first(), Some(b' ' | b'\t')) {
+            return Err("obsolete line folding");
+        }
+        let key_end = memchr(b':', h_str).ok_or("missing ':'")?;
         let key = &h_str[0..key_end];
-        let value = &h_str[value_start..h_str.len()];
+        if key.is_empty() || !key.iter().all(|b| is_token(*b)) {
+            return Err("invalid header name");
+        }
+        let value = trim_ows(&h_str[key_end + 1..]);
+        if value.iter().any(|b| matches!(b, b'\r' | b'\n' | b'\0')) {
+            return Err("invalid header value");
+        }
         Ok(Self {
-            key: String::from_utf8_lossy(key).to_string().to_lowercase(),
+            key: String::from_utf8_lossy(key).to_lowercase(),
             value: String::from_utf8_lossy(value).to_string(),
         })
     }
 }
 
+/// tchar from RFC 7230 3.2.6
+fn is_token(b: u8) -> bool {
+    b.is_ascii_alphanumeric() || b"!#$%&'*+-.^_`|~".contains(&b)
+}
+
+fn trim_ows(mut value: &[u8]) -> &[u8] {
+    while let [b' ' | b'\t', rest @ ..] = value {
+        value = rest;
+    }
+    while let [rest @ .., b' ' | b'\t'] = value {
+        value = rest;
+    }
+    value
+}
+
 impl From<&Header> for String {
     fn from(header: &Header) -> String {
         format!("{}: {}", header.key, header.value)
@@ -270,6 +309,122 @@ impl Header {
     }
 }
 
+/// Headers with case-insensitive names, a name can have multiple values
+/// keeps the order headers were added in
+#[derive(Debug, PartialEq, Eq, Clone, Default)]
+pub struct HeaderMap {
+    entries: Vec<Header>,
+}
+
+impl HeaderMap {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// First value for name
+    pub fn get(&self, name: &str) -> Option<&str> {
+        self.entries
+            .iter()
+            .find(|h| h.key.eq_ignore_ascii_case(name))
+            .map(|h| h.value.as_str())
+    }
+
+    /// Every value for name in order
+    pub fn get_all<'a>(&'a self, name: &'a str) -> impl Iterator<Item = &'a str> + 'a {
+        self.entries
+            .iter()
+            .filter(move |h| h.key.eq_ignore_ascii_case(name))
+            .map(|h| h.value.as_str())
+    }
+
+    /// All values for name combined into one, Cookie values are joined with "; " everything else
+    /// with ", " (RFC 7230 3.2.2)
+    pub fn get_joined(&self, name: &str) -> Option<String> {
+        let seperator = if name.eq_ignore_ascii_case("cookie") {
+            "; "
+        } else {
+            ", "
+        };
+        let values: Vec<&str> = self.get_all(name).collect();
+        if values.is_empty() {
+            None
+        } else {
+            Some(values.join(seperator))
+        }
+    }
+
+    pub fn contains(&self, name: &str) -> bool {
+        self.entries
+            .iter()
+            .any(|h| h.key.eq_ignore_ascii_case(name))
+    }
+
+    /// Add header keeping any existing values for the same name
+    pub fn append(&mut self, header: impl IntoHeader) {
+        self.entries.push(header.into_header());
+    }
+
+    /// Add header replacing any existing values for the same name
+    pub fn insert(&mut self, header: impl IntoHeader) {
+        let header = header.into_header();
+        self.remove(&header.key);
+        self.entries.push(header);
+    }
+
+    /// Remove all values for name, returns true if anything was removed
+    pub fn remove(&mut self, name: &str) -> bool {
+        let len = self.entries.len();
+        self.entries.retain(|h| !h.key.eq_ignore_ascii_case(name));
+        len != self.entries.len()
+    }
+
+    /// Append headers from other whose names aren't already set
+    pub fn extend_missing(&mut self, other: HeaderMap) {
+        let missing: Vec<Header> = other
+            .entries
+            .into_iter()
+            .filter(|h| !self.contains(&h.key))
+            .collect();
+        self.entries.extend(missing);
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &Header> {
+        self.entries.iter()
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+}
+
+impl<H: IntoHeader> FromIterator<H> for HeaderMap {
+    fn from_iter<I: IntoIterator<Item = H>>(iter: I) -> Self {
+        HeaderMap {
+            entries: iter.into_iter().map(IntoHeader::into_header).collect(),
+        }
+    }
+}
+
+impl IntoIterator for HeaderMap {
+    type Item = Header;
+    type IntoIter = std::vec::IntoIter<Header>;
+    fn into_iter(self) -> Self::IntoIter {
+        self.entries.into_iter()
+    }
+}
+
+impl<'a> IntoIterator for &'a HeaderMap {
+    type Item = &'a Header;
+    type IntoIter = std::slice::Iter<'a, Header>;
+    fn into_iter(self) -> Self::IntoIter {
+        self.entries.iter()
+    }
+}
+
 impl fmt::Display for Version {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         let s = match self {
@@ -298,3 +453,80 @@ impl From<Version> for String {
         version.to_string()
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_header() {
+        let header = Header::try_from("Content-Type:text/html").unwrap();
+        assert_eq!(Header::new("content-type", "text/html"), header);
+        let header = Header::try_from("X-Time: \t 12:30: now \t").unwrap();
+        assert_eq!("12:30: now", header.value);
+        let header = Header::try_from("X-Empty:").unwrap();
+        assert_eq!("", header.value);
+
+        assert!(Header::try_from(" folded: value").is_err());
+        assert!(Header::try_from("\tfolded").is_err());
+        assert!(Header::try_from("Bad Name: value").is_err());
+        assert!(Header::try_from("Name : value").is_err());
+        assert!(Header::try_from(": value").is_err());
+        assert!(Header::try_from("no colon").is_err());
+    }
+
+    #[test]
+    fn mime_types() {
+        assert_eq!("text/html; charset=utf-8", String::from(MimeType::HTML));
+        assert_eq!("image/png", String::from(MimeType::PNG));
+        assert_eq!(
+            "application/wasm",
+            String::from(MimeType::from_extension("wasm"))
+        );
+        assert_eq!(MimeType::PlainText, MimeType::from_extension("unknown"));
+        assert_eq!(
+            MimeType::JavaScript,
+            MimeType::from(PathBuf::from("app.mjs"))
+        );
+
+        assert_eq!(
+            Ok(MimeType::Json),
+            "Application/JSON".parse().map_err(|_| ())
+        );
+        let parsed: MimeType = "text/html; charset=ISO-8859-1".parse().unwrap();
+        assert_eq!("text/html", parsed.media_type());
+        assert_eq!(Some("ISO-8859-1"), parsed.charset());
+        assert_eq!("text/html; charset=ISO-8859-1", String::from(&parsed));
+        let parsed: MimeType = "multipart/form-data; boundary=abc".parse().unwrap();
+        assert_eq!(Some("abc"), parsed.boundary());
+        assert_eq!(None, parsed.charset());
+        let parsed: MimeType = "application/vnd.api+json".parse().unwrap();
+        assert_eq!(
+            Some("utf-8"),
+            MimeType::from_static("application/vnd.api+json").charset()
+        );
+        assert_eq!("application/vnd.api+json", String::from(parsed));
+        assert!("not a type".parse::<MimeType>().is_err());
+    }
+
+    #[test]
+    fn header_map() {
+        let mut headers = HeaderMap::new();
+        headers.append(("Accept", "text/html"));
+        headers.append(("accept", "application/json"));
+        headers.append(("Cookie", "a=1"));
+        headers.append(("COOKIE", "b=2"));
+        assert_eq!(Some("text/html"), headers.get("ACCEPT"));
+        assert_eq!(
+            Some("text/html, application/json".to_string()),
+            headers.get_joined("Accept")
+        );
+        assert_eq!(Some("a=1; b=2".to_string()), headers.get_joined("cookie"));
+
+        headers.insert(("Accept", "*/*"));
+        assert_eq!(vec!["*/*"], headers.get_all("accept").collect::<Vec<_>>());
+        assert!(headers.remove("cookie"));
+        assert!(!headers.contains("Cookie"));
+        assert_eq!(1, headers.len());
+    }
+}
@@ -0,0 +1,527 @@
+//! HTTP/2, over TLS when the client picks h2 with ALPN or cleartext with prior knowledge (h2c)
+//! streams are multiplexed on one connection and each one is routed like an HTTP/1 request,
+//! framing, HPACK and flow control are handled by the h2 crate
+use crate::{
+    check_head,
+    http::StatusCode,
+    multipart::MultipartConfig,
+    parse_spooled,
+    request::{self, Request},
+    response::Response,
+    route_request,
+    routes::Router,
+    Upload, VirtualHosts,
+};
+use bytes::{Bytes, BytesMut};
+use futures::StreamExt;
+use h2::{server::SendResponse, Reason, RecvStream, SendStream};
+use std::{
+    net::SocketAddr,
+    path::PathBuf,
+    pin::Pin,
+    sync::Arc,
+    task::{Context, Poll},
+    time::Duration,
+};
+use tokio::{
+    io::{AsyncRead, AsyncWrite, ReadBuf},
+    select,
+    sync::RwLock,
+    task::JoinSet,
+    time::{sleep, timeout},
+};
+use tokio_util::sync::CancellationToken;
+
+/// ALPN protocol id for HTTP/2 over TLS
+pub const ALPN_H2: &[u8] = b"h2";
+
+/// First bytes a client sends on an HTTP/2 connection
+pub const PREFACE: &[u8] = b"PRI * HTTP/2.0\r\n\r\nSM\r\n\r\n";
+
+/// Streams a client may have open at once
+const MAX_CONCURRENT_STREAMS: u32 = 128;
+
+/// Largest body read into memory for a route without a body size limit, multipart bodies are
+/// limited by their [`MultipartConfig`] instead
+const MAX_BUFFERED_BODY: usize = 16 * 1024 * 1024;
+
+/// Headers that only mean something to a single HTTP/1 connection, HTTP/2 doesn't allow them
+const CONNECTION_HEADERS: &[&str] = &[
+    "connection",
+    "keep-alive",
+    "proxy-connection",
+    "transfer-encoding",
+    "upgrade",
+];
+
+/// What an HTTP/2 connection needs from the server to route its streams
+pub(crate) struct Http2<S> {
+    pub router: Arc<RwLock<Router<S>>>,
+    pub vhosts: Arc<RwLock<VirtualHosts<S>>>,
+    pub doc_root: PathBuf,
+    pub client_ip: SocketAddr,
+    pub cancel: CancellationToken,
+    /// how long the connection may sit without open streams and a body may stall
+    pub timeout: Duration,
+}
+
+impl<S> Http2<S>
+where
+    S: Clone + Send + Sync + 'static,
+{
+    /// Serve streams until the client goes away or the connection has no open streams for the
+    /// timeout, on shutdown a GOAWAY is sent and open streams are finished
+    pub async fn serve<T>(self, io: T)
+    where
+        T: AsyncRead + AsyncWrite + Unpin + Send + 'static,
+    {
+        let ip = self.client_ip;
+        let handshake = h2::server::Builder::new()
+            .max_concurrent_streams(MAX_CONCURRENT_STREAMS)
+            .handshake::<_, Bytes>(io);
+        let mut connection = match timeout(self.timeout, handshake).await {
+            Ok(Ok(connection)) => connection,
+            Ok(Err(error)) => {
+                tracing::debug!("{ip}: HTTP/2 handshake failed: {error}");
+                return;
+            }
+            Err(_) => {
+                tracing::debug!("{ip}: HTTP/2 handshake timed out");
+                return;
+            }
+        };
+        let server = Arc::new(self);
+        let mut shutting_down = false;
+        // streams being handled, the connection is idle without any
+        let mut streams = JoinSet::new();
+        loop {
+            let idle = streams.is_empty();
+            // accept also drives the connection, it has to be polled until it's done
+            let accepted = select! {
+                _ = server.cancel.cancelled(), if !shutting_down => {
+                    tracing::debug!("{ip}: HTTP/2 connection shutting down");
+                    connection.graceful_shutdown();
+                    shutting_down = true;
+                    continue;
+                }
+                _ = sleep(server.timeout), if idle => {
+                    tracing::debug!("{ip}: HTTP/2 connection timed out");
+                    break;
+                }
+                _ = streams.join_next(), if !idle => continue,
+                accepted = connection.accept() => accepted,
+            };
+            match accepted {
+                Some(Ok((request, respond))) => {
+                    let server = server.clone();
+                    streams.spawn(async move { server.handle(request, respond).await });
+                }
+                Some(Err(error)) => {
+                    tracing::debug!("{ip}: HTTP/2 connection error: {error}");
+                    break;
+                }
+                None => {
+                    tracing::debug!("{ip}: HTTP/2 connection closed");
+                    break;
+                }
+            }
+        }
+        // streams still writing finish on their own
+        streams.detach_all();
+    }
+
+    /// Check the head, read the whole request body, route it and send the response on the same
+    /// stream, a head the router rejects is answered without reading the body. Multipart bodies
+    /// are parsed while they're read, over their limits they're refused as soon as that's known
+    async fn handle(&self, request: ::http::Request<RecvStream>, mut respond: SendResponse<Bytes>) {
+        let ip = self.client_ip;
+        let (parts, mut body) = request.into_parts();
+        let head = match to_head(&parts) {
+            Ok(head) => head,
+            Err(error) => return self.reject(&parts, error, &mut respond).await,
+        };
+        if let Some(response) = check_head(&self.router, &self.vhosts, &self.doc_root, &head).await
+        {
+            tracing::info!(
+                "{ip}: {} {} answered before the body: {}",
+                head.method(),
+                head.path(),
+                response.status()
+            );
+            if let Err(error) = self.send(response, &mut respond).await {
+                tracing::debug!("{ip}: HTTP/2 error writing response: {error}");
+            }
+            return;
+        }
+        // Content-Length is optional in HTTP/2 so the limit is enforced while reading too
+        let limit = self.router.read().await.body_limit(&head).await;
+        let config = self.router.read().await.multipart_config_for(&head).await;
+        // a multipart body is parsed as it arrives so file parts are spooled instead of kept
+        let mut upload = Upload::new(head.clone(), &config);
+        let limit = limit.or_else(|| upload.is_none().then_some(MAX_BUFFERED_BODY));
+        let mut data = vec![];
+        let mut received = 0;
+        loop {
+            let chunk = match timeout(self.timeout, body.data()).await {
+                Ok(Some(Ok(chunk))) => chunk,
+                Ok(Some(Err(error))) => {
+                    tracing::debug!("{ip}: HTTP/2 error reading body: {error}");
+                    return;
+                }
+                Ok(None) => break,
+                Err(_) => {
+                    let response =
+                        Response::error(StatusCode::REQUEST_TIMEOUT, "Request Timeout".into());
+                    return self.refuse(&head, response, &mut respond).await;
+                }
+            };
+            // let the client send more right away
+            let _ = body.flow_control().release_capacity(chunk.len());
+            received += chunk.len();
+            if limit.is_some_and(|limit| received > limit) {
+                let response =
+                    Response::error(StatusCode::PAYLOAD_TOO_LARGE, "Payload Too Large".into());
+                return self.refuse(&head, response, &mut respond).await;
+            }
+            let parsed = match upload.as_mut() {
+                Some(upload) => upload.parse(chunk, false).await,
+                None => {
+                    data.extend_from_slice(&chunk);
+                    Ok(None)
+                }
+            };
+            if let Err(error) = parsed {
+                let status = error.status();
+                let response = Response::error(status, format!("{status}: {error}").into());
+                return self.refuse(&head, response, &mut respond).await;
+            }
+        }
+        let request = match upload.as_mut() {
+            Some(upload) => match upload.parse(Bytes::new(), true).await {
+                Ok(request) => request.ok_or(request::Error::UploadFailed),
+                Err(error) => Err(error),
+            },
+            None => to_request(&parts, data, &config).await,
+        };
+        let request = match request {
+            Ok(request) => request,
+            Err(error) => return self.reject(&parts, error, &mut respond).await,
+        };
+        tracing::info!(
+            "{ip}: {} {} Request for: {}",
+            request.method(),
+            request.version(),
+            request.path()
+        );
+        let response = route_request(&self.router, &self.vhosts, &self.doc_root, &request).await;
+        if let Err(error) = self.send(response, &mut respond).await {
+            tracing::debug!("{ip}: HTTP/2 error writing response: {error}");
+        }
+    }
+
+    /// Answer a stream with response while its body is being read, the rest isn't read
+    async fn refuse(&self, head: &Request, response: Response, respond: &mut SendResponse<Bytes>) {
+        let ip = self.client_ip;
+        tracing::info!(
+            "{ip}: {} {} refused while reading the body: {}",
+            head.method(),
+            head.path(),
+            response.status()
+        );
+        let response = self
+            .router
+            .read()
+            .await
+            .handle_error(Some(head), &self.doc_root, response)
+            .await;
+        if let Err(error) = self.send(response, respond).await {
+            tracing::debug!("{ip}: HTTP/2 error writing response: {error}");
+        }
+    }
+
+    /// Answer a stream whose request couldn't be parsed
+    async fn reject(
+        &self,
+        parts: &::http::request::Parts,
+        error: request::Error,
+        respond: &mut SendResponse<Bytes>,
+    ) {
+        let ip = self.client_ip;
+        let status = error.status();
+        let message = format!("{status}: {error}");
+        tracing::warn!("{ip}: {message} HTTP/2 {} {}", parts.method, parts.uri);
+        let response = Response::error(status, message.into());
+        let response = self
+            .router
+            .read()
+            .await
+            .handle_error(None, &self.doc_root, response)
+            .await;
+        if let Err(error) = self.send(response, respond).await {
+            tracing::debug!("{ip}: HTTP/2 error writing response: {error}");
+        }
+    }
+
+    async fn send(
+        &self,
+        mut response: Response,
+        respond: &mut SendResponse<Bytes>,
+    ) -> Result<(), h2::Error> {
+        let stream = response.take_stream().filter(|_| response.has_body());
+        let head = match response_head(&response) {
+            Ok(head) => head,
+            Err(error) => {
+                tracing::error!("response can't be sent over HTTP/2: {error}");
+                respond.send_reset(Reason::INTERNAL_ERROR);
+                return Ok(());
+            }
+        };
+        let body = if response.has_body() {
+            Bytes::copy_from_slice(response.body())
+        } else {
+            Bytes::new()
+        };
+        let end_of_stream = body.is_empty() && stream.is_none();
+        let mut send = respond.send_response(head, end_of_stream)?;
+        if !body.is_empty() {
+            send_data(&mut send, body, stream.is_none()).await?;
+        }
+        if let Some(mut stream) = stream {
+            loop {
+                let chunk = select! {
+                    _ = self.cancel.cancelled() => None,
+                    chunk = stream.next() => chunk,
+                };
+                match chunk {
+                    Some(chunk) if chunk.is_empty() => continue,
+                    Some(chunk) => send_data(&mut send, chunk.into(), false).await?,
+                    None => break,
+                }
+            }
+            send.send_data(Bytes::new(), true)?;
+        }
+        Ok(())
+    }
+}
+
+/// Send data once the client's flow control window has room for it
+async fn send_data(
+    send: &mut SendStream<Bytes>,
+    mut data: Bytes,
+    end_of_stream: bool,
+) -> Result<(), h2::Error> {
+    while !data.is_empty() {
+        send.reserve_capacity(data.len());
+        let capacity = match futures::future::poll_fn(|cx| send.poll_capacity(cx)).await {
+            Some(capacity) => capacity?,
+            // the stream was reset
+            None => return Err(Reason::CANCEL.into()),
+        };
+        if capacity == 0 {
+            continue;
+        }
+        let chunk = data.split_to(capacity.min(data.len()));
+        send.send_data(chunk, end_of_stream && data.is_empty())?;
+    }
+    Ok(())
+}
+
+/// Request for an HTTP/2 stream, :authority becomes the Host header
+async fn to_request(
+    parts: &::http::request::Parts,
+    body: Vec<u8>,
+    config: &MultipartConfig,
+) -> Result<Request, request::Error> {
+    let length = (!body.is_empty()).then_some(body.len());
+    let mut bytes = head_bytes(parts, length)?;
+    bytes.extend(body);
+    parse_spooled(bytes.into(), config).await
+}
+
+/// Head of an HTTP/2 stream before its body is read, with the Content-Length the client sent
+fn to_head(parts: &::http::request::Parts) -> Result<Request, request::Error> {
+    let length = parts
+        .headers
+        .get("content-length")
+        .and_then(|length| length.to_str().ok())
+        .and_then(|length| length.parse().ok());
+    Request::from_head(head_bytes(parts, length)?.into())
+}
+
+/// HTTP/1 head for parts, the request parser only reads that
+fn head_bytes(
+    parts: &::http::request::Parts,
+    length: Option<usize>,
+) -> Result<Vec<u8>, request::Error> {
+    let path = parts
+        .uri
+        .path_and_query()
+        .map(|p| p.as_str())
+        .unwrap_or("/");
+    let mut head = format!("{} {path} HTTP/2\r\n", parts.method);
+    let host = parts
+        .uri
+        .authority()
+        .map(|a| a.as_str())
+        .or_else(|| parts.headers.get("host").and_then(|h| h.to_str().ok()));
+    if let Some(host) = host {
+        head.push_str(&format!("Host: {host}\r\n"));
+    }
+    for (name, value) in &parts.headers {
+        if name == "host" || name == "content-length" {
+            continue;
+        }
+        let value = value.to_str().map_err(|_| request::Error::InvalidHeader)?;
+        head.push_str(&format!("{name}: {value}\r\n"));
+    }
+    if let Some(length) = length {
+        head.push_str(&format!("Content-Length: {length}\r\n"));
+    }
+    head.push_str("\r\n");
+    Ok(head.into_bytes())
+}
+
+/// Status and headers of response, without the ones HTTP/2 doesn't allow
+fn response_head(response: &Response) -> Result<::http::Response<()>, ::http::Error> {
+    let mut head = ::http::Response::builder().status(response.status());
+    if response.has_body() {
+        if !response.is_streamed() {
+            head = head.header("content-length", response.body().len());
+        }
+        if !response.headers().contains("content-type") {
+            head = head.header("content-type", String::from(&response.mime()));
+        }
+    }
+    for header in response.headers() {
+        let key = header.key.to_ascii_lowercase();
+        if key == "content-length" || CONNECTION_HEADERS.contains(&key.as_str()) {
+            continue;
+        }
+        head = head.header(key, &header.value);
+    }
+    head.body(())
+}
+
+/// Stream that gives back bytes already read from it before reading more, used when the
+/// preface was read while looking for an HTTP/1 request
+pub(crate) struct Rewind<T> {
+    read: BytesMut,
+    inner: T,
+}
+
+impl<T> Rewind<T> {
+    pub fn new(read: BytesMut, inner: T) -> Self {
+        Rewind { read, inner }
+    }
+}
+
+impl<T: AsyncRead + Unpin> AsyncRead for Rewind<T> {
+    fn poll_read(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<std::io::Result<()>> {
+        if !self.read.is_empty() {
+            let n = self.read.len().min(buf.remaining());
+            buf.put_slice(&self.read.split_to(n));
+            return Poll::Ready(Ok(()));
+        }
+        Pin::new(&mut self.inner).poll_read(cx, buf)
+    }
+}
+
+impl<T: AsyncWrite + Unpin> AsyncWrite for Rewind<T> {
+    fn poll_write(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<std::io::Result<usize>> {
+        Pin::new(&mut self.inner).poll_write(cx, buf)
+    }
+
+    fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<std::io::Result<()>> {
+        Pin::new(&mut self.inner).poll_flush(cx)
+    }
+
+    fn poll_shutdown(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<std::io::Result<()>> {
+        Pin::new(&mut self.inner).poll_shutdown(cx)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::http::{Method, MimeType, Version};
+    use tokio::io::AsyncReadExt;
+
+    #[tokio::test]
+    async fn requests() {
+        let (parts, _) = ::http::Request::builder()
+            .method("POST")
+            .uri("https://example.com:8443/form?a=1")
+            .header("content-type", "application/x-www-form-urlencoded")
+            .header("content-length", "99")
+            .header("cookie", "a=1")
+            .header("cookie", "b=2")
+            .body(())
+            .unwrap()
+            .into_parts();
+        let request = to_request(&parts, b"x=1&y=2".to_vec(), &MultipartConfig::default())
+            .await
+            .unwrap();
+        assert_eq!(&Method::POST, request.method());
+        assert_eq!(Version::V2_0, request.version());
+        assert_eq!("/form", request.path());
+        assert_eq!(Some(&"a=1".to_string()), request.query_string());
+        assert_eq!("example.com", request.hostname());
+        let request::FormTypes::XUrlEncoded(form) = request.form_data() else {
+            panic!("form wasn't parsed");
+        };
+        assert_eq!(Some("2"), form.get("y"));
+        assert_eq!(
+            Some("a=1; b=2".to_string()),
+            request.get_header_value("cookie")
+        );
+        assert!(request.keep_alive());
+
+        let (parts, _) = ::http::Request::builder()
+            .method("DELETE")
+            .uri("http://localhost/")
+            .body(())
+            .unwrap()
+            .into_parts();
+        let request = to_request(&parts, vec![], &MultipartConfig::default()).await;
+        assert!(request.is_err());
+    }
+
+    #[test]
+    fn response_heads() {
+        let mut response = Response::new(StatusCode::OK, b"hi".to_vec(), MimeType::PlainText);
+        response.add_header(("Connection", "keep-alive"));
+        response.add_header(("Set-Cookie", "a=1"));
+        response.add_header(("Set-Cookie", "b=2"));
+        let head = response_head(&response).unwrap();
+        assert_eq!(StatusCode::OK, head.status());
+        assert_eq!("2", head.headers()["content-length"]);
+        assert_eq!("text/plain; charset=utf-8", head.headers()["content-type"]);
+        assert_eq!(2, head.headers().get_all("set-cookie").iter().count());
+        assert!(head.headers().get("connection").is_none());
+
+        let not_modified = Response::new(StatusCode::NOT_MODIFIED, vec![], MimeType::HTML);
+        let head = response_head(&not_modified).unwrap();
+        assert!(head.headers().get("content-length").is_none());
+    }
+
+    #[tokio::test]
+    async fn rewind() {
+        let (mut client, server) = tokio::io::duplex(64);
+        tokio::io::AsyncWriteExt::write_all(&mut client, b" world")
+            .await
+            .unwrap();
+        drop(client);
+        let mut rewind = Rewind::new(BytesMut::from(&b"hello"[..]), server);
+        let mut read = String::new();
+        rewind.read_to_string(&mut read).await.unwrap();
+        assert_eq!("hello world", read);
+    }
+}
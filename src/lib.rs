@@ -1,20 +1,38 @@
+pub mod acme;
+pub mod autoindex;
+pub mod compression;
+pub mod conditional;
 pub mod cookies;
+pub mod error_pages;
+pub mod form;
+pub mod headers;
 pub mod http;
+pub mod http2;
 pub mod methods;
+pub mod mime;
+pub mod multipart;
+pub mod negotiate;
+pub mod query;
+pub mod range;
 pub mod request;
 pub mod response;
 pub mod routes;
+pub mod sse;
 pub mod state;
+pub mod static_files;
+#[cfg(test)]
+mod test_support;
 pub mod thread_pool;
 pub mod utils;
 pub mod virtual_host;
+pub mod websocket;
 
 use anyhow::Context;
-use bytes::{BufMut, BytesMut};
+use bytes::{Buf, BufMut, Bytes, BytesMut};
 use futures::StreamExt;
+use multipart::MultipartConfig;
 use response::Response;
 use routes::Router;
-use rustls_acme::{caches::DirCache, AcmeConfig};
 use std::{
     collections::HashMap,
     fmt::Debug,
@@ -39,6 +57,8 @@ use tokio_rustls::{
 };
 use tokio_util::sync::CancellationToken;
 
+type VirtualHosts<S> = HashMap<String, virtual_host::VirtualHost<S>>;
+
 pub struct Server<S> {
     listener: TcpListener,
     acceptor: Option<TlsAcceptor>,
@@ -47,6 +67,7 @@ pub struct Server<S> {
     cancel: CancellationToken,
     doc_root: PathBuf,
     timeout: Duration,
+    max_pipelined: usize,
 }
 
 trait ConnectionStream: AsyncWrite + AsyncRead + Unpin + Send + Sync {}
@@ -57,6 +78,9 @@ impl<T> ConnectionStream for T where T: AsyncReadExt + AsyncWriteExt + Unpin + S
 pub struct Connection {
     stream: Box<dyn ConnectionStream>,
     client_ip: std::net::SocketAddr,
+    cancel: CancellationToken,
+    /// h2 was picked with ALPN
+    http2: bool,
 }
 
 impl Connection {
@@ -67,12 +91,56 @@ impl Connection {
     }
 
     #[tracing::instrument(level = "debug", skip(self, response))]
-    pub async fn write_response(&mut self, response: Response) -> tokio::io::Result<()> {
+    pub async fn write_response(&mut self, mut response: Response) -> tokio::io::Result<()> {
         let response_buffer = response.to_send_buffer();
         log::trace!("Writing: {}Bytes", response_buffer.len());
         self.write_all(&response_buffer).await?;
+        let chunked = response.is_chunked();
+        if let Some(stream) = response.take_stream() {
+            self.write_chunks(stream, chunked).await?;
+        }
         Ok(())
     }
+
+    /// Write each item of stream as a chunk as soon as it's produced, unframed when not chunked
+    /// a failed write means the client is gone, on shutdown the body is ended early
+    #[tracing::instrument(level = "debug", skip(self, stream))]
+    async fn write_chunks(
+        &mut self,
+        mut stream: response::BodyStream,
+        chunked: bool,
+    ) -> tokio::io::Result<()> {
+        self.stream.flush().await?;
+        loop {
+            let chunk = select! {
+                _ = self.cancel.cancelled() => {
+                    tracing::debug!("ending streamed body, server shutting down");
+                    None
+                }
+                chunk = stream.next() => chunk,
+            };
+            let Some(chunk) = chunk else {
+                break;
+            };
+            // an empty chunk would end the body
+            if chunk.is_empty() {
+                continue;
+            }
+            if chunked {
+                let mut framed = format!("{:X}\r\n", chunk.len()).into_bytes();
+                framed.extend_from_slice(&chunk);
+                framed.extend_from_slice(b"\r\n");
+                self.write_all(&framed).await?;
+            } else {
+                self.write_all(&chunk).await?;
+            }
+            self.stream.flush().await?;
+        }
+        if chunked {
+            self.write_all(b"0\r\n\r\n").await?;
+        }
+        self.stream.flush().await
+    }
 }
 
 impl<S> Server<S>
@@ -94,6 +162,7 @@ where
             cancel: CancellationToken::new(),
             doc_root: PathBuf::from(doc_root.as_ref()),
             timeout: Duration::from_secs(30),
+            max_pipelined: 16,
         })
     }
 
@@ -108,11 +177,12 @@ where
         let files = vec![cert, key];
         let context = format!("Opening: {:#?}, {:#?}", cert, key);
         let (mut keys, certs) = load_keys_and_certs(&files).context(context)?;
-        let config = rustls::ServerConfig::builder()
+        let mut config = rustls::ServerConfig::builder()
             .with_safe_defaults()
             .with_no_client_auth()
             .with_single_cert(certs, keys.remove(0))
             .context("Loading Certs")?;
+        config.alpn_protocols = vec![http2::ALPN_H2.to_vec(), b"http/1.1".to_vec()];
         let acceptor = TlsAcceptor::from(Arc::new(config));
         let listener = tokio::net::TcpListener::bind(ip)
             .await
@@ -125,9 +195,12 @@ where
             cancel: CancellationToken::new(),
             doc_root: PathBuf::from(doc_root.as_ref()),
             timeout: Duration::from_secs(60),
+            max_pipelined: 16,
         })
     }
 
+    /// Bind TLS with certificates from Let's Encrypt (staging) using TLS-ALPN-01
+    /// use [`Server::bind_acme`] to pick the directory, cache or challenge type
     #[tracing::instrument(level = "debug", skip(router, domains))]
     pub async fn bind_tls_alpn(
         ip: &str,
@@ -136,36 +209,33 @@ where
         domains: impl IntoIterator<Item = impl AsRef<str>>,
         email: &str,
     ) -> Result<Self, anyhow::Error> {
-        let contact = format!("mailto:{email}");
-        let acme = AcmeConfig::new(domains)
-            .contact_push(&contact)
-            .cache(DirCache::new("./rustls_acme_cache"));
-        let mut state = acme.state();
-        let resolver = state.resolver();
-        let config = rustls::ServerConfig::builder()
-            .with_safe_defaults()
-            .with_no_client_auth()
-            .with_cert_resolver(resolver);
-        tokio::spawn(async move {
-            loop {
-                match state.next().await.unwrap() {
-                    Ok(ok) => log::info!("event: {:?}", ok),
-                    Err(err) => log::error!("error: {:?}", err),
-                }
-            }
-        });
-        let acceptor = TlsAcceptor::from(Arc::new(config));
+        let acme = acme::AcmeConfig::new(domains, email);
+        Self::bind_acme(ip, router, doc_root, acme).await
+    }
+
+    /// Bind TLS with certificates ordered and renewed through ACME
+    #[tracing::instrument(level = "debug", skip(router))]
+    pub async fn bind_acme(
+        ip: &str,
+        router: Router<S>,
+        doc_root: impl AsRef<Path> + Debug,
+        acme: acme::AcmeConfig,
+    ) -> Result<Self, anyhow::Error> {
         let listener = tokio::net::TcpListener::bind(ip)
             .await
             .context("binding tls")?;
+        let cancel = CancellationToken::new();
+        let config = acme.start(cancel.clone());
+        let acceptor = TlsAcceptor::from(Arc::new(config));
         Ok(Server {
             listener,
             router: Arc::new(RwLock::new(router)),
             virtual_hosts: Arc::new(RwLock::new(HashMap::new())),
             acceptor: Some(acceptor),
-            cancel: CancellationToken::new(),
+            cancel,
             doc_root: PathBuf::from(doc_root.as_ref()),
             timeout: Duration::from_secs(60),
+            max_pipelined: 16,
         })
     }
 
@@ -181,6 +251,32 @@ where
         locked.insert(virtual_host.hostname().to_string(), virtual_host);
     }
 
+    /// Most requests answered back to back from what a client pipelined before it has to wait,
+    /// the connection is closed after that many and the client resends the rest. Default 16,
+    /// 1 turns pipelining off
+    ///
+    /// # Panics
+    ///
+    /// Panics if `max_pipelined` is zero
+    pub fn set_max_pipelined(&mut self, max_pipelined: usize) {
+        assert!(max_pipelined > 0, "max_pipelined can't be zero");
+        self.max_pipelined = max_pipelined;
+    }
+
+    pub fn max_pipelined(&self) -> usize {
+        self.max_pipelined
+    }
+
+    /// How long a connection may wait on the client, for its next request or the rest of a body,
+    /// before it's closed. HTTP/2 connections are closed once they've had no open streams for it
+    pub fn set_read_timeout(&mut self, timeout: Duration) {
+        self.timeout = timeout;
+    }
+
+    pub fn read_timeout(&self) -> Duration {
+        self.timeout
+    }
+
     #[tracing::instrument(level = "debug", skip(self))]
     pub async fn accept(&self) -> tokio::io::Result<Connection> {
         let (stream, client_ip) = self.listener.accept().await?;
@@ -189,7 +285,9 @@ where
             match acceptor.accept(stream).await {
                 Ok(s) => Ok(Connection {
                     client_ip,
+                    http2: s.get_ref().1.alpn_protocol() == Some(http2::ALPN_H2),
                     stream: Box::new(tokio_rustls::TlsStream::Server(s)),
+                    cancel: self.cancel.clone(),
                 }),
                 Err(_) => Err(tokio::io::Error::new(
                     tokio::io::ErrorKind::Other,
@@ -200,6 +298,8 @@ where
             Ok(Connection {
                 client_ip,
                 stream: Box::new(stream),
+                cancel: self.cancel.clone(),
+                http2: false,
             })
         }
     }
@@ -212,12 +312,46 @@ where
         let vhosts = self.virtual_hosts();
         let ip = connection.client_ip;
         let timeout_duration = self.timeout;
+        let max_pipelined = self.max_pipelined;
+        if connection.http2 {
+            let server = http2::Http2 {
+                router,
+                vhosts,
+                doc_root,
+                client_ip: ip,
+                cancel: token,
+                timeout: timeout_duration,
+            };
+            return tokio::spawn(server.serve(connection.stream));
+        }
         let read_loop = async move {
             let mut request_bytes = BytesMut::with_capacity(1024);
+            // requests answered since the client last waited on a response
+            let mut pipelined = 0;
+            // a request was answered, h2c prior knowledge is only accepted before the first
+            let mut answered = false;
+            // the head of the request being read was checked before its body arrived
+            let mut head_checked = false;
+            // multipart body being parsed as it arrives
+            let mut upload: Option<Upload> = None;
+            // largest a chunked body being read can get, its length isn't known up front
+            let mut chunked_limit: Option<usize> = None;
+            let multipart_config = router.read().await.multipart_config().clone();
+            // multipart config of the request being read, its total follows the route's limit
+            let mut request_config: Option<MultipartConfig> = None;
             let mut buffer = vec![0; 1024]; //Vector to avoid buffer on stack
-            while let Ok(stream_read_result) =
-                timeout(timeout_duration, connection.stream.read(&mut buffer)).await
-            {
+            loop {
+                // only stop between requests so responses being written can finish
+                let read = select! {
+                    _ = token.cancelled() => {
+                        tracing::debug!("shutting down listen thread");
+                        return;
+                    }
+                    read = timeout(timeout_duration, connection.stream.read(&mut buffer)) => read,
+                };
+                let Ok(stream_read_result) = read else {
+                    break;
+                };
                 match stream_read_result {
                     Ok(0) => {
                         tracing::debug!("{ip}: Connection Terminated by client");
@@ -228,56 +362,90 @@ where
                         for b in buffer.iter().take(n) {
                             request_bytes.put_u8(*b);
                         }
-                        let request_result =
-                            request::Request::from_bytes(request_bytes.clone().into());
-                        match request_result {
-                            Ok(r) => {
-                                let path = r.path();
-                                let host = r.hostname();
-                                tracing::info!(
-                                    "{ip}: {} {} Request for: {}",
-                                    r.method(),
-                                    r.version(),
-                                    path
-                                );
-
-                                let html_path = if let Some(vhost) = vhosts.read().await.get(host) {
-                                    vhost.root_dir().clone()
-                                } else {
-                                    doc_root.clone()
-                                };
-                                let router_locked = router.read().await;
-                                let response = router_locked.route(&r, &html_path).await;
-                                tracing::debug!("{ip}|{path}: Writing Response");
-                                if let Err(error) = connection.write_response(response).await {
-                                    // not clearing string here so we can try
-                                    // again, otherwise might be terminated
-                                    // connection which will be handled
-                                    tracing::error!(
-                                        "{ip}|{path}: Error Writing response: {}",
-                                        error.to_string()
-                                    );
-                                } else {
-                                    //clear buffer
-                                    tracing::trace!(
-                                        "{ip}|{path}: Wrote response, clearing request buffer"
-                                    );
-                                    if r.keep_alive() {
-                                        connection.stream.flush().await.expect("Error flushing");
-                                        request_bytes.clear();
-                                    } else {
-                                        tracing::debug!(
-                                            "{ip}|{path}: Shutting down Stream, no keep alive"
-                                        );
-                                        //returning should drop the connection and shutdown the socket
-                                        return;
+                        // h2c with prior knowledge starts the connection with the preface
+                        if !answered && http2::PREFACE.starts_with(&request_bytes) {
+                            continue;
+                        }
+                        if !answered && request_bytes.starts_with(http2::PREFACE) {
+                            tracing::debug!("{ip}: HTTP/2 prior knowledge");
+                            let stream = http2::Rewind::new(request_bytes, connection.stream);
+                            let server = http2::Http2 {
+                                router,
+                                vhosts,
+                                doc_root,
+                                client_ip: ip,
+                                cancel: token,
+                                timeout: timeout_duration,
+                            };
+                            server.serve(stream).await;
+                            return;
+                        }
+                        // answer every complete request already read, in order
+                        loop {
+                            let parsed = match upload.as_mut() {
+                                Some(active) => match active.feed(&mut request_bytes).await {
+                                    Ok(Some(request)) => {
+                                        upload = None;
+                                        // the body was consumed as it was parsed
+                                        Ok((request, 0))
                                     }
+                                    Ok(None) => break,
+                                    Err(e) => Err(e),
+                                },
+                                None => {
+                                    let config =
+                                        request_config.as_ref().unwrap_or(&multipart_config);
+                                    parse_request(&request_bytes, config).await
                                 }
-                            }
-                            Err(e) => match e {
-                                request::Error::InvalidString
-                                | request::Error::MissingBlankLine => {}
-                                request::Error::WaitingOnBody(pb) => {
+                            };
+                            // bounded by its encoded size, the chunks aren't decoded until the end
+                            let too_large =
+                                |length: usize| chunked_limit.is_some_and(|l| length > l);
+                            let parsed = match parsed {
+                                Err(request::Error::WaitingOnBody(None))
+                                    if too_large(request_bytes.len()) =>
+                                {
+                                    Err(request::Error::BodyTooLarge)
+                                }
+                                Ok((_, length)) if too_large(length) => {
+                                    Err(request::Error::BodyTooLarge)
+                                }
+                                parsed => parsed,
+                            };
+                            let (r, length) = match parsed {
+                                Ok(parsed) => parsed,
+                                Err(request::Error::MissingBlankLine) => break,
+                                // anything else left over is a bad request, not one to wait on
+                                Err(request::Error::InvalidString) if request_bytes.is_empty() => {
+                                    break
+                                }
+                                Err(request::Error::WaitingOnBody(pb)) => {
+                                    if !head_checked {
+                                        head_checked = true;
+                                        let open = answer_head(
+                                            &mut connection,
+                                            &router,
+                                            &vhosts,
+                                            &doc_root,
+                                            &request_bytes,
+                                        )
+                                        .await;
+                                        if !open {
+                                            return;
+                                        }
+                                        request_config =
+                                            multipart_config_for(&router, &request_bytes).await;
+                                        let config =
+                                            request_config.as_ref().unwrap_or(&multipart_config);
+                                        upload = Upload::start(&mut request_bytes, config);
+                                        if upload.is_some() {
+                                            continue;
+                                        }
+                                        if pb.is_none() {
+                                            chunked_limit =
+                                                body_limit(&router, &request_bytes).await;
+                                        }
+                                    }
                                     if let Some(bytes_left) = pb {
                                         let free_bytes =
                                             request_bytes.capacity() - request_bytes.len();
@@ -286,15 +454,19 @@ where
                                             request_bytes.reserve(bytes_left - free_bytes);
                                         }
                                     }
+                                    break;
                                 }
-                                _ => {
-                                    let error_res = format!("400 bad request: {}", e);
+                                Err(e) => {
+                                    let status = e.status();
+                                    let error_res = format!("{status}: {e}");
                                     let req_string = String::from_utf8_lossy(&buffer);
                                     tracing::warn!("{ip}: {} Request: {}", error_res, req_string);
-                                    let response = Response::error(
-                                        http::StatusCode::BAD_REQUEST,
-                                        error_res.into(),
-                                    );
+                                    let response = Response::error(status, error_res.into());
+                                    let response = router
+                                        .read()
+                                        .await
+                                        .handle_error(None, &doc_root, response)
+                                        .await;
                                     if let Err(err) = connection.write_response(response).await {
                                         tracing::error!(
                                             "{ip}: Error Writing Data: {}",
@@ -305,7 +477,70 @@ where
                                     tracing::warn!("{ip}: Shutting down Stream, bad request");
                                     return;
                                 }
-                            },
+                            };
+                            let path = r.path();
+                            tracing::info!(
+                                "{ip}: {} {} Request for: {}",
+                                r.method(),
+                                r.version(),
+                                path
+                            );
+                            let rejected = if head_checked {
+                                None
+                            } else {
+                                check_head(&router, &vhosts, &doc_root, &r).await
+                            };
+                            head_checked = false;
+                            chunked_limit = None;
+                            request_config = None;
+                            let mut response = match rejected {
+                                Some(response) => response,
+                                None => route_request(&router, &vhosts, &doc_root, &r).await,
+                            };
+                            let upgrade = response.take_upgrade();
+                            pipelined += 1;
+                            answered = true;
+                            if pipelined >= max_pipelined && request_bytes.len() > length {
+                                // the client can't queue up unbounded work, it sends the
+                                // rest again on a new connection
+                                tracing::warn!("{ip}: Too many pipelined requests, closing");
+                                response.set_header(("Connection", "close"));
+                            }
+                            let keep_alive = match_version(&r, &mut response);
+                            tracing::debug!("{ip}|{path}: Writing Response");
+                            if let Err(error) = connection.write_response(response).await {
+                                // not clearing string here so we can try
+                                // again, otherwise might be terminated
+                                // connection which will be handled
+                                tracing::error!(
+                                    "{ip}|{path}: Error Writing response: {}",
+                                    error.to_string()
+                                );
+                                break;
+                            }
+                            // drop the answered request, anything after it was pipelined
+                            request_bytes.advance(length);
+                            if let Some(upgrade) = upgrade {
+                                // the socket owns the connection now, anything after the
+                                // request is already its first frames
+                                tracing::debug!("{ip}|{path}: Upgrading connection");
+                                let read = request_bytes.split();
+                                // not part of the read loop so shutdown can close it cleanly
+                                tokio::spawn(upgrade.start(connection.stream, read, token));
+                                return;
+                            }
+                            if !keep_alive {
+                                tracing::debug!("{ip}|{path}: Shutting down Stream, no keep alive");
+                                //returning should drop the connection and shutdown the socket
+                                return;
+                            }
+                            connection.stream.flush().await.expect("Error flushing");
+                            if request_bytes.is_empty() {
+                                tracing::trace!("{ip}|{path}: Wrote response, waiting on next");
+                                pipelined = 0;
+                                break;
+                            }
+                            tracing::trace!("{ip}|{path}: Wrote response, next is pipelined");
                         }
                     }
                     Err(err) => {
@@ -328,15 +563,7 @@ where
             */
         };
 
-        tokio::spawn(async move {
-            select! {
-                _ = read_loop => {
-                }
-                _ = token.cancelled() => {
-                    tracing::debug!("shutting down listen thread");
-                }
-            }
-        })
+        tokio::spawn(read_loop)
     }
 
     #[tracing::instrument(level = "debug", skip(self))]
@@ -375,6 +602,250 @@ where
         }
     }
 }
+/// Final response for the head of a request instead of reading its body, None to read it
+/// a client that sent Expect: 100-continue waits for this before sending the body
+async fn check_head<S>(
+    router: &RwLock<Router<S>>,
+    vhosts: &RwLock<VirtualHosts<S>>,
+    doc_root: &Path,
+    head: &request::Request,
+) -> Option<Response>
+where
+    S: Clone + Send + Sync + 'static,
+{
+    let locked = router.read().await;
+    if let Some(response) = locked.check_head(head).await {
+        return Some(locked.handle_error(Some(head), doc_root, response).await);
+    }
+    let expects = head.get_header_value("Expect").is_some();
+    if expects && !locked.accepts_body(head).await {
+        drop(locked);
+        // nothing reads the body so the head is enough to answer
+        return Some(route_request(router, vhosts, doc_root, head).await);
+    }
+    None
+}
+
+/// Head of a request in bytes whose body is still to come, the client either gets 100 Continue
+/// if it expects that or the final response, false when the connection is done
+async fn answer_head<S>(
+    connection: &mut Connection,
+    router: &RwLock<Router<S>>,
+    vhosts: &RwLock<VirtualHosts<S>>,
+    doc_root: &Path,
+    bytes: &[u8],
+) -> bool
+where
+    S: Clone + Send + Sync + 'static,
+{
+    let ip = connection.client_ip;
+    let head_end = memchr::memmem::find(bytes, b"\r\n\r\n").map_or(bytes.len(), |i| i + 4);
+    let Ok(head) = request::Request::from_head(Bytes::copy_from_slice(&bytes[..head_end])) else {
+        // the error is answered once the whole request is parsed
+        return true;
+    };
+    if let Some(mut response) = check_head(router, vhosts, doc_root, &head).await {
+        tracing::info!(
+            "{ip}: {} {} answered before the body: {}",
+            head.method(),
+            head.path(),
+            response.status()
+        );
+        // the body isn't read so the connection can't be reused
+        response.set_header(("Connection", "close"));
+        match_version(&head, &mut response);
+        if let Err(err) = connection.write_response(response).await {
+            tracing::error!("{ip}: Error Writing Data: {err}");
+        }
+        return false;
+    }
+    // 1.0 clients don't know 1xx responses
+    if head.get_header_value("Expect").is_some() && head.version() >= http::Version::V1_1 {
+        tracing::debug!("{ip}: 100 Continue for {}", head.path());
+        if let Err(err) = connection.write_all(b"HTTP/1.1 100 Continue\r\n\r\n").await {
+            tracing::error!("{ip}: Error Writing Data: {err}");
+            return false;
+        }
+        return connection.stream.flush().await.is_ok();
+    }
+    true
+}
+
+/// Most bytes the request whose head starts bytes can take up, head included
+async fn body_limit<S>(router: &RwLock<Router<S>>, bytes: &[u8]) -> Option<usize>
+where
+    S: Clone + Send + Sync + 'static,
+{
+    let head_end = memchr::memmem::find(bytes, b"\r\n\r\n")? + 4;
+    let head = request::Request::from_head(Bytes::copy_from_slice(&bytes[..head_end])).ok()?;
+    let limit = router.read().await.body_limit(&head).await?;
+    Some(head_end + limit)
+}
+
+/// Multipart config for the request whose head starts bytes, see [`Router::multipart_config_for`]
+async fn multipart_config_for<S>(
+    router: &RwLock<Router<S>>,
+    bytes: &[u8],
+) -> Option<MultipartConfig>
+where
+    S: Clone + Send + Sync + 'static,
+{
+    let head_end = memchr::memmem::find(bytes, b"\r\n\r\n")? + 4;
+    let head = request::Request::from_head(Bytes::copy_from_slice(&bytes[..head_end])).ok()?;
+    Some(router.read().await.multipart_config_for(&head).await)
+}
+
+/// Multipart body parsed as it's read instead of buffering all of it, file parts are spooled
+struct Upload {
+    head: request::Request,
+    /// taken while it runs on the blocking pool
+    parser: Option<multipart::Parser>,
+    /// body bytes still to come over HTTP/1, an HTTP/2 stream tells when it ends
+    remaining: usize,
+}
+
+impl Upload {
+    /// Upload for the body of head, None unless it's multipart/form-data with a boundary
+    fn new(head: request::Request, config: &MultipartConfig) -> Option<Upload> {
+        let content_type = head.typed_header::<headers::ContentType>()?;
+        if content_type.essence() != "multipart/form-data" {
+            return None;
+        }
+        let boundary = request::Request::multipart_boundary(head.headers()).ok()?;
+        Some(Upload {
+            head,
+            parser: Some(multipart::Parser::new(&boundary, config.clone())),
+            remaining: 0,
+        })
+    }
+
+    /// Start on the body of the multipart request whose head starts bytes, dropping the head
+    /// from bytes. None for other requests, errors are found once the whole request is parsed
+    fn start(bytes: &mut BytesMut, config: &MultipartConfig) -> Option<Upload> {
+        let head_end = memchr::memmem::find(bytes, b"\r\n\r\n")? + 4;
+        let head = request::Request::from_head(Bytes::copy_from_slice(&bytes[..head_end])).ok()?;
+        let remaining = head.get_header_value("Content-Length")?.parse().ok()?;
+        let mut upload = Upload::new(head, config)?;
+        upload.remaining = remaining;
+        bytes.advance(head_end);
+        Some(upload)
+    }
+
+    /// Parse the part of bytes that belongs to the body, the request once all of it was read
+    async fn feed(
+        &mut self,
+        bytes: &mut BytesMut,
+    ) -> Result<Option<request::Request>, request::Error> {
+        let body = bytes.split_to(self.remaining.min(bytes.len()));
+        self.remaining -= body.len();
+        self.parse(body.freeze(), self.remaining == 0).await
+    }
+
+    /// Parse the next part of the body, the request once the last part was parsed
+    /// file parts are written to disk so parsing runs on the blocking pool
+    async fn parse(
+        &mut self,
+        body: Bytes,
+        last: bool,
+    ) -> Result<Option<request::Request>, request::Error> {
+        let mut parser = self.parser.take().ok_or(request::Error::UploadFailed)?;
+        let (parser, entries) = tokio::task::spawn_blocking(move || {
+            let entries = match parser.feed(&body) {
+                Ok(()) if !last => Ok(None),
+                Ok(()) => parser.finish().map(Some),
+                Err(error) => Err(error),
+            };
+            (parser, entries)
+        })
+        .await
+        .map_err(|_| request::Error::UploadFailed)?;
+        let Some(entries) = entries? else {
+            self.parser = Some(parser);
+            return Ok(None);
+        };
+        let mut request = self.head.clone();
+        request.set_form_data(request::Request::multipart_form(entries));
+        Ok(Some(request))
+    }
+}
+
+/// First request in bytes and its length
+/// HTTP/2 has its own framing, it's only spoken through http2
+async fn parse_request(
+    bytes: &[u8],
+    config: &MultipartConfig,
+) -> Result<(request::Request, usize), request::Error> {
+    let length = request::Request::message_length(bytes)?;
+    let request = parse_spooled(Bytes::copy_from_slice(&bytes[..length]), config).await?;
+    match request.version() {
+        http::Version::V2_0 => Err(request::Error::UnsupportedHTTPVersion),
+        _ => Ok((request, length)),
+    }
+}
+
+/// Parse a whole request, multipart file parts can be spooled to disk so those are parsed on
+/// the blocking pool, anything else right away
+async fn parse_spooled(
+    bytes: Bytes,
+    config: &MultipartConfig,
+) -> Result<request::Request, request::Error> {
+    if memchr::memmem::find(&bytes, b"multipart/form-data").is_none() {
+        return request::Request::from_bytes_with(bytes, config);
+    }
+    let config = config.clone();
+    tokio::task::spawn_blocking(move || request::Request::from_bytes_with(bytes, &config))
+        .await
+        .unwrap_or(Err(request::Error::UploadFailed))
+}
+
+/// Answer in the request's version and say whether the connection stays open
+/// 1.0 keep alive has to be confirmed, a 1.0 streamed body ends by closing the connection
+fn match_version(request: &request::Request, response: &mut Response) -> bool {
+    let version = request.version();
+    response.set_version(version);
+    let close_requested = response
+        .headers()
+        .get_joined("connection")
+        .is_some_and(|c| c.split(',').any(|t| t.trim().eq_ignore_ascii_case("close")));
+    let delimited = !response.is_streamed() || response.is_chunked();
+    let keep_alive = request.keep_alive() && !close_requested && delimited;
+    if !response.headers().contains("connection") {
+        if version == http::Version::V1_0 && keep_alive {
+            response.set_header(("Connection", "keep-alive"));
+        } else if version == http::Version::V1_1 && !keep_alive {
+            response.set_header(("Connection", "close"));
+        }
+    }
+    keep_alive
+}
+
+/// Route request with the doc root, static settings and error pages of its virtual host
+async fn route_request<S>(
+    router: &RwLock<Router<S>>,
+    vhosts: &RwLock<VirtualHosts<S>>,
+    doc_root: &Path,
+    request: &request::Request,
+) -> Response
+where
+    S: Clone + Send + Sync + 'static,
+{
+    let (html_path, static_config, error_pages) =
+        if let Some(vhost) = vhosts.read().await.get(request.hostname()) {
+            (
+                vhost.root_dir().clone(),
+                vhost.static_config().cloned(),
+                Some(vhost.error_pages().clone()),
+            )
+        } else {
+            (doc_root.to_path_buf(), None, None)
+        };
+    let router = router.read().await;
+    let static_config = static_config.as_ref().unwrap_or(router.static_config());
+    router
+        .route_with(request, &html_path, static_config, error_pages.as_ref())
+        .await
+}
+
 fn load_keys_and_certs(paths: &Vec<&Path>) -> std::io::Result<(Vec<PrivateKey>, Vec<Certificate>)> {
     let mut keys = vec![];
     let mut certs = vec![];
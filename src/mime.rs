@@ -0,0 +1,231 @@
+//! Extension table and content sniffing for [`MimeType`]
+use crate::http::MimeType;
+use std::path::Path;
+use tokio::io::AsyncReadExt;
+
+/// Lowercase extension without the dot and its media type
+const EXTENSIONS: &[(&str, &str)] = &[
+    // text
+    ("html", "text/html"),
+    ("htm", "text/html"),
+    ("txt", "text/plain"),
+    ("css", "text/css"),
+    ("js", "text/javascript"),
+    ("mjs", "text/javascript"),
+    ("cjs", "text/javascript"),
+    ("csv", "text/csv"),
+    ("md", "text/markdown"),
+    ("ics", "text/calendar"),
+    ("vtt", "text/vtt"),
+    // structured data
+    ("json", "application/json"),
+    ("map", "application/json"),
+    ("jsonld", "application/ld+json"),
+    ("webmanifest", "application/manifest+json"),
+    ("xml", "application/xml"),
+    ("xhtml", "application/xhtml+xml"),
+    ("rss", "application/rss+xml"),
+    ("atom", "application/atom+xml"),
+    ("yaml", "application/yaml"),
+    ("yml", "application/yaml"),
+    ("toml", "application/toml"),
+    ("wasm", "application/wasm"),
+    // images
+    ("svg", "image/svg+xml"),
+    ("ico", "image/vnd.microsoft.icon"),
+    ("png", "image/png"),
+    ("apng", "image/apng"),
+    ("jpg", "image/jpeg"),
+    ("jpeg", "image/jpeg"),
+    ("gif", "image/gif"),
+    ("webp", "image/webp"),
+    ("avif", "image/avif"),
+    ("bmp", "image/bmp"),
+    ("tif", "image/tiff"),
+    ("tiff", "image/tiff"),
+    // fonts
+    ("woff", "font/woff"),
+    ("woff2", "font/woff2"),
+    ("ttf", "font/ttf"),
+    ("otf", "font/otf"),
+    ("eot", "application/vnd.ms-fontobject"),
+    // audio and video
+    ("mp3", "audio/mpeg"),
+    ("ogg", "audio/ogg"),
+    ("oga", "audio/ogg"),
+    ("opus", "audio/opus"),
+    ("wav", "audio/wav"),
+    ("flac", "audio/flac"),
+    ("aac", "audio/aac"),
+    ("m4a", "audio/mp4"),
+    ("mp4", "video/mp4"),
+    ("m4v", "video/mp4"),
+    ("webm", "video/webm"),
+    ("ogv", "video/ogg"),
+    ("mov", "video/quicktime"),
+    ("m3u8", "application/vnd.apple.mpegurl"),
+    // documents and archives
+    ("pdf", "application/pdf"),
+    ("epub", "application/epub+zip"),
+    ("zip", "application/zip"),
+    ("gz", "application/gzip"),
+    ("tar", "application/x-tar"),
+    ("7z", "application/x-7z-compressed"),
+    ("bin", "application/octet-stream"),
+];
+
+/// Media type for a file extension, None if it isn't in the table
+pub fn from_extension(extension: &str) -> Option<MimeType> {
+    EXTENSIONS
+        .iter()
+        .find(|(e, _)| e.eq_ignore_ascii_case(extension))
+        .map(|(_, media_type)| MimeType::from_static(media_type))
+}
+
+/// Types that get charset=utf-8 in their Content-Type
+pub fn is_text(media_type: &str) -> bool {
+    media_type.starts_with("text/")
+        || media_type == "application/json"
+        || media_type == "application/xml"
+        || media_type == "application/javascript"
+        || media_type == "application/yaml"
+        || media_type == "application/toml"
+        || media_type == "image/svg+xml"
+        || media_type.ends_with("+json")
+        || media_type.ends_with("+xml")
+}
+
+/// Bytes at the start of a file and the type they identify, None in the pattern matches anything
+const SIGNATURES: &[(&[Option<u8>], &str)] = &[
+    (&bytes(b"%PDF-"), "application/pdf"),
+    (&bytes(b"\x89PNG\r\n\x1a\n"), "image/png"),
+    (&bytes(b"\xff\xd8\xff"), "image/jpeg"),
+    (&bytes(b"GIF87a"), "image/gif"),
+    (&bytes(b"GIF89a"), "image/gif"),
+    (&riff(b"WEBP"), "image/webp"),
+    (&riff(b"WAVE"), "audio/wav"),
+    (&ftyp(b"avif"), "image/avif"),
+    (&ftyp(b"isom"), "video/mp4"),
+    (&ftyp(b"mp41"), "video/mp4"),
+    (&ftyp(b"mp42"), "video/mp4"),
+    (&bytes(b"\x00asm"), "application/wasm"),
+    (&bytes(b"wOFF"), "font/woff"),
+    (&bytes(b"wOF2"), "font/woff2"),
+    (&bytes(b"PK\x03\x04"), "application/zip"),
+    (&bytes(b"\x1f\x8b\x08"), "application/gzip"),
+    (&bytes(b"\x1a\x45\xdf\xa3"), "video/webm"),
+    (&bytes(b"OggS\x00"), "audio/ogg"),
+    (&bytes(b"ID3"), "audio/mpeg"),
+    (&bytes(b"fLaC"), "audio/flac"),
+];
+
+const fn bytes<const N: usize>(signature: &[u8; N]) -> [Option<u8>; N] {
+    let mut pattern = [None; N];
+    let mut i = 0;
+    while i < N {
+        pattern[i] = Some(signature[i]);
+        i += 1;
+    }
+    pattern
+}
+
+/// RIFF container, 4 bytes of size then the format
+const fn riff(format: &[u8; 4]) -> [Option<u8>; 12] {
+    let mut pattern = [None; 12];
+    let riff = b"RIFF";
+    let mut i = 0;
+    while i < 4 {
+        pattern[i] = Some(riff[i]);
+        pattern[i + 8] = Some(format[i]);
+        i += 1;
+    }
+    pattern
+}
+
+/// ISO base media file, 4 bytes of box size then ftyp and the major brand
+const fn ftyp(brand: &[u8; 4]) -> [Option<u8>; 12] {
+    let mut pattern = [None; 12];
+    let ftyp = b"ftyp";
+    let mut i = 0;
+    while i < 4 {
+        pattern[i + 4] = Some(ftyp[i]);
+        pattern[i + 8] = Some(brand[i]);
+        i += 1;
+    }
+    pattern
+}
+
+/// Guess the type of a file from its first bytes, used for files without a known extension
+/// anything without a signature is plain text if it's utf-8 without control characters,
+/// otherwise binary. Never sniffs html, svg or xml so an upload can't turn into a page that
+/// runs scripts
+pub fn sniff(head: &[u8]) -> MimeType {
+    for (pattern, media_type) in SIGNATURES {
+        if head.len() >= pattern.len()
+            && pattern
+                .iter()
+                .zip(head)
+                .all(|(p, b)| p.map(|p| p == *b).unwrap_or(true))
+        {
+            return MimeType::from_static(media_type);
+        }
+    }
+    let text = match std::str::from_utf8(head) {
+        Ok(text) => text,
+        // the read may have cut a multi byte character in half
+        Err(error) if error.error_len().is_none() => {
+            std::str::from_utf8(&head[..error.valid_up_to()]).unwrap_or_default()
+        }
+        Err(_) => return MimeType::Binary,
+    };
+    let binary = text
+        .chars()
+        .any(|c| c.is_control() && !matches!(c, '\t' | '\n' | '\r' | '\x0c' | '\x1b'));
+    if binary {
+        MimeType::Binary
+    } else {
+        MimeType::PlainText
+    }
+}
+
+/// Sniff the first 512 bytes of a file
+pub async fn sniff_file(path: &Path) -> Result<MimeType, std::io::Error> {
+    let file = tokio::fs::File::open(path).await?;
+    let mut head = Vec::with_capacity(512);
+    file.take(512).read_to_end(&mut head).await?;
+    Ok(sniff(&head))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn extensions() {
+        let media_type = |ext| from_extension(ext).map(|m| m.media_type().to_string());
+        assert_eq!(Some(MimeType::JavaScript), from_extension("mjs"));
+        assert_eq!(Some(MimeType::HTML), from_extension("HTM"));
+        assert_eq!(Some("application/wasm".into()), media_type("wasm"));
+        assert_eq!(Some("font/woff2".into()), media_type("woff2"));
+        assert_eq!(Some("video/mp4".into()), media_type("mp4"));
+        assert_eq!(Some("text/csv".into()), media_type("csv"));
+        assert_eq!(None, from_extension("nope"));
+    }
+
+    #[test]
+    fn sniffing() {
+        let media_type = |head: &[u8]| sniff(head).media_type().to_string();
+        assert_eq!("application/pdf", media_type(b"%PDF-1.7\n"));
+        assert_eq!("image/png", media_type(b"\x89PNG\r\n\x1a\n\x00\x00"));
+        assert_eq!("image/webp", media_type(b"RIFF\x10\x00\x00\x00WEBPVP8 "));
+        assert_eq!("video/mp4", media_type(b"\x00\x00\x00\x18ftypmp42\x00"));
+        assert_eq!("image/avif", media_type(b"\x00\x00\x00\x1cftypavif"));
+        assert_eq!("application/wasm", media_type(b"\x00asm\x01\x00\x00\x00"));
+        assert_eq!("text/plain", media_type(b"hello\r\nworld\t\xc3\xa9"));
+        assert_eq!("text/plain", media_type(b"cut in half \xc3"));
+        assert_eq!("text/plain", media_type(b"<html><script>"));
+        assert_eq!("application/octet-stream", media_type(b"\x00\x01\x02"));
+        assert_eq!("application/octet-stream", media_type(b"\xff\xfe"));
+        assert_eq!("text/plain", media_type(b""));
+    }
+}
@@ -0,0 +1,656 @@
+//! Streaming multipart/form-data parser (RFC 7578), file parts over a threshold are spooled to
+//! temp files instead of being kept in memory. Files sent together as a multipart/mixed part
+//! (RFC 2388 5.2) are flattened into one field each under the same name
+use crate::{
+    headers::{ContentDisposition, ContentType, TypedHeader},
+    http::{Header, HeaderMap},
+    request::MultiPartFormEntry,
+};
+use core::fmt;
+use memchr::memmem;
+use rand::{distributions::Alphanumeric, thread_rng, Rng};
+use std::{
+    fs::{File, OpenOptions},
+    io::{self, Write},
+    path::{Path, PathBuf},
+    sync::Arc,
+};
+
+/// Part headers past this are malformed
+const MAX_PART_HEAD: usize = 8 * 1024;
+/// Largest value of a single part unless set otherwise
+const DEFAULT_MAX_FIELD_SIZE: usize = 1024 * 1024;
+/// Largest multipart body unless set otherwise or the route has a body size limit
+const DEFAULT_MAX_TOTAL_SIZE: usize = 16 * 1024 * 1024;
+
+/// Limits and spooling for multipart bodies, set on a [`crate::routes::Router`]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MultipartConfig {
+    spool_threshold: usize,
+    max_field_size: usize,
+    /// None follows the body size limit of the route
+    max_total_size: Option<usize>,
+    temp_dir: PathBuf,
+}
+
+impl Default for MultipartConfig {
+    fn default() -> Self {
+        MultipartConfig {
+            spool_threshold: 64 * 1024,
+            max_field_size: DEFAULT_MAX_FIELD_SIZE,
+            max_total_size: None,
+            temp_dir: std::env::temp_dir(),
+        }
+    }
+}
+
+impl MultipartConfig {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// File parts larger than this are written to a temp file
+    pub fn spool_threshold(&self) -> usize {
+        self.spool_threshold
+    }
+
+    pub fn set_spool_threshold(&mut self, spool_threshold: usize) {
+        self.spool_threshold = spool_threshold;
+    }
+
+    /// Largest value of a single part, files included, 413 past it. 1 MiB by default, raise it
+    /// for larger uploads
+    pub fn max_field_size(&self) -> usize {
+        self.max_field_size
+    }
+
+    pub fn set_max_field_size(&mut self, size: usize) {
+        self.max_field_size = size;
+    }
+
+    /// Largest multipart body including part headers and boundaries, 413 past it
+    /// None unless set, the body size limit of the route is used then, 16 MiB without one
+    pub fn max_total_size(&self) -> Option<usize> {
+        self.max_total_size
+    }
+
+    pub fn set_max_total_size(&mut self, size: usize) {
+        self.max_total_size = Some(size);
+    }
+
+    /// Where spooled files are created, the system temp dir by default
+    pub fn temp_dir(&self) -> &Path {
+        &self.temp_dir
+    }
+
+    pub fn set_temp_dir(&mut self, temp_dir: impl Into<PathBuf>) {
+        self.temp_dir = temp_dir.into();
+    }
+
+    /// Config for a body the route takes at most limit of, an unset total follows that limit
+    pub(crate) fn with_body_limit(&self, limit: Option<usize>) -> MultipartConfig {
+        let mut config = self.clone();
+        config.max_total_size = self.max_total_size.or(limit);
+        config
+    }
+}
+
+#[derive(Debug)]
+pub enum Error {
+    Malformed(&'static str),
+    FieldTooLarge,
+    TooLarge,
+    Io(io::Error),
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::Malformed(reason) => write!(f, "Malformed multipart body: {reason}"),
+            Error::FieldTooLarge => write!(f, "Multipart field too large"),
+            Error::TooLarge => write!(f, "Multipart body too large"),
+            Error::Io(error) => write!(f, "Error spooling multipart field: {error}"),
+        }
+    }
+}
+
+impl std::error::Error for Error {}
+
+impl From<io::Error> for Error {
+    fn from(error: io::Error) -> Self {
+        Error::Io(error)
+    }
+}
+
+/// Upload written to a temp file, removed once the last reference to it is dropped
+#[derive(Debug, PartialEq, Eq)]
+pub struct SpooledFile {
+    path: PathBuf,
+    len: u64,
+}
+
+impl SpooledFile {
+    fn create(dir: &Path) -> io::Result<(SpooledFile, File)> {
+        loop {
+            let name: String = thread_rng()
+                .sample_iter(&Alphanumeric)
+                .take(16)
+                .map(char::from)
+                .collect();
+            let path = dir.join(format!("nucleus-upload-{name}"));
+            match OpenOptions::new().write(true).create_new(true).open(&path) {
+                Ok(file) => return Ok((SpooledFile { path, len: 0 }, file)),
+                Err(error) if error.kind() == io::ErrorKind::AlreadyExists => continue,
+                Err(error) => return Err(error),
+            }
+        }
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    pub fn len(&self) -> u64 {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// Copy the upload somewhere it outlives the request
+    pub async fn persist(&self, to: impl AsRef<Path>) -> io::Result<()> {
+        tokio::fs::copy(&self.path, to).await.map(|_| ())
+    }
+}
+
+impl Drop for SpooledFile {
+    fn drop(&mut self) {
+        let path = std::mem::take(&mut self.path);
+        let remove = move || {
+            if let Err(error) = std::fs::remove_file(&path) {
+                tracing::warn!("Error removing upload {}: {error}", path.display());
+            }
+        };
+        // uploads are mostly dropped on runtime threads, which shouldn't wait on the disk
+        match tokio::runtime::Handle::try_current() {
+            Ok(runtime) => drop(runtime.spawn_blocking(remove)),
+            Err(_) => remove(),
+        }
+    }
+}
+
+/// Value of a multipart field
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum FieldData {
+    Memory(Vec<u8>),
+    Spooled(Arc<SpooledFile>),
+}
+
+impl FieldData {
+    pub fn len(&self) -> u64 {
+        match self {
+            FieldData::Memory(value) => value.len() as u64,
+            FieldData::Spooled(file) => file.len(),
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum State {
+    Preamble,
+    Boundary,
+    Headers,
+    Body,
+    Done,
+}
+
+/// Field being read, its value moves to a temp file once it passes the threshold
+struct Field {
+    name: String,
+    file_name: Option<String>,
+    content_type: Option<String>,
+    headers: HeaderMap,
+    value: Vec<u8>,
+    spool: Option<(SpooledFile, File)>,
+    /// files of a multipart/mixed part
+    mixed: Option<Box<Parser>>,
+    len: usize,
+}
+
+/// Incremental parser, fed the body as it's read
+/// spooled file parts are written with blocking IO, async code runs it on the blocking pool
+pub struct Parser {
+    delimiter: Vec<u8>,
+    config: MultipartConfig,
+    buffer: Vec<u8>,
+    state: State,
+    field: Option<Field>,
+    entries: Vec<MultiPartFormEntry>,
+    total: usize,
+    /// name of the multipart/mixed field this parses the files of
+    parent: Option<String>,
+}
+
+impl Parser {
+    pub fn new(boundary: &str, config: MultipartConfig) -> Self {
+        Self::with_parent(boundary, config, None)
+    }
+
+    fn with_parent(boundary: &str, config: MultipartConfig, parent: Option<String>) -> Self {
+        let mut delimiter = b"\r\n--".to_vec();
+        delimiter.extend_from_slice(boundary.as_bytes());
+        Parser {
+            delimiter,
+            config,
+            // the first boundary doesn't need a CRLF before it
+            buffer: b"\r\n".to_vec(),
+            state: State::Preamble,
+            field: None,
+            entries: vec![],
+            total: 0,
+            parent,
+        }
+    }
+
+    /// Parse the next bytes of the body, anything after the closing boundary is ignored
+    pub fn feed(&mut self, bytes: &[u8]) -> Result<(), Error> {
+        self.total += bytes.len();
+        let max_total = self.config.max_total_size.unwrap_or(DEFAULT_MAX_TOTAL_SIZE);
+        if self.total > max_total {
+            return Err(Error::TooLarge);
+        }
+        if self.state == State::Done {
+            return Ok(());
+        }
+        self.buffer.extend_from_slice(bytes);
+        while self.step()? {}
+        Ok(())
+    }
+
+    /// Every field once the body is complete
+    pub fn finish(&mut self) -> Result<Vec<MultiPartFormEntry>, Error> {
+        if self.state != State::Done {
+            return Err(Error::Malformed("missing closing boundary"));
+        }
+        Ok(std::mem::take(&mut self.entries))
+    }
+
+    /// Parse what's buffered, false when more bytes are needed
+    fn step(&mut self) -> Result<bool, Error> {
+        match self.state {
+            State::Preamble => match memmem::find(&self.buffer, &self.delimiter) {
+                Some(i) => {
+                    self.buffer.drain(..i + self.delimiter.len());
+                    self.state = State::Boundary;
+                    Ok(true)
+                }
+                None => {
+                    let keep = self.delimiter.len() - 1;
+                    if self.buffer.len() > keep {
+                        self.buffer.drain(..self.buffer.len() - keep);
+                    }
+                    Ok(false)
+                }
+            },
+            State::Boundary => {
+                if self.buffer.starts_with(b"--") {
+                    self.state = State::Done;
+                    self.buffer.clear();
+                    return Ok(false);
+                }
+                let Some(line_end) = memmem::find(&self.buffer, b"\r\n") else {
+                    if self.buffer.len() > MAX_PART_HEAD {
+                        return Err(Error::Malformed("invalid boundary line"));
+                    }
+                    return Ok(false);
+                };
+                // whitespace is allowed after the boundary (RFC 2046 5.1.1)
+                if !self.buffer[..line_end]
+                    .iter()
+                    .all(|b| matches!(b, b' ' | b'\t'))
+                {
+                    return Err(Error::Malformed("invalid boundary line"));
+                }
+                self.buffer.drain(..line_end + 2);
+                self.state = State::Headers;
+                Ok(true)
+            }
+            State::Headers => {
+                let head_end = if self.buffer.starts_with(b"\r\n") {
+                    Some(0)
+                } else {
+                    memmem::find(&self.buffer, b"\r\n\r\n").map(|i| i + 2)
+                };
+                let Some(head_end) = head_end else {
+                    if self.buffer.len() > MAX_PART_HEAD {
+                        return Err(Error::Malformed("part headers too large"));
+                    }
+                    return Ok(false);
+                };
+                let field = self.field(&self.buffer[..head_end])?;
+                self.field = Some(field);
+                self.buffer.drain(..head_end + 2);
+                self.state = State::Body;
+                Ok(true)
+            }
+            State::Body => match memmem::find(&self.buffer, &self.delimiter) {
+                Some(i) => {
+                    let value: Vec<u8> = self.buffer.drain(..i + self.delimiter.len()).collect();
+                    self.write(&value[..i])?;
+                    self.finish_field()?;
+                    self.state = State::Boundary;
+                    Ok(true)
+                }
+                None => {
+                    // the end could be the start of a delimiter
+                    let keep = self.delimiter.len() - 1;
+                    if self.buffer.len() > keep {
+                        let value: Vec<u8> =
+                            self.buffer.drain(..self.buffer.len() - keep).collect();
+                        self.write(&value)?;
+                    }
+                    Ok(false)
+                }
+            },
+            State::Done => Ok(false),
+        }
+    }
+
+    /// Field from the headers of a part, CRLF terminated lines
+    /// files of a multipart/mixed part take its name and don't need a Content-Disposition
+    fn field(&self, head: &[u8]) -> Result<Field, Error> {
+        let mut headers = HeaderMap::new();
+        for line in head.split(|b| *b == b'\n').filter(|l| !l.is_empty()) {
+            let line = line.strip_suffix(b"\r").unwrap_or(line);
+            let header =
+                Header::try_from(line).map_err(|_| Error::Malformed("invalid part header"))?;
+            headers.append(header);
+        }
+        let disposition = match headers.get_joined(ContentDisposition::NAME) {
+            Some(value) => Some(
+                ContentDisposition::decode(&value)
+                    .map_err(|_| Error::Malformed("invalid Content-Disposition"))?,
+            ),
+            None if self.parent.is_some() => None,
+            None => return Err(Error::Malformed("missing Content-Disposition")),
+        };
+        let name = disposition
+            .as_ref()
+            .and_then(|d| d.name())
+            .or(self.parent.as_deref())
+            .ok_or(Error::Malformed("missing field name"))?;
+        let content_type = headers.get_joined("content-type");
+        let mut mixed = None;
+        // only one level of nesting is allowed
+        if let (Some(value), None) = (&content_type, &self.parent) {
+            let parsed = ContentType::decode(value)
+                .map_err(|_| Error::Malformed("invalid part Content-Type"))?;
+            if parsed.essence() == "multipart/mixed" {
+                let boundary = parsed
+                    .boundary()
+                    .ok_or(Error::Malformed("missing multipart/mixed boundary"))?;
+                let parser =
+                    Parser::with_parent(boundary, self.config.clone(), Some(name.to_string()));
+                mixed = Some(Box::new(parser));
+            }
+        }
+        Ok(Field {
+            name: name.to_string(),
+            file_name: disposition.as_ref().and_then(|d| d.filename()),
+            content_type,
+            headers,
+            value: vec![],
+            spool: None,
+            mixed,
+            len: 0,
+        })
+    }
+
+    fn write(&mut self, bytes: &[u8]) -> Result<(), Error> {
+        let Some(field) = self.field.as_mut() else {
+            return Ok(());
+        };
+        if let Some(mixed) = field.mixed.as_mut() {
+            // limits apply to each file
+            return mixed.feed(bytes);
+        }
+        field.len += bytes.len();
+        if field.len > self.config.max_field_size {
+            return Err(Error::FieldTooLarge);
+        }
+        let spool = field.file_name.is_some() && field.len > self.config.spool_threshold;
+        if spool && field.spool.is_none() {
+            let (spooled, mut file) = SpooledFile::create(&self.config.temp_dir)?;
+            file.write_all(&field.value)?;
+            field.value = vec![];
+            field.spool = Some((spooled, file));
+        }
+        match field.spool.as_mut() {
+            Some((_, file)) => file.write_all(bytes)?,
+            None => field.value.extend_from_slice(bytes),
+        }
+        Ok(())
+    }
+
+    fn finish_field(&mut self) -> Result<(), Error> {
+        let Some(field) = self.field.take() else {
+            return Ok(());
+        };
+        if let Some(mut mixed) = field.mixed {
+            self.entries.extend(mixed.finish()?);
+            return Ok(());
+        }
+        let data = match field.spool {
+            Some((mut spooled, mut file)) => {
+                file.flush()?;
+                spooled.len = field.len as u64;
+                FieldData::Spooled(Arc::new(spooled))
+            }
+            None => FieldData::Memory(field.value),
+        };
+        self.entries.push(MultiPartFormEntry::new(
+            field.name,
+            field.file_name,
+            field.content_type,
+            field.headers,
+            data,
+        ));
+        Ok(())
+    }
+}
+
+/// Parsed multipart/form-data body
+/// keeps every field in the order they were sent, so repeated names (eg. <input multiple>)
+/// are not lost
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub struct MultipartForm {
+    entries: Vec<MultiPartFormEntry>,
+}
+
+impl MultipartForm {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn push(&mut self, entry: MultiPartFormEntry) {
+        self.entries.push(entry);
+    }
+
+    /// First field named name
+    pub fn get(&self, name: &str) -> Option<&MultiPartFormEntry> {
+        self.entries.iter().find(|entry| entry.name() == name)
+    }
+
+    /// Every field named name in the order they were sent
+    pub fn get_all<'a>(
+        &'a self,
+        name: &'a str,
+    ) -> impl Iterator<Item = &'a MultiPartFormEntry> + 'a {
+        self.entries
+            .iter()
+            .filter(move |entry| entry.name() == name)
+    }
+
+    pub fn contains_key(&self, name: &str) -> bool {
+        self.get(name).is_some()
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &MultiPartFormEntry> {
+        self.entries.iter()
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+}
+
+impl<'a> IntoIterator for &'a MultipartForm {
+    type Item = &'a MultiPartFormEntry;
+    type IntoIter = std::slice::Iter<'a, MultiPartFormEntry>;
+    fn into_iter(self) -> Self::IntoIter {
+        self.entries.iter()
+    }
+}
+
+impl IntoIterator for MultipartForm {
+    type Item = MultiPartFormEntry;
+    type IntoIter = std::vec::IntoIter<MultiPartFormEntry>;
+    fn into_iter(self) -> Self::IntoIter {
+        self.entries.into_iter()
+    }
+}
+
+impl FromIterator<MultiPartFormEntry> for MultipartForm {
+    fn from_iter<I: IntoIterator<Item = MultiPartFormEntry>>(iter: I) -> Self {
+        MultipartForm {
+            entries: iter.into_iter().collect(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const BODY: &[u8] = b"preamble\r\n\
+        --boundary\r\n\
+        Content-Disposition: form-data; name=\"field1\"\r\n\
+        \r\n\
+        value1\r\n\
+        --boundary  \r\n\
+        Content-Disposition: form-data; name=\"file\"; filename=\"a.txt\"\r\n\
+        Content-Type: text/plain\r\n\
+        \r\n\
+        0123456789abcdef\r\n\
+        --boundary--\r\n\
+        epilogue";
+
+    fn parse(config: MultipartConfig, chunk: usize) -> Result<Vec<MultiPartFormEntry>, Error> {
+        let mut parser = Parser::new("boundary", config);
+        for bytes in BODY.chunks(chunk) {
+            parser.feed(bytes)?;
+        }
+        parser.finish()
+    }
+
+    #[test]
+    fn chunked_feeding() {
+        for chunk in [1, 3, 7, BODY.len()] {
+            let entries = parse(MultipartConfig::new(), chunk).unwrap();
+            assert_eq!(2, entries.len());
+            assert_eq!("field1", entries[0].name());
+            assert_eq!(Some(&b"value1"[..]), entries[0].value());
+            assert_eq!(Some(&"a.txt".to_string()), entries[1].file_name());
+            assert_eq!(Some(&"text/plain".to_string()), entries[1].content_type());
+            assert_eq!(Some(&b"0123456789abcdef"[..]), entries[1].value());
+        }
+    }
+
+    #[test]
+    fn spooling() {
+        let mut config = MultipartConfig::new();
+        config.set_spool_threshold(4);
+        let entries = parse(config, 5).unwrap();
+        // only files are spooled
+        assert_eq!(Some(&b"value1"[..]), entries[0].value());
+        assert!(entries[0].spooled().is_none());
+        assert_eq!(None, entries[1].value());
+        let FieldData::Spooled(file) = entries[1].data().clone() else {
+            panic!("file wasn't spooled");
+        };
+        assert_eq!(Some(&*file), entries[1].spooled());
+        assert_eq!(16, file.len());
+        let path = file.path().to_path_buf();
+        assert_eq!(b"0123456789abcdef".to_vec(), std::fs::read(&path).unwrap());
+        drop(entries);
+        drop(file);
+        assert!(!path.exists());
+    }
+
+    #[test]
+    fn limits() {
+        let mut config = MultipartConfig::new();
+        config.set_max_field_size(10);
+        assert!(matches!(parse(config, 4), Err(Error::FieldTooLarge)));
+        let mut config = MultipartConfig::new();
+        config.set_max_total_size(64);
+        assert!(matches!(parse(config, 4), Err(Error::TooLarge)));
+        // an unset total follows the route's body limit, a set one is kept
+        let config = MultipartConfig::new().with_body_limit(Some(64));
+        assert!(matches!(parse(config, 4), Err(Error::TooLarge)));
+        let mut config = MultipartConfig::new();
+        config.set_max_total_size(1024);
+        assert_eq!(
+            Some(1024),
+            config.with_body_limit(Some(64)).max_total_size()
+        );
+    }
+
+    #[test]
+    fn default_limits() {
+        let mut parser = Parser::new("boundary", MultipartConfig::new());
+        parser
+            .feed(b"--boundary\r\nContent-Disposition: form-data; name=\"big\"\r\n\r\n")
+            .unwrap();
+        let chunk = vec![b'x'; 64 * 1024];
+        let fed = (0..).map(|_| parser.feed(&chunk)).find(Result::is_err);
+        assert!(matches!(fed, Some(Err(Error::FieldTooLarge))));
+
+        // the preamble counts towards the total too
+        let mut parser = Parser::new("boundary", MultipartConfig::new());
+        let fed = (0..).map(|_| parser.feed(&chunk)).find(Result::is_err);
+        assert!(matches!(fed, Some(Err(Error::TooLarge))));
+        assert!(parser.total <= DEFAULT_MAX_TOTAL_SIZE + chunk.len());
+    }
+
+    #[test]
+    fn malformed() {
+        let malformed = |body: &[u8]| {
+            let mut parser = Parser::new("boundary", MultipartConfig::new());
+            parser.feed(body).and_then(|_| parser.finish()).err()
+        };
+        // cut off
+        assert!(matches!(
+            malformed(&BODY[..BODY.len() - 30]),
+            Some(Error::Malformed(_))
+        ));
+        assert!(matches!(malformed(b""), Some(Error::Malformed(_))));
+        assert!(matches!(
+            malformed(b"--boundary\r\nContent-Type: text/plain\r\n\r\nx\r\n--boundary--"),
+            Some(Error::Malformed("missing Content-Disposition"))
+        ));
+        assert!(matches!(
+            malformed(b"--boundaryx\r\n"),
+            Some(Error::Malformed("invalid boundary line"))
+        ));
+    }
+}
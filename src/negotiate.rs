@@ -0,0 +1,159 @@
+//! Content negotiation, picking a representation with the Accept header
+use crate::{
+    headers::Accept,
+    http::{MimeType, StatusCode},
+    request::Request,
+    response::{IntoResponse, Response},
+};
+
+/// Best of available for accept, ties go to the earlier type
+/// a missing Accept header accepts anything so the first type is used
+pub fn preferred<'a>(accept: Option<&Accept>, available: &'a [MimeType]) -> Option<&'a MimeType> {
+    let Some(accept) = accept.filter(|a| !a.0.is_empty()) else {
+        return available.first();
+    };
+    let mut best: Option<(&MimeType, u16)> = None;
+    for mime in available {
+        let quality = accept.quality(mime.media_type());
+        if quality > 0 && best.map(|(_, q)| quality > q).unwrap_or(true) {
+            best = Some((mime, quality));
+        }
+    }
+    best.map(|(mime, _)| mime)
+}
+
+type Render = Box<dyn FnOnce() -> Response + Send>;
+
+/// Responder with one representation per type, only the one the client prefers is rendered
+/// adds Vary: Accept and sends 406 Not Acceptable if none of them are accepted
+/// ```ignore
+/// Negotiate::new(&request)
+///     .with(MimeType::Json, move || serde_json::to_string(&user).unwrap())
+///     .with(MimeType::HTML, move || format!("<h1>{}</h1>", name))
+/// ```
+pub struct Negotiate {
+    accept: Option<Accept>,
+    representations: Vec<(MimeType, Render)>,
+}
+
+impl Negotiate {
+    pub fn new(request: &Request) -> Self {
+        Negotiate {
+            accept: request.typed_header::<Accept>(),
+            representations: vec![],
+        }
+    }
+
+    /// Add a representation, render is only called if mime is picked
+    /// successful responses are sent as mime, errors keep their own type
+    pub fn with<F, R>(mut self, mime: MimeType, render: F) -> Self
+    where
+        F: FnOnce() -> R + Send + 'static,
+        R: IntoResponse,
+    {
+        self.representations
+            .push((mime, Box::new(move || render().into_response())));
+        self
+    }
+
+    /// Types in the order they were added
+    pub fn available(&self) -> Vec<MimeType> {
+        self.representations
+            .iter()
+            .map(|(mime, _)| mime.clone())
+            .collect()
+    }
+}
+
+impl From<Negotiate> for Response {
+    fn from(negotiate: Negotiate) -> Self {
+        let available = negotiate.available();
+        let chosen = preferred(negotiate.accept.as_ref(), &available);
+        let mut response = match chosen.and_then(|c| available.iter().position(|m| m == c)) {
+            Some(index) => {
+                let (mime, render) = negotiate
+                    .representations
+                    .into_iter()
+                    .nth(index)
+                    .expect("index from available");
+                let mut response = render();
+                if response.status().is_success() {
+                    response.set_mime(mime);
+                }
+                response
+            }
+            None => {
+                let types: Vec<&str> = available.iter().map(|m| m.media_type()).collect();
+                let message = format!("Not Acceptable, available: {}", types.join(", "));
+                Response::error(StatusCode::NOT_ACCEPTABLE, message.into())
+            }
+        };
+        response.add_vary("Accept");
+        response
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn request(accept: Option<&str>) -> Request {
+        let accept = accept
+            .map(|a| format!("Accept: {a}\r\n"))
+            .unwrap_or_default();
+        Request::from_string(format!("GET / HTTP/1.1\r\nHost: test\r\n{accept}\r\n")).unwrap()
+    }
+
+    #[test]
+    fn preferred_types() {
+        let csv = MimeType::from_extension("csv");
+        let available = [MimeType::Json, MimeType::HTML, csv.clone()];
+        let check = |accept| request(accept).preferred(&available).cloned();
+        assert_eq!(Some(MimeType::Json), check(None));
+        assert_eq!(Some(MimeType::HTML), check(Some("text/html")));
+        assert_eq!(
+            Some(MimeType::HTML),
+            check(Some("text/*, application/json;q=0.5"))
+        );
+        assert_eq!(Some(csv.clone()), check(Some("text/html;q=0.2, text/csv")));
+        assert_eq!(Some(MimeType::Json), check(Some("*/*")));
+        assert_eq!(
+            Some(MimeType::HTML),
+            check(Some("*/*;q=0.1, text/html;q=0.8"))
+        );
+        assert_eq!(None, check(Some("image/png")));
+        assert_eq!(None, check(Some("*/*, application/json;q=0, text/*;q=0")));
+    }
+
+    #[test]
+    fn negotiate_responses() {
+        let negotiate = |accept| {
+            Negotiate::new(&request(accept))
+                .with(MimeType::Json, || "{\"a\":1}")
+                .with(MimeType::HTML, || "<p>1</p>")
+                .with(MimeType::PlainText, || -> Response {
+                    panic!("not picked so never rendered")
+                })
+                .into_response()
+        };
+        let response = negotiate(Some("text/html, application/json;q=0.9"));
+        assert_eq!(MimeType::HTML, response.mime());
+        assert_eq!(b"<p>1</p>", response.body());
+        assert_eq!(Some("Accept"), response.headers().get("vary"));
+
+        let response = negotiate(Some("application/*"));
+        assert_eq!(MimeType::Json, response.mime());
+        assert_eq!(b"{\"a\":1}", response.body());
+
+        let response = negotiate(Some("image/*"));
+        assert_eq!(StatusCode::NOT_ACCEPTABLE, response.status());
+        assert_eq!(Some("Accept"), response.headers().get("vary"));
+
+        // errors from a representation keep their type
+        let response = Negotiate::new(&request(None))
+            .with(MimeType::Json, || StatusCode::NOT_FOUND)
+            .into_response();
+        assert_eq!(StatusCode::NOT_FOUND, response.status());
+        assert_eq!(MimeType::HTML, response.mime());
+    }
+}
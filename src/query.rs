@@ -0,0 +1,611 @@
+use crate::utils;
+use core::fmt;
+use memchr::{memchr, memchr_iter};
+use serde::de::{
+    self, DeserializeOwned, DeserializeSeed, EnumAccess, IntoDeserializer, MapAccess, SeqAccess,
+    VariantAccess, Visitor,
+};
+
+/// Decoded query string or urlencoded form
+/// keeps every key value pair in the order they were sent, so repeated keys are not lost
+/// a key without '=' (eg. ?debug) is stored with an empty value
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub struct QueryMap {
+    entries: Vec<(String, String)>,
+}
+
+/// Error deserializing a [`QueryMap`] into a struct
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Error(String);
+
+/// Keys like a[b][c] are turned into a tree before deserializing
+#[derive(Debug, Clone, PartialEq)]
+enum Node {
+    Leaf(String),
+    Seq(Vec<Node>),
+    Map(Vec<(String, Node)>),
+}
+
+impl QueryMap {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Parse raw query string bytes (without the leading '?')
+    /// errors if a key or value has an invalid percent escape or isn't utf-8
+    pub fn parse(query: &[u8]) -> Result<Self, anyhow::Error> {
+        let mut entries = vec![];
+        let mut start = 0;
+        let ends = memchr_iter(b'&', query).chain(std::iter::once(query.len()));
+        for end in ends {
+            let pair = &query[start..end];
+            start = end + 1;
+            if pair.is_empty() {
+                continue;
+            }
+            let (key, value) = match memchr(b'=', pair) {
+                Some(equal_i) => (&pair[..equal_i], &pair[equal_i + 1..]),
+                None => (pair, &b""[..]),
+            };
+            let key = utils::decode_form_component(key)
+                .ok_or_else(|| anyhow::Error::msg("Invalid encoding in key"))?;
+            let value = utils::decode_form_component(value)
+                .ok_or_else(|| anyhow::Error::msg("Invalid encoding in value"))?;
+            entries.push((key, value));
+        }
+        Ok(QueryMap { entries })
+    }
+
+    pub fn push(&mut self, key: &str, value: &str) {
+        self.entries.push((key.to_string(), value.to_string()));
+    }
+
+    /// First value for key
+    pub fn get(&self, key: &str) -> Option<&str> {
+        self.entries
+            .iter()
+            .find(|(k, _)| k == key)
+            .map(|(_, v)| v.as_str())
+    }
+
+    /// Every value for key in the order they were sent
+    pub fn get_all<'a>(&'a self, key: &'a str) -> impl Iterator<Item = &'a str> + 'a {
+        self.entries
+            .iter()
+            .filter(move |(k, _)| k == key)
+            .map(|(_, v)| v.as_str())
+    }
+
+    /// True for keys sent with or without a value, use for flags like ?debug
+    pub fn contains_key(&self, key: &str) -> bool {
+        self.entries.iter().any(|(k, _)| k == key)
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (&str, &str)> {
+        self.entries.iter().map(|(k, v)| (k.as_str(), v.as_str()))
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    /// Deserialize into T
+    /// repeated keys and keys ending in [] fill Vec fields, a[b]=c fills nested structs and maps,
+    /// a[0]=x&a[1]=y fills Vecs in index order. A flag without a value is true for bool fields
+    pub fn deserialize<T: DeserializeOwned>(&self) -> Result<T, Error> {
+        let mut root = vec![];
+        for (key, value) in &self.entries {
+            insert(&mut root, &split_key(key), value.clone());
+        }
+        T::deserialize(Node::Map(root))
+    }
+}
+
+impl<'a> IntoIterator for &'a QueryMap {
+    type Item = &'a (String, String);
+    type IntoIter = std::slice::Iter<'a, (String, String)>;
+    fn into_iter(self) -> Self::IntoIter {
+        self.entries.iter()
+    }
+}
+
+impl IntoIterator for QueryMap {
+    type Item = (String, String);
+    type IntoIter = std::vec::IntoIter<(String, String)>;
+    fn into_iter(self) -> Self::IntoIter {
+        self.entries.into_iter()
+    }
+}
+
+impl FromIterator<(String, String)> for QueryMap {
+    fn from_iter<I: IntoIterator<Item = (String, String)>>(iter: I) -> Self {
+        QueryMap {
+            entries: iter.into_iter().collect(),
+        }
+    }
+}
+
+/// Most [x] levels a key is split into, building and deserializing the tree recurses per level
+const MAX_DEPTH: usize = 32;
+
+/// a[b][] -> ["a", "b", ""], keys with unbalanced brackets are used as is
+/// past [`MAX_DEPTH`] levels the rest of the key is kept as one literal part, eg. "x][y"
+fn split_key(key: &str) -> Vec<&str> {
+    let Some(open) = key.find('[') else {
+        return vec![key];
+    };
+    if open == 0 || !key.ends_with(']') {
+        return vec![key];
+    }
+    let mut parts = vec![&key[..open]];
+    let mut rest = &key[open..];
+    while let Some(stripped) = rest.strip_prefix('[') {
+        if parts.len() > MAX_DEPTH {
+            // rest is the end of key so it ends with ']'
+            parts.push(&stripped[..stripped.len() - 1]);
+            return parts;
+        }
+        match stripped.find(']') {
+            Some(close) => {
+                parts.push(&stripped[..close]);
+                rest = &stripped[close + 1..];
+            }
+            None => return vec![key],
+        }
+    }
+    if rest.is_empty() {
+        parts
+    } else {
+        vec![key]
+    }
+}
+
+fn insert(map: &mut Vec<(String, Node)>, path: &[&str], value: String) {
+    let (first, rest) = match path.split_first() {
+        Some(split) => split,
+        None => return,
+    };
+    let index = match map.iter().position(|(k, _)| k == first) {
+        Some(index) => index,
+        None if rest.is_empty() => {
+            map.push((first.to_string(), Node::Leaf(value)));
+            return;
+        }
+        None => {
+            let node = match rest[0] {
+                "" => Node::Seq(vec![]),
+                _ => Node::Map(vec![]),
+            };
+            map.push((first.to_string(), node));
+            map.len() - 1
+        }
+    };
+    let node = &mut map[index].1;
+    match (node, rest) {
+        // a[]=x, anything after [] is ignored
+        (Node::Seq(seq), ["", ..]) => seq.push(Node::Leaf(value)),
+        (Node::Map(inner), [_, ..]) => insert(inner, rest, value),
+        // repeated plain key, a=x&a=y
+        (Node::Seq(seq), []) => seq.push(Node::Leaf(value)),
+        (node @ Node::Leaf(_), []) | (node @ Node::Leaf(_), [""]) => {
+            let old = std::mem::replace(node, Node::Seq(vec![]));
+            *node = Node::Seq(vec![old, Node::Leaf(value)]);
+        }
+        _ => {
+            tracing::debug!("conflicting query key {first} ignored");
+        }
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+impl std::error::Error for Error {}
+
+impl de::Error for Error {
+    fn custom<T: fmt::Display>(msg: T) -> Self {
+        Error(msg.to_string())
+    }
+}
+
+impl Node {
+    /// Single string for scalar fields, the last value wins when a key is repeated
+    fn into_leaf(self) -> Result<String, Error> {
+        match self {
+            Node::Leaf(s) => Ok(s),
+            Node::Seq(mut seq) => match seq.pop() {
+                Some(node) => node.into_leaf(),
+                None => Err(de::Error::custom("expected a value, found empty list")),
+            },
+            Node::Map(_) => Err(de::Error::custom("expected a value, found nested keys")),
+        }
+    }
+
+    fn into_seq(self) -> Vec<Node> {
+        match self {
+            Node::Seq(seq) => seq,
+            Node::Leaf(s) => vec![Node::Leaf(s)],
+            // a[1]=y&a[0]=x
+            Node::Map(mut map) => {
+                if map.iter().all(|(k, _)| k.parse::<usize>().is_ok()) {
+                    map.sort_by_key(|(k, _)| k.parse::<usize>().unwrap_or_default());
+                    map.into_iter().map(|(_, v)| v).collect()
+                } else {
+                    vec![Node::Map(map)]
+                }
+            }
+        }
+    }
+
+    fn parse<T: std::str::FromStr>(self, expected: &str) -> Result<T, Error> {
+        let leaf = self.into_leaf()?;
+        leaf.trim()
+            .parse()
+            .map_err(|_| de::Error::custom(format!("invalid {expected}: {leaf:?}")))
+    }
+}
+
+macro_rules! deserialize_parsed {
+    ($($method:ident => $visit:ident: $ty:ty),* $(,)?) => {
+        $(
+            fn $method<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Error> {
+                visitor.$visit(self.parse::<$ty>(stringify!($ty))?)
+            }
+        )*
+    };
+}
+
+impl<'de> de::Deserializer<'de> for Node {
+    type Error = Error;
+
+    fn deserialize_any<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Error> {
+        match self {
+            Node::Leaf(s) => visitor.visit_string(s),
+            Node::Seq(seq) => visitor.visit_seq(SeqDeserializer(seq.into_iter())),
+            Node::Map(map) => visitor.visit_map(MapDeserializer {
+                iter: map.into_iter(),
+                value: None,
+            }),
+        }
+    }
+
+    fn deserialize_bool<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Error> {
+        let leaf = self.into_leaf()?;
+        match leaf.to_lowercase().as_str() {
+            "" | "true" | "on" | "yes" | "1" => visitor.visit_bool(true),
+            "false" | "off" | "no" | "0" => visitor.visit_bool(false),
+            _ => Err(de::Error::custom(format!("invalid bool: {leaf:?}"))),
+        }
+    }
+
+    deserialize_parsed! {
+        deserialize_i8 => visit_i8: i8,
+        deserialize_i16 => visit_i16: i16,
+        deserialize_i32 => visit_i32: i32,
+        deserialize_i64 => visit_i64: i64,
+        deserialize_u8 => visit_u8: u8,
+        deserialize_u16 => visit_u16: u16,
+        deserialize_u32 => visit_u32: u32,
+        deserialize_u64 => visit_u64: u64,
+        deserialize_f32 => visit_f32: f32,
+        deserialize_f64 => visit_f64: f64,
+        deserialize_char => visit_char: char,
+    }
+
+    fn deserialize_str<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Error> {
+        visitor.visit_string(self.into_leaf()?)
+    }
+
+    fn deserialize_string<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Error> {
+        visitor.visit_string(self.into_leaf()?)
+    }
+
+    fn deserialize_bytes<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Error> {
+        visitor.visit_byte_buf(self.into_leaf()?.into_bytes())
+    }
+
+    fn deserialize_byte_buf<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Error> {
+        visitor.visit_byte_buf(self.into_leaf()?.into_bytes())
+    }
+
+    fn deserialize_option<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Error> {
+        visitor.visit_some(self)
+    }
+
+    fn deserialize_unit<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Error> {
+        visitor.visit_unit()
+    }
+
+    fn deserialize_unit_struct<V: Visitor<'de>>(
+        self,
+        _name: &'static str,
+        visitor: V,
+    ) -> Result<V::Value, Error> {
+        visitor.visit_unit()
+    }
+
+    fn deserialize_newtype_struct<V: Visitor<'de>>(
+        self,
+        _name: &'static str,
+        visitor: V,
+    ) -> Result<V::Value, Error> {
+        visitor.visit_newtype_struct(self)
+    }
+
+    fn deserialize_seq<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Error> {
+        visitor.visit_seq(SeqDeserializer(self.into_seq().into_iter()))
+    }
+
+    fn deserialize_tuple<V: Visitor<'de>>(
+        self,
+        _len: usize,
+        visitor: V,
+    ) -> Result<V::Value, Error> {
+        self.deserialize_seq(visitor)
+    }
+
+    fn deserialize_tuple_struct<V: Visitor<'de>>(
+        self,
+        _name: &'static str,
+        _len: usize,
+        visitor: V,
+    ) -> Result<V::Value, Error> {
+        self.deserialize_seq(visitor)
+    }
+
+    fn deserialize_map<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Error> {
+        match self {
+            Node::Map(map) => visitor.visit_map(MapDeserializer {
+                iter: map.into_iter(),
+                value: None,
+            }),
+            _ => Err(de::Error::custom("expected nested keys, eg. a[b]=c")),
+        }
+    }
+
+    fn deserialize_struct<V: Visitor<'de>>(
+        self,
+        _name: &'static str,
+        _fields: &'static [&'static str],
+        visitor: V,
+    ) -> Result<V::Value, Error> {
+        self.deserialize_map(visitor)
+    }
+
+    fn deserialize_enum<V: Visitor<'de>>(
+        self,
+        _name: &'static str,
+        _variants: &'static [&'static str],
+        visitor: V,
+    ) -> Result<V::Value, Error> {
+        visitor.visit_enum(UnitVariant(self.into_leaf()?))
+    }
+
+    fn deserialize_identifier<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Error> {
+        self.deserialize_string(visitor)
+    }
+
+    fn deserialize_ignored_any<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Error> {
+        visitor.visit_unit()
+    }
+}
+
+impl<'de> IntoDeserializer<'de, Error> for Node {
+    type Deserializer = Self;
+    fn into_deserializer(self) -> Self::Deserializer {
+        self
+    }
+}
+
+struct SeqDeserializer(std::vec::IntoIter<Node>);
+
+impl<'de> SeqAccess<'de> for SeqDeserializer {
+    type Error = Error;
+    fn next_element_seed<T: DeserializeSeed<'de>>(
+        &mut self,
+        seed: T,
+    ) -> Result<Option<T::Value>, Error> {
+        match self.0.next() {
+            Some(node) => seed.deserialize(node).map(Some),
+            None => Ok(None),
+        }
+    }
+}
+
+struct MapDeserializer {
+    iter: std::vec::IntoIter<(String, Node)>,
+    value: Option<Node>,
+}
+
+impl<'de> MapAccess<'de> for MapDeserializer {
+    type Error = Error;
+    fn next_key_seed<K: DeserializeSeed<'de>>(
+        &mut self,
+        seed: K,
+    ) -> Result<Option<K::Value>, Error> {
+        match self.iter.next() {
+            Some((key, value)) => {
+                self.value = Some(value);
+                seed.deserialize(Node::Leaf(key)).map(Some)
+            }
+            None => Ok(None),
+        }
+    }
+
+    fn next_value_seed<V: DeserializeSeed<'de>>(&mut self, seed: V) -> Result<V::Value, Error> {
+        match self.value.take() {
+            Some(value) => seed.deserialize(value),
+            None => Err(de::Error::custom("value requested before key")),
+        }
+    }
+}
+
+struct UnitVariant(String);
+
+impl<'de> EnumAccess<'de> for UnitVariant {
+    type Error = Error;
+    type Variant = Self;
+    fn variant_seed<V: DeserializeSeed<'de>>(self, seed: V) -> Result<(V::Value, Self), Error> {
+        let variant = seed.deserialize(Node::Leaf(self.0.clone()))?;
+        Ok((variant, self))
+    }
+}
+
+impl<'de> VariantAccess<'de> for UnitVariant {
+    type Error = Error;
+    fn unit_variant(self) -> Result<(), Error> {
+        Ok(())
+    }
+
+    fn newtype_variant_seed<T: DeserializeSeed<'de>>(self, _seed: T) -> Result<T::Value, Error> {
+        Err(de::Error::custom("only unit enum variants are supported"))
+    }
+
+    fn tuple_variant<V: Visitor<'de>>(self, _len: usize, _visitor: V) -> Result<V::Value, Error> {
+        Err(de::Error::custom("only unit enum variants are supported"))
+    }
+
+    fn struct_variant<V: Visitor<'de>>(
+        self,
+        _fields: &'static [&'static str],
+        _visitor: V,
+    ) -> Result<V::Value, Error> {
+        Err(de::Error::custom("only unit enum variants are supported"))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde::Deserialize;
+    use std::collections::HashMap;
+
+    #[test]
+    fn repeated_and_flags() {
+        let query = QueryMap::parse(b"tag=a&debug&tag=b&q=ice+cream&&empty=").unwrap();
+        assert_eq!(Some("a"), query.get("tag"));
+        assert_eq!(vec!["a", "b"], query.get_all("tag").collect::<Vec<_>>());
+        assert!(query.contains_key("debug"));
+        assert_eq!(Some(""), query.get("debug"));
+        assert_eq!(Some("ice cream"), query.get("q"));
+        assert_eq!(Some(""), query.get("empty"));
+        assert_eq!(None, query.get("missing"));
+        let keys: Vec<_> = query.iter().map(|(k, _)| k).collect();
+        assert_eq!(vec!["tag", "debug", "tag", "q", "empty"], keys);
+        assert!(QueryMap::parse(b"a=%zz").is_err());
+    }
+
+    #[derive(Debug, Deserialize, PartialEq)]
+    enum Order {
+        #[serde(rename = "asc")]
+        Asc,
+        #[serde(rename = "desc")]
+        Desc,
+    }
+
+    #[derive(Debug, Deserialize, PartialEq)]
+    struct Filter {
+        min: u32,
+        max: Option<u32>,
+    }
+
+    #[derive(Debug, Deserialize, PartialEq)]
+    struct Search {
+        q: String,
+        page: u32,
+        tag: Vec<String>,
+        ids: Vec<u64>,
+        debug: bool,
+        #[serde(default)]
+        verbose: bool,
+        order: Order,
+        filter: Filter,
+        extra: HashMap<String, String>,
+        missing: Option<String>,
+    }
+
+    #[test]
+    fn deserialize_struct() {
+        let query = QueryMap::parse(
+            b"q=rust+web&page=2&tag=a&tag=b&ids[]=3&ids[]=4&debug&order=desc\
+            &filter[min]=1&filter[max]=10&extra[color]=red&extra[size]=xl",
+        )
+        .unwrap();
+        let search: Search = query.deserialize().expect("deserialize");
+        assert_eq!(
+            Search {
+                q: "rust web".to_string(),
+                page: 2,
+                tag: vec!["a".to_string(), "b".to_string()],
+                ids: vec![3, 4],
+                debug: true,
+                verbose: false,
+                order: Order::Desc,
+                filter: Filter {
+                    min: 1,
+                    max: Some(10)
+                },
+                extra: HashMap::from([
+                    ("color".to_string(), "red".to_string()),
+                    ("size".to_string(), "xl".to_string())
+                ]),
+                missing: None,
+            },
+            search
+        );
+    }
+
+    #[derive(Debug, Deserialize, PartialEq)]
+    struct Lists {
+        single: Vec<String>,
+        indexed: Vec<String>,
+    }
+
+    #[test]
+    fn deserialize_lists() {
+        let query = QueryMap::parse(b"single=one&indexed[1]=y&indexed[0]=x").unwrap();
+        let lists: Lists = query.deserialize().unwrap();
+        assert_eq!(vec!["one".to_string()], lists.single);
+        assert_eq!(vec!["x".to_string(), "y".to_string()], lists.indexed);
+    }
+
+    #[test]
+    fn deep_keys() {
+        let levels = 100_000;
+        let query = format!("a{}=1", "[x]".repeat(levels));
+        let key = query.strip_suffix("=1").unwrap();
+        let parts = split_key(key);
+        assert_eq!(MAX_DEPTH + 2, parts.len());
+        assert_eq!(3 * (levels - MAX_DEPTH) - 2, parts[MAX_DEPTH + 1].len());
+
+        // deserializing doesn't recurse past the cap either
+        let query = QueryMap::parse(query.as_bytes()).unwrap();
+        let mut value: serde_json::Value = query.deserialize().unwrap();
+        let mut depth = 0;
+        while let serde_json::Value::Object(mut map) = value {
+            assert_eq!(1, map.len());
+            value = map.values_mut().next().unwrap().take();
+            depth += 1;
+        }
+        assert_eq!(MAX_DEPTH + 2, depth);
+        assert_eq!(serde_json::Value::String("1".into()), value);
+    }
+
+    #[test]
+    fn deserialize_errors() {
+        let query = QueryMap::parse(b"min=abc").unwrap();
+        let error = query.deserialize::<Filter>().unwrap_err();
+        assert!(error.to_string().contains("u32"), "{error}");
+
+        let query = QueryMap::parse(b"max=1").unwrap();
+        let error = query.deserialize::<Filter>().unwrap_err();
+        assert!(error.to_string().contains("min"), "{error}");
+    }
+}
@@ -0,0 +1,219 @@
+//! Byte range requests (RFC 7233) for files served from disk
+use crate::{
+    headers::{ContentRange, ContentType, ETag, IfRange, MediaType, Range, TypedHeader},
+    http::{Method, MimeType, StatusCode},
+    request::Request,
+    response::Response,
+};
+use rand::{distributions::Alphanumeric, thread_rng, Rng};
+use std::{io::SeekFrom, path::Path, time::SystemTime};
+use tokio::io::{AsyncReadExt, AsyncSeekExt};
+
+/// More ranges than this and the Range header is ignored, stops clients asking for a file one
+/// byte at a time
+const MAX_RANGES: usize = 32;
+
+/// What part of a representation to send
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub enum RangeOutcome {
+    /// no usable Range header, send everything with 200
+    Full,
+    /// first and last byte (inclusive) of each range to send with 206, sorted and merged
+    Partial(Vec<(u64, u64)>),
+    /// 416
+    Unsatisfiable,
+}
+
+/// Decide what to send for a representation of len bytes
+pub fn evaluate(
+    request: &Request,
+    len: u64,
+    etag: Option<&ETag>,
+    last_modified: Option<SystemTime>,
+) -> RangeOutcome {
+    if request.method() != &Method::GET {
+        return RangeOutcome::Full;
+    }
+    let Some(range) = request.typed_header::<Range>() else {
+        return RangeOutcome::Full;
+    };
+    if let Some(if_range) = request.typed_header::<IfRange>() {
+        if !if_range.matches(etag, last_modified) {
+            return RangeOutcome::Full;
+        }
+    }
+    if range.ranges().len() > MAX_RANGES {
+        tracing::debug!("ignoring range header with {} ranges", range.ranges().len());
+        return RangeOutcome::Full;
+    }
+    let mut ranges: Vec<(u64, u64)> = range
+        .ranges()
+        .iter()
+        .filter_map(|r| r.resolve(len))
+        .collect();
+    if ranges.is_empty() {
+        return RangeOutcome::Unsatisfiable;
+    }
+    ranges.sort();
+    let mut merged: Vec<(u64, u64)> = Vec::with_capacity(ranges.len());
+    for (first, last) in ranges {
+        match merged.last_mut() {
+            Some(previous) if first <= previous.1.saturating_add(1) => {
+                previous.1 = previous.1.max(last);
+            }
+            _ => merged.push((first, last)),
+        }
+    }
+    if merged == [(0, len - 1)] {
+        return RangeOutcome::Full;
+    }
+    RangeOutcome::Partial(merged)
+}
+
+/// 416 with the length the client should have asked for
+pub fn unsatisfiable(len: u64) -> Response {
+    let mut response = Response::error(
+        StatusCode::RANGE_NOT_SATISFIABLE,
+        "Range Not Satisfiable".into(),
+    );
+    response.set_typed(ContentRange::unsatisfied(len));
+    response
+}
+
+/// 206 with only the requested bytes read from path
+/// a single range is sent as is, multiple ranges as multipart/byteranges
+pub async fn partial_file(
+    path: &Path,
+    ranges: &[(u64, u64)],
+    len: u64,
+    mime: MimeType,
+) -> Result<Response, std::io::Error> {
+    let mut file = tokio::fs::File::open(path).await?;
+    if let [(first, last)] = ranges {
+        let body = read_range(&mut file, *first, *last).await?;
+        let mut response = Response::new(StatusCode::PARTIAL_CONTENT, body, mime);
+        response.set_typed(ContentRange::bytes(*first, *last, len));
+        return Ok(response);
+    }
+
+    let boundary: String = thread_rng()
+        .sample_iter(&Alphanumeric)
+        .take(24)
+        .map(char::from)
+        .collect();
+    let part_type = ContentType::from(&mime).encode();
+    let mut body = vec![];
+    for (first, last) in ranges {
+        let content_range = ContentRange::bytes(*first, *last, len).encode();
+        body.extend_from_slice(
+            format!(
+                "--{boundary}\r\nContent-Type: {part_type}\r\nContent-Range: {content_range}\r\n\r\n"
+            )
+            .as_bytes(),
+        );
+        body.append(&mut read_range(&mut file, *first, *last).await?);
+        body.extend_from_slice(b"\r\n");
+    }
+    body.extend_from_slice(format!("--{boundary}--\r\n").as_bytes());
+    let mut response = Response::new(StatusCode::PARTIAL_CONTENT, body, mime);
+    let media_type = MediaType::new("multipart/byteranges").with_param("boundary", &boundary);
+    response.set_typed(ContentType(media_type));
+    Ok(response)
+}
+
+async fn read_range(
+    file: &mut tokio::fs::File,
+    first: u64,
+    last: u64,
+) -> Result<Vec<u8>, std::io::Error> {
+    file.seek(SeekFrom::Start(first)).await?;
+    let mut buffer = vec![0; (last - first + 1) as usize];
+    file.read_exact(&mut buffer).await?;
+    Ok(buffer)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::TempDir;
+
+    fn request(headers: &str) -> Request {
+        Request::from_string(format!("GET / HTTP/1.1\r\nHost: test\r\n{headers}\r\n"))
+            .expect("Error Parsing")
+    }
+
+    #[test]
+    fn evaluate_ranges() {
+        let check = |headers| evaluate(&request(headers), 100, None, None);
+        assert_eq!(RangeOutcome::Full, check(""));
+        assert_eq!(
+            RangeOutcome::Partial(vec![(0, 9)]),
+            check("Range: bytes=0-9\r\n")
+        );
+        assert_eq!(
+            RangeOutcome::Partial(vec![(90, 99)]),
+            check("Range: bytes=-10\r\n")
+        );
+        assert_eq!(
+            RangeOutcome::Partial(vec![(0, 19), (50, 99)]),
+            check("Range: bytes=50-, 10-19, 0-12\r\n")
+        );
+        assert_eq!(RangeOutcome::Full, check("Range: bytes=0-\r\n"));
+        assert_eq!(
+            RangeOutcome::Unsatisfiable,
+            check("Range: bytes=100-200\r\n")
+        );
+        assert_eq!(RangeOutcome::Full, check("Range: bytes=oops\r\n"));
+
+        let many: Vec<String> = (0..40).map(|i| format!("{}-{}", i * 2, i * 2)).collect();
+        let headers = format!("Range: bytes={}\r\n", many.join(","));
+        assert_eq!(RangeOutcome::Full, check(&headers));
+    }
+
+    #[test]
+    fn if_range() {
+        let etag = ETag::strong("abc");
+        let check = |headers| evaluate(&request(headers), 100, Some(&etag), None);
+        assert_eq!(
+            RangeOutcome::Partial(vec![(0, 9)]),
+            check("Range: bytes=0-9\r\nIf-Range: \"abc\"\r\n")
+        );
+        assert_eq!(
+            RangeOutcome::Full,
+            check("Range: bytes=0-9\r\nIf-Range: \"old\"\r\n")
+        );
+    }
+
+    #[tokio::test]
+    async fn partial_responses() {
+        let dir = TempDir::new("range");
+        let path = dir.join("digits.txt");
+        tokio::fs::write(&path, b"0123456789").await.unwrap();
+
+        let response = partial_file(&path, &[(2, 4)], 10, MimeType::PlainText)
+            .await
+            .unwrap();
+        assert_eq!(StatusCode::PARTIAL_CONTENT, response.status());
+        assert_eq!(
+            Some("bytes 2-4/10"),
+            response.headers().get("content-range")
+        );
+        let buffer = String::from_utf8(response.to_send_buffer()).unwrap();
+        assert!(buffer.ends_with("\r\n\r\n234"), "{buffer}");
+
+        let response = partial_file(&path, &[(0, 1), (8, 9)], 10, MimeType::PlainText)
+            .await
+            .unwrap();
+        let content_type = response.typed_header::<ContentType>().unwrap();
+        assert_eq!("multipart/byteranges", content_type.essence());
+        let boundary = content_type.boundary().unwrap();
+        let buffer = String::from_utf8(response.to_send_buffer()).unwrap();
+        let expected = format!(
+            "--{boundary}\r\nContent-Type: text/plain; charset=utf-8\r\nContent-Range: bytes 0-1/10\r\n\r\n01\r\n\
+            --{boundary}\r\nContent-Type: text/plain; charset=utf-8\r\nContent-Range: bytes 8-9/10\r\n\r\n89\r\n\
+            --{boundary}--\r\n"
+        );
+        assert!(buffer.ends_with(&expected), "{buffer}");
+        assert_eq!(1, buffer.matches("multipart/byteranges").count());
+    }
+}
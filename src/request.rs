@@ -1,5 +1,9 @@
 use crate::{
-    http::{Header, Method, Version},
+    headers::{Accept, ContentType, TypedHeader},
+    http::{Header, HeaderMap, Method, MimeType, StatusCode, Version},
+    multipart::{self, FieldData, MultipartConfig, MultipartForm, SpooledFile},
+    negotiate,
+    query::QueryMap,
     utils,
 };
 use bytes::Bytes;
@@ -10,18 +14,21 @@ use std::{collections::HashMap, format, vec};
 #[derive(Debug, Clone, PartialEq, Eq)]
 pub enum FormTypes {
     None,
-    MultiPart(HashMap<String, MultiPartFormEntry>),
-    XUrlEncoded(HashMap<String, String>), //simple string key value pair
+    MultiPart(MultipartForm),
+    /// every key value pair in order, repeated keys like checkboxes are kept
+    XUrlEncoded(QueryMap),
 }
 
 #[derive(PartialEq, Debug, Clone)]
 pub struct Request {
     method: Method,
     path: String,
+    raw_path: String,
     version: Version,
     host: String,
     query_string: Option<String>,
-    headers: HashMap<String, String>,
+    query: QueryMap,
+    headers: HeaderMap,
     body: Vec<u8>,
     form_data: FormTypes,
     keep_alive: bool,
@@ -32,6 +39,7 @@ pub enum Error {
     InvalidString,
     InvalidMethod,
     InvalidHTTPVersion,
+    UnsupportedHTTPVersion,
     MissingBlankLine,
     NoHostHeader,
     InvalidContentLength,
@@ -39,6 +47,15 @@ pub enum Error {
     MissingMultiPartBoundary,
     MissingContentLength,
     InvalidUrlEncodedForm,
+    InvalidUrlEncoding,
+    InvalidHeader,
+    InvalidMultipart,
+    MultipartTooLarge,
+    UploadFailed,
+    UnsupportedTransferEncoding,
+    PathOutsideRoot,
+    InvalidChunkedBody,
+    BodyTooLarge,
 }
 
 impl std::error::Error for Error {
@@ -53,13 +70,23 @@ impl From<&Error> for String {
             Error::InvalidString => "Invalid String".to_string(),
             Error::NoHostHeader => "No VHost Specified".to_string(),
             Error::InvalidMethod => "Invalid Method Requested".to_string(),
-            Error::InvalidHTTPVersion => "Unsupported HTTP version Request".to_string(),
+            Error::InvalidHTTPVersion => "Invalid HTTP version Request".to_string(),
+            Error::UnsupportedHTTPVersion => "Unsupported HTTP version Request".to_string(),
             Error::MissingBlankLine => "Missing Blank Line".to_string(),
             Error::WaitingOnBody(_) => "Waiting On Body".to_string(),
             Error::InvalidContentLength => "Content Length Invalid".to_string(),
             Error::MissingMultiPartBoundary => "Missing Mulipart boundary".to_string(),
             Error::MissingContentLength => "Missing Content Length Header".to_string(),
             Error::InvalidUrlEncodedForm => "Invalid URL Encoded Form".to_string(),
+            Error::InvalidUrlEncoding => "Invalid Percent Encoding In URL".to_string(),
+            Error::InvalidHeader => "Invalid Header Line".to_string(),
+            Error::InvalidMultipart => "Invalid Multipart Body".to_string(),
+            Error::MultipartTooLarge => "Multipart Body Too Large".to_string(),
+            Error::UploadFailed => "Error Storing Upload".to_string(),
+            Error::UnsupportedTransferEncoding => "Transfer-Encoding Not Supported".to_string(),
+            Error::PathOutsideRoot => "Path Outside Root".to_string(),
+            Error::InvalidChunkedBody => "Invalid Chunked Body".to_string(),
+            Error::BodyTooLarge => "Body Too Large".to_string(),
         }
     }
 }
@@ -69,7 +96,8 @@ pub struct MultiPartFormEntry {
     name: String,
     file_name: Option<String>,
     content_type: Option<String>,
-    value: Vec<u8>,
+    headers: HeaderMap,
+    data: FieldData,
 }
 
 impl MultiPartFormEntry {
@@ -80,11 +108,13 @@ impl MultiPartFormEntry {
             let lines = header.split("\r\n");
             let mut form_args: HashMap<&str, &str> = HashMap::new();
             let mut content_type = None;
+            let mut headers = HeaderMap::new();
             for line in lines {
                 let name_value_split: Vec<_> = line.split(": ").collect();
                 if let (Some(header_name), Some(header_value)) =
                     (name_value_split.first(), name_value_split.get(1))
                 {
+                    headers.append((*header_name, *header_value));
                     match header_name.to_lowercase().as_str() {
                         "content-type" => {
                             content_type = Some(header_value.to_string());
@@ -109,7 +139,8 @@ impl MultiPartFormEntry {
                     name,
                     file_name: file,
                     content_type,
-                    value: body.as_bytes().into(),
+                    headers,
+                    data: FieldData::Memory(body.as_bytes().into()),
                 })
             } else {
                 Err(anyhow::Error::msg("Missing Name"))
@@ -124,6 +155,7 @@ impl MultiPartFormEntry {
         if let Some(blank_line) = memmem::find(form, b"\r\n\r\n") {
             let mut form_args: HashMap<String, String> = HashMap::new();
             let mut content_type = None;
+            let mut headers = HeaderMap::new();
             let header = &form[0..blank_line + 2];
             let mut body = &form[blank_line + 4..];
             if body[body.len() - 1] == b'\n' && body[body.len() - 2] == b'\r' {
@@ -138,6 +170,9 @@ impl MultiPartFormEntry {
                     let name_b = &new_header[0..colon_i];
                     let name = String::from_utf8_lossy(name_b);
                     let value = &new_header[colon_i + 2..];
+                    if let Ok(header) = Header::try_from(new_header) {
+                        headers.append(header);
+                    }
                     match name.to_lowercase().as_str() {
                         "content-type" => {
                             content_type = Some(String::from_utf8_lossy(value).to_string());
@@ -163,7 +198,8 @@ impl MultiPartFormEntry {
                     name,
                     file_name: file,
                     content_type,
-                    value: body.into(),
+                    headers,
+                    data: FieldData::Memory(body.into()),
                 })
             } else {
                 Err(anyhow::Error::msg("Missing Name"))
@@ -178,7 +214,8 @@ impl MultiPartFormEntry {
             name: name.to_string(),
             file_name: None,
             content_type: None,
-            value: value.to_string().into(),
+            headers: HeaderMap::new(),
+            data: FieldData::Memory(value.into()),
         }
     }
 
@@ -187,7 +224,8 @@ impl MultiPartFormEntry {
             name: name.to_string(),
             file_name: Some(file_name.to_string()),
             content_type: None,
-            value: value.to_string().into(),
+            headers: HeaderMap::new(),
+            data: FieldData::Memory(value.into()),
         }
     }
 
@@ -199,8 +237,62 @@ impl MultiPartFormEntry {
         self.content_type.as_ref()
     }
 
-    pub fn value(&self) -> &[u8] {
-        self.value.as_ref()
+    pub(crate) fn new(
+        name: String,
+        file_name: Option<String>,
+        content_type: Option<String>,
+        headers: HeaderMap,
+        data: FieldData,
+    ) -> Self {
+        MultiPartFormEntry {
+            name,
+            file_name,
+            content_type,
+            headers,
+            data,
+        }
+    }
+
+    /// Every header of the part, eg. Content-Transfer-Encoding
+    pub fn headers(&self) -> &HeaderMap {
+        &self.headers
+    }
+
+    /// Value kept in memory, None for file parts over [`MultipartConfig::spool_threshold`], those
+    /// are written to a temp file instead, use [`Self::spooled`] or [`Self::bytes`] for them
+    pub fn value(&self) -> Option<&[u8]> {
+        match &self.data {
+            FieldData::Memory(value) => Some(value),
+            FieldData::Spooled(_) => None,
+        }
+    }
+
+    /// Temp file the value was written to, None when it's kept in memory
+    pub fn spooled(&self) -> Option<&SpooledFile> {
+        match &self.data {
+            FieldData::Memory(_) => None,
+            FieldData::Spooled(file) => Some(file),
+        }
+    }
+
+    pub fn data(&self) -> &FieldData {
+        &self.data
+    }
+
+    /// Whole value, read back from disk if it was spooled
+    pub async fn bytes(&self) -> std::io::Result<Vec<u8>> {
+        match &self.data {
+            FieldData::Memory(value) => Ok(value.clone()),
+            FieldData::Spooled(file) => tokio::fs::read(file.path()).await,
+        }
+    }
+
+    pub fn len(&self) -> u64 {
+        self.data.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.data.is_empty()
     }
 
     pub fn name(&self) -> &str {
@@ -213,55 +305,35 @@ impl fmt::Display for Error {
     }
 }
 
-impl From<Error> for String {
-    fn from(value: Error) -> Self {
-        String::from(&value)
+impl Error {
+    /// Status to answer a request that failed to parse with
+    pub fn status(&self) -> StatusCode {
+        match self {
+            Error::UnsupportedHTTPVersion => StatusCode::HTTP_VERSION_NOT_SUPPORTED,
+            Error::MultipartTooLarge | Error::BodyTooLarge => StatusCode::PAYLOAD_TOO_LARGE,
+            Error::UploadFailed => StatusCode::INTERNAL_SERVER_ERROR,
+            Error::UnsupportedTransferEncoding => StatusCode::NOT_IMPLEMENTED,
+            Error::PathOutsideRoot => StatusCode::FORBIDDEN,
+            _ => StatusCode::BAD_REQUEST,
+        }
     }
 }
 
-fn get_boundary(content_type_value_str: &str) -> Result<&str, anyhow::Error> {
-    let parts: Vec<_> = content_type_value_str.split(';').collect();
-    if parts.len() > 1 {
-        let nv: Vec<_> = parts[1].split('=').collect();
-        if let Some(boundary) = nv.get(1) {
-            Ok(strip_quotes(boundary))
-        } else {
-            Err(anyhow::Error::msg("Invalid boundary"))
+impl From<multipart::Error> for Error {
+    fn from(error: multipart::Error) -> Self {
+        match error {
+            multipart::Error::Malformed(_) => Error::InvalidMultipart,
+            multipart::Error::FieldTooLarge | multipart::Error::TooLarge => {
+                Error::MultipartTooLarge
+            }
+            multipart::Error::Io(_) => Error::UploadFailed,
         }
-    } else {
-        Err(anyhow::Error::msg("Boundary Missing from string"))
     }
 }
 
-fn get_multiparts_entries_from_bytes(
-    body: &[u8],
-    boundary: &[u8],
-) -> anyhow::Result<HashMap<String, MultiPartFormEntry>> {
-    let mut end_marker = vec![b'-', b'-'];
-    end_marker.extend_from_slice(boundary);
-    let boundary_marker = end_marker.clone();
-    end_marker.extend_from_slice(b"--");
-    if memmem::find(body, &end_marker).is_some() {
-        //we have an en marker go through the bodies, body is anything after marker before next
-        //marker or end boundary
-        let mut body_spliter = memmem::find_iter(body, &boundary_marker);
-        let mut entries = HashMap::new();
-        if let Some(mut last_bound) = body_spliter.next() {
-            last_bound += boundary_marker.len();
-            for bound in body_spliter {
-                let current_body = &body[last_bound..bound];
-                last_bound = bound + boundary_marker.len();
-                //FIXME: make this use bytes
-                if let Ok(entry) = MultiPartFormEntry::from_bytes(current_body) {
-                    entries.insert(entry.name.clone(), entry);
-                }
-            }
-        } else {
-            return Err(anyhow::Error::msg("Missing boundaries"));
-        }
-        Ok(entries)
-    } else {
-        Err(anyhow::Error::msg("Not Full Body"))
+impl From<Error> for String {
+    fn from(value: Error) -> Self {
+        String::from(&value)
     }
 }
 
@@ -292,10 +364,16 @@ impl Request {
         &self.method
     }
 
+    /// Decoded and normalized path, this is what routes are matched against
     pub fn path(&self) -> &str {
         &self.path
     }
 
+    /// Path exactly as it was sent by the client, before percent decoding and normalization
+    pub fn raw_path(&self) -> &str {
+        &self.raw_path
+    }
+
     pub fn version(&self) -> Version {
         self.version
     }
@@ -308,14 +386,28 @@ impl Request {
         &self.body
     }
 
+    /// Value of header, if it was sent more than once all the values are joined
     pub fn get_header_value(&self, header_name: &str) -> Option<String> {
-        let lower = header_name.to_lowercase();
-        Self::header_value(&self.headers, &lower)
+        Self::header_value(&self.headers, header_name)
+    }
+
+    pub fn header_value(headers: &HeaderMap, header_name: &str) -> Option<String> {
+        headers.get_joined(header_name)
+    }
+
+    pub fn headers(&self) -> &HeaderMap {
+        &self.headers
+    }
+
+    /// Parse header T, None if it's missing or malformed
+    pub fn typed_header<T: TypedHeader>(&self) -> Option<T> {
+        self.headers.typed()
     }
 
-    pub fn header_value(headers: &HashMap<String, String>, header_name: &str) -> Option<String> {
-        let lower = header_name.to_lowercase();
-        return headers.get(&lower).cloned();
+    /// Type from available the client prefers according to Accept, None if it accepts none of them
+    /// ties go to the earlier type, see [`crate::negotiate::Negotiate`] to build the response
+    pub fn preferred<'a>(&self, available: &'a [MimeType]) -> Option<&'a MimeType> {
+        negotiate::preferred(self.typed_header::<Accept>().as_ref(), available)
     }
 
     pub fn keep_alive(&self) -> bool {
@@ -323,9 +415,10 @@ impl Request {
     }
 
     pub fn from_lines(lines: &Vec<&str>) -> Result<Request, Error> {
-        let mut headers = HashMap::new();
+        let mut headers = HeaderMap::new();
         let host;
         let mut query_string = None;
+        let mut query = QueryMap::new();
         let body = vec![];
         let form_data = FormTypes::None;
 
@@ -344,8 +437,10 @@ impl Request {
         //second string is url
         let url = request_seperated[1].to_string();
         let url_split: Vec<&str> = url.split('?').collect(); //anything after ? is query string
-        let path = url_split[0].to_string();
+        let raw_path = url_split[0].to_string();
+        let path = Self::decode_path(&raw_path)?;
         if url_split.len() > 1 {
+            query = Self::parse_query(url_split[1])?;
             query_string = Some(url_split[1].to_string());
         }
 
@@ -353,17 +448,16 @@ impl Request {
         let version = match request_seperated[2] {
             "HTTP/1.0" => Version::V1_0,
             "HTTP/1.1" => Version::V1_1,
-            "HTTP/2.2" => Version::V2_0,
-            _ => return Err(Error::InvalidHTTPVersion),
+            "HTTP/2" | "HTTP/2.0" => Version::V2_0,
+            other => return Err(Self::version_error(other.as_bytes())),
         };
 
         //4th is optional headers
         if lines.len() > 1 {
             //FIXME: Dont we need to collect here?
-            for line in lines.iter().skip(1) {
-                if let Ok(header) = Header::try_from(*line) {
-                    headers.insert(header.key, header.value);
-                }
+            for line in lines.iter().skip(1).filter(|l| !l.is_empty()) {
+                let header = Header::try_from(*line).map_err(|_| Error::InvalidHeader)?;
+                headers.append(header);
                 //headers.push(lines[i].to_string());
             }
         }
@@ -373,19 +467,24 @@ impl Request {
             // get rid if port if its included in host name
             let hostname_only: Vec<&str> = hostname.split(':').collect();
             host = hostname_only[0].to_string();
-        } else {
-            //FIXME: should we only error when its > http 1.0????
+        } else if version >= Version::V1_1 {
             return Err(Error::NoHostHeader);
+        } else {
+            // optional before 1.1, routed to the default host
+            host = String::new();
         }
         //last is optional headers
-        let keep_alive = Self::determine_keep_alive(version, headers.get("connection"));
+        let keep_alive =
+            Self::determine_keep_alive(version, headers.get_joined("connection").as_deref());
         Ok(Request {
             method,
             version,
             path,
+            raw_path,
             headers,
             host,
             query_string,
+            query,
             body,
             form_data,
             keep_alive,
@@ -393,6 +492,25 @@ impl Request {
     }
 
     pub fn from_bytes(request_bytes: Bytes) -> Result<Request, Error> {
+        Self::from_bytes_with(request_bytes, &MultipartConfig::default())
+    }
+
+    /// Request with multipart bodies parsed using config
+    pub fn from_bytes_with(
+        request_bytes: Bytes,
+        config: &MultipartConfig,
+    ) -> Result<Request, Error> {
+        Self::parse(request_bytes, Some(config))
+    }
+
+    /// Request from its head alone with an empty body, eg. to check it before reading the body
+    pub fn from_head(request_bytes: Bytes) -> Result<Request, Error> {
+        Self::parse(request_bytes, None)
+    }
+
+    /// Without a multipart config only the head is parsed
+    fn parse(request_bytes: Bytes, config: Option<&MultipartConfig>) -> Result<Request, Error> {
+        let head_only = config.is_none();
         let bytes = request_bytes;
         if bytes.is_empty() {
             return Err(Error::InvalidString);
@@ -401,13 +519,19 @@ impl Request {
         if let Some(blank_line_index) = memmem::find(&bytes, b"\r\n\r\n") {
             let req_header = bytes.slice(0..blank_line_index + 2); //include last crlf for easier
                                                                    //header parsing
-            let mut req_body = bytes.slice(blank_line_index + 4..bytes.len());
+            let body_start = if head_only {
+                bytes.len()
+            } else {
+                blank_line_index + 4
+            };
+            let mut req_body = bytes.slice(body_start..bytes.len());
             let mut req_header_lines = memmem::find_iter(&req_header, "\r\n");
             if let Some(i) = req_header_lines.next() {
                 let url;
-                let mut headers = HashMap::new(); 
+                let mut headers = HeaderMap::new();
                 let host;
                 let mut query_string = None;
+                let mut query_map = QueryMap::new();
                 let mut form_data = FormTypes::None;
                 let request_line = req_header.slice(0..i);
                 let mut header_start = i + 2;
@@ -420,25 +544,31 @@ impl Request {
 
                 let method: Method =
                     Method::try_from(method_b.as_ref()).map_err(|_| Error::InvalidMethod)?;
-                let version =
-                    Version::try_from(version_b.as_ref()).map_err(|_| Error::InvalidHTTPVersion)?;
+                let version = Version::try_from(version_b.as_ref())
+                    .map_err(|_| Self::version_error(&version_b))?;
                 //check for query_string in url
                 if let Some(qmark) = memchr(b'?', &url_b) {
                     let query = url_b.slice(qmark + 1..url_b.len());
                     let url_slice = url_b.slice(0..qmark);
-                    query_string = Some(String::from_utf8_lossy(query.as_ref()).to_string());
-                    url = String::from_utf8_lossy(url_slice.as_ref()).to_string();
+                    let query =
+                        std::str::from_utf8(&query).map_err(|_| Error::InvalidUrlEncoding)?;
+                    query_map = Self::parse_query(query)?;
+                    query_string = Some(query.to_string());
+                    url = String::from_utf8(url_slice.to_vec())
+                        .map_err(|_| Error::InvalidUrlEncoding)?;
                 } else {
-                    url = String::from_utf8_lossy(url_b.as_ref()).to_string();
+                    url =
+                        String::from_utf8(url_b.to_vec()).map_err(|_| Error::InvalidUrlEncoding)?;
                 }
+                let path = Self::decode_path(&url)?;
 
                 // go through rest of the lines in header and parse out any headers
                 for line_end in req_header_lines {
                     let header_line = req_header.slice(header_start..line_end);
                     header_start = line_end + 2;
-                    if let Ok(header) = Header::try_from(header_line.as_ref()) {
-                        headers.insert(header.key, header.value);
-                    }
+                    let header =
+                        Header::try_from(header_line.as_ref()).map_err(|_| Error::InvalidHeader)?;
+                    headers.append(header);
                 }
 
                 //make sure we have a host header
@@ -447,49 +577,56 @@ impl Request {
                     // get rid if port if its included in host name
                     let hostname_only: Vec<&str> = hostname.split(':').collect();
                     host = hostname_only[0].to_string();
-                } else {
-                    //FIXME: should we only error when its > http 1.0????
+                } else if version >= Version::V1_1 {
                     return Err(Error::NoHostHeader);
+                } else {
+                    // optional before 1.1, routed to the default host
+                    host = String::new();
                 }
 
                 //lastly check we got the full body of the request
-                if let Some(content_length) = Self::header_value(&headers, "Content-Length") {
-                    if let Ok(len) = content_length.parse() {
-                        if req_body.len() < len {
-                            return Err(Error::WaitingOnBody(Some(len - req_body.len())));
-                        }
-                    } else {
+                let content_length = if head_only {
+                    None
+                } else {
+                    Self::header_value(&headers, "Content-Length")
+                };
+                let transfer_encoding = if head_only {
+                    None
+                } else {
+                    headers.get_joined("Transfer-Encoding")
+                };
+                if let Some(codings) = transfer_encoding {
+                    if content_length.is_some() {
                         return Err(Error::InvalidContentLength);
                     }
+                    Self::check_chunked(&codings)?;
+                    let mut decoded = Vec::new();
+                    Self::read_chunked(&req_body, Some(&mut decoded))?;
+                    req_body = decoded.into();
+                } else if let Some(content_length) = content_length {
+                    let len = Self::parse_content_length(&content_length)?;
+                    if req_body.len() < len {
+                        return Err(Error::WaitingOnBody(Some(len - req_body.len())));
+                    }
                 }
-                if let Some(content_type) = Self::header_value(&headers, "Content-Type") {
+                let content_type = if head_only {
+                    None
+                } else {
+                    Self::header_value(&headers, "Content-Type")
+                };
+                if let Some(content_type) = content_type {
                     match content_type {
-                        x if x.contains("multipart/form-data;") => {
-                            match get_boundary(&x) {
-                                Ok(boundary) => {
-                                    match get_multiparts_entries_from_bytes(
-                                        &req_body,
-                                        boundary.as_bytes(),
-                                    ) {
-                                        Ok(entries) => {
-                                            form_data = FormTypes::MultiPart(entries);
-                                            req_body.clear(); //clear since we parsed it
-                                                              //return Ok(req);
-                                        }
-                                        Err(_) => {
-                                            return Err(Error::WaitingOnBody(None));
-                                        }
-                                    }
-                                }
-                                Err(e) => {
-                                    log::debug!("Error Parsing Boundary: {}", e.to_string());
-                                    return Err(Error::MissingMultiPartBoundary);
-                                }
-                            }
+                        x if x.contains("multipart/form-data") => {
+                            let boundary = Self::multipart_boundary(&headers)?;
+                            let config = config.cloned().unwrap_or_default();
+                            let mut parser = multipart::Parser::new(&boundary, config);
+                            parser.feed(&req_body)?;
+                            form_data = Self::multipart_form(parser.finish()?);
+                            req_body.clear(); //clear since we parsed it
                         }
                         x if x.contains("application/x-www-form-urlencoded") => {
                             //Parse here
-                            match utils::parse_query_string(&req_body) {
+                            match QueryMap::parse(&req_body) {
                                 Ok(form) => {
                                     form_data = FormTypes::XUrlEncoded(form);
                                     req_body.clear();
@@ -506,14 +643,19 @@ impl Request {
                         _ => {}
                     }
                 }
-                let keep_alive = Self::determine_keep_alive(version, headers.get("connection"));
+                let keep_alive = Self::determine_keep_alive(
+                    version,
+                    headers.get_joined("connection").as_deref(),
+                );
                 Ok(Request {
                     method,
                     version,
-                    path: url,
+                    path,
+                    raw_path: url,
                     headers,
                     host,
                     query_string,
+                    query: query_map,
                     body: req_body.to_vec(),
                     form_data,
                     keep_alive,
@@ -522,38 +664,294 @@ impl Request {
                 //no headers, we need at least the host header
                 panic!("request parsing: Somehow missing CRLF even though CRLFCRLF was present");
             }
+        } else if let Some(request) = Self::from_simple_request(&bytes) {
+            request
         } else {
             Err(Error::MissingBlankLine)
         }
     }
 
+    /// Length of the first request in bytes, head and Content-Length or chunked body, so requests
+    /// pipelined after it can be told apart. Without either a request has no body (RFC 7230 3.3.3)
+    /// Transfer-Encoding with Content-Length and Content-Length values that disagree are errors,
+    /// guessing where the body ends would let a request be smuggled inside another's body
+    pub fn message_length(bytes: &[u8]) -> Result<usize, Error> {
+        let Some(blank_line_index) = memmem::find(bytes, b"\r\n\r\n") else {
+            return match Self::simple_request_line(bytes) {
+                Some(line_end) => Ok(line_end + 1),
+                None if bytes.is_empty() => Err(Error::InvalidString),
+                None => Err(Error::MissingBlankLine),
+            };
+        };
+        let head_length = blank_line_index + 4;
+        let mut content_length = None;
+        let mut codings: Option<String> = None;
+        for line in bytes[..blank_line_index].split(|b| *b == b'\n').skip(1) {
+            let Some(colon) = memchr(b':', line) else {
+                continue;
+            };
+            let name = line[..colon].trim_ascii();
+            let value = std::str::from_utf8(&line[colon + 1..]).map_err(|_| Error::InvalidHeader);
+            if name.eq_ignore_ascii_case(b"transfer-encoding") {
+                let value = value?.trim();
+                codings = Some(match codings {
+                    Some(codings) => format!("{codings}, {value}"),
+                    None => value.to_string(),
+                });
+            }
+            if name.eq_ignore_ascii_case(b"content-length") {
+                let length = value
+                    .map_err(|_| Error::InvalidContentLength)
+                    .and_then(Self::parse_content_length)?;
+                if content_length.is_some_and(|first| first != length) {
+                    return Err(Error::InvalidContentLength);
+                }
+                content_length = Some(length);
+            }
+        }
+        if let Some(codings) = codings {
+            if content_length.is_some() {
+                return Err(Error::InvalidContentLength);
+            }
+            Self::check_chunked(&codings)?;
+            return Ok(head_length + Self::read_chunked(&bytes[head_length..], None)?);
+        }
+        let body_length = content_length.unwrap_or(0);
+        let available = bytes.len() - head_length;
+        if available < body_length {
+            return Err(Error::WaitingOnBody(Some(body_length - available)));
+        }
+        Ok(head_length + body_length)
+    }
+
+    /// Transfer-Encoding codings the body can be decoded from, only chunked on its own is
+    /// (RFC 7230 3.3.1)
+    fn check_chunked(codings: &str) -> Result<(), Error> {
+        let mut codings = codings.split(',').map(str::trim).filter(|c| !c.is_empty());
+        match (codings.next(), codings.next()) {
+            (Some(coding), None) if coding.eq_ignore_ascii_case("chunked") => Ok(()),
+            _ => Err(Error::UnsupportedTransferEncoding),
+        }
+    }
+
+    /// Length of the chunked body at the start of bytes up to the end of its trailers, the data
+    /// of its chunks is appended to decoded (RFC 7230 4.1)
+    fn read_chunked(bytes: &[u8], mut decoded: Option<&mut Vec<u8>>) -> Result<usize, Error> {
+        let line_end = |start: usize| {
+            memmem::find(&bytes[start..], b"\r\n")
+                .map(|i| start + i)
+                .ok_or(Error::WaitingOnBody(None))
+        };
+        let mut position = 0;
+        loop {
+            let size_end = line_end(position)?;
+            let line = &bytes[position..size_end];
+            // chunk extensions are ignored
+            let size = &line[..memchr(b';', line).unwrap_or(line.len())];
+            if size.is_empty() || !size.iter().all(u8::is_ascii_hexdigit) {
+                return Err(Error::InvalidChunkedBody);
+            }
+            let size = std::str::from_utf8(size)
+                .ok()
+                .and_then(|size| usize::from_str_radix(size, 16).ok())
+                .ok_or(Error::InvalidChunkedBody)?;
+            position = size_end + 2;
+            if size == 0 {
+                break;
+            }
+            let data_end = position
+                .checked_add(size)
+                .ok_or(Error::InvalidChunkedBody)?;
+            if bytes.len() < data_end + 2 {
+                return Err(Error::WaitingOnBody(None));
+            }
+            if &bytes[data_end..data_end + 2] != b"\r\n" {
+                return Err(Error::InvalidChunkedBody);
+            }
+            if let Some(decoded) = decoded.as_mut() {
+                decoded.extend_from_slice(&bytes[position..data_end]);
+            }
+            position = data_end + 2;
+        }
+        // trailer fields aren't kept, they end with an empty line
+        loop {
+            let trailer_end = line_end(position)?;
+            let empty = trailer_end == position;
+            position = trailer_end + 2;
+            if empty {
+                return Ok(position);
+            }
+        }
+    }
+
+    /// Content-Length from its comma separated values, repeated ones have to be the same
+    /// (RFC 7230 3.3.2)
+    fn parse_content_length(values: &str) -> Result<usize, Error> {
+        let mut lengths = values.split(',').map(|length| {
+            length
+                .trim()
+                .parse::<usize>()
+                .map_err(|_| Error::InvalidContentLength)
+        });
+        let first = lengths.next().unwrap_or(Err(Error::InvalidContentLength))?;
+        for length in lengths {
+            if length? != first {
+                return Err(Error::InvalidContentLength);
+            }
+        }
+        Ok(first)
+    }
+
+    /// End of the line when bytes start with a complete HTTP/0.9 request line
+    fn simple_request_line(bytes: &[u8]) -> Option<usize> {
+        let line_end = memchr(b'\n', bytes)?;
+        let line = &bytes[..line_end];
+        let line = line.strip_suffix(b"\r").unwrap_or(line);
+        let space = memchr(b' ', line)?;
+        let url = &line[space + 1..];
+        (!url.is_empty() && memchr(b' ', url).is_none()).then_some(line_end)
+    }
+
+    /// HTTP/0.9 request, a single "GET /path" line with no version, headers or body
+    /// None when bytes don't hold a complete one
+    fn from_simple_request(bytes: &Bytes) -> Option<Result<Request, Error>> {
+        let line_end = Self::simple_request_line(bytes)?;
+        let line = bytes.slice(0..line_end);
+        let line = line.strip_suffix(b"\r").unwrap_or(&line);
+        let space = memchr(b' ', line)?;
+        let (method, url) = (&line[..space], &line[space + 1..]);
+        if method != b"GET" {
+            return Some(Err(Error::InvalidMethod));
+        }
+        let Ok(url) = std::str::from_utf8(url) else {
+            return Some(Err(Error::InvalidUrlEncoding));
+        };
+        Some(Self::simple_request(url))
+    }
+
+    fn simple_request(url: &str) -> Result<Request, Error> {
+        let (raw_path, query_string) = match url.split_once('?') {
+            Some((path, query)) => (path, Some(query)),
+            None => (url, None),
+        };
+        let query = match query_string {
+            Some(query) => Self::parse_query(query)?,
+            None => QueryMap::new(),
+        };
+        Ok(Request {
+            method: Method::GET,
+            version: Version::V0_9,
+            path: Self::decode_path(raw_path)?,
+            raw_path: raw_path.to_string(),
+            headers: HeaderMap::new(),
+            host: String::new(),
+            query_string: query_string.map(str::to_string),
+            query,
+            body: vec![],
+            form_data: FormTypes::None,
+            keep_alive: false,
+        })
+    }
+
+    /// Well formed versions we don't speak are unsupported, anything else is a bad request line
+    fn version_error(version: &[u8]) -> Error {
+        let number = version.strip_prefix(b"HTTP/").unwrap_or_default();
+        if !number.is_empty() && number.iter().all(|b| b.is_ascii_digit() || *b == b'.') {
+            Error::UnsupportedHTTPVersion
+        } else {
+            Error::InvalidHTTPVersion
+        }
+    }
+
     pub fn from_string(request_str: String) -> Result<Request, Error> {
         let bytes = Bytes::from(request_str);
         Self::from_bytes(bytes)
     }
 
+    /// Percent decode and normalize path, rejects invalid escapes and encoded NUL bytes
+    /// '..' segments going above root are rejected instead of stopping at root, they're only
+    /// sent to get out of a doc root
+    fn decode_path(raw_path: &str) -> Result<String, Error> {
+        let decoded =
+            utils::percent_decode(raw_path.as_bytes()).ok_or(Error::InvalidUrlEncoding)?;
+        let decoded = String::from_utf8(decoded).map_err(|_| Error::InvalidUrlEncoding)?;
+        if decoded.contains('\0') {
+            return Err(Error::InvalidUrlEncoding);
+        }
+        if decoded.starts_with('/') {
+            let mut depth = 0_usize;
+            for segment in decoded.split('/') {
+                match segment {
+                    "" | "." => {}
+                    ".." => depth = depth.checked_sub(1).ok_or(Error::PathOutsideRoot)?,
+                    _ => depth += 1,
+                }
+            }
+        }
+        Ok(utils::normalize_path(&decoded))
+    }
+
+    /// Query strings are kept raw, but must be decodable
+    fn parse_query(query: &str) -> Result<QueryMap, Error> {
+        QueryMap::parse(query.as_bytes()).map_err(|_| Error::InvalidUrlEncoding)
+    }
+
+    /// Raw query string, not percent decoded
     pub fn query_string(&self) -> Option<&String> {
         self.query_string.as_ref()
     }
 
+    /// Decoded query string, empty if the url had none
+    pub fn query(&self) -> &QueryMap {
+        &self.query
+    }
+
     pub fn form_data(&self) -> &FormTypes {
         &self.form_data
     }
+
+    /// Fields of a multipart/form-data body in order, None for any other body
+    /// the body is parsed while it's read, before the handler runs, so large files are already
+    /// spooled to disk and nothing more is read from the client
+    pub fn multipart(&self) -> Option<&MultipartForm> {
+        match &self.form_data {
+            FormTypes::MultiPart(form) => Some(form),
+            _ => None,
+        }
+    }
+
+    pub(crate) fn set_form_data(&mut self, form_data: FormTypes) {
+        self.form_data = form_data;
+    }
+
+    /// Boundary of a multipart/form-data Content-Type
+    pub(crate) fn multipart_boundary(headers: &HeaderMap) -> Result<String, Error> {
+        headers
+            .typed::<ContentType>()
+            .and_then(|content_type| content_type.boundary().map(str::to_string))
+            .filter(|boundary| !boundary.is_empty())
+            .ok_or(Error::MissingMultiPartBoundary)
+    }
+
+    pub(crate) fn multipart_form(entries: Vec<MultiPartFormEntry>) -> FormTypes {
+        FormTypes::MultiPart(entries.into_iter().collect())
+    }
     
     /// determin if request wants to keep connection alive
     /// if connection header present this value is controlled by that
     /// otherwise determined by default behavior for version passed
-    fn determine_keep_alive(version: Version, connection_header: Option<&String>) -> bool {
-        if let Some(conn) = connection_header {
-            conn.to_lowercase() == "keep-alive"
+    /// Connection is a token list eg. "keep-alive, Upgrade", close always wins
+    fn determine_keep_alive(version: Version, connection_header: Option<&str>) -> bool {
+        let tokens: Vec<String> = connection_header
+            .map(|conn| conn.split(',').map(|t| t.trim().to_lowercase()).collect())
+            .unwrap_or_default();
+        if tokens.iter().any(|t| t == "close") {
+            false
+        } else if tokens.iter().any(|t| t == "keep-alive") {
+            version >= Version::V1_0
         } else {
-            // no conncection header so use version default 
-            match version {
-                Version::V0_9 => false,
-                Version::V1_0 => false,
-                Version::V1_1 => true,
-                Version::V2_0 => true,
-            }
+            // no conncection header so use version default
+            version >= Version::V1_1
         }
     }
 }
@@ -566,25 +964,25 @@ mod tests {
 
     #[test]
     fn x_url_encoded_form() {
-        let mut map = HashMap::new();
-        map.insert("field1".to_string(), "value1".to_string());
-        map.insert("field2".to_string(), "value2".to_string());
+        let map = QueryMap::from_iter([
+            ("field1".to_string(), "value1".to_string()),
+            ("field2".to_string(), "value2".to_string()),
+        ]);
 
         let expected = Request {
             method: Method::POST,
             version: Version::V1_1,
             path: "/test".to_string(),
+            raw_path: "/test".to_string(),
             body: vec![],
-            headers: HashMap::from([
-                ("host".to_string(), "foo.example".to_string()),
-                (
-                    "content-type".to_string(),
-                    "application/x-www-form-urlencoded".to_string(),
-                ),
-                ("content-length".to_string(), "27".to_string()),
+            headers: HeaderMap::from_iter([
+                ("host", "foo.example"),
+                ("content-type", "application/x-www-form-urlencoded"),
+                ("content-length", "27"),
             ]),
             host: "foo.example".to_string(),
             query_string: None,
+            query: QueryMap::new(),
             form_data: FormTypes::XUrlEncoded(map),
             keep_alive: true,
         };
@@ -601,28 +999,6 @@ mod tests {
 
     #[test]
     fn multipart_form() {
-        let field1 = MultiPartFormEntry::name_value("field1", "value1");
-        let field2 = MultiPartFormEntry::file("field2", "example.txt", "value2");
-        let mut map = HashMap::new();
-        map.insert(field1.name.clone(), field1);
-        map.insert(field2.name.clone(), field2);
-        let expected = Request {
-            method: Method::POST,
-            version: Version::V1_1,
-            path: "/test".to_string(),
-            body: vec![],
-            headers: HashMap::from([
-                ("host".to_string(), "foo.example".to_string()),
-                (
-                    "content-type".to_string(),
-                    "multipart/form-data;boundary=\"boundary\"".to_string(),
-                ),
-            ]),
-            host: "foo.example".to_string(),
-            query_string: None,
-            form_data: FormTypes::MultiPart(map),
-            keep_alive: true,
-        };
         let request_str = Bytes::from_static(
             b"POST /test HTTP/1.1\r\n\
         Host: foo.example\r\n\
@@ -634,13 +1010,80 @@ mod tests {
         value1\r\n\
         --boundary\r\n\
         Content-Disposition: form-data; name=\"field2\"; filename=\"example.txt\"\r\n\
+        Content-Transfer-Encoding: binary\r\n\
         \r\n\
         value2\r\n\
+        --boundary\r\n\
+        Content-Disposition: form-data; name=\"field2\"; filename*=UTF-8''%C3%A9.txt\r\n\
+        \r\n\
+        value3\r\n\
         --boundary--",
         );
 
         let request = Request::from_bytes(request_str).expect("Could not build request");
-        assert_eq!(expected, request);
+        assert!(request.body().is_empty());
+        let FormTypes::MultiPart(form) = request.form_data() else {
+            panic!("not a multipart form");
+        };
+        let field1 = form.get("field1").unwrap();
+        assert_eq!(Some(&b"value1"[..]), field1.value());
+        assert_eq!(None, field1.file_name());
+        // repeated names are kept in order
+        let files: Vec<_> = form.get_all("field2").collect();
+        assert_eq!(2, files.len());
+        assert_eq!(Some(&"example.txt".to_string()), files[0].file_name());
+        assert_eq!(Some(&b"value2"[..]), files[0].value());
+        assert_eq!(
+            Some("binary".to_string()),
+            files[0].headers().get_joined("Content-Transfer-Encoding")
+        );
+        assert_eq!(Some(&"é.txt".to_string()), files[1].file_name());
+        assert_eq!(Some(&b"value3"[..]), files[1].value());
+    }
+
+    #[test]
+    fn multipart_mixed() {
+        let request_str = Bytes::from_static(
+            b"POST /test HTTP/1.1\r\n\
+        Host: foo.example\r\n\
+        Content-Type: multipart/form-data; boundary=AaB03x\r\n\
+        \r\n\
+        --AaB03x\r\n\
+        Content-Disposition: form-data; name=\"submit-name\"\r\n\
+        \r\n\
+        Larry\r\n\
+        --AaB03x\r\n\
+        Content-Disposition: form-data; name=\"files\"\r\n\
+        Content-Type: multipart/mixed; boundary=BbC04y\r\n\
+        \r\n\
+        --BbC04y\r\n\
+        Content-Disposition: file; filename=\"file1.txt\"\r\n\
+        Content-Type: text/plain\r\n\
+        \r\n\
+        ... contents of file1.txt ...\r\n\
+        --BbC04y\r\n\
+        Content-Disposition: file; filename=\"file2.gif\"\r\n\
+        Content-Type: image/gif\r\n\
+        \r\n\
+        GIF89a\r\n\
+        --BbC04y--\r\n\
+        --AaB03x--\r\n",
+        );
+        let request = Request::from_bytes(request_str).expect("Could not build request");
+        let FormTypes::MultiPart(form) = request.form_data() else {
+            panic!("not a multipart form");
+        };
+        let names: Vec<_> = form.iter().map(|entry| entry.name()).collect();
+        assert_eq!(vec!["submit-name", "files", "files"], names);
+        let files: Vec<_> = form.get_all("files").collect();
+        assert_eq!(Some(&"file1.txt".to_string()), files[0].file_name());
+        assert_eq!(Some(&"text/plain".to_string()), files[0].content_type());
+        assert_eq!(
+            Some(&b"... contents of file1.txt ..."[..]),
+            files[0].value()
+        );
+        assert_eq!(Some(&"file2.gif".to_string()), files[1].file_name());
+        assert_eq!(Some(&b"GIF89a"[..]), files[1].value());
     }
 
     #[test]
@@ -651,6 +1094,142 @@ mod tests {
         assert_eq!(expected, request);
     }
 
+    #[test]
+    fn unsupported_version() {
+        let request =
+            |version: &str| Request::from_string(format!("GET / {version}\r\nHost: test\r\n\r\n"));
+        assert_eq!(Err(Error::UnsupportedHTTPVersion), request("HTTP/3.0"));
+        assert_eq!(Err(Error::UnsupportedHTTPVersion), request("HTTP/1.2"));
+        assert_eq!(Err(Error::InvalidHTTPVersion), request("HTTP/one"));
+    }
+
+    #[test]
+    fn simple_request() {
+        let request = Request::from_bytes(Bytes::from_static(b"GET /a%20b?x=1\r\n")).unwrap();
+        assert_eq!(Version::V0_9, request.version());
+        assert_eq!("/a b", request.path());
+        assert_eq!(Some("1"), request.query().get("x"));
+        assert_eq!("", request.hostname());
+        assert!(!request.keep_alive());
+        let bare_newline = Request::from_bytes(Bytes::from_static(b"GET /\n")).unwrap();
+        assert_eq!(Version::V0_9, bare_newline.version());
+        assert_eq!(
+            Err(Error::InvalidMethod),
+            Request::from_bytes(Bytes::from_static(b"POST /\r\n"))
+        );
+        // still waiting on the rest of the line or the headers
+        assert_eq!(
+            Err(Error::MissingBlankLine),
+            Request::from_bytes(Bytes::from_static(b"GET /"))
+        );
+        assert_eq!(
+            Err(Error::MissingBlankLine),
+            Request::from_bytes(Bytes::from_static(b"GET / HTTP/1.1\r\nHost: test\r\n"))
+        );
+    }
+
+    #[test]
+    fn message_length() {
+        let get = b"GET / HTTP/1.1\r\nHost: test\r\n\r\n";
+        let post = b"POST / HTTP/1.1\r\nHost: test\r\ncontent-length: 5\r\n\r\nhello";
+        let pipelined = [&get[..], &post[..], &get[..]].concat();
+        assert_eq!(Ok(get.len()), Request::message_length(&pipelined));
+        assert_eq!(
+            Ok(post.len()),
+            Request::message_length(&pipelined[get.len()..])
+        );
+        assert_eq!(
+            Err(Error::WaitingOnBody(Some(2))),
+            Request::message_length(&post[..post.len() - 2])
+        );
+        assert_eq!(
+            Err(Error::MissingBlankLine),
+            Request::message_length(&get[..get.len() - 2])
+        );
+        assert_eq!(
+            Err(Error::InvalidContentLength),
+            Request::message_length(b"POST / HTTP/1.1\r\nContent-Length: x\r\n\r\n")
+        );
+        assert_eq!(Ok(7), Request::message_length(b"GET /\r\nGET /\r\n"));
+        let repeated = b"POST / HTTP/1.1\r\nContent-Length: 5\r\ncontent-length: 5, 5\r\n\r\nhello";
+        assert_eq!(Ok(repeated.len()), Request::message_length(repeated));
+        assert_eq!(
+            Err(Error::InvalidContentLength),
+            Request::message_length(
+                b"POST / HTTP/1.1\r\nContent-Length: 5\r\nContent-Length: 0\r\n\r\n"
+            )
+        );
+        let chunked = b"POST / HTTP/1.1\r\nTransfer-Encoding: chunked\r\n\r\n\
+            4;ext=1\r\nhell\r\n1\r\no\r\n0\r\nExpires: never\r\n\r\n";
+        let pipelined = [&chunked[..], &get[..]].concat();
+        assert_eq!(Ok(chunked.len()), Request::message_length(&pipelined));
+        assert_eq!(
+            Err(Error::WaitingOnBody(None)),
+            Request::message_length(&chunked[..chunked.len() - 2])
+        );
+        assert_eq!(
+            Err(Error::UnsupportedTransferEncoding),
+            Request::message_length(b"POST / HTTP/1.1\r\nTransfer-Encoding: gzip\r\n\r\n")
+        );
+        assert_eq!(
+            Err(Error::UnsupportedTransferEncoding),
+            Request::message_length(
+                b"POST / HTTP/1.1\r\nTransfer-Encoding: gzip\r\n\
+                Transfer-Encoding: chunked\r\n\r\n"
+            )
+        );
+        assert_eq!(
+            Err(Error::InvalidContentLength),
+            Request::message_length(
+                b"POST / HTTP/1.1\r\nTransfer-Encoding: chunked\r\nContent-Length: 0\r\n\r\n"
+            )
+        );
+        assert_eq!(
+            Err(Error::InvalidChunkedBody),
+            Request::message_length(
+                b"POST / HTTP/1.1\r\nTransfer-Encoding: chunked\r\n\r\n+4\r\nhell\r\n0\r\n\r\n"
+            )
+        );
+        assert_eq!(
+            Err(Error::InvalidChunkedBody),
+            Request::message_length(
+                b"POST / HTTP/1.1\r\nTransfer-Encoding: chunked\r\n\r\n2\r\nhell\r\n0\r\n\r\n"
+            )
+        );
+    }
+
+    #[test]
+    fn chunked_body() {
+        let request = Request::from_bytes(Bytes::from_static(
+            b"POST / HTTP/1.1\r\nHost: test\r\nTransfer-Encoding: chunked\r\n\r\n\
+            4\r\nhell\r\nA\r\no, chunked\r\n0\r\n\r\n",
+        ))
+        .unwrap();
+        assert_eq!(b"hello, chunked".as_slice(), request.body().as_slice());
+    }
+
+    #[test]
+    fn optional_host() {
+        let request = Request::from_bytes(Bytes::from_static(b"GET / HTTP/1.0\r\n\r\n")).unwrap();
+        assert_eq!(Version::V1_0, request.version());
+        assert_eq!("", request.hostname());
+        assert_eq!(
+            Err(Error::NoHostHeader),
+            Request::from_bytes(Bytes::from_static(b"GET / HTTP/1.1\r\n\r\n"))
+        );
+    }
+
+    #[test]
+    fn keep_alive() {
+        let keep_alive = |version, connection| Request::determine_keep_alive(version, connection);
+        assert!(!keep_alive(Version::V1_0, None));
+        assert!(keep_alive(Version::V1_0, Some("Keep-Alive")));
+        assert!(keep_alive(Version::V1_1, None));
+        assert!(keep_alive(Version::V1_1, Some("keep-alive, Upgrade")));
+        assert!(!keep_alive(Version::V1_1, Some("Upgrade, close")));
+        assert!(!keep_alive(Version::V0_9, Some("keep-alive")));
+    }
+
     #[test]
     fn no_blank_line_new() {
         let expected = Err(Error::MissingBlankLine);
@@ -665,10 +1244,12 @@ mod tests {
             method: Method::GET,
             version: Version::V1_1,
             path: "/".to_string(),
+            raw_path: "/".to_string(),
             body: vec![],
-            headers: HashMap::from([("host".to_string(), "test".to_string())]),
+            headers: HeaderMap::from_iter([("host", "test")]),
             host: "test".to_string(),
             query_string: None,
+            query: QueryMap::new(),
             form_data: FormTypes::None,
             keep_alive: true,
         };
@@ -684,10 +1265,12 @@ mod tests {
             method: Method::GET,
             version: Version::V1_1,
             path: "/index.html".to_string(),
+            raw_path: "/index.html".to_string(),
             body: vec![],
-            headers: HashMap::from([("host".to_string(), "test".to_string())]),
+            headers: HeaderMap::from_iter([("host", "test")]),
             host: "test".to_string(),
             query_string: Some("test=true".to_string()),
+            query: QueryMap::from_iter([("test".to_string(), "true".to_string())]),
             form_data: FormTypes::None,
             keep_alive: true,
         };
@@ -698,20 +1281,98 @@ mod tests {
         assert_eq!(expected, request);
     }
 
+    #[test]
+    fn decoded_path() {
+        let request = Request::from_bytes(Bytes::from_static(
+            b"GET /caf%C3%A9//menu/./drinks/../food%20items?q=ice+cream HTTP/1.1\r\nHost: test\r\n\r\n",
+        ))
+        .expect("Error Parsing");
+        assert_eq!("/café/menu/food items", request.path());
+        assert_eq!(
+            "/caf%C3%A9//menu/./drinks/../food%20items",
+            request.raw_path()
+        );
+        assert_eq!(Some(&"q=ice+cream".to_string()), request.query_string());
+        assert_eq!(Some("ice cream"), request.query().get("q"));
+    }
+
+    #[test]
+    fn encoded_traversal() {
+        for path in [
+            "/../outside.txt",
+            "/%2e%2e/outside.txt",
+            "/%2E%2E/outside.txt",
+            "/sub/%2e%2e/%2e%2e/outside.txt",
+            "/..%2foutside.txt",
+            "/%2e%2e%2foutside.txt",
+            "/sub/.%2e/..%2F..",
+        ] {
+            assert_eq!(
+                Err(Error::PathOutsideRoot),
+                Request::decode_path(path),
+                "{path}"
+            );
+        }
+        for path in ["/index.html%00.png", "/%zz", "/%c0%ae%c0%ae/outside.txt"] {
+            assert_eq!(
+                Err(Error::InvalidUrlEncoding),
+                Request::decode_path(path),
+                "{path}"
+            );
+        }
+        assert_eq!(
+            Ok("/page.html".into()),
+            Request::decode_path("/sub/%2e%2e/page%2Ehtml")
+        );
+        // backslashes aren't separators here, static files reject them
+        assert_eq!(
+            Ok("/sub\\..\\outside.txt".into()),
+            Request::decode_path("/sub%5c..%5coutside.txt")
+        );
+        assert_eq!(StatusCode::FORBIDDEN, Error::PathOutsideRoot.status());
+    }
+
+    #[test]
+    fn multi_valued_query() {
+        let request = Request::from_bytes(Bytes::from_static(
+            b"GET /search?tag=a&debug&tag=b HTTP/1.1\r\nHost: test\r\n\r\n",
+        ))
+        .expect("Error Parsing");
+        let query = request.query();
+        assert_eq!(vec!["a", "b"], query.get_all("tag").collect::<Vec<_>>());
+        assert!(query.contains_key("debug"));
+    }
+
+    #[test]
+    fn invalid_url_encoding() {
+        for req in [
+            &b"GET /bad%zz HTTP/1.1\r\nHost: test\r\n\r\n"[..],
+            b"GET /bad%2 HTTP/1.1\r\nHost: test\r\n\r\n",
+            b"GET /nul%00.html HTTP/1.1\r\nHost: test\r\n\r\n",
+            b"GET /bad%FF HTTP/1.1\r\nHost: test\r\n\r\n",
+            b"GET /ok?q=%G1 HTTP/1.1\r\nHost: test\r\n\r\n",
+        ] {
+            let request = Request::from_bytes(Bytes::copy_from_slice(req));
+            assert_eq!(Err(Error::InvalidUrlEncoding), request);
+        }
+    }
+
     #[test]
     fn new_headers() {
         let expected = Request {
             method: Method::GET,
             version: Version::V1_1,
             path: "/".to_string(),
+            raw_path: "/".to_string(),
             body: vec![],
-            headers: HashMap::from([
-                ("host".to_string(), "test".to_string()),
-                ("header1".to_string(), "hi".to_string()),
-                ("header2".to_string(), "Bye".to_string()),
+            headers: HeaderMap::from_iter([
+                ("host", "test"),
+                ("header1", "hi"),
+                ("header2", "Bye"),
             ]),
             host: "test".to_string(),
             query_string: None,
+            query: QueryMap::new(),
             form_data: FormTypes::None,
             keep_alive: true,
         };
@@ -731,4 +1392,51 @@ mod tests {
             panic!("No error");
         }
     }
+
+    #[test]
+    fn repeated_headers() {
+        let request = Request::from_string(
+            "GET / HTTP/1.1\r\nHost:test\r\nCookie: a=1\r\nX-Forwarded-For: 1.1.1.1\r\n\
+            cookie: b=2\r\nx-forwarded-for:  2.2.2.2 \r\nX-Time: 12:30: now\r\n\r\n"
+                .to_owned(),
+        )
+        .expect("Error Parsing");
+        assert_eq!("test", request.hostname());
+        assert_eq!(
+            Some("a=1; b=2".to_string()),
+            request.get_header_value("COOKIE")
+        );
+        let forwarded: Vec<&str> = request.headers().get_all("X-Forwarded-For").collect();
+        assert_eq!(vec!["1.1.1.1", "2.2.2.2"], forwarded);
+        assert_eq!(Some("12:30: now"), request.headers().get("x-time"));
+    }
+
+    #[test]
+    fn invalid_headers() {
+        for req in [
+            "GET / HTTP/1.1\r\nHost: test\r\nX-Long: a\r\n  continued\r\n\r\n",
+            "GET / HTTP/1.1\r\nHost: test\r\nBad Name: value\r\n\r\n",
+            "GET / HTTP/1.1\r\nHost : test\r\n\r\n",
+            "GET / HTTP/1.1\r\nHost: test\r\nno colon\r\n\r\n",
+        ] {
+            let request = Request::from_string(req.to_owned());
+            assert_eq!(Err(Error::InvalidHeader), request, "{req:?}");
+        }
+    }
+
+    #[test]
+    fn typed_headers() {
+        use crate::headers::{Accept, Authorization, Bearer, Range};
+        let request = Request::from_string(
+            "GET / HTTP/1.1\r\nHost: test\r\nAuthorization: Bearer token123\r\n\
+            Accept: text/html\r\nAccept: */*;q=0.5\r\nRange: bytes=oops\r\n\r\n"
+                .to_owned(),
+        )
+        .expect("Error Parsing");
+        let auth = request.typed_header::<Authorization<Bearer>>().unwrap();
+        assert_eq!("token123", auth.credentials().token());
+        let accept = request.typed_header::<Accept>().unwrap();
+        assert_eq!(500, accept.quality("image/png"));
+        assert_eq!(None, request.typed_header::<Range>());
+    }
 }
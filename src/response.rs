@@ -1,17 +1,61 @@
-use std::convert::Infallible;
+use std::{
+    convert::Infallible,
+    fmt,
+    pin::Pin,
+    task::{Context, Poll},
+};
 
-use crate::http::{Header, IntoHeader, MimeType, StatusCode, Version};
+use crate::{
+    headers::TypedHeader,
+    http::{HeaderMap, IntoHeader, MimeType, StatusCode, Version},
+    websocket::Upgrade,
+};
 use anyhow;
+use futures::Stream;
 
 pub type ResponseBody = Vec<u8>;
 
+/// Body sent a chunk at a time as it's produced, eg. server-sent events
+/// the response is sent with Transfer-Encoding: chunked and ends with the stream,
+/// HTTP/1.0 clients don't know chunked so get the raw body and the connection is closed after
+pub struct BodyStream(Pin<Box<dyn Stream<Item = Vec<u8>> + Send>>);
+
+impl BodyStream {
+    pub fn new(stream: impl Stream<Item = Vec<u8>> + Send + 'static) -> Self {
+        BodyStream(Box::pin(stream))
+    }
+}
+
+impl Stream for BodyStream {
+    type Item = Vec<u8>;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        self.0.as_mut().poll_next(cx)
+    }
+}
+
+impl fmt::Debug for BodyStream {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("BodyStream")
+    }
+}
+
+/// Streams can't be compared so streamed responses never are equal
+impl PartialEq for BodyStream {
+    fn eq(&self, _: &Self) -> bool {
+        false
+    }
+}
+
 #[derive(Debug, PartialEq)]
 pub struct Response {
     version: Version,
     status: StatusCode,
     body: ResponseBody,
     mime: MimeType,
-    headers: Vec<Header>,
+    headers: HeaderMap,
+    upgrade: Option<Upgrade>,
+    stream: Option<BodyStream>,
 }
 
 pub trait IntoResponse {
@@ -37,7 +81,9 @@ impl Response {
             mime,
             body,
             version,
-            headers: vec![],
+            headers: HeaderMap::new(),
+            upgrade: None,
+            stream: None,
         }
     }
 
@@ -49,7 +95,9 @@ impl Response {
             body,
             version,
             mime,
-            headers: vec![],
+            headers: HeaderMap::new(),
+            upgrade: None,
+            stream: None,
         }
     }
 
@@ -57,47 +105,154 @@ impl Response {
         self.mime = mime;
     }
 
-    pub fn to_send_buffer(&self) -> Vec<u8> {
-        //transform response to array of bytes to be sent
+    pub fn body(&self) -> &[u8] {
+        &self.body
+    }
+
+    pub fn set_body(&mut self, body: ResponseBody) {
+        self.body = body;
+    }
+
+    /// Move the body out, leaving it empty
+    pub(crate) fn take_body(&mut self) -> ResponseBody {
+        std::mem::take(&mut self.body)
+    }
+
+    /// Send stream as the body instead of the buffered one
+    pub fn set_stream(&mut self, stream: BodyStream) {
+        self.stream = Some(stream);
+    }
+
+    pub fn is_streamed(&self) -> bool {
+        self.stream.is_some()
+    }
+
+    pub(crate) fn take_stream(&mut self) -> Option<BodyStream> {
+        self.stream.take()
+    }
+
+    /// Streamed body framed with Transfer-Encoding: chunked, otherwise it ends when the
+    /// connection is closed
+    pub(crate) fn is_chunked(&self) -> bool {
+        self.is_streamed() && self.version >= Version::V1_1
+    }
+
+    /// Status line and headers including the blank line
+    /// Content-Length is always computed from the body, a Content-Type header overrides mime
+    /// streamed bodies are chunked instead, or have neither before 1.1
+    /// 1xx, 204 and 304 responses have no body so get neither (RFC 7230 3.3.2)
+    /// HTTP/0.9 responses are only the body
+    fn head(&self) -> String {
+        if self.version == Version::V0_9 {
+            return String::new();
+        }
         let status: &str = &self.status.to_string();
         let length = self.body.len();
         let version: &str = self.version.into();
-        let content_type: String = String::from(&self.mime);
-        let mut headers_string = "".to_string();
+        let mut head = format!("{version} {status}\r\n");
+        if self.has_body() {
+            if self.is_chunked() {
+                head.push_str("Transfer-Encoding: chunked\r\n");
+            } else if !self.is_streamed() {
+                head.push_str(&format!("Content-Length: {length}\r\n"));
+            }
+            if !self.headers.contains("content-type") {
+                let content_type: String = String::from(&self.mime);
+                head.push_str(&format!("Content-Type: {content_type}\r\n"));
+            }
+        }
         for header in &self.headers {
+            if header.key == "content-length" || header.key == "transfer-encoding" {
+                continue;
+            }
             let header_string: String = String::from(header);
-            headers_string.push_str(&header_string);
-            headers_string.push_str("\r\n");
+            head.push_str(&header_string);
+            head.push_str("\r\n");
         }
-        let mut buffer: Vec<u8> = Vec::new();
-        let response = format!(
-            "{version} {status}\r\n\
-            Content-Length: {length}\r\n\
-            Content-Type: {content_type}\r\n\
-            {headers_string}\r\n"
-        );
-        buffer.append(&mut response.into_bytes());
-        for byte in &self.body {
-            buffer.push(*byte);
+        head.push_str("\r\n");
+        head
+    }
+
+    pub(crate) fn has_body(&self) -> bool {
+        !(self.status.is_informational()
+            || self.status == StatusCode::NO_CONTENT
+            || self.status == StatusCode::NOT_MODIFIED)
+    }
+
+    /// Only the head for streamed responses
+    pub fn to_send_buffer(&self) -> Vec<u8> {
+        //transform response to array of bytes to be sent
+        let mut buffer: Vec<u8> = self.head().into_bytes();
+        if !self.is_streamed() {
+            buffer.extend_from_slice(&self.body);
         }
         buffer
     }
 
+    /// Add header, keeps existing headers with the same name eg. multiple Set-Cookie
     pub fn add_header(&mut self, value: impl IntoHeader) {
-        let header = value.into_header();
-        self.headers.push(header);
+        self.headers.append(value);
+    }
+
+    /// Set header replacing any existing headers with the same name
+    pub fn set_header(&mut self, value: impl IntoHeader) {
+        self.headers.insert(value);
+    }
+
+    /// Set typed header replacing any existing headers with the same name
+    pub fn set_typed<T: TypedHeader>(&mut self, header: T) {
+        self.headers.set_typed(header);
+    }
+
+    pub fn typed_header<T: TypedHeader>(&self) -> Option<T> {
+        self.headers.typed()
+    }
+
+    /// Add field to the Vary header unless it's already listed
+    pub fn add_vary(&mut self, field: &str) {
+        let vary = self.headers.get_joined("vary");
+        match vary {
+            Some(vary)
+                if vary
+                    .split(',')
+                    .any(|f| f.trim() == "*" || f.trim().eq_ignore_ascii_case(field)) => {}
+            Some(vary) => self.set_header(("Vary", format!("{vary}, {field}").as_str())),
+            None => self.set_header(("Vary", field)),
+        }
+    }
+
+    pub fn headers(&self) -> &HeaderMap {
+        &self.headers
+    }
+
+    pub fn headers_mut(&mut self) -> &mut HeaderMap {
+        &mut self.headers
     }
 
     pub fn version(&self) -> Version {
         self.version
     }
 
+    /// Version the response is written in, the server matches it to the request
+    pub fn set_version(&mut self, version: Version) {
+        self.version = version;
+    }
+
     pub fn status(&self) -> StatusCode {
         self.status
     }
 
     pub fn mime(&self) -> MimeType {
-        self.mime
+        self.mime.clone()
+    }
+
+    /// Take over the connection once this response is written, used for WebSocket routes
+    pub fn set_upgrade(&mut self, upgrade: Upgrade) {
+        self.upgrade = Some(upgrade);
+    }
+
+    pub(crate) fn take_upgrade(&mut self) -> Option<Upgrade> {
+        self.upgrade.take()
     }
 }
 
@@ -108,7 +263,9 @@ impl From<Vec<u8>> for Response {
             body: bytes,
             mime: MimeType::Binary,
             version: Version::V1_1,
-            headers: vec![],
+            headers: HeaderMap::new(),
+            upgrade: None,
+            stream: None,
         }
     }
 }
@@ -120,7 +277,9 @@ impl From<String> for Response {
             body: string.into(),
             mime: MimeType::HTML,
             version: Version::V1_1,
-            headers: vec![],
+            headers: HeaderMap::new(),
+            upgrade: None,
+            stream: None,
         }
     }
 }
@@ -132,7 +291,9 @@ impl From<&str> for Response {
             body: string.into(),
             mime: MimeType::HTML,
             version: Version::V1_1,
-            headers: vec![],
+            headers: HeaderMap::new(),
+            upgrade: None,
+            stream: None,
         }
     }
 }
@@ -146,7 +307,9 @@ impl From<anyhow::Error> for Response {
             body: message.into(),
             mime: MimeType::HTML,
             version: Version::V1_1,
-            headers: vec![],
+            headers: HeaderMap::new(),
+            upgrade: None,
+            stream: None,
         }
     }
 }
@@ -159,25 +322,8 @@ impl From<Infallible> for Response {
 
 impl From<Response> for String {
     fn from(response: Response) -> String {
-        let status: &str = response.status.as_str();
-        let length = response.body.len();
-        let version: &str = response.version.into();
         let body: &str = &String::from_utf8_lossy(&response.body);
-        let content_type: String = response.mime.into();
-        let mut headers_string = "".to_string();
-        for header in response.headers {
-            let header_string: String = header.into();
-            headers_string.push_str(&header_string);
-            headers_string.push_str("\r\n");
-        }
-        let response = format!(
-            "{version} {status}\r\n\
-            Content-Length: {length}\r\n\
-            Content-Type: {content_type}\r\n\
-            {headers_string}\r\n\
-            {body}"
-        );
-        response
+        format!("{}{body}", response.head())
     }
 }
 
@@ -188,7 +334,9 @@ impl From<std::io::Error> for Response {
             body: error.to_string().into(),
             mime: MimeType::HTML,
             version: Version::V1_1,
-            headers: vec![],
+            headers: HeaderMap::new(),
+            upgrade: None,
+            stream: None,
         }
     }
 }
@@ -206,7 +354,9 @@ impl From<StatusCode> for Response {
             status: value,
             body,
             mime: MimeType::HTML,
-            headers: vec![],
+            headers: HeaderMap::new(),
+            upgrade: None,
+            stream: None,
         }
     }
 }
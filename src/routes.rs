@@ -1,16 +1,30 @@
 use crate::{
-    http::{self, Header, Method, MimeType},
+    acme::Http01Challenges,
+    autoindex,
+    compression::{self, CompressionConfig, Encoding},
+    conditional::{self, Precondition},
+    error_pages::{ErrorHandler, ErrorPages},
+    headers::{AcceptEncoding, ETag, LastModified},
+    http::{self, Header, HeaderMap, Method, MimeType},
+    multipart::MultipartConfig,
+    range::{self, RangeOutcome},
     request::Request,
     response::{IntoResponse, Response},
     state::{FromRequest, State},
+    static_files::{AutoIndex, StaticConfig},
+    utils,
+    websocket::{self, SocketResolver, Upgrade},
 };
 use async_trait::async_trait;
 use enum_map::{enum_map, EnumMap};
+use futures::FutureExt;
 use std::{
     collections::HashMap,
     future::Future,
+    panic::AssertUnwindSafe,
     path::{Path, PathBuf},
     sync::Arc,
+    time::Duration,
     vec,
 };
 use tokio::sync::RwLock;
@@ -39,16 +53,30 @@ where
 }
 
 pub enum RouteResolver<S> {
-    Static { file_path: String },
+    Static {
+        file_path: String,
+    },
     Redirect(String),
     Function(Arc<Box<dyn RequestResolver<S>>>),
-    Embed(&'static [u8], MimeType),
+    /// body, its mime type and the ETag hashed from it once when the route is made
+    Embed(&'static [u8], MimeType, ETag),
+    WebSocket {
+        resolver: Arc<Box<dyn SocketResolver<S>>>,
+        protocols: Vec<String>,
+    },
 }
 
+/// Check of a request head before its body is read, the Err status is sent instead of reading
+/// the body, its error handler can fill in the page
+pub type HeadCheck = Arc<dyn Fn(&Request) -> Result<(), http::StatusCode> + Send + Sync>;
+
 pub struct Route<S> {
     method: Method,
     path: String,
     resolver: RouteResolver<S>,
+    timeout: Option<Duration>,
+    max_body_size: Option<usize>,
+    check: Option<HeadCheck>,
 }
 
 pub type Routes<R> = Arc<RwLock<EnumMap<Method, HashMap<String, Route<R>>>>>;
@@ -59,6 +87,14 @@ pub struct Router<S> {
     state: State<S>,
     mime_headers: Vec<(MimeType, Header)>,
     default_headers: Vec<Header>,
+    acme_challenges: Option<Http01Challenges>,
+    static_config: StaticConfig,
+    compression: Option<CompressionConfig>,
+    error_pages: ErrorPages,
+    handler_timeout: Option<Duration>,
+    timeout_status: http::StatusCode,
+    max_body_size: Option<usize>,
+    multipart: MultipartConfig,
 }
 
 impl<S> Router<S>
@@ -75,6 +111,14 @@ where
             state: State(state),
             mime_headers: vec![],
             default_headers: Header::new_server(), // default server headers. server sw name
+            acme_challenges: None,
+            static_config: StaticConfig::default(),
+            compression: None,
+            error_pages: ErrorPages::new(),
+            handler_timeout: None,
+            timeout_status: http::StatusCode::SERVICE_UNAVAILABLE,
+            max_body_size: None,
+            multipart: MultipartConfig::default(),
         }
     }
 
@@ -101,21 +145,190 @@ where
         self.mime_headers.push((mime, header));
     }
 
-    /// Add default and mime headers to req
+    /// Answer ACME HTTP-01 challenges before any other route
+    /// needed on the port 80 router when certificates use [`crate::acme::ChallengeType::Http01`]
     #[tracing::instrument(level = "debug", skip(self))]
-    fn push_headers(&self, response: &mut Response) {
-        //FIXME: Do we need to worry about duplicates ?
-        //add default headers first then mime specific ones
-        for header in &self.default_headers {
-            response.add_header(header);
-        }
+    pub fn add_acme_challenges(&mut self, challenges: Http01Challenges) {
+        self.acme_challenges = Some(challenges);
+    }
 
-        let mime = response.mime();
-        for (key, header) in &self.mime_headers {
-            if key == &mime {
-                response.add_header(header);
+    /// How files from the doc root are served, eg. dot files and symlinks
+    #[tracing::instrument(level = "debug", skip(self))]
+    pub fn set_static_config(&mut self, config: StaticConfig) {
+        self.static_config = config;
+    }
+
+    pub fn static_config(&self) -> &StaticConfig {
+        &self.static_config
+    }
+
+    /// Serve static files ending in extension as mime, eg. ("mjs", MimeType::JavaScript)
+    /// only changes the router's own [`StaticConfig`], a virtual host given its own with
+    /// [`crate::virtual_host::VirtualHost::set_static_config`] keeps that config's types
+    #[tracing::instrument(level = "debug", skip(self))]
+    pub fn set_mime_type(&mut self, extension: &str, mime: MimeType) {
+        self.static_config.set_mime_type(extension, mime);
+    }
+
+    /// Compress responses for clients that send Accept-Encoding, off by default
+    #[tracing::instrument(level = "debug", skip(self))]
+    pub fn set_compression(&mut self, config: CompressionConfig) {
+        self.compression = Some(config);
+    }
+
+    pub fn compression(&self) -> Option<&CompressionConfig> {
+        self.compression.as_ref()
+    }
+
+    /// Replace the response for an error status, eg. serve 404.html for every 404
+    #[tracing::instrument(level = "debug", skip(self, handler))]
+    pub fn add_error_handler(&mut self, status: http::StatusCode, handler: ErrorHandler) {
+        self.error_pages.add(status, handler);
+    }
+
+    pub fn error_pages(&self) -> &ErrorPages {
+        &self.error_pages
+    }
+
+    /// Abort handlers that take longer than this, routes with their own timeout use that instead
+    #[tracing::instrument(level = "debug", skip(self))]
+    pub fn set_handler_timeout(&mut self, timeout: Duration) {
+        self.handler_timeout = Some(timeout);
+    }
+
+    pub fn handler_timeout(&self) -> Option<Duration> {
+        self.handler_timeout
+    }
+
+    /// Status sent when a handler times out, 503 by default, eg. 504 behind a gateway
+    /// the body can be changed with an error handler for the same status
+    #[tracing::instrument(level = "debug", skip(self))]
+    pub fn set_timeout_status(&mut self, status: http::StatusCode) {
+        self.timeout_status = status;
+    }
+
+    pub fn timeout_status(&self) -> http::StatusCode {
+        self.timeout_status
+    }
+
+    /// Bodies over size get 413 without being read, routes with their own limit use that instead
+    #[tracing::instrument(level = "debug", skip(self))]
+    pub fn set_max_body_size(&mut self, size: usize) {
+        self.max_body_size = Some(size);
+    }
+
+    pub fn max_body_size(&self) -> Option<usize> {
+        self.max_body_size
+    }
+
+    /// Limits for multipart/form-data bodies and where file uploads are spooled
+    #[tracing::instrument(level = "debug", skip(self))]
+    pub fn set_multipart_config(&mut self, config: MultipartConfig) {
+        self.multipart = config;
+    }
+
+    pub fn multipart_config(&self) -> &MultipartConfig {
+        &self.multipart
+    }
+
+    /// Multipart config for request's body, an unset total follows the body limit of its route
+    pub(crate) async fn multipart_config_for(&self, request: &Request) -> MultipartConfig {
+        self.multipart
+            .with_body_limit(self.body_limit(request).await)
+    }
+
+    /// Final response for a request whose body hasn't been read yet, None when the body is wanted
+    /// used to answer Expect: 100-continue (RFC 7231 5.1.1), so 417 for any other expectation,
+    /// 413 over the body size limit or what the route's check rejected with
+    #[tracing::instrument(level = "debug", skip(self))]
+    pub async fn check_head(&self, request: &Request) -> Option<Response> {
+        if let Some(expect) = request.get_header_value("Expect") {
+            if !expect.eq_ignore_ascii_case("100-continue") {
+                return Some(Response::error(
+                    http::StatusCode::EXPECTATION_FAILED,
+                    "Expectation Failed".into(),
+                ));
+            }
+        }
+        let routes = self.routes();
+        let routes_locked = &routes.read().await[*request.method()];
+        let route = Self::find_route(routes_locked, request.path());
+        let limit = route.and_then(|r| r.max_body_size).or(self.max_body_size);
+        // a chunked body has no length up front, only once it was read
+        let length = match request.get_header_value("Content-Length") {
+            Some(length) => length.parse::<usize>().ok(),
+            None => Some(request.body().len()),
+        };
+        if let (Some(limit), Some(length)) = (limit, length) {
+            if length > limit {
+                return Some(Response::error(
+                    http::StatusCode::PAYLOAD_TOO_LARGE,
+                    "Payload Too Large".into(),
+                ));
             }
         }
+        route
+            .and_then(|route| route.check.as_ref())
+            .and_then(|check| check(request).err())
+            .map(|status| {
+                let reason = status.canonical_reason().unwrap_or("");
+                Response::error(status, reason.into())
+            })
+    }
+
+    /// Largest body the route for request takes, the router's limit if the route has none
+    pub async fn body_limit(&self, request: &Request) -> Option<usize> {
+        let routes = self.routes();
+        let routes_locked = &routes.read().await[*request.method()];
+        Self::find_route(routes_locked, request.path())
+            .and_then(|route| route.max_body_size)
+            .or(self.max_body_size)
+    }
+
+    /// A handler route would read request's body, otherwise it can be answered from the head
+    pub async fn accepts_body(&self, request: &Request) -> bool {
+        let routes = self.routes();
+        let routes_locked = &routes.read().await[*request.method()];
+        matches!(
+            Self::find_route(routes_locked, request.path()).map(Route::resolver),
+            Some(RouteResolver::Function(_))
+        )
+    }
+
+    /// Run the error handler for response's status, request is None if it couldn't be parsed
+    #[tracing::instrument(level = "debug", skip(self, doc_root))]
+    pub async fn handle_error(
+        &self,
+        request: Option<&Request>,
+        doc_root: impl AsRef<Path>,
+        response: Response,
+    ) -> Response {
+        let mut response = self
+            .error_pages
+            .apply(request, doc_root.as_ref(), &self.static_config, response)
+            .await;
+        self.push_headers(&mut response);
+        response
+    }
+
+    /// Add default and mime headers to req
+    /// headers already set on the response win over mime specific ones, which win over defaults
+    #[tracing::instrument(level = "debug", skip(self))]
+    fn push_headers(&self, response: &mut Response) {
+        let mime = response.mime();
+        let mut headers: HeaderMap = self
+            .mime_headers
+            .iter()
+            .filter(|(key, _)| key == &mime)
+            .map(|(_, header)| header)
+            .collect();
+        let defaults: HeaderMap = self
+            .default_headers
+            .iter()
+            .filter(|header| !headers.contains(&header.key))
+            .collect();
+        headers.extend_missing(defaults);
+        response.headers_mut().extend_missing(headers);
     }
 
     pub fn new_routes() -> Routes<S> {
@@ -125,45 +338,65 @@ where
         Arc::new(RwLock::new(map))
     }
 
+    /// Route request and compress the body, conditional requests for static and embedded files
+    /// are answered with 304 or 412, handlers can use [`conditional::evaluate`] themselves
     #[tracing::instrument(level = "debug", skip(self, doc_root))]
     pub async fn route(&self, request: &Request, doc_root: impl AsRef<Path>) -> Response {
-        let routes = self.routes();
-        let routes_locked = &routes.read().await[*request.method()];
-        let mut matching_route = None;
+        self.route_with(request, doc_root, &self.static_config, None)
+            .await
+    }
 
-        //look for route mathcing requested URL
-        if let Some(route) = routes_locked.get(request.path()) {
-            //found exact route match
-            matching_route = Some(route);
-        } else {
-            // go through ancestors appending * on the end and see if we have any matches
-            let path = Path::new(request.path());
-            if let Some(parent) = path.parent() {
-                let ancestors = parent.ancestors();
-                for a in ancestors {
-                    if let Some(globed) = a.join("*").to_str() {
-                        if let Some(route) = routes_locked.get(globed) {
-                            matching_route = Some(route);
-                        }
-                    }
-                }
-            } else {
-                //no parent so its root, check for catch all bare *
-                if let Some(route) = routes_locked.get("*") {
-                    matching_route = Some(route);
-                }
+    /// Route request serving static files with static_config instead of the router's own,
+    /// used for virtual hosts with their own settings
+    /// error_pages are tried before the router's own error handlers
+    #[tracing::instrument(level = "debug", skip(self, doc_root, static_config, error_pages))]
+    pub async fn route_with(
+        &self,
+        request: &Request,
+        doc_root: impl AsRef<Path>,
+        static_config: &StaticConfig,
+        error_pages: Option<&ErrorPages>,
+    ) -> Response {
+        let doc_root = doc_root.as_ref();
+        let mut response = self.resolve(request, doc_root, static_config).await;
+        let status = response.status();
+        for pages in error_pages.into_iter().chain([&self.error_pages]) {
+            if pages.get(status).is_some() {
+                response = pages
+                    .apply(Some(request), doc_root, static_config, response)
+                    .await;
+                break;
             }
         }
+        self.push_headers(&mut response);
+        match &self.compression {
+            Some(compression) => compression.compress(request, response).await,
+            None => response,
+        }
+    }
+
+    async fn resolve(
+        &self,
+        request: &Request,
+        doc_root: &Path,
+        static_config: &StaticConfig,
+    ) -> Response {
+        if let Some(challenges) = &self.acme_challenges {
+            if let Some(response) = challenges.respond(request).await {
+                return response;
+            }
+        }
+        let routes = self.routes();
+        let routes_locked = &routes.read().await[*request.method()];
+        let matching_route = Self::find_route(routes_locked, request.path());
 
         //serve specific route if we match
         if let Some(route) = matching_route {
             tracing::debug!("Found matching route");
             match route.resolver() {
                 RouteResolver::Static { file_path } => {
-                    let path = doc_root.as_ref().join(file_path);
-                    let mut res = Self::get_file(path).await;
-                    self.push_headers(&mut res);
-                    res
+                    self.serve_static(doc_root, file_path, request, static_config)
+                        .await
                 }
                 RouteResolver::Redirect(redirect_to) => {
                     let mut response = Response::new(
@@ -171,62 +404,260 @@ where
                         vec![],
                         MimeType::PlainText,
                     );
-                    self.push_headers(&mut response);
-                    response.add_header(("Location", redirect_to));
+                    response.set_header(("Location", redirect_to));
                     response
                 }
                 RouteResolver::Function(resolver) => {
                     let resolver = resolver.clone();
-                    let mut response = resolver
-                        .resolve(self.state.clone(), request.to_owned())
-                        .await;
-                    self.push_headers(&mut response);
-                    response
+                    // a panicking handler shouldn't take the connection down with it
+                    let resolved =
+                        AssertUnwindSafe(resolver.resolve(self.state.clone(), request.to_owned()))
+                            .catch_unwind();
+                    let resolved = match route.timeout().or(self.handler_timeout) {
+                        Some(limit) => match tokio::time::timeout(limit, resolved).await {
+                            Ok(resolved) => resolved,
+                            Err(_) => {
+                                tracing::warn!(
+                                    route = route.path(),
+                                    method = %request.method(),
+                                    path = request.path(),
+                                    "handler timed out after {limit:?}"
+                                );
+                                let reason = self.timeout_status.canonical_reason().unwrap_or("");
+                                return Response::error(self.timeout_status, reason.into());
+                            }
+                        },
+                        None => resolved.await,
+                    };
+                    match resolved {
+                        Ok(response) => response,
+                        Err(panic) => {
+                            tracing::error!(
+                                method = %request.method(),
+                                path = request.path(),
+                                "handler panicked: {}",
+                                utils::panic_message(panic.as_ref())
+                            );
+                            Response::error(
+                                http::StatusCode::INTERNAL_SERVER_ERROR,
+                                "Internal Server Error".into(),
+                            )
+                        }
+                    }
                 }
-                RouteResolver::Embed(body, mime_type) => {
+                RouteResolver::Embed(body, mime_type, etag) => {
                     let mut response =
-                        Response::new(http::StatusCode::OK, body.to_vec(), *mime_type);
-                    self.push_headers(&mut response);
+                        Response::new(http::StatusCode::OK, body.to_vec(), mime_type.clone());
+                    response.set_typed(etag.clone());
+                    conditional::apply(request, response)
+                }
+                RouteResolver::WebSocket {
+                    resolver,
+                    protocols,
+                } => {
+                    let mut response = websocket::handshake(request, protocols);
+                    if response.status() == http::StatusCode::SWITCHING_PROTOCOLS {
+                        // handler timeouts don't apply, the socket lives as long as it needs
+                        let resolver = resolver.clone();
+                        let state = self.state.clone();
+                        let request = request.to_owned();
+                        let protocol = response
+                            .headers()
+                            .get("Sec-WebSocket-Protocol")
+                            .map(str::to_string);
+                        response.set_upgrade(Upgrade::new(protocol, move |socket| async move {
+                            resolver.run(state, request, socket).await
+                        }));
+                    }
                     response
                 }
             }
         } else {
             tracing::debug!("Trying static file serve");
-            let mut file_path = PathBuf::from(request.path());
-            if file_path.is_absolute() {
-                if let Ok(path) = file_path.strip_prefix("/") {
-                    file_path = path.to_path_buf();
-                } else {
-                    let mut response =
-                        Response::error(http::StatusCode::NOT_FOUND, "File Not Found".into());
-                    self.push_headers(&mut response);
-                    return response;
+            self.serve_static(doc_root, request.path(), request, static_config)
+                .await
+        }
+    }
+
+    /// Route for path, an exact match or the wild card of an ancestor
+    fn find_route<'a>(routes: &'a HashMap<String, Route<S>>, path: &str) -> Option<&'a Route<S>> {
+        let mut matching_route = None;
+
+        //look for route mathcing requested URL
+        if let Some(route) = routes.get(path) {
+            //found exact route match
+            matching_route = Some(route);
+        } else {
+            // go through ancestors appending * on the end and see if we have any matches
+            let path = Path::new(path);
+            if let Some(parent) = path.parent() {
+                let ancestors = parent.ancestors();
+                for a in ancestors {
+                    if let Some(globed) = a.join("*").to_str() {
+                        if let Some(route) = routes.get(globed) {
+                            matching_route = Some(route);
+                        }
+                    }
                 }
+            } else {
+                //no parent so its root, check for catch all bare *
+                if let Some(route) = routes.get("*") {
+                    matching_route = Some(route);
+                }
+            }
+        }
+        matching_route
+    }
+
+    /// Serve a file under root, request_path is checked so it can't escape root
+    #[tracing::instrument(level = "debug", skip(self, request, config))]
+    async fn serve_static(
+        &self,
+        root: &Path,
+        request_path: &str,
+        request: &Request,
+        config: &StaticConfig,
+    ) -> Response {
+        let path = match config.resolve(root, request_path).await {
+            Ok(path) => path,
+            Err(status) => {
+                tracing::warn!("static path rejected: {request_path} {status}");
+                let message = match status {
+                    http::StatusCode::FORBIDDEN => "Permission Denied",
+                    http::StatusCode::BAD_REQUEST => "Invalid Path",
+                    _ => "Static File Not Found",
+                };
+                return Response::error(status, message.into());
+            }
+        };
+        match tokio::fs::metadata(&path).await {
+            Ok(metadata) if metadata.is_dir() => {
+                Self::serve_dir(root, path, request_path, request, config).await
+            }
+            _ => Self::get_file(path, request, config).await,
+        }
+    }
+
+    /// Redirect to add a trailing slash, then serve the first index file or a listing
+    #[tracing::instrument(level = "debug", skip(request, config))]
+    async fn serve_dir(
+        root: &Path,
+        path: PathBuf,
+        request_path: &str,
+        request: &Request,
+        config: &StaticConfig,
+    ) -> Response {
+        // only redirect urls, a static route mapped onto a directory is served as is
+        if !request_path.ends_with('/') && request_path == request.path() {
+            let mut location = utils::percent_encode_path(request.path());
+            location.push('/');
+            if let Some(query) = request.query_string() {
+                location.push('?');
+                location.push_str(query);
+            }
+            let mut response = Response::new(
+                http::StatusCode::MOVED_PERMANENTLY,
+                vec![],
+                MimeType::PlainText,
+            );
+            response.set_header(("Location", location.as_str()));
+            return response;
+        }
+        let dir_path = request_path.trim_end_matches('/');
+        for index in config.index_files() {
+            let index_path = format!("{dir_path}/{index}");
+            let Ok(index) = config.resolve(root, &index_path).await else {
+                continue;
+            };
+            if let Ok(metadata) = tokio::fs::metadata(&index).await {
+                if metadata.is_file() {
+                    return Self::get_file(index, request, config).await;
+                }
+            }
+        }
+        if config.autoindex() == AutoIndex::Off {
+            return Response::error(http::StatusCode::NOT_FOUND, "Static File Not Found".into());
+        }
+        match autoindex::listing(root, &path, &format!("{dir_path}/"), config, request).await {
+            Ok(response) => response,
+            Err(err) => {
+                tracing::warn!("directory listing error: {err}");
+                Response::error(
+                    http::StatusCode::INTERNAL_SERVER_ERROR,
+                    "Directory Listing Failed".into(),
+                )
             }
-            let final_path = doc_root.as_ref().join(file_path);
-            let mut response = Self::get_file(final_path).await;
-            self.push_headers(&mut response);
-            response
         }
     }
 
-    #[tracing::instrument(level = "debug")]
-    async fn get_file(path: PathBuf) -> Response {
-        match tokio::fs::read(&path).await {
-            Ok(contents) => {
-                let mime: MimeType = path.into();
-                Response::new(http::StatusCode::OK, contents, mime)
+    /// Serve file with ETag and Last-Modified, the file isn't read if the client's copy is current
+    /// and only the requested bytes are read for range requests
+    /// a precompressed sibling is served instead when the client accepts its encoding
+    #[tracing::instrument(level = "debug", skip(request, config))]
+    async fn get_file(path: PathBuf, request: &Request, config: &StaticConfig) -> Response {
+        // content type always comes from the original file, the sibling only sets the encoding
+        let mime = config.file_mime_type(&path).await;
+        let variants = config.precompressed_variants(&path).await;
+        let accepted = request.typed_header::<AcceptEncoding>().and_then(|accept| {
+            let encodings: Vec<Encoding> = variants.iter().map(|(e, _)| *e).collect();
+            compression::negotiate(&encodings, &accept)
+        });
+        let (path, encoding) = match variants.iter().find(|(e, _)| Some(*e) == accepted) {
+            Some((encoding, sibling)) => (sibling.clone(), Some(*encoding)),
+            None => (path, None),
+        };
+        let file = async {
+            let metadata = tokio::fs::metadata(&path).await?;
+            let etag = conditional::file_etag(&metadata);
+            let last_modified = metadata.modified().ok();
+            let mut response = match conditional::evaluate(request, Some(&etag), last_modified) {
+                Precondition::Failed => {
+                    return Ok(Response::error(
+                        http::StatusCode::PRECONDITION_FAILED,
+                        "Precondition Failed".into(),
+                    ))
+                }
+                Precondition::NotModified => {
+                    Response::new(http::StatusCode::NOT_MODIFIED, vec![], mime)
+                }
+                Precondition::Passed => {
+                    let len = metadata.len();
+                    match range::evaluate(request, len, Some(&etag), last_modified) {
+                        RangeOutcome::Full => {
+                            let contents = tokio::fs::read(&path).await?;
+                            Response::new(http::StatusCode::OK, contents, mime)
+                        }
+                        RangeOutcome::Partial(ranges) => {
+                            range::partial_file(&path, &ranges, len, mime).await?
+                        }
+                        RangeOutcome::Unsatisfiable => range::unsatisfiable(len),
+                    }
+                }
+            };
+            response.set_header(("Accept-Ranges", "bytes"));
+            response.set_typed(etag);
+            if let Some(modified) = last_modified {
+                response.set_typed(LastModified::new(modified));
+            }
+            if let Some(encoding) = encoding.filter(|_| response.status().is_success()) {
+                response.set_header(("Content-Encoding", encoding.as_str()));
             }
+            if !variants.is_empty() {
+                response.add_vary("Accept-Encoding");
+            }
+            Ok::<_, std::io::Error>(response)
+        };
+        match file.await {
+            Ok(response) => response,
             Err(err) => {
                 tracing::warn!("static load error:{}", err.to_string());
                 match err.kind() {
                     std::io::ErrorKind::PermissionDenied => {
                         Response::error(http::StatusCode::FORBIDDEN, "Permission Denied".into())
                     }
-                    _ => Response::error(
-                        http::StatusCode::NOT_FOUND,
-                        "Static File Not Found".into(),
-                    ),
+                    _ => {
+                        Response::error(http::StatusCode::NOT_FOUND, "Static File Not Found".into())
+                    }
                 }
             }
         }
@@ -244,6 +675,9 @@ where
             path: path.to_string(),
             resolver: RouteResolver::Redirect(redirect_url.to_string()),
             method,
+            timeout: None,
+            max_body_size: None,
+            check: None,
         }
     }
 
@@ -254,6 +688,9 @@ where
             path: "*".to_string(),
             resolver: RouteResolver::Redirect(redirect_url.to_string()),
             method,
+            timeout: None,
+            max_body_size: None,
+            check: None,
         }
     }
 
@@ -267,6 +704,9 @@ where
             path: path.to_string(),
             resolver,
             method,
+            timeout: None,
+            max_body_size: None,
+            check: None,
         }
     }
 
@@ -280,6 +720,9 @@ where
             path: path.to_string(),
             resolver,
             method,
+            timeout: None,
+            max_body_size: None,
+            check: None,
         }
     }
 
@@ -287,11 +730,14 @@ where
     /// when this route is requested the static data is return with the passes mime type
     pub fn embed(path: &str, body: &'static [u8], mime: MimeType) -> Self {
         let method = Method::GET;
-        let resolver = RouteResolver::Embed(body, mime);
+        let resolver = RouteResolver::Embed(body, mime, conditional::content_etag(body));
         Route {
             method,
             path: path.into(),
             resolver,
+            timeout: None,
+            max_body_size: None,
+            check: None,
         }
     }
 
@@ -309,9 +755,90 @@ where
             path: path.to_string(),
             resolver,
             method,
+            timeout: None,
+            max_body_size: None,
+            check: None,
         }
     }
 
+    /// WebSocket at path, handler gets the socket once the handshake is done
+    /// ```ignore
+    /// async fn echo(_: (), _: Request, mut socket: WebSocket) {
+    ///     while let Some(Ok(Message::Text(text))) = socket.recv().await {
+    ///         let _ = socket.send_text(text).await;
+    ///     }
+    /// }
+    /// Route::websocket("/echo", echo)
+    /// ```
+    pub fn websocket<R>(path: &str, func: R) -> Self
+    where
+        R: SocketResolver<S>,
+    {
+        let method = Method::GET;
+        let resolver = RouteResolver::WebSocket {
+            resolver: Arc::new(Box::new(func)),
+            protocols: vec![],
+        };
+        Route {
+            path: path.to_string(),
+            resolver,
+            method,
+            timeout: None,
+            max_body_size: None,
+            check: None,
+        }
+    }
+
+    /// Subprotocols a websocket route speaks, most preferred first, ignored for other routes
+    pub fn with_protocols(mut self, protocols: &[&str]) -> Self {
+        if let RouteResolver::WebSocket {
+            protocols: ref mut supported,
+            ..
+        } = self.resolver
+        {
+            *supported = protocols.iter().map(|p| p.to_string()).collect();
+        }
+        self
+    }
+
+    /// Abort this route's handler after timeout, overrides the router's handler timeout
+    pub fn with_timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = Some(timeout);
+        self
+    }
+
+    pub fn timeout(&self) -> Option<Duration> {
+        self.timeout
+    }
+
+    /// Bodies over size get 413 without being read, overrides the router's limit
+    pub fn with_max_body_size(mut self, size: usize) -> Self {
+        self.max_body_size = Some(size);
+        self
+    }
+
+    pub fn max_body_size(&self) -> Option<usize> {
+        self.max_body_size
+    }
+
+    /// Check the request head before the body is read, eg. authentication
+    /// the Err status is sent instead of reading the body and running the handler
+    /// ```ignore
+    /// Route::post("/upload", upload).with_check(|request| {
+    ///     match request.get_header_value("Authorization") {
+    ///         Some(token) if token == "Bearer secret" => Ok(()),
+    ///         _ => Err(StatusCode::UNAUTHORIZED),
+    ///     }
+    /// })
+    /// ```
+    pub fn with_check<F>(mut self, check: F) -> Self
+    where
+        F: Fn(&Request) -> Result<(), http::StatusCode> + Send + Sync + 'static,
+    {
+        self.check = Some(Arc::new(check));
+        self
+    }
+
     pub fn method(&self) -> &Method {
         &self.method
     }
@@ -355,6 +882,8 @@ macro_rules! embed_route {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::negotiate::Negotiate;
+    use crate::test_support::TempDir;
     use crate::virtual_host::VirtualHost;
 
     #[tokio::test]
@@ -362,13 +891,14 @@ mod tests {
         let route: Route<()> = embed_route!("/test", "../index.html");
         assert_eq!(route.path, "/test", "route path incorrect");
         assert_eq!(route.method, Method::GET, "route method incorrect");
-        if let RouteResolver::Embed(body, mime) = route.resolver {
+        if let RouteResolver::Embed(body, mime, etag) = route.resolver {
             assert_eq!(
                 include_bytes!("../index.html"),
                 body,
                 "embedded body incorect"
             );
             assert_eq!(MimeType::HTML, mime);
+            assert_eq!(conditional::content_etag(body), etag);
         } else {
             panic!("wrong route type");
         }
@@ -383,7 +913,11 @@ mod tests {
         router.add_route(Route::get_static("/", "index.html")).await;
 
         let file = tokio::fs::read_to_string("./index.html").await.unwrap();
+        let metadata = tokio::fs::metadata("./index.html").await.unwrap();
         let mut expected = Response::from(file);
+        expected.set_header(("Accept-Ranges", "bytes"));
+        expected.set_typed(conditional::file_etag(&metadata));
+        expected.set_typed(LastModified::new(metadata.modified().unwrap()));
         router.push_headers(&mut expected);
         assert_eq!(http::StatusCode::OK, expected.status());
 
@@ -396,6 +930,407 @@ mod tests {
         assert_eq!(expected, response);
     }
 
+    #[tokio::test]
+    async fn route_conditional() {
+        let mut router = Router::new(());
+        router.add_route(Route::get_static("/", "index.html")).await;
+        router
+            .add_route(embed_route!("/embed", "../index.html"))
+            .await;
+
+        for path in ["/", "/embed"] {
+            let request =
+                Request::from_string(format!("GET {path} HTTP/1.1\r\nHost: localhost\r\n\r\n"))
+                    .unwrap();
+            let response = router.route(&request, "./").await;
+            assert_eq!(http::StatusCode::OK, response.status());
+            let etag = response.typed_header::<ETag>().expect("missing etag");
+
+            let request = Request::from_string(format!(
+                "GET {path} HTTP/1.1\r\nHost: localhost\r\nIf-None-Match: {etag}\r\n\r\n"
+            ))
+            .unwrap();
+            let response = router.route(&request, "./").await;
+            assert_eq!(http::StatusCode::NOT_MODIFIED, response.status(), "{path}");
+            assert_eq!(Some(etag.clone()), response.typed_header::<ETag>());
+
+            let request = Request::from_string(format!(
+                "GET {path} HTTP/1.1\r\nHost: localhost\r\nIf-Match: \"other\"\r\n\r\n"
+            ))
+            .unwrap();
+            let response = router.route(&request, "./").await;
+            assert_eq!(
+                http::StatusCode::PRECONDITION_FAILED,
+                response.status(),
+                "{path}"
+            );
+        }
+
+        let request = Request::from_string(
+            "GET / HTTP/1.1\r\nHost: localhost\r\nIf-Modified-Since: Fri, 01 Jan 2100 00:00:00 GMT\r\n\r\n"
+                .to_owned(),
+        )
+        .unwrap();
+        let response = router.route(&request, "./").await;
+        assert_eq!(http::StatusCode::NOT_MODIFIED, response.status());
+        assert!(response.typed_header::<LastModified>().is_some());
+
+        // a handler already ran, what its ETag means is up to it
+        async fn tagged(_: (), _: Request) -> Result<Response, String> {
+            let mut response = Response::from("tagged");
+            response.set_typed(ETag::strong("v1"));
+            Ok(response)
+        }
+        router.add_route(Route::get("/tagged", tagged)).await;
+        let request = Request::from_string(
+            "GET /tagged HTTP/1.1\r\nHost: localhost\r\nIf-None-Match: \"v1\"\r\n\r\n".to_owned(),
+        )
+        .unwrap();
+        let response = router.route(&request, "./").await;
+        assert_eq!(http::StatusCode::OK, response.status());
+    }
+
+    #[tokio::test]
+    async fn route_range() {
+        let mut router = Router::new(());
+        router.add_route(Route::get_static("/", "index.html")).await;
+        let file = tokio::fs::read("./index.html").await.unwrap();
+
+        let request = Request::from_string(
+            "GET / HTTP/1.1\r\nHost: localhost\r\nRange: bytes=0-9\r\n\r\n".to_owned(),
+        )
+        .unwrap();
+        let response = router.route(&request, "./").await;
+        assert_eq!(http::StatusCode::PARTIAL_CONTENT, response.status());
+        assert_eq!(Some("bytes"), response.headers().get("accept-ranges"));
+        let buffer = response.to_send_buffer();
+        assert!(buffer.ends_with(&file[0..10]));
+
+        let request = Request::from_string(format!(
+            "GET / HTTP/1.1\r\nHost: localhost\r\nRange: bytes={}-\r\n\r\n",
+            file.len()
+        ))
+        .unwrap();
+        let response = router.route(&request, "./").await;
+        assert_eq!(http::StatusCode::RANGE_NOT_SATISFIABLE, response.status());
+        let content_range = format!("bytes */{}", file.len());
+        assert_eq!(
+            Some(content_range.as_str()),
+            response.headers().get("content-range")
+        );
+
+        let request = Request::from_string(
+            "GET / HTTP/1.1\r\nHost: localhost\r\nRange: bytes=0-9\r\nIf-Range: \"old\"\r\n\r\n"
+                .to_owned(),
+        )
+        .unwrap();
+        let response = router.route(&request, "./").await;
+        assert_eq!(http::StatusCode::OK, response.status());
+    }
+
+    #[tokio::test]
+    async fn route_compressed() {
+        let mut router = Router::new(());
+        router.add_route(Route::get_static("/", "index.html")).await;
+        let mut compression = CompressionConfig::new();
+        compression.set_min_size(0);
+        router.set_compression(compression);
+
+        let request = Request::from_string(
+            "GET / HTTP/1.1\r\nHost: localhost\r\nAccept-Encoding: gzip\r\n\r\n".to_owned(),
+        )
+        .unwrap();
+        let response = router.route(&request, "./").await;
+        assert_eq!(http::StatusCode::OK, response.status());
+        assert_eq!(Some("gzip"), response.headers().get("content-encoding"));
+        let etag = response.typed_header::<ETag>().unwrap();
+        assert!(etag.is_weak());
+
+        // weak etag from the compressed response still gets a 304
+        let request = Request::from_string(format!(
+            "GET / HTTP/1.1\r\nHost: localhost\r\nAccept-Encoding: gzip\r\nIf-None-Match: {etag}\r\n\r\n"
+        ))
+        .unwrap();
+        let response = router.route(&request, "./").await;
+        assert_eq!(http::StatusCode::NOT_MODIFIED, response.status());
+
+        // partial content is never compressed
+        let request = Request::from_string(
+            "GET / HTTP/1.1\r\nHost: localhost\r\nAccept-Encoding: gzip\r\nRange: bytes=0-9\r\n\r\n"
+                .to_owned(),
+        )
+        .unwrap();
+        let response = router.route(&request, "./").await;
+        assert_eq!(http::StatusCode::PARTIAL_CONTENT, response.status());
+        assert_eq!(None, response.headers().get("content-encoding"));
+    }
+
+    #[tokio::test]
+    async fn route_precompressed() {
+        let root = TempDir::new("precompressed");
+        std::fs::write(root.join("app.js"), "plain").unwrap();
+        std::fs::write(root.join("app.js.br"), "brotli").unwrap();
+        std::fs::write(root.join("app.js.gz"), "gzip").unwrap();
+        std::fs::write(root.join("other.js"), "other").unwrap();
+        let mut router = Router::new(());
+        let mut config = StaticConfig::default();
+        config.set_precompressed(vec![Encoding::Brotli, Encoding::Gzip]);
+        router.set_static_config(config);
+
+        let get = |path: &str, accept: &str| {
+            Request::from_string(format!(
+                "GET {path} HTTP/1.1\r\nHost: localhost\r\nAccept-Encoding: {accept}\r\n\r\n"
+            ))
+            .unwrap()
+        };
+        for (accept, encoding, body) in [
+            ("gzip, br", Some("br"), "brotli"),
+            ("gzip, br;q=0.5", Some("gzip"), "gzip"),
+            ("identity", None, "plain"),
+        ] {
+            let response = router.route(&get("/app.js", accept), &root).await;
+            assert_eq!(http::StatusCode::OK, response.status(), "{accept}");
+            assert_eq!(MimeType::JavaScript, response.mime());
+            assert_eq!(encoding, response.headers().get("content-encoding"));
+            assert_eq!(Some("Accept-Encoding"), response.headers().get("vary"));
+            assert_eq!(body.as_bytes(), response.body());
+        }
+
+        // each sibling has its own etag
+        let response = router.route(&get("/app.js", "br"), &root).await;
+        let etag = response.typed_header::<ETag>().unwrap();
+        let request = Request::from_string(format!(
+            "GET /app.js HTTP/1.1\r\nHost: localhost\r\nAccept-Encoding: br\r\nIf-None-Match: {etag}\r\n\r\n"
+        ))
+        .unwrap();
+        let response = router.route(&request, &root).await;
+        assert_eq!(http::StatusCode::NOT_MODIFIED, response.status());
+        assert_eq!(None, response.headers().get("content-encoding"));
+        let response = router.route(&get("/app.js", "gzip"), &root).await;
+        assert_ne!(Some(etag), response.typed_header::<ETag>());
+
+        // no siblings, no Vary
+        let response = router.route(&get("/other.js", "br"), &root).await;
+        assert_eq!(b"other", response.body());
+        assert_eq!(None, response.headers().get("vary"));
+    }
+
+    #[tokio::test]
+    async fn route_directories() {
+        let root = TempDir::new("directories");
+        std::fs::create_dir_all(root.join("docs")).unwrap();
+        std::fs::create_dir_all(root.join("files/sub dir")).unwrap();
+        std::fs::write(root.join("docs/index.htm"), "docs index").unwrap();
+        std::fs::write(root.join("files/a.txt"), "a").unwrap();
+        let mut router = Router::new(());
+        router.add_route(Route::get_static("/mapped", "docs")).await;
+        let get = |path: &str| {
+            Request::from_string(format!("GET {path} HTTP/1.1\r\nHost: localhost\r\n\r\n")).unwrap()
+        };
+
+        let response = router.route(&get("/docs/"), &root).await;
+        assert_eq!(http::StatusCode::OK, response.status());
+        assert_eq!(b"docs index", response.body());
+        let response = router.route(&get("/mapped"), &root).await;
+        assert_eq!(b"docs index", response.body());
+
+        let response = router.route(&get("/docs?page=2"), &root).await;
+        assert_eq!(http::StatusCode::MOVED_PERMANENTLY, response.status());
+        assert_eq!(Some("/docs/?page=2"), response.headers().get("location"));
+        let response = router.route(&get("/files/sub%20dir"), &root).await;
+        assert_eq!(
+            Some("/files/sub%20dir/"),
+            response.headers().get("location")
+        );
+
+        // no index and listings are off
+        let response = router.route(&get("/files/"), &root).await;
+        assert_eq!(http::StatusCode::NOT_FOUND, response.status());
+
+        let mut config = StaticConfig::default();
+        config.set_autoindex(AutoIndex::Html);
+        let mut vhost = VirtualHost::new("localhost", "", root.to_str().unwrap(), router);
+        vhost.set_static_config(config);
+        let response = vhost.route(&get("/files/")).await;
+        assert_eq!(http::StatusCode::OK, response.status());
+        assert_eq!(MimeType::HTML, response.mime());
+        let body = String::from_utf8(response.body().to_vec()).unwrap();
+        assert!(
+            body.contains("<a href=\"sub%20dir/\">sub dir/</a>"),
+            "{body}"
+        );
+        assert!(body.contains("<a href=\"a.txt\">a.txt</a>"), "{body}");
+    }
+
+    #[tokio::test]
+    async fn route_error_handlers() {
+        let mut router = Router::new(());
+        router.add_route(Route::get("/", hello)).await;
+        router.add_error_handler(
+            http::StatusCode::NOT_FOUND,
+            ErrorHandler::embed(b"router 404", MimeType::HTML),
+        );
+        router.add_default_header(Header::new("X-Default", "yes"));
+        let request =
+            Request::from_string("GET /missing HTTP/1.1\r\nHost: localhost\r\n\r\n".to_owned())
+                .unwrap();
+
+        let response = router.route(&request, "./").await;
+        assert_eq!(http::StatusCode::NOT_FOUND, response.status());
+        assert_eq!(b"router 404", response.body());
+        assert_eq!(Some("yes"), response.headers().get("x-default"));
+
+        // virtual host handlers win over the router's
+        let mut vhost = VirtualHost::new("localhost", "", "./", router.clone());
+        vhost.add_error_handler(http::StatusCode::NOT_FOUND, ErrorHandler::file("404.html"));
+        let response = vhost.route(&request).await;
+        assert_eq!(http::StatusCode::NOT_FOUND, response.status());
+        assert_eq!(std::fs::read("./404.html").unwrap(), response.body());
+    }
+
+    async fn panics(_: (), _: Request) -> Result<String, String> {
+        panic!("handler bug");
+    }
+
+    #[tokio::test]
+    async fn route_panic() {
+        let mut router = Router::new(());
+        router.add_route(Route::get("/panic", panics)).await;
+        let request =
+            Request::from_string("GET /panic HTTP/1.1\r\nHost: localhost\r\n\r\n".to_owned())
+                .unwrap();
+        let response = router.route(&request, "./").await;
+        assert_eq!(http::StatusCode::INTERNAL_SERVER_ERROR, response.status());
+
+        router.add_error_handler(
+            http::StatusCode::INTERNAL_SERVER_ERROR,
+            ErrorHandler::embed(b"sorry", MimeType::HTML),
+        );
+        let response = router.route(&request, "./").await;
+        assert_eq!(http::StatusCode::INTERNAL_SERVER_ERROR, response.status());
+        assert_eq!(b"sorry", response.body());
+    }
+
+    async fn slow(_: (), _: Request) -> Result<String, String> {
+        tokio::time::sleep(Duration::from_millis(200)).await;
+        Ok("slow".to_owned())
+    }
+
+    #[tokio::test]
+    async fn route_timeout() {
+        let mut router = Router::new(());
+        router
+            .add_route(Route::get("/slow", slow).with_timeout(Duration::from_millis(20)))
+            .await;
+        router
+            .add_route(Route::get("/patient", slow).with_timeout(Duration::from_secs(5)))
+            .await;
+        router.add_route(Route::get("/default", slow)).await;
+        let get = |path: &str| {
+            Request::from_string(format!("GET {path} HTTP/1.1\r\nHost: localhost\r\n\r\n")).unwrap()
+        };
+
+        let response = router.route(&get("/slow"), "./").await;
+        assert_eq!(http::StatusCode::SERVICE_UNAVAILABLE, response.status());
+        let response = router.route(&get("/default"), "./").await;
+        assert_eq!(b"slow", response.body());
+
+        router.set_handler_timeout(Duration::from_millis(20));
+        router.set_timeout_status(http::StatusCode::GATEWAY_TIMEOUT);
+        router.add_error_handler(
+            http::StatusCode::GATEWAY_TIMEOUT,
+            ErrorHandler::embed(b"try again later", MimeType::HTML),
+        );
+        let response = router.route(&get("/default"), "./").await;
+        assert_eq!(http::StatusCode::GATEWAY_TIMEOUT, response.status());
+        assert_eq!(b"try again later", response.body());
+        let response = router.route(&get("/patient"), "./").await;
+        assert_eq!(b"slow", response.body());
+    }
+
+    #[tokio::test]
+    async fn route_mime_types() {
+        let root = TempDir::new("mime");
+        std::fs::write(root.join("module.mjs"), "export {}").unwrap();
+        std::fs::write(root.join("app.wasm"), b"\x00asm").unwrap();
+        std::fs::write(root.join("data.dat"), "1,2,3").unwrap();
+        let mut router = Router::new(());
+        router.set_mime_type("dat", "text/csv; header=present".parse().unwrap());
+        let get = |path: &str| {
+            Request::from_string(format!("GET {path} HTTP/1.1\r\nHost: localhost\r\n\r\n")).unwrap()
+        };
+
+        let response = router.route(&get("/module.mjs"), &root).await;
+        assert_eq!(MimeType::JavaScript, response.mime());
+        let response = router.route(&get("/app.wasm"), &root).await;
+        let buffer = String::from_utf8_lossy(&response.to_send_buffer()).to_string();
+        assert!(
+            buffer.contains("Content-Type: application/wasm\r\n"),
+            "{buffer}"
+        );
+        let response = router.route(&get("/data.dat"), &root).await;
+        let buffer = String::from_utf8_lossy(&response.to_send_buffer()).to_string();
+        assert!(
+            buffer.contains("Content-Type: text/csv; header=present\r\n"),
+            "{buffer}"
+        );
+    }
+
+    async fn report(_: (), request: Request) -> Result<Negotiate, String> {
+        Ok(Negotiate::new(&request)
+            .with(MimeType::Json, || "[1,2]")
+            .with(MimeType::from_extension("csv"), || "1\n2\n"))
+    }
+
+    #[tokio::test]
+    async fn route_negotiate() {
+        let mut router = Router::new(());
+        router.add_route(Route::get("/report", report)).await;
+        let get = |accept: &str| {
+            Request::from_string(format!(
+                "GET /report HTTP/1.1\r\nHost: localhost\r\nAccept: {accept}\r\n\r\n"
+            ))
+            .unwrap()
+        };
+
+        let response = router.route(&get("text/csv"), "./").await;
+        assert_eq!(b"1\n2\n", response.body());
+        assert_eq!("text/csv", response.mime().media_type());
+        assert_eq!(Some("Accept"), response.headers().get("vary"));
+        let response = router.route(&get("text/html"), "./").await;
+        assert_eq!(http::StatusCode::NOT_ACCEPTABLE, response.status());
+    }
+
+    async fn socket(_: (), _: Request, _: websocket::WebSocket) {}
+
+    #[tokio::test]
+    async fn route_websocket() {
+        let mut router = Router::new(());
+        router.set_compression(CompressionConfig::default());
+        router
+            .add_route(Route::websocket("/ws", socket).with_protocols(&["v2", "v1"]))
+            .await;
+        let get = |headers: &str| {
+            Request::from_string(format!(
+                "GET /ws HTTP/1.1\r\nHost: localhost\r\nAccept-Encoding: gzip\r\n{headers}\r\n"
+            ))
+            .unwrap()
+        };
+        let upgrade = "Upgrade: websocket\r\nConnection: Upgrade\r\nSec-WebSocket-Version: 13\r\n\
+            Sec-WebSocket-Key: dGhlIHNhbXBsZSBub25jZQ==\r\nSec-WebSocket-Protocol: v1, v2\r\n";
+        let mut response = router.route(&get(upgrade), "./").await;
+        assert_eq!(http::StatusCode::SWITCHING_PROTOCOLS, response.status());
+        assert_eq!(Some("v2"), response.headers().get("sec-websocket-protocol"));
+        assert_eq!(None, response.headers().get("vary"));
+        assert!(response.take_upgrade().is_some());
+        let head = String::from_utf8(response.to_send_buffer()).unwrap();
+        assert!(!head.contains("Content-Length"), "{head}");
+
+        let mut response = router.route(&get(""), "./").await;
+        assert_eq!(http::StatusCode::UPGRADE_REQUIRED, response.status());
+        assert!(response.take_upgrade().is_none());
+    }
+
     async fn hello(_: (), _: Request) -> Result<String, String> {
         Ok("hello".to_owned())
     }
@@ -434,4 +1369,99 @@ mod tests {
         let response = router.route(&request, "./").await;
         assert_eq!(expected, response);
     }
+
+    async fn cached(_: (), _: Request) -> Result<Response, String> {
+        let mut response = Response::from("cached");
+        response.set_header(("Cache-Control", "max-age=60"));
+        Ok(response)
+    }
+
+    #[tokio::test]
+    async fn route_header_precedence() {
+        let mut router = Router::new(());
+        router.add_route(Route::get("/", cached)).await;
+        router.add_default_header(Header::new("Cache-Control", "no-store"));
+        router.add_default_header(Header::new("X-Frame-Options", "DENY"));
+        router.add_mime_header(Header::new("x-frame-options", "SAMEORIGIN"), MimeType::HTML);
+        router.add_mime_header(Header::new("Link", "</a.css>"), MimeType::HTML);
+        router.add_mime_header(Header::new("Link", "</b.js>"), MimeType::HTML);
+
+        let request =
+            Request::from_string("GET / HTTP/1.1\r\nHost: localhost\r\n\r\n".to_owned()).unwrap();
+        let response = router.route(&request, "./").await;
+        let headers = response.headers();
+        assert_eq!(Some("max-age=60"), headers.get("cache-control"));
+        assert_eq!(1, headers.get_all("cache-control").count());
+        assert_eq!(
+            vec!["SAMEORIGIN"],
+            headers.get_all("X-Frame-Options").collect::<Vec<_>>()
+        );
+        assert_eq!(
+            vec!["</a.css>", "</b.js>"],
+            headers.get_all("link").collect::<Vec<_>>()
+        );
+
+        let buffer = String::from_utf8(response.to_send_buffer()).unwrap();
+        assert_eq!(1, buffer.matches("Content-Length").count());
+    }
+
+    #[tokio::test]
+    async fn route_decoded_path() {
+        let mut router = Router::new(());
+        router.add_route(Route::get("/café", hello)).await;
+
+        let mut expected = Response::from("hello");
+        router.push_headers(&mut expected);
+
+        let request =
+            Request::from_string("GET //caf%C3%A9 HTTP/1.1\r\nHost: localhost\r\n\r\n".to_owned())
+                .unwrap();
+        let response = router.route(&request, "./").await;
+        assert_eq!(expected, response);
+    }
+
+    #[tokio::test]
+    async fn check_head() {
+        let mut router = Router::new(());
+        router.set_max_body_size(10);
+        router
+            .add_route(Route::post("/small", hello).with_check(|request| {
+                match request.get_header_value("Authorization") {
+                    Some(_) => Ok(()),
+                    None => Err(http::StatusCode::UNAUTHORIZED),
+                }
+            }))
+            .await;
+        router
+            .add_route(Route::post("/large", hello).with_max_body_size(100))
+            .await;
+        router.add_route(Route::get_static("/", "index.html")).await;
+        let head = |path: &str, headers: &str| {
+            let head = format!("POST {path} HTTP/1.1\r\nHost: test\r\n{headers}\r\n");
+            Request::from_head(head.into()).unwrap()
+        };
+        let status = |response: Option<Response>| response.map(|r| r.status());
+
+        let request = head("/small", "Content-Length: 5\r\nAuthorization: yes\r\n");
+        assert_eq!(None, status(router.check_head(&request).await));
+        assert!(router.accepts_body(&request).await);
+        let request = head("/small", "Content-Length: 5\r\n");
+        assert_eq!(
+            Some(http::StatusCode::UNAUTHORIZED),
+            status(router.check_head(&request).await)
+        );
+        let request = head("/small", "Content-Length: 11\r\nAuthorization: yes\r\n");
+        assert_eq!(
+            Some(http::StatusCode::PAYLOAD_TOO_LARGE),
+            status(router.check_head(&request).await)
+        );
+        let request = head("/large", "Content-Length: 50\r\nExpect: 100-Continue\r\n");
+        assert_eq!(None, status(router.check_head(&request).await));
+        let request = head("/large", "Content-Length: 50\r\nExpect: something\r\n");
+        assert_eq!(
+            Some(http::StatusCode::EXPECTATION_FAILED),
+            status(router.check_head(&request).await)
+        );
+        assert!(!router.accepts_body(&head("/missing", "")).await);
+    }
 }
@@ -0,0 +1,235 @@
+//! Server-sent events, a text/event-stream response written as events are produced
+use crate::{
+    http::{MimeType, StatusCode},
+    response::{BodyStream, Response},
+};
+use futures::{Stream, StreamExt};
+use std::{fmt::Write, time::Duration};
+
+/// One event, fields left unset aren't sent
+/// ```ignore
+/// Event::default().event("price").id("42").data("{\"btc\":1}")
+/// ```
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct Event {
+    id: Option<String>,
+    event: Option<String>,
+    data: Option<String>,
+    retry: Option<Duration>,
+    comment: Option<String>,
+}
+
+impl Event {
+    /// Data is sent as one data line per line, the client joins them back with newlines
+    pub fn data(mut self, data: impl Into<String>) -> Self {
+        self.data = Some(data.into());
+        self
+    }
+
+    /// Event type, the client dispatches it to listeners for this name instead of onmessage
+    /// line breaks are removed
+    pub fn event(mut self, event: impl Into<String>) -> Self {
+        self.event = Some(single_line(event.into()));
+        self
+    }
+
+    /// Sent back by the client in Last-Event-ID when it reconnects, line breaks are removed
+    pub fn id(mut self, id: impl Into<String>) -> Self {
+        self.id = Some(single_line(id.into()));
+        self
+    }
+
+    /// How long the client waits before reconnecting
+    pub fn retry(mut self, retry: Duration) -> Self {
+        self.retry = Some(retry);
+        self
+    }
+
+    /// Comment line, ignored by the client
+    pub fn comment(mut self, comment: impl Into<String>) -> Self {
+        self.comment = Some(comment.into());
+        self
+    }
+
+    /// Wire format, ends with the blank line that dispatches the event
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut event = String::new();
+        if let Some(comment) = &self.comment {
+            for line in lines(comment) {
+                let _ = writeln!(event, ": {line}");
+            }
+        }
+        if let Some(name) = &self.event {
+            let _ = writeln!(event, "event: {name}");
+        }
+        if let Some(id) = &self.id {
+            let _ = writeln!(event, "id: {id}");
+        }
+        if let Some(retry) = self.retry {
+            let _ = writeln!(event, "retry: {}", retry.as_millis());
+        }
+        if let Some(data) = &self.data {
+            for line in lines(data) {
+                let _ = writeln!(event, "data: {line}");
+            }
+        }
+        event.push('\n');
+        event.into_bytes()
+    }
+}
+
+/// Split on every line break a client splits on, \r\n, \r and \n, a lone \r left in would
+/// start a new field. Unlike str::lines a trailing empty line is kept
+fn lines(value: &str) -> impl Iterator<Item = &str> {
+    let mut rest = Some(value);
+    std::iter::from_fn(move || {
+        let current = rest?;
+        match current.find(['\r', '\n']) {
+            Some(end) => {
+                let next = if current[end..].starts_with("\r\n") {
+                    end + 2
+                } else {
+                    end + 1
+                };
+                rest = Some(&current[next..]);
+                Some(&current[..end])
+            }
+            None => {
+                rest = None;
+                Some(current)
+            }
+        }
+    })
+}
+
+fn single_line(value: String) -> String {
+    value.replace(['\r', '\n'], "")
+}
+
+/// Event stream responder, a comment is sent when no event was sent for the keep alive interval
+/// so proxies don't close the connection and a client that went away is noticed
+/// ```ignore
+/// async fn ticks(_: (), _: Request) -> Result<Sse<impl Stream<Item = Event>>, String> {
+///     let ticks = IntervalStream::new(tokio::time::interval(Duration::from_secs(1)))
+///         .map(|_| Event::default().data("tick"));
+///     Ok(Sse::new(ticks))
+/// }
+/// ```
+pub struct Sse<S> {
+    stream: S,
+    keep_alive: Option<Duration>,
+}
+
+impl<S> Sse<S>
+where
+    S: Stream<Item = Event> + Send + 'static,
+{
+    /// Keep alive comments every 15 seconds
+    pub fn new(stream: S) -> Self {
+        Sse {
+            stream,
+            keep_alive: Some(Duration::from_secs(15)),
+        }
+    }
+
+    /// Send keep alive comments every interval, None to turn them off
+    pub fn keep_alive(mut self, interval: Option<Duration>) -> Self {
+        self.keep_alive = interval;
+        self
+    }
+}
+
+impl<S> From<Sse<S>> for Response
+where
+    S: Stream<Item = Event> + Send + 'static,
+{
+    fn from(sse: Sse<S>) -> Self {
+        let events = sse.stream.map(|event| event.to_bytes());
+        let body = match sse.keep_alive {
+            Some(interval) => BodyStream::new(keep_alive(events, interval)),
+            None => BodyStream::new(events),
+        };
+        let mut response = Response::new(
+            StatusCode::OK,
+            vec![],
+            MimeType::from_static("text/event-stream"),
+        );
+        response.set_header(("Cache-Control", "no-cache"));
+        // stops nginx from buffering the stream
+        response.set_header(("X-Accel-Buffering", "no"));
+        response.set_stream(body);
+        response
+    }
+}
+
+/// Items of stream, with a keep alive comment whenever it's quiet for interval
+fn keep_alive<S>(stream: S, interval: Duration) -> impl Stream<Item = Vec<u8>> + Send
+where
+    S: Stream<Item = Vec<u8>> + Send + 'static,
+{
+    futures::stream::unfold(Box::pin(stream), move |mut stream| async move {
+        match tokio::time::timeout(interval, stream.next()).await {
+            Ok(Some(item)) => Some((item, stream)),
+            Ok(None) => None,
+            Err(_) => Some((b": keep-alive\n\n".to_vec(), stream)),
+        }
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn events() {
+        let event = Event::default()
+            .event("update")
+            .id("7\n")
+            .retry(Duration::from_secs(3))
+            .data("line one\r\nline two\n");
+        assert_eq!(
+            "event: update\nid: 7\nretry: 3000\ndata: line one\ndata: line two\ndata: \n\n",
+            String::from_utf8(event.to_bytes()).unwrap()
+        );
+        let comment = Event::default().comment("hi");
+        assert_eq!(b": hi\n\n".to_vec(), comment.to_bytes());
+        assert_eq!(b"\n".to_vec(), Event::default().to_bytes());
+
+        // a lone CR is a line break to the client, it can't smuggle in fields
+        let event = Event::default().data("x\rid: evil").comment("a\rretry: 1");
+        assert_eq!(
+            ": a\n: retry: 1\ndata: x\ndata: id: evil\n\n",
+            String::from_utf8(event.to_bytes()).unwrap()
+        );
+    }
+
+    #[tokio::test]
+    async fn responses() {
+        let events =
+            futures::stream::iter([Event::default().data("a"), Event::default().data("b")]);
+        let mut response = Response::from(Sse::new(events));
+        assert_eq!("text/event-stream", response.mime().media_type());
+        assert_eq!(Some("no-cache"), response.headers().get("cache-control"));
+        let head = String::from_utf8(response.to_send_buffer()).unwrap();
+        assert!(head.contains("Transfer-Encoding: chunked"), "{head}");
+        assert!(!head.contains("Content-Length"), "{head}");
+        let body: Vec<Vec<u8>> = response.take_stream().unwrap().collect().await;
+        assert_eq!(vec![b"data: a\n\n".to_vec(), b"data: b\n\n".to_vec()], body);
+    }
+
+    #[tokio::test]
+    async fn keep_alives() {
+        let slow = futures::stream::once(async {
+            tokio::time::sleep(Duration::from_millis(100)).await;
+            Event::default().data("late")
+        });
+        let mut response =
+            Response::from(Sse::new(slow).keep_alive(Some(Duration::from_millis(30))));
+        let body: Vec<Vec<u8>> = response.take_stream().unwrap().collect().await;
+        assert_eq!(Some(&b"data: late\n\n".to_vec()), body.last());
+        assert!(body.len() > 1);
+        assert!(body[..body.len() - 1]
+            .iter()
+            .all(|chunk| chunk == b": keep-alive\n\n"));
+    }
+}
@@ -0,0 +1,392 @@
+use crate::{
+    compression::Encoding,
+    http::{MimeType, StatusCode},
+    mime,
+};
+use std::{
+    ffi::OsString,
+    path::{Path, PathBuf},
+};
+
+/// What to do with paths that have a segment starting with '.', eg. /.git/config
+#[derive(Debug, PartialEq, Eq, Clone, Copy, Default)]
+pub enum DotFiles {
+    Allow,
+    /// respond 403 Forbidden
+    Deny,
+    /// respond 404 as if the file didnt exist
+    #[default]
+    Ignore,
+}
+
+/// How symlinks under the doc root are treated
+#[derive(Debug, PartialEq, Eq, Clone, Copy, Default)]
+pub enum Symlinks {
+    /// never serve a path that goes through a symlink
+    Deny,
+    /// follow symlinks as long as the target is still inside the doc root
+    #[default]
+    WithinRoot,
+    /// follow symlinks anywhere, only use if you control everything under the doc root
+    Follow,
+}
+
+/// Listing for directories that don't have an index file
+#[derive(Debug, PartialEq, Eq, Clone, Copy, Default)]
+pub enum AutoIndex {
+    /// respond 404
+    #[default]
+    Off,
+    Html,
+    Json,
+    /// json for clients that prefer application/json over text/html, html otherwise
+    Negotiate,
+}
+
+/// Settings for files served from a doc root or vhost root dir
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub struct StaticConfig {
+    dot_files: DotFiles,
+    symlinks: Symlinks,
+    precompressed: Vec<Encoding>,
+    index_files: Vec<String>,
+    autoindex: AutoIndex,
+    mime_types: Vec<(String, MimeType)>,
+    sniff: bool,
+}
+
+impl Default for StaticConfig {
+    fn default() -> Self {
+        StaticConfig {
+            dot_files: DotFiles::default(),
+            symlinks: Symlinks::default(),
+            precompressed: vec![],
+            index_files: vec!["index.html".to_string(), "index.htm".to_string()],
+            autoindex: AutoIndex::default(),
+            mime_types: vec![],
+            sniff: false,
+        }
+    }
+}
+
+impl StaticConfig {
+    pub fn dot_files(&self) -> DotFiles {
+        self.dot_files
+    }
+
+    pub fn set_dot_files(&mut self, dot_files: DotFiles) {
+        self.dot_files = dot_files;
+    }
+
+    pub fn symlinks(&self) -> Symlinks {
+        self.symlinks
+    }
+
+    pub fn set_symlinks(&mut self, symlinks: Symlinks) {
+        self.symlinks = symlinks;
+    }
+
+    /// Encodings to look for precompressed siblings of, eg. app.js.br and app.js.gz next to
+    /// app.js. Off by default, when several are accepted equally the first one wins
+    pub fn precompressed(&self) -> &[Encoding] {
+        &self.precompressed
+    }
+
+    pub fn set_precompressed(&mut self, encodings: Vec<Encoding>) {
+        self.precompressed = encodings;
+    }
+
+    /// Files tried in order when a directory is requested
+    pub fn index_files(&self) -> &[String] {
+        &self.index_files
+    }
+
+    pub fn set_index_files(&mut self, index_files: Vec<String>) {
+        self.index_files = index_files;
+    }
+
+    pub fn autoindex(&self) -> AutoIndex {
+        self.autoindex
+    }
+
+    pub fn set_autoindex(&mut self, autoindex: AutoIndex) {
+        self.autoindex = autoindex;
+    }
+
+    /// Extensions with their type overriden, see [`Self::set_mime_type`]
+    pub fn mime_types(&self) -> &[(String, MimeType)] {
+        &self.mime_types
+    }
+
+    /// Serve files ending in extension as mime instead of what the table in [`crate::mime`] says
+    pub fn set_mime_type(&mut self, extension: &str, mime: MimeType) {
+        let extension = extension.trim_start_matches('.').to_ascii_lowercase();
+        self.mime_types.retain(|(e, _)| e != &extension);
+        self.mime_types.push((extension, mime));
+    }
+
+    /// Guess the type of files with unknown extensions from their first bytes, off by default
+    pub fn sniff(&self) -> bool {
+        self.sniff
+    }
+
+    pub fn set_sniff(&mut self, sniff: bool) {
+        self.sniff = sniff;
+    }
+
+    /// Type for path from the overrides then the extension table, None if it isn't known
+    pub fn mime_type(&self, path: &Path) -> Option<MimeType> {
+        let extension = path.extension()?.to_str()?;
+        self.mime_types
+            .iter()
+            .find(|(e, _)| e.eq_ignore_ascii_case(extension))
+            .map(|(_, mime)| mime.clone())
+            .or_else(|| mime::from_extension(extension))
+    }
+
+    /// Type for a file on disk, unknown extensions are sniffed if enabled or sent as plain text
+    pub async fn file_mime_type(&self, path: &Path) -> MimeType {
+        if let Some(mime) = self.mime_type(path) {
+            return mime;
+        }
+        if self.sniff {
+            match mime::sniff_file(path).await {
+                Ok(mime) => return mime,
+                Err(error) => tracing::debug!("couldn't sniff {path:?}: {error}"),
+            }
+        }
+        MimeType::PlainText
+    }
+
+    /// Precompressed siblings of a resolved path that exist on disk
+    /// only regular files are used so a sibling can't be a symlink out of the doc root
+    pub async fn precompressed_variants(&self, path: &Path) -> Vec<(Encoding, PathBuf)> {
+        let mut variants = vec![];
+        for encoding in &self.precompressed {
+            let mut sibling = OsString::from(path.as_os_str());
+            sibling.push(".");
+            sibling.push(encoding.extension());
+            let sibling = PathBuf::from(sibling);
+            match tokio::fs::symlink_metadata(&sibling).await {
+                Ok(meta) if meta.is_file() => variants.push((*encoding, sibling)),
+                _ => {}
+            }
+        }
+        variants
+    }
+
+    /// Map a decoded request path onto a file under root
+    /// the returned path is canonical and guaranteed to be inside root unless symlinks are set to
+    /// Follow. Errors are the status code that should be returned to the client
+    pub async fn resolve(&self, root: &Path, request_path: &str) -> Result<PathBuf, StatusCode> {
+        if request_path.contains('\0') || request_path.contains('\\') {
+            return Err(StatusCode::BAD_REQUEST);
+        }
+
+        let mut path = root.to_path_buf();
+        for segment in request_path.split('/') {
+            match segment {
+                "" | "." => {}
+                ".." => return Err(StatusCode::FORBIDDEN),
+                s if s.starts_with('.') => match self.dot_files {
+                    DotFiles::Allow => path.push(s),
+                    DotFiles::Deny => return Err(StatusCode::FORBIDDEN),
+                    DotFiles::Ignore => return Err(StatusCode::NOT_FOUND),
+                },
+                s => path.push(s),
+            }
+        }
+
+        let canonical_root = tokio::fs::canonicalize(root).await.map_err(io_status)?;
+        if self.symlinks == Symlinks::Deny {
+            // check every component below root, canonicalize would silently resolve them
+            let relative = path.strip_prefix(root).map_err(|_| StatusCode::FORBIDDEN)?;
+            let mut current = root.to_path_buf();
+            for component in relative.components() {
+                current.push(component);
+                let meta = tokio::fs::symlink_metadata(&current)
+                    .await
+                    .map_err(io_status)?;
+                if meta.file_type().is_symlink() {
+                    tracing::warn!("refusing to serve symlink: {:?}", current);
+                    return Err(StatusCode::FORBIDDEN);
+                }
+            }
+        }
+        let canonical = tokio::fs::canonicalize(&path).await.map_err(io_status)?;
+        if self.symlinks != Symlinks::Follow && !canonical.starts_with(&canonical_root) {
+            tracing::warn!("refusing to serve path outside of root: {:?}", canonical);
+            return Err(StatusCode::FORBIDDEN);
+        }
+        Ok(canonical)
+    }
+}
+
+fn io_status(err: std::io::Error) -> StatusCode {
+    match err.kind() {
+        std::io::ErrorKind::PermissionDenied => StatusCode::FORBIDDEN,
+        _ => StatusCode::NOT_FOUND,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::TempDir;
+    use std::os::unix::fs::symlink;
+
+    /// doc root with index.html, .secret, sub/page.html, a symlink inside root and one escaping it
+    fn test_root() -> (TempDir, PathBuf) {
+        let base = TempDir::new("static");
+        let root = base.join("root");
+        std::fs::create_dir_all(root.join("sub")).unwrap();
+        std::fs::write(root.join("index.html"), "index").unwrap();
+        std::fs::write(root.join(".secret"), "secret").unwrap();
+        std::fs::write(root.join("sub/page.html"), "page").unwrap();
+        std::fs::write(base.join("outside.txt"), "outside").unwrap();
+        symlink(root.join("sub/page.html"), root.join("inside_link.html")).unwrap();
+        symlink(base.join("outside.txt"), root.join("outside_link.txt")).unwrap();
+        (base, root)
+    }
+
+    #[tokio::test]
+    async fn serves_files_in_root() {
+        let (_base, root) = test_root();
+        let config = StaticConfig::default();
+        let canonical_root = std::fs::canonicalize(&root).unwrap();
+        assert_eq!(
+            Ok(canonical_root.join("index.html")),
+            config.resolve(&root, "/index.html").await
+        );
+        assert_eq!(
+            Ok(canonical_root.join("sub/page.html")),
+            config.resolve(&root, "/sub/./page.html").await
+        );
+        assert_eq!(
+            Ok(canonical_root.join("sub/page.html")),
+            config.resolve(&root, "//sub//page.html").await
+        );
+        assert_eq!(
+            Err(StatusCode::NOT_FOUND),
+            config.resolve(&root, "/missing.html").await
+        );
+    }
+
+    #[tokio::test]
+    async fn traversal_attempts() {
+        let (_base, root) = test_root();
+        let config = StaticConfig::default();
+        let attempts = [
+            "/../outside.txt",
+            "/sub/../../outside.txt",
+            "../outside.txt",
+            "/..\\outside.txt",
+            "/sub\\..\\..\\outside.txt",
+            "/index.html\0.png",
+            "/outside_link.txt",
+        ];
+        for attempt in attempts {
+            let result = config.resolve(&root, attempt).await;
+            assert!(result.is_err(), "{attempt} resolved to {:?}", result);
+        }
+    }
+
+    #[tokio::test]
+    async fn dot_files() {
+        let (_base, root) = test_root();
+        let mut config = StaticConfig::default();
+        assert_eq!(
+            Err(StatusCode::NOT_FOUND),
+            config.resolve(&root, "/.secret").await
+        );
+        assert_eq!(
+            Err(StatusCode::NOT_FOUND),
+            config.resolve(&root, "/sub/.hidden/page.html").await
+        );
+        config.set_dot_files(DotFiles::Deny);
+        assert_eq!(
+            Err(StatusCode::FORBIDDEN),
+            config.resolve(&root, "/.secret").await
+        );
+        config.set_dot_files(DotFiles::Allow);
+        assert!(config.resolve(&root, "/.secret").await.is_ok());
+    }
+
+    #[tokio::test]
+    async fn symlinks() {
+        let (_base, root) = test_root();
+        let mut config = StaticConfig::default();
+        assert!(config.resolve(&root, "/inside_link.html").await.is_ok());
+        assert_eq!(
+            Err(StatusCode::FORBIDDEN),
+            config.resolve(&root, "/outside_link.txt").await
+        );
+
+        config.set_symlinks(Symlinks::Deny);
+        assert_eq!(
+            Err(StatusCode::FORBIDDEN),
+            config.resolve(&root, "/inside_link.html").await
+        );
+
+        config.set_symlinks(Symlinks::Follow);
+        assert!(config.resolve(&root, "/outside_link.txt").await.is_ok());
+    }
+
+    #[tokio::test]
+    async fn precompressed_siblings() {
+        let (base, root) = test_root();
+        std::fs::write(root.join("index.html.gz"), "gz").unwrap();
+        std::fs::write(root.join("index.html.br"), "br").unwrap();
+        symlink(base.join("outside.txt"), root.join("sub/page.html.gz")).unwrap();
+        let mut config = StaticConfig::default();
+        let index = config.resolve(&root, "/index.html").await.unwrap();
+        assert!(config.precompressed_variants(&index).await.is_empty());
+
+        config.set_precompressed(vec![Encoding::Gzip, Encoding::Brotli]);
+        let variants = config.precompressed_variants(&index).await;
+        assert_eq!(
+            vec![
+                (Encoding::Gzip, index.with_extension("html.gz")),
+                (Encoding::Brotli, index.with_extension("html.br"))
+            ],
+            variants
+        );
+        let page = config.resolve(&root, "/sub/page.html").await.unwrap();
+        assert!(config.precompressed_variants(&page).await.is_empty());
+    }
+
+    #[tokio::test]
+    async fn mime_types() {
+        let (_base, root) = test_root();
+        std::fs::write(root.join("doc.unknown"), b"%PDF-1.4\n").unwrap();
+        std::fs::write(root.join("README"), "read me").unwrap();
+        let mut config = StaticConfig::default();
+        assert_eq!(
+            Some(MimeType::HTML),
+            config.mime_type(Path::new("index.HTML"))
+        );
+        assert_eq!(None, config.mime_type(Path::new("README")));
+        assert_eq!(
+            MimeType::PlainText,
+            config.file_mime_type(&root.join("doc.unknown")).await
+        );
+
+        config.set_sniff(true);
+        config.set_mime_type(".html", MimeType::PlainText);
+        assert_eq!(
+            Some(MimeType::PlainText),
+            config.mime_type(Path::new("index.html"))
+        );
+        assert_eq!(
+            "application/pdf",
+            config
+                .file_mime_type(&root.join("doc.unknown"))
+                .await
+                .media_type()
+        );
+        assert_eq!(
+            MimeType::PlainText,
+            config.file_mime_type(&root.join("README")).await
+        );
+    }
+}
@@ -1,9 +1,9 @@
+use crate::query::QueryMap;
 use base64::{engine::general_purpose, Engine as _};
 use bytes::Bytes;
-use memchr::{memchr, memchr_iter};
 use rand::{distributions::Alphanumeric, thread_rng, Rng};
 use secrecy::SecretString;
-use std::collections::HashMap;
+use std::{any::Any, collections::HashMap};
 
 pub fn generate_random_secret() -> SecretString {
     let rand_string: String = thread_rng()
@@ -23,39 +23,102 @@ pub fn base64_decode(encoded: String) -> Result<Vec<u8>, anyhow::Error> {
     Ok(general_purpose::STANDARD_NO_PAD.decode(encoded)?)
 }
 
+/// Parse a query string into a map, when a key is repeated the last value wins
+/// use [`crate::query::QueryMap`] to keep every value
 pub fn parse_query_string(query: &Bytes) -> Result<HashMap<String, String>, anyhow::Error> {
-    let mut map = HashMap::new();
-    if query.is_empty() {
-        return Ok(map);
-    }
-    //Each entry is split with &
-    let mut and_iter = memchr_iter(b'&', query);
-    let mut next_slice_start_at = 0;
-    let mut data_left = true;
-    while data_left {
-        let slice;
-        match and_iter.next() {
-            Some(next_and) => {
-                slice = query.slice(next_slice_start_at..next_and);
-                next_slice_start_at = next_and + 1;
+    Ok(QueryMap::parse(query)?.into_iter().collect())
+}
+
+/// Decode %XX escapes, returns None if an escape is truncated or not hex
+pub fn percent_decode(input: &[u8]) -> Option<Vec<u8>> {
+    let mut decoded = Vec::with_capacity(input.len());
+    let mut i = 0;
+    while i < input.len() {
+        if input[i] == b'%' {
+            let hex = input.get(i + 1..i + 3)?;
+            if !hex.iter().all(u8::is_ascii_hexdigit) {
+                return None;
             }
-            None => {
-                //we are at the end
-                slice = query.slice(next_slice_start_at..);
-                data_left = false;
+            let hex = std::str::from_utf8(hex).ok()?;
+            decoded.push(u8::from_str_radix(hex, 16).ok()?);
+            i += 3;
+        } else {
+            decoded.push(input[i]);
+            i += 1;
+        }
+    }
+    Some(decoded)
+}
+
+/// Percent encode everything in a path except unreserved characters and '/'
+pub fn percent_encode_path(path: &str) -> String {
+    let mut encoded = String::with_capacity(path.len());
+    for b in path.bytes() {
+        match b {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'.' | b'_' | b'~' | b'/' => {
+                encoded.push(b as char)
             }
+            _ => encoded.push_str(&format!("%{b:02X}")),
         }
-        //each entry is key value pair split by =
-        if let Some(equal_i) = memchr(b'=', &slice) {
-            let key = String::from_utf8_lossy(&slice.slice(..equal_i)).to_string();
-            let value = String::from_utf8_lossy(&slice.slice(equal_i + 1..)).to_string();
-            map.insert(key, value);
-        } else {
-            return Err(anyhow::Error::msg("Invalid entry"));
+    }
+    encoded
+}
+
+/// Decode a key or value from a query string or urlencoded form, '+' is a space
+pub fn decode_form_component(component: &[u8]) -> Option<String> {
+    let spaced: Vec<u8> = component
+        .iter()
+        .map(|b| if *b == b'+' { b' ' } else { *b })
+        .collect();
+    String::from_utf8(percent_decode(&spaced)?).ok()
+}
+
+/// Message from a caught panic payload, panic! with a literal or format string gives a &str or
+/// String, anything else is unknown
+pub fn panic_message(payload: &(dyn Any + Send)) -> &str {
+    if let Some(message) = payload.downcast_ref::<&str>() {
+        message
+    } else if let Some(message) = payload.downcast_ref::<String>() {
+        message
+    } else {
+        "unknown panic"
+    }
+}
+
+/// Collapse duplicate slashes and resolve '.' and '..' segments (RFC 3986 5.2.4)
+/// a trailing slash is kept, paths not starting with '/' are returned as is. A '..' above root is
+/// dropped here, request paths with one don't get this far, parsing the request rejects them
+/// with 403 ([`crate::request::Error::PathOutsideRoot`])
+pub fn normalize_path(path: &str) -> String {
+    if !path.starts_with('/') {
+        return path.to_string();
+    }
+    let mut segments: Vec<&str> = vec![];
+    let mut trailing_slash = false;
+    for segment in path.split('/').skip(1) {
+        trailing_slash = true;
+        match segment {
+            "" | "." => {}
+            ".." => {
+                segments.pop();
+            }
+            s => {
+                segments.push(s);
+                trailing_slash = false;
+            }
         }
     }
-    Ok(map)
+    let mut normalized = String::with_capacity(path.len());
+    for segment in &segments {
+        normalized.push('/');
+        normalized.push_str(segment);
+    }
+    if trailing_slash || segments.is_empty() {
+        normalized.push('/');
+    }
+    normalized
 }
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -71,4 +134,56 @@ mod tests {
         );
         assert_eq!("ice cream", query.get("food").expect("Food Misisng in map"));
     }
+
+    #[test]
+    fn test_percent_decode() {
+        assert_eq!(
+            Some(b"/caf\xc3\xa9 x".to_vec()),
+            percent_decode(b"/caf%C3%a9%20x")
+        );
+        assert_eq!(Some(b"plain".to_vec()), percent_decode(b"plain"));
+        assert_eq!(None, percent_decode(b"bad%2"));
+        assert_eq!(None, percent_decode(b"bad%zz"));
+        assert_eq!(None, percent_decode(b"bad%+1"));
+    }
+
+    #[test]
+    fn test_percent_encode_path() {
+        assert_eq!("/docs/a-b_c.~txt", percent_encode_path("/docs/a-b_c.~txt"));
+        assert_eq!("/caf%C3%A9%20x%3F", percent_encode_path("/café x?"));
+        assert_eq!(
+            Some("/café x?".as_bytes().to_vec()),
+            percent_decode(percent_encode_path("/café x?").as_bytes())
+        );
+    }
+
+    #[test]
+    fn test_query_string_decoding() {
+        let query_bytes = Bytes::from_static(b"q=ice+cream%21&caf%C3%A9=%2B1");
+        let query = parse_query_string(&query_bytes).expect("Error Parsing Query String");
+        assert_eq!("ice cream!", query.get("q").unwrap());
+        assert_eq!("+1", query.get("café").unwrap());
+
+        let query_bytes = Bytes::from_static(b"q=bad%zz");
+        assert!(parse_query_string(&query_bytes).is_err());
+
+        let query_bytes = Bytes::from_static(b"debug&tag=a&tag=b");
+        let query = parse_query_string(&query_bytes).expect("Error Parsing Query String");
+        assert_eq!("", query.get("debug").unwrap());
+        assert_eq!("b", query.get("tag").unwrap());
+    }
+
+    #[test]
+    fn test_normalize_path() {
+        assert_eq!("/", normalize_path("/"));
+        assert_eq!("/", normalize_path("//"));
+        assert_eq!("/a/b", normalize_path("//a//b"));
+        assert_eq!("/docs/", normalize_path("/docs/"));
+        assert_eq!("/a/", normalize_path("/a/."));
+        assert_eq!("/a/c", normalize_path("/a/./b/../c"));
+        assert_eq!("/", normalize_path("/a/.."));
+        assert_eq!("/a/", normalize_path("/a/b/.."));
+        assert_eq!("/etc/passwd", normalize_path("/../../etc/passwd"));
+        assert_eq!("*", normalize_path("*"));
+    }
 }
@@ -1,11 +1,20 @@
 use std::path::PathBuf;
 
-use crate::{request::Request, response::Response, routes::Router};
+use crate::{
+    error_pages::{ErrorHandler, ErrorPages},
+    http::StatusCode,
+    request::Request,
+    response::Response,
+    routes::Router,
+    static_files::StaticConfig,
+};
 
 pub struct VirtualHost<S> {
     hostname: String,
     root_dir: PathBuf, // root dir for static files, eg. /var/www/default
     router: Router<S>,
+    static_config: Option<StaticConfig>,
+    error_pages: ErrorPages,
 }
 
 impl<S> VirtualHost<S>
@@ -18,6 +27,8 @@ where
             //ip: ip.to_string(),
             root_dir: PathBuf::from(root_dir),
             router,
+            static_config: None,
+            error_pages: ErrorPages::new(),
         }
     }
 
@@ -29,7 +40,37 @@ where
         &self.root_dir
     }
 
+    /// Static file settings for this host, eg. index files and directory listings
+    /// the router's settings are used when not set
+    pub fn static_config(&self) -> Option<&StaticConfig> {
+        self.static_config.as_ref()
+    }
+
+    pub fn set_static_config(&mut self, config: StaticConfig) {
+        self.static_config = Some(config);
+    }
+
+    /// Error handlers for this host, tried before the router's
+    pub fn error_pages(&self) -> &ErrorPages {
+        &self.error_pages
+    }
+
+    pub fn add_error_handler(&mut self, status: StatusCode, handler: ErrorHandler) {
+        self.error_pages.add(status, handler);
+    }
+
     pub async fn route(&self, request: &Request) -> Response {
-        self.router.route(request, &self.root_dir).await
+        let static_config = self
+            .static_config
+            .as_ref()
+            .unwrap_or(self.router.static_config());
+        self.router
+            .route_with(
+                request,
+                &self.root_dir,
+                static_config,
+                Some(&self.error_pages),
+            )
+            .await
     }
 }
@@ -0,0 +1,796 @@
+//! WebSocket routes, the RFC 6455 handshake and a message level socket
+use crate::{
+    http::{MimeType, StatusCode, Version},
+    request::Request,
+    response::Response,
+    state::{FromRequest, State},
+    utils, ConnectionStream,
+};
+use async_trait::async_trait;
+use base64::{engine::general_purpose, Engine as _};
+use bytes::{Buf, BytesMut};
+use futures::{future::BoxFuture, FutureExt};
+use sha1::{Digest, Sha1};
+use std::{fmt, future::Future, panic::AssertUnwindSafe};
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio_util::sync::CancellationToken;
+
+/// Appended to the client's key before hashing it for Sec-WebSocket-Accept
+pub const GUID: &str = "258EAFA5-E914-47DA-95CA-C5AB0DC85B11";
+
+/// Only version of the protocol there is
+pub const VERSION: &str = "13";
+
+const OP_CONTINUATION: u8 = 0x0;
+const OP_TEXT: u8 = 0x1;
+const OP_BINARY: u8 = 0x2;
+const OP_CLOSE: u8 = 0x8;
+const OP_PING: u8 = 0x9;
+const OP_PONG: u8 = 0xA;
+
+/// Async function that talks to a client once the connection is upgraded
+/// the connection is closed when it returns
+#[async_trait]
+pub trait SocketResolver<S>: Send + Sync + 'static {
+    async fn run(&self, state: State<S>, request: Request, socket: WebSocket);
+}
+
+#[async_trait]
+impl<F, P, Fut> SocketResolver<P> for F
+where
+    Fut: Future<Output = ()> + Send + 'static,
+    F: Fn(P, Request, WebSocket) -> Fut + Send + Sync + 'static,
+    P: FromRequest<P> + Send + Sync + 'static,
+{
+    async fn run(&self, state: State<P>, request: Request, socket: WebSocket) {
+        (self)(P::from_request(state, request.clone()), request, socket).await
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Message {
+    Text(String),
+    Binary(Vec<u8>),
+    Ping(Vec<u8>),
+    Pong(Vec<u8>),
+    Close(Option<CloseFrame>),
+}
+
+/// Status code and reason of a close frame
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CloseFrame {
+    pub code: u16,
+    pub reason: String,
+}
+
+impl CloseFrame {
+    pub const NORMAL: u16 = 1000;
+    pub const GOING_AWAY: u16 = 1001;
+    pub const PROTOCOL_ERROR: u16 = 1002;
+    pub const UNSUPPORTED: u16 = 1003;
+    pub const INVALID_DATA: u16 = 1007;
+    pub const POLICY: u16 = 1008;
+    pub const TOO_BIG: u16 = 1009;
+    pub const INTERNAL_ERROR: u16 = 1011;
+
+    pub fn new(code: u16, reason: &str) -> Self {
+        CloseFrame {
+            code,
+            reason: reason.to_string(),
+        }
+    }
+
+    /// Codes a peer may send, the rest are reserved or only used locally
+    fn valid_code(code: u16) -> bool {
+        matches!(code, 1000..=1003 | 1007..=1014 | 3000..=4999)
+    }
+}
+
+#[derive(Debug)]
+pub enum Error {
+    Io(std::io::Error),
+    /// the client broke the protocol, the connection was closed with 1002
+    Protocol(&'static str),
+    /// message over the size limit, the connection was closed with 1009
+    TooBig,
+    /// text that isn't utf-8, the connection was closed with 1007
+    InvalidUtf8,
+    /// a close frame was already sent
+    Closed,
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::Io(error) => write!(f, "websocket io error: {error}"),
+            Error::Protocol(reason) => write!(f, "websocket protocol error: {reason}"),
+            Error::TooBig => write!(f, "websocket message too big"),
+            Error::InvalidUtf8 => write!(f, "websocket text isn't utf-8"),
+            Error::Closed => write!(f, "websocket closed"),
+        }
+    }
+}
+
+impl std::error::Error for Error {}
+
+impl From<std::io::Error> for Error {
+    fn from(error: std::io::Error) -> Self {
+        Error::Io(error)
+    }
+}
+
+impl Error {
+    fn close_code(&self) -> u16 {
+        match self {
+            Error::Protocol(_) => CloseFrame::PROTOCOL_ERROR,
+            Error::TooBig => CloseFrame::TOO_BIG,
+            Error::InvalidUtf8 => CloseFrame::INVALID_DATA,
+            Error::Io(_) | Error::Closed => CloseFrame::INTERNAL_ERROR,
+        }
+    }
+}
+
+/// Sec-WebSocket-Accept for a client's Sec-WebSocket-Key
+pub fn accept_key(key: &str) -> String {
+    let mut hasher = Sha1::new();
+    hasher.update(key.trim().as_bytes());
+    hasher.update(GUID.as_bytes());
+    general_purpose::STANDARD.encode(hasher.finalize())
+}
+
+/// Check request is a WebSocket upgrade and answer with 101 Switching Protocols
+/// the first of protocols the client also offers is picked as the subprotocol
+/// a plain request or an unsupported version gets 426, a bad key 400
+/// HTTP/2 has no 101 or Upgrade (RFC 7540 8.1.1) so those requests get 505
+pub fn handshake(request: &Request, protocols: &[String]) -> Response {
+    if request.version() == Version::V2_0 {
+        return Response::error(
+            StatusCode::HTTP_VERSION_NOT_SUPPORTED,
+            "WebSockets Need HTTP/1.1".into(),
+        );
+    }
+    let has_token = |name: &str, token: &str| {
+        request
+            .headers()
+            .get_all(name)
+            .flat_map(|value| value.split(','))
+            .any(|t| t.trim().eq_ignore_ascii_case(token))
+    };
+    if !has_token("Upgrade", "websocket") || !has_token("Connection", "upgrade") {
+        let mut response = Response::error(
+            StatusCode::UPGRADE_REQUIRED,
+            "WebSocket Upgrade Required".into(),
+        );
+        response.set_header(("Upgrade", "websocket"));
+        response.set_header(("Connection", "Upgrade"));
+        response.set_header(("Sec-WebSocket-Version", VERSION));
+        return response;
+    }
+    if request
+        .headers()
+        .get("Sec-WebSocket-Version")
+        .map(str::trim)
+        != Some(VERSION)
+    {
+        let mut response = Response::error(
+            StatusCode::UPGRADE_REQUIRED,
+            "Unsupported WebSocket Version".into(),
+        );
+        response.set_header(("Sec-WebSocket-Version", VERSION));
+        return response;
+    }
+    let key = request.headers().get("Sec-WebSocket-Key").map(str::trim);
+    let valid_key = key
+        .and_then(|key| general_purpose::STANDARD.decode(key).ok())
+        .map(|key| key.len() == 16)
+        .unwrap_or(false);
+    let (Some(key), true) = (key, valid_key) else {
+        return Response::error(StatusCode::BAD_REQUEST, "Invalid Sec-WebSocket-Key".into());
+    };
+    let offered = request.headers().get_joined("Sec-WebSocket-Protocol");
+    let protocol = offered.and_then(|offered| {
+        protocols
+            .iter()
+            .find(|p| offered.split(',').any(|o| o.trim() == p.as_str()))
+    });
+    let mut response = Response::new(StatusCode::SWITCHING_PROTOCOLS, vec![], MimeType::PlainText);
+    response.set_header(("Upgrade", "websocket"));
+    response.set_header(("Connection", "Upgrade"));
+    response.set_header(("Sec-WebSocket-Accept", accept_key(key).as_str()));
+    if let Some(protocol) = protocol {
+        response.set_header(("Sec-WebSocket-Protocol", protocol.as_str()));
+    }
+    response
+}
+
+type Handler = Box<dyn FnOnce(WebSocket) -> BoxFuture<'static, ()> + Send + Sync>;
+
+/// Handler waiting for the connection, carried by a 101 response until it has been written
+pub struct Upgrade {
+    protocol: Option<String>,
+    handler: Handler,
+}
+
+impl Upgrade {
+    pub fn new<F, Fut>(protocol: Option<String>, handler: F) -> Self
+    where
+        F: FnOnce(WebSocket) -> Fut + Send + Sync + 'static,
+        Fut: Future<Output = ()> + Send + 'static,
+    {
+        Upgrade {
+            protocol,
+            handler: Box::new(move |socket| handler(socket).boxed()),
+        }
+    }
+
+    /// Hand the connection to the handler, read holds bytes that arrived after the request
+    pub(crate) async fn start(
+        self,
+        stream: Box<dyn ConnectionStream>,
+        read: BytesMut,
+        cancel: CancellationToken,
+    ) {
+        let socket = WebSocket {
+            stream,
+            buffer: read,
+            protocol: self.protocol,
+            cancel,
+            max_message_size: 16 * 1024 * 1024,
+            fragments: None,
+            close_sent: false,
+            close_received: false,
+        };
+        // same as a regular handler, a panic only takes its own connection down
+        if let Err(panic) = AssertUnwindSafe((self.handler)(socket))
+            .catch_unwind()
+            .await
+        {
+            tracing::error!(
+                "websocket handler panicked: {}",
+                utils::panic_message(panic.as_ref())
+            );
+        }
+    }
+}
+
+impl fmt::Debug for Upgrade {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Upgrade")
+            .field("protocol", &self.protocol)
+            .finish_non_exhaustive()
+    }
+}
+
+/// Handlers can't be compared so responses carrying one never are equal
+impl PartialEq for Upgrade {
+    fn eq(&self, _: &Self) -> bool {
+        false
+    }
+}
+
+#[derive(Debug, PartialEq)]
+struct Frame {
+    fin: bool,
+    opcode: u8,
+    payload: Vec<u8>,
+}
+
+/// Take one client frame off the front of buffer, None until all of it has arrived
+fn parse_frame(buffer: &mut BytesMut, max_size: usize) -> Result<Option<Frame>, Error> {
+    if buffer.len() < 2 {
+        return Ok(None);
+    }
+    let fin = buffer[0] & 0x80 != 0;
+    let opcode = buffer[0] & 0x0F;
+    if buffer[0] & 0x70 != 0 {
+        return Err(Error::Protocol("reserved bits set without an extension"));
+    }
+    if !matches!(
+        opcode,
+        OP_CONTINUATION | OP_TEXT | OP_BINARY | OP_CLOSE | OP_PING | OP_PONG
+    ) {
+        return Err(Error::Protocol("unknown opcode"));
+    }
+    if buffer[1] & 0x80 == 0 {
+        return Err(Error::Protocol("client frames must be masked"));
+    }
+    let (len, header) = match buffer[1] & 0x7F {
+        126 if buffer.len() >= 4 => (u16::from_be_bytes([buffer[2], buffer[3]]) as u64, 4),
+        127 if buffer.len() >= 10 => {
+            let mut len = [0; 8];
+            len.copy_from_slice(&buffer[2..10]);
+            (u64::from_be_bytes(len), 10)
+        }
+        126 | 127 => return Ok(None),
+        len => (len as u64, 2),
+    };
+    if opcode >= OP_CLOSE && (!fin || len > 125) {
+        return Err(Error::Protocol(
+            "control frames can't be fragmented or over 125 bytes",
+        ));
+    }
+    if len > max_size as u64 {
+        return Err(Error::TooBig);
+    }
+    let len = len as usize;
+    if buffer.len() < header + 4 + len {
+        buffer.reserve(header + 4 + len - buffer.len());
+        return Ok(None);
+    }
+    let mut mask = [0; 4];
+    mask.copy_from_slice(&buffer[header..header + 4]);
+    buffer.advance(header + 4);
+    let mut payload = buffer.split_to(len).to_vec();
+    for (i, byte) in payload.iter_mut().enumerate() {
+        *byte ^= mask[i % 4];
+    }
+    Ok(Some(Frame {
+        fin,
+        opcode,
+        payload,
+    }))
+}
+
+/// Unmasked, unfragmented server frame
+fn encode_frame(opcode: u8, payload: &[u8]) -> Vec<u8> {
+    let mut frame = Vec::with_capacity(payload.len() + 10);
+    frame.push(0x80 | opcode);
+    match payload.len() {
+        len if len < 126 => frame.push(len as u8),
+        len if len <= u16::MAX as usize => {
+            frame.push(126);
+            frame.extend_from_slice(&(len as u16).to_be_bytes());
+        }
+        len => {
+            frame.push(127);
+            frame.extend_from_slice(&(len as u64).to_be_bytes());
+        }
+    }
+    frame.extend_from_slice(payload);
+    frame
+}
+
+/// Upgraded connection, works the same over TCP and TLS
+/// pings are answered for you, when the server shuts down the client is sent 1001 Going Away
+/// and recv returns None
+pub struct WebSocket {
+    stream: Box<dyn ConnectionStream>,
+    buffer: BytesMut,
+    protocol: Option<String>,
+    cancel: CancellationToken,
+    max_message_size: usize,
+    /// opcode and payload of a message split over several frames
+    fragments: Option<(u8, Vec<u8>)>,
+    close_sent: bool,
+    close_received: bool,
+}
+
+impl WebSocket {
+    /// Subprotocol picked during the handshake
+    pub fn protocol(&self) -> Option<&str> {
+        self.protocol.as_deref()
+    }
+
+    /// Largest message accepted from the client, 16MiB by default
+    pub fn set_max_message_size(&mut self, size: usize) {
+        self.max_message_size = size;
+    }
+
+    pub fn max_message_size(&self) -> usize {
+        self.max_message_size
+    }
+
+    /// Resolves when the server starts shutting down, for handlers that only send
+    pub async fn shutdown(&self) {
+        self.cancel.cancelled().await
+    }
+
+    /// Next message from the client, None once the connection is closed
+    /// pings are answered before they're returned, a close from the client is echoed
+    /// protocol errors close the connection with the matching code and are returned once
+    pub async fn recv(&mut self) -> Option<Result<Message, Error>> {
+        if self.close_received {
+            return None;
+        }
+        let cancel = self.cancel.clone();
+        loop {
+            let frame = tokio::select! {
+                _ = cancel.cancelled() => {
+                    let _ = self.close(CloseFrame::GOING_AWAY, "Server Shutting Down").await;
+                    self.close_received = true;
+                    return None;
+                }
+                frame = self.read_frame() => frame,
+            };
+            let message = match frame {
+                Ok(Some(frame)) => self.handle_frame(frame).await,
+                Ok(None) => {
+                    // peer went away without a close frame
+                    self.close_received = true;
+                    return None;
+                }
+                Err(error) => Err(error),
+            };
+            match message {
+                Ok(Some(message)) => return Some(Ok(message)),
+                Ok(None) => continue,
+                Err(error) => {
+                    if !matches!(error, Error::Io(_)) {
+                        let _ = self.close(error.close_code(), "").await;
+                    }
+                    self.close_received = true;
+                    return Some(Err(error));
+                }
+            }
+        }
+    }
+
+    /// Send a message, sending Close starts the closing handshake and nothing can be sent after it
+    pub async fn send(&mut self, message: Message) -> Result<(), Error> {
+        if self.close_sent {
+            return Err(Error::Closed);
+        }
+        if self.cancel.is_cancelled() {
+            let close = CloseFrame::new(CloseFrame::GOING_AWAY, "Server Shutting Down");
+            let _ = self.send_close(Some(close)).await;
+            return Err(Error::Closed);
+        }
+        let frame = match message {
+            Message::Text(text) => encode_frame(OP_TEXT, text.as_bytes()),
+            Message::Binary(data) => encode_frame(OP_BINARY, &data),
+            Message::Ping(data) => encode_frame(OP_PING, &data[..data.len().min(125)]),
+            Message::Pong(data) => encode_frame(OP_PONG, &data[..data.len().min(125)]),
+            Message::Close(close) => return self.send_close(close).await,
+        };
+        self.stream.write_all(&frame).await?;
+        self.stream.flush().await?;
+        Ok(())
+    }
+
+    pub async fn send_text(&mut self, text: impl Into<String>) -> Result<(), Error> {
+        self.send(Message::Text(text.into())).await
+    }
+
+    pub async fn send_binary(&mut self, data: impl Into<Vec<u8>>) -> Result<(), Error> {
+        self.send(Message::Binary(data.into())).await
+    }
+
+    /// Send a close frame unless one was already sent
+    pub async fn close(&mut self, code: u16, reason: &str) -> Result<(), Error> {
+        if self.close_sent {
+            return Ok(());
+        }
+        self.send_close(Some(CloseFrame::new(code, reason))).await
+    }
+
+    async fn send_close(&mut self, close: Option<CloseFrame>) -> Result<(), Error> {
+        self.close_sent = true;
+        let mut payload = vec![];
+        if let Some(close) = close {
+            // control frames are limited to 125 bytes, don't cut a character in half
+            let mut end = close.reason.len().min(123);
+            while !close.reason.is_char_boundary(end) {
+                end -= 1;
+            }
+            payload.extend_from_slice(&close.code.to_be_bytes());
+            payload.extend_from_slice(&close.reason.as_bytes()[..end]);
+        }
+        self.stream
+            .write_all(&encode_frame(OP_CLOSE, &payload))
+            .await?;
+        self.stream.flush().await?;
+        Ok(())
+    }
+
+    /// None when the client closed the connection
+    async fn read_frame(&mut self) -> Result<Option<Frame>, Error> {
+        loop {
+            if let Some(frame) = parse_frame(&mut self.buffer, self.max_message_size)? {
+                return Ok(Some(frame));
+            }
+            if self.stream.read_buf(&mut self.buffer).await? == 0 {
+                return Ok(None);
+            }
+        }
+    }
+
+    /// Message for a frame, None for the start or middle of a fragmented message
+    async fn handle_frame(&mut self, frame: Frame) -> Result<Option<Message>, Error> {
+        match frame.opcode {
+            OP_PING => {
+                if !self.close_sent {
+                    self.send(Message::Pong(frame.payload.clone())).await?;
+                }
+                Ok(Some(Message::Ping(frame.payload)))
+            }
+            OP_PONG => Ok(Some(Message::Pong(frame.payload))),
+            OP_CLOSE => {
+                let close = match frame.payload.len() {
+                    0 => None,
+                    1 => return Err(Error::Protocol("close payload of one byte")),
+                    _ => {
+                        let code = u16::from_be_bytes([frame.payload[0], frame.payload[1]]);
+                        if !CloseFrame::valid_code(code) {
+                            return Err(Error::Protocol("invalid close code"));
+                        }
+                        let reason = String::from_utf8(frame.payload[2..].to_vec())
+                            .map_err(|_| Error::InvalidUtf8)?;
+                        Some(CloseFrame { code, reason })
+                    }
+                };
+                self.close_received = true;
+                if !self.close_sent {
+                    let code = close.as_ref().map(|c| c.code).unwrap_or(CloseFrame::NORMAL);
+                    self.close(code, "").await?;
+                }
+                Ok(Some(Message::Close(close)))
+            }
+            OP_CONTINUATION => {
+                let Some((_, data)) = self.fragments.as_mut() else {
+                    return Err(Error::Protocol("continuation without a message"));
+                };
+                if data.len() + frame.payload.len() > self.max_message_size {
+                    return Err(Error::TooBig);
+                }
+                data.extend_from_slice(&frame.payload);
+                if !frame.fin {
+                    return Ok(None);
+                }
+                let (opcode, data) = self.fragments.take().expect("checked above");
+                Self::data_message(opcode, data).map(Some)
+            }
+            opcode => {
+                if self.fragments.is_some() {
+                    return Err(Error::Protocol("new message before the last one finished"));
+                }
+                if !frame.fin {
+                    self.fragments = Some((opcode, frame.payload));
+                    return Ok(None);
+                }
+                Self::data_message(opcode, frame.payload).map(Some)
+            }
+        }
+    }
+
+    fn data_message(opcode: u8, data: Vec<u8>) -> Result<Message, Error> {
+        if opcode == OP_TEXT {
+            String::from_utf8(data)
+                .map(Message::Text)
+                .map_err(|_| Error::InvalidUtf8)
+        } else {
+            Ok(Message::Binary(data))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio::io::DuplexStream;
+
+    fn request(headers: &str) -> Request {
+        Request::from_string(format!("GET /ws HTTP/1.1\r\nHost: test\r\n{headers}\r\n")).unwrap()
+    }
+
+    const UPGRADE: &str = "Upgrade: websocket\r\nConnection: keep-alive, Upgrade\r\n\
+        Sec-WebSocket-Version: 13\r\nSec-WebSocket-Key: dGhlIHNhbXBsZSBub25jZQ==\r\n";
+
+    /// Masked frame as a client would send it
+    fn client_frame(fin: bool, opcode: u8, payload: &[u8]) -> Vec<u8> {
+        let mask = [0x37, 0xfa, 0x21, 0x3d];
+        let mut frame = vec![if fin { 0x80 | opcode } else { opcode }];
+        match payload.len() {
+            len if len < 126 => frame.push(0x80 | len as u8),
+            len => {
+                frame.push(0x80 | 126);
+                frame.extend_from_slice(&(len as u16).to_be_bytes());
+            }
+        }
+        frame.extend_from_slice(&mask);
+        frame.extend(payload.iter().enumerate().map(|(i, b)| b ^ mask[i % 4]));
+        frame
+    }
+
+    fn connect() -> (WebSocket, DuplexStream, CancellationToken) {
+        let (server, client) = tokio::io::duplex(1 << 20);
+        let cancel = CancellationToken::new();
+        let socket = WebSocket {
+            stream: Box::new(server),
+            buffer: BytesMut::new(),
+            protocol: None,
+            cancel: cancel.clone(),
+            max_message_size: 1024,
+            fragments: None,
+            close_sent: false,
+            close_received: false,
+        };
+        (socket, client, cancel)
+    }
+
+    async fn read_server_frame(client: &mut DuplexStream) -> (u8, Vec<u8>) {
+        let mut head = [0; 2];
+        client.read_exact(&mut head).await.unwrap();
+        assert_eq!(0, head[1] & 0x80, "server frames aren't masked");
+        let mut payload = vec![0; (head[1] & 0x7F) as usize];
+        client.read_exact(&mut payload).await.unwrap();
+        (head[0] & 0x0F, payload)
+    }
+
+    #[test]
+    fn handshakes() {
+        assert_eq!(
+            "s3pPLMBiTxaQ9kYGzzhZRbK+xOo=",
+            accept_key("dGhlIHNhbXBsZSBub25jZQ==")
+        );
+        let protocols = vec!["chat".to_string(), "superchat".to_string()];
+        let response = handshake(&request(UPGRADE), &protocols);
+        assert_eq!(StatusCode::SWITCHING_PROTOCOLS, response.status());
+        assert_eq!(Some("websocket"), response.headers().get("upgrade"));
+        assert_eq!(
+            Some("s3pPLMBiTxaQ9kYGzzhZRbK+xOo="),
+            response.headers().get("sec-websocket-accept")
+        );
+        assert_eq!(None, response.headers().get("sec-websocket-protocol"));
+
+        let offer = format!("{UPGRADE}Sec-WebSocket-Protocol: superchat, chat\r\n");
+        let response = handshake(&request(&offer), &protocols);
+        assert_eq!(
+            Some("chat"),
+            response.headers().get("sec-websocket-protocol")
+        );
+        let offer = format!("{UPGRADE}Sec-WebSocket-Protocol: other\r\n");
+        let response = handshake(&request(&offer), &protocols);
+        assert_eq!(None, response.headers().get("sec-websocket-protocol"));
+
+        let response = handshake(&request(""), &[]);
+        assert_eq!(StatusCode::UPGRADE_REQUIRED, response.status());
+        let old = UPGRADE.replace("Version: 13", "Version: 8");
+        let response = handshake(&request(&old), &[]);
+        assert_eq!(StatusCode::UPGRADE_REQUIRED, response.status());
+        assert_eq!(Some("13"), response.headers().get("sec-websocket-version"));
+        let short = UPGRADE.replace("dGhlIHNhbXBsZSBub25jZQ==", "c2hvcnQ=");
+        let response = handshake(&request(&short), &[]);
+        assert_eq!(StatusCode::BAD_REQUEST, response.status());
+
+        let h2 = Request::from_string(format!("GET /ws HTTP/2\r\nHost: test\r\n{UPGRADE}\r\n"));
+        let response = handshake(&h2.unwrap(), &[]);
+        assert_eq!(StatusCode::HTTP_VERSION_NOT_SUPPORTED, response.status());
+    }
+
+    #[test]
+    fn frames() {
+        let mut buffer = BytesMut::from(&client_frame(true, OP_TEXT, b"hello")[..]);
+        buffer.extend_from_slice(&client_frame(true, OP_BINARY, &[7; 300])[..10]);
+        let frame = parse_frame(&mut buffer, 1024).unwrap().unwrap();
+        assert_eq!(
+            (true, OP_TEXT, b"hello".to_vec()),
+            (frame.fin, frame.opcode, frame.payload)
+        );
+        // the second frame hasn't fully arrived
+        assert_eq!(None, parse_frame(&mut buffer, 1024).unwrap());
+        buffer.extend_from_slice(&client_frame(true, OP_BINARY, &[7; 300])[10..]);
+        assert_eq!(
+            300,
+            parse_frame(&mut buffer, 1024)
+                .unwrap()
+                .unwrap()
+                .payload
+                .len()
+        );
+        assert!(buffer.is_empty());
+
+        let mut unmasked = BytesMut::from(&encode_frame(OP_TEXT, b"hi")[..]);
+        assert!(matches!(
+            parse_frame(&mut unmasked, 1024),
+            Err(Error::Protocol(_))
+        ));
+        let mut big = BytesMut::from(&client_frame(true, OP_BINARY, &[0; 2000])[..4]);
+        assert!(matches!(parse_frame(&mut big, 1024), Err(Error::TooBig)));
+        let mut ping = BytesMut::from(&client_frame(false, OP_PING, b"")[..]);
+        assert!(matches!(
+            parse_frame(&mut ping, 1024),
+            Err(Error::Protocol(_))
+        ));
+
+        assert_eq!(vec![0x81, 2, b'h', b'i'], encode_frame(OP_TEXT, b"hi"));
+        assert_eq!([0x82, 126, 1, 44], encode_frame(OP_BINARY, &[0; 300])[..4]);
+    }
+
+    #[tokio::test]
+    async fn messages() {
+        let (mut socket, mut client, _) = connect();
+        client
+            .write_all(&client_frame(true, OP_TEXT, b"hello"))
+            .await
+            .unwrap();
+        client
+            .write_all(&client_frame(false, OP_BINARY, b"ab"))
+            .await
+            .unwrap();
+        client
+            .write_all(&client_frame(true, OP_PING, b"p"))
+            .await
+            .unwrap();
+        client
+            .write_all(&client_frame(true, OP_CONTINUATION, b"cd"))
+            .await
+            .unwrap();
+        let close = [&1000u16.to_be_bytes()[..], b"bye"].concat();
+        client
+            .write_all(&client_frame(true, OP_CLOSE, &close))
+            .await
+            .unwrap();
+
+        assert_eq!(
+            Message::Text("hello".into()),
+            socket.recv().await.unwrap().unwrap()
+        );
+        // control frames can arrive in the middle of a fragmented message
+        assert_eq!(
+            Message::Ping(b"p".to_vec()),
+            socket.recv().await.unwrap().unwrap()
+        );
+        assert_eq!(
+            (OP_PONG, b"p".to_vec()),
+            read_server_frame(&mut client).await
+        );
+        assert_eq!(
+            Message::Binary(b"abcd".to_vec()),
+            socket.recv().await.unwrap().unwrap()
+        );
+        assert_eq!(
+            Message::Close(Some(CloseFrame::new(1000, "bye"))),
+            socket.recv().await.unwrap().unwrap()
+        );
+        assert_eq!(
+            (OP_CLOSE, 1000u16.to_be_bytes().to_vec()),
+            read_server_frame(&mut client).await
+        );
+        assert!(socket.recv().await.is_none());
+        assert!(matches!(socket.send_text("late").await, Err(Error::Closed)));
+    }
+
+    #[tokio::test]
+    async fn protocol_errors() {
+        let (mut socket, mut client, _) = connect();
+        client
+            .write_all(&client_frame(true, OP_TEXT, b"\xff\xfe"))
+            .await
+            .unwrap();
+        assert!(matches!(socket.recv().await, Some(Err(Error::InvalidUtf8))));
+        let (opcode, payload) = read_server_frame(&mut client).await;
+        assert_eq!(OP_CLOSE, opcode);
+        assert_eq!(CloseFrame::INVALID_DATA.to_be_bytes(), payload[..2]);
+        assert!(socket.recv().await.is_none());
+
+        let (mut socket, mut client, _) = connect();
+        client
+            .write_all(&client_frame(true, OP_CONTINUATION, b"x"))
+            .await
+            .unwrap();
+        assert!(matches!(socket.recv().await, Some(Err(Error::Protocol(_)))));
+        let (_, payload) = read_server_frame(&mut client).await;
+        assert_eq!(CloseFrame::PROTOCOL_ERROR.to_be_bytes(), payload[..2]);
+
+        // peer gone without a close
+        let (mut socket, client, _) = connect();
+        drop(client);
+        assert!(socket.recv().await.is_none());
+    }
+
+    #[tokio::test]
+    async fn shutdown() {
+        let (mut socket, mut client, cancel) = connect();
+        socket.send_text("hi").await.unwrap();
+        assert_eq!(
+            (OP_TEXT, b"hi".to_vec()),
+            read_server_frame(&mut client).await
+        );
+        cancel.cancel();
+        assert!(socket.recv().await.is_none());
+        let (opcode, payload) = read_server_frame(&mut client).await;
+        assert_eq!(OP_CLOSE, opcode);
+        assert_eq!(CloseFrame::GOING_AWAY.to_be_bytes(), payload[..2]);
+        assert!(matches!(socket.send_text("late").await, Err(Error::Closed)));
+    }
+}
@@ -0,0 +1,112 @@
+use get_port::tcp::TcpPort;
+use get_port::{Ops, Range};
+use nucleus_http::{
+    error_pages::ErrorHandler,
+    http::{MimeType, StatusCode},
+    request::Request,
+    response::Response,
+    routes::{Route, Router},
+    Server,
+};
+use std::format;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+async fn raw_request(port: u16, request: &str) -> String {
+    let mut stream = tokio::net::TcpStream::connect(format!("127.0.0.1:{port}"))
+        .await
+        .unwrap();
+    stream.write_all(request.as_bytes()).await.unwrap();
+    let mut response = String::new();
+    stream.read_to_string(&mut response).await.unwrap();
+    response
+}
+
+async fn bad_request(request: Option<Request>, error: Response) -> Response {
+    let body = format!("custom {} parsed: {}", error.status(), request.is_some());
+    Response::new(StatusCode::BAD_REQUEST, body.into(), MimeType::PlainText)
+}
+
+#[tokio::test]
+async fn error_pages() {
+    let tcp_port = TcpPort::in_range(
+        "127.0.0.1",
+        Range {
+            min: 6000,
+            max: 8000,
+        },
+    )
+    .unwrap();
+    let listener_ip = format!("0.0.0.0:{}", tcp_port);
+    let mut router = Router::new(());
+    router.add_error_handler(StatusCode::NOT_FOUND, ErrorHandler::file("404.html"));
+    router.add_error_handler(StatusCode::BAD_REQUEST, ErrorHandler::function(bad_request));
+    let server = Server::bind(&listener_ip, router, "./").await.unwrap();
+    tokio::spawn(async move { server.serve().await.expect("Server Shutdown") });
+
+    let response = raw_request(
+        tcp_port,
+        "GET /missing HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n\r\n",
+    )
+    .await;
+    assert!(response.starts_with("HTTP/1.1 404"), "{response}");
+    assert!(response.contains("Content-Type: text/html"), "{response}");
+    assert!(
+        response.ends_with(include_str!("../404.html")),
+        "{response}"
+    );
+
+    let response = raw_request(tcp_port, "GET / HTTP/1.1\r\nHost: localhost\r\nbad\r\n\r\n").await;
+    assert!(response.starts_with("HTTP/1.1 400"), "{response}");
+    assert!(
+        response.ends_with("custom 400 Bad Request parsed: false"),
+        "{response}"
+    );
+}
+
+async fn panics(_: (), _: Request) -> Result<String, String> {
+    panic!("handler bug");
+}
+
+async fn hello(_: (), _: Request) -> Result<String, String> {
+    Ok("hello".to_string())
+}
+
+#[tokio::test]
+async fn handler_panic() {
+    let tcp_port = TcpPort::in_range(
+        "127.0.0.1",
+        Range {
+            min: 6000,
+            max: 8000,
+        },
+    )
+    .unwrap();
+    let listener_ip = format!("0.0.0.0:{}", tcp_port);
+    let mut router = Router::new(());
+    router.add_route(Route::get("/panic", panics)).await;
+    router.add_route(Route::get("/hello", hello)).await;
+    let server = Server::bind(&listener_ip, router, "./").await.unwrap();
+    tokio::spawn(async move { server.serve().await.expect("Server Shutdown") });
+
+    // the same connection still answers after the handler panicked
+    let mut stream = tokio::net::TcpStream::connect(format!("127.0.0.1:{tcp_port}"))
+        .await
+        .unwrap();
+    stream
+        .write_all(b"GET /panic HTTP/1.1\r\nHost: localhost\r\n\r\n")
+        .await
+        .unwrap();
+    let mut buffer = vec![0; 4096];
+    let n = stream.read(&mut buffer).await.unwrap();
+    let response = String::from_utf8_lossy(&buffer[..n]);
+    assert!(response.starts_with("HTTP/1.1 500"), "{response}");
+
+    stream
+        .write_all(b"GET /hello HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n\r\n")
+        .await
+        .unwrap();
+    let mut response = String::new();
+    stream.read_to_string(&mut response).await.unwrap();
+    assert!(response.starts_with("HTTP/1.1 200"), "{response}");
+    assert!(response.ends_with("hello"), "{response}");
+}
@@ -0,0 +1,205 @@
+use get_port::tcp::TcpPort;
+use get_port::{Ops, Range};
+use nucleus_http::{
+    http::StatusCode,
+    request::Request,
+    routes::{Route, Router},
+    Server,
+};
+use std::{format, time::Duration};
+use tokio::{
+    io::{AsyncReadExt, AsyncWriteExt},
+    net::TcpStream,
+};
+
+async fn echo(_: (), request: Request) -> Result<String, String> {
+    Ok(String::from_utf8_lossy(request.body()).to_string())
+}
+
+async fn connect() -> TcpStream {
+    let tcp_port = TcpPort::in_range(
+        "127.0.0.1",
+        Range {
+            min: 6000,
+            max: 8000,
+        },
+    )
+    .unwrap();
+    let listener_ip = format!("0.0.0.0:{}", tcp_port);
+    let mut router = Router::new(());
+    router.set_max_body_size(16);
+    router.add_route(Route::post("/echo", echo)).await;
+    let private = Route::post("/private", echo).with_check(|request| {
+        match request.get_header_value("Authorization") {
+            Some(_) => Ok(()),
+            None => Err(StatusCode::UNAUTHORIZED),
+        }
+    });
+    router.add_route(private).await;
+    let server = Server::bind(&listener_ip, router, "./").await.unwrap();
+    tokio::spawn(async move { server.serve().await.expect("Server Shutdown") });
+    TcpStream::connect(format!("127.0.0.1:{tcp_port}"))
+        .await
+        .unwrap()
+}
+
+fn post(path: &str, length: usize, headers: &str) -> String {
+    format!("POST {path} HTTP/1.1\r\nHost: localhost\r\nContent-Length: {length}\r\n{headers}\r\n")
+}
+
+async fn read_until(stream: &mut TcpStream, end: &str) -> String {
+    let mut received = vec![];
+    while !String::from_utf8_lossy(&received).ends_with(end) {
+        let mut buffer = [0; 512];
+        let n = tokio::time::timeout(Duration::from_secs(5), stream.read(&mut buffer))
+            .await
+            .expect("missing response")
+            .unwrap();
+        assert_ne!(0, n, "{}", String::from_utf8_lossy(&received));
+        received.extend_from_slice(&buffer[..n]);
+    }
+    String::from_utf8(received).unwrap()
+}
+
+/// Final response sent without reading the body, then the connection is closed
+async fn rejected(stream: &mut TcpStream) -> String {
+    let mut received = vec![];
+    tokio::time::timeout(Duration::from_secs(5), stream.read_to_end(&mut received))
+        .await
+        .expect("connection wasn't closed")
+        .unwrap();
+    String::from_utf8(received).unwrap()
+}
+
+#[tokio::test]
+async fn continue_body() {
+    let mut stream = connect().await;
+    let head = post("/echo", 5, "Expect: 100-continue\r\n");
+    stream.write_all(head.as_bytes()).await.unwrap();
+    assert_eq!(
+        "HTTP/1.1 100 Continue\r\n\r\n",
+        read_until(&mut stream, "\r\n\r\n").await
+    );
+    stream.write_all(b"hello").await.unwrap();
+    let response = read_until(&mut stream, "hello").await;
+    assert!(response.starts_with("HTTP/1.1 200 OK\r\n"), "{response}");
+
+    // the connection is still usable
+    let request = format!("{}hi", post("/echo", 2, ""));
+    stream.write_all(request.as_bytes()).await.unwrap();
+    let response = read_until(&mut stream, "\r\n\r\nhi").await;
+    assert!(response.starts_with("HTTP/1.1 200 OK\r\n"), "{response}");
+}
+
+#[tokio::test]
+async fn too_large() {
+    let mut stream = connect().await;
+    let head = post("/echo", 1000, "Expect: 100-continue\r\n");
+    stream.write_all(head.as_bytes()).await.unwrap();
+    let response = rejected(&mut stream).await;
+    assert!(
+        response.starts_with("HTTP/1.1 413 Payload Too Large\r\n"),
+        "{response}"
+    );
+    assert!(response.contains("connection: close\r\n"), "{response}");
+
+    // a chunked body is only known to be too large while it's read
+    for first_chunk in ["", "20\r\n0123456789abcdef0123456789abcdef\r\n"] {
+        let mut stream = connect().await;
+        let head = "POST /echo HTTP/1.1\r\nHost: localhost\r\nTransfer-Encoding: chunked\r\n\r\n";
+        stream.write_all(head.as_bytes()).await.unwrap();
+        stream.write_all(first_chunk.as_bytes()).await.unwrap();
+        tokio::time::sleep(Duration::from_millis(50)).await;
+        let rest = "20\r\n0123456789abcdef0123456789abcdef\r\n0\r\n\r\n";
+        // the server may have closed already
+        let _ = stream.write_all(rest.as_bytes()).await;
+        let response = rejected(&mut stream).await;
+        assert!(
+            response.starts_with("HTTP/1.1 413 Payload Too Large\r\n"),
+            "{response}"
+        );
+    }
+}
+
+#[tokio::test]
+async fn check_failed() {
+    // without Expect the head is checked before waiting on the body too
+    let mut stream = connect().await;
+    stream
+        .write_all(post("/private", 5, "").as_bytes())
+        .await
+        .unwrap();
+    let response = rejected(&mut stream).await;
+    assert!(
+        response.starts_with("HTTP/1.1 401 Unauthorized\r\n"),
+        "{response}"
+    );
+}
+
+#[tokio::test]
+async fn expectation_failed() {
+    let mut stream = connect().await;
+    let head = post("/echo", 5, "Expect: something-else\r\n");
+    stream.write_all(head.as_bytes()).await.unwrap();
+    let response = rejected(&mut stream).await;
+    assert!(
+        response.starts_with("HTTP/1.1 417 Expectation Failed\r\n"),
+        "{response}"
+    );
+}
+
+#[tokio::test]
+async fn http2() {
+    // h2c with prior knowledge, streams go through the same checks
+    let (client, connection) = h2::client::handshake(connect().await).await.unwrap();
+    tokio::spawn(async move {
+        let _ = connection.await;
+    });
+    let request = |path: &str, headers: &[(&str, &str)]| {
+        let mut request = ::http::Request::builder()
+            .method("POST")
+            .uri(format!("http://localhost{path}"));
+        for (key, value) in headers {
+            request = request.header(*key, *value);
+        }
+        request.body(()).unwrap()
+    };
+
+    let mut client = client.ready().await.unwrap();
+    let (response, _body) = client
+        .send_request(request("/private", &[]), false)
+        .unwrap();
+    assert_eq!(StatusCode::UNAUTHORIZED, response.await.unwrap().status());
+
+    let mut client = client.ready().await.unwrap();
+    let (response, _body) = client
+        .send_request(request("/echo", &[("content-length", "1000")]), false)
+        .unwrap();
+    assert_eq!(
+        StatusCode::PAYLOAD_TOO_LARGE,
+        response.await.unwrap().status()
+    );
+
+    // without Content-Length the limit is checked as the body comes in
+    let mut client = client.ready().await.unwrap();
+    let (response, mut body) = client.send_request(request("/echo", &[]), false).unwrap();
+    body.send_data(vec![b'a'; 100].into(), true).unwrap();
+    assert_eq!(
+        StatusCode::PAYLOAD_TOO_LARGE,
+        response.await.unwrap().status()
+    );
+
+    let mut client = client.ready().await.unwrap();
+    let (response, mut body) = client
+        .send_request(request("/private", &[("authorization", "yes")]), false)
+        .unwrap();
+    body.send_data("hello".into(), true).unwrap();
+    let response = response.await.unwrap();
+    assert_eq!(StatusCode::OK, response.status());
+    let mut body = response.into_body();
+    let mut data = vec![];
+    while let Some(chunk) = body.data().await {
+        data.extend_from_slice(&chunk.unwrap());
+    }
+    assert_eq!(b"hello", &data[..]);
+}
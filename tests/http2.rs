@@ -0,0 +1,293 @@
+#[path = "../src/test_support.rs"]
+mod test_support;
+use get_port::tcp::TcpPort;
+use get_port::{Ops, Range};
+use h2::client::ResponseFuture;
+use nucleus_http::{
+    multipart::{FieldData, MultipartConfig},
+    request::Request,
+    routes::{Route, Router},
+    Server,
+};
+use std::{
+    format,
+    sync::Arc,
+    time::{Duration, Instant},
+};
+use tokio::io::{AsyncRead, AsyncWrite};
+use tokio_rustls::rustls::{self, Certificate, RootCertStore, ServerName};
+
+async fn echo(_: (), request: Request) -> Result<String, String> {
+    let body = String::from_utf8_lossy(request.body()).to_string();
+    Ok(format!(
+        "{} {} {} {body}",
+        request.method(),
+        request.version(),
+        request.path()
+    ))
+}
+
+async fn slow(_: (), _: Request) -> Result<String, String> {
+    tokio::time::sleep(Duration::from_millis(300)).await;
+    Ok("slow".to_string())
+}
+
+/// Name, size and whether it was spooled of every field in order
+async fn upload(_: (), request: Request) -> Result<String, String> {
+    let multipart = request.multipart().ok_or("not multipart")?;
+    let mut fields = vec![];
+    for field in multipart {
+        let bytes = field.bytes().await.map_err(|e| e.to_string())?;
+        let spooled = matches!(field.data(), FieldData::Spooled(_));
+        fields.push(format!("{} {} {spooled}", field.name(), bytes.len()));
+    }
+    Ok(fields.join(","))
+}
+
+fn port() -> u16 {
+    TcpPort::in_range(
+        "127.0.0.1",
+        Range {
+            min: 6000,
+            max: 8000,
+        },
+    )
+    .unwrap()
+}
+
+async fn router() -> Router<()> {
+    let mut router = Router::new(());
+    router.add_route(Route::get("/echo", echo)).await;
+    router.add_route(Route::post("/echo", echo)).await;
+    router.add_route(Route::get("/slow", slow)).await;
+    router.add_route(Route::post("/upload", upload)).await;
+    let mut config = MultipartConfig::new();
+    config.set_spool_threshold(1024);
+    config.set_max_field_size(64 * 1024);
+    router.set_multipart_config(config);
+    router
+}
+
+fn form(file_size: usize) -> Vec<u8> {
+    let mut body = b"--xyz\r\n\
+        Content-Disposition: form-data; name=\"title\"\r\n\
+        \r\n\
+        hello\r\n\
+        --xyz\r\n\
+        Content-Disposition: form-data; name=\"file\"; filename=\"data.bin\"\r\n\
+        \r\n"
+        .to_vec();
+    body.extend((0..file_size).map(|i| (i % 251) as u8));
+    body.extend_from_slice(b"\r\n--xyz--\r\n");
+    body
+}
+
+async fn read_body(response: ResponseFuture) -> (::http::StatusCode, String) {
+    let response = response.await.unwrap();
+    let status = response.status();
+    let mut body = response.into_body();
+    let mut data = vec![];
+    while let Some(chunk) = body.data().await {
+        let chunk = chunk.unwrap();
+        let _ = body.flow_control().release_capacity(chunk.len());
+        data.extend_from_slice(&chunk);
+    }
+    (status, String::from_utf8(data).unwrap())
+}
+
+/// Slow and fast requests on one connection, the fast one shouldn't wait for the slow one
+async fn multiplexed<T>(io: T, scheme: &str)
+where
+    T: AsyncRead + AsyncWrite + Unpin + Send + 'static,
+{
+    let (client, connection) = h2::client::handshake(io).await.unwrap();
+    tokio::spawn(async move {
+        let _ = connection.await;
+    });
+    let mut client = client.ready().await.unwrap();
+    let request = |method: &str, path: &str| {
+        ::http::Request::builder()
+            .method(method)
+            .uri(format!("{scheme}://localhost{path}"))
+            .body(())
+            .unwrap()
+    };
+    let start = Instant::now();
+    let (slow, _) = client.send_request(request("GET", "/slow"), true).unwrap();
+    let mut client = client.ready().await.unwrap();
+    let (fast, _) = client.send_request(request("GET", "/echo"), true).unwrap();
+    let mut client = client.ready().await.unwrap();
+    let (post, mut body) = client
+        .send_request(request("POST", "/echo"), false)
+        .unwrap();
+    body.send_data("hello".into(), true).unwrap();
+
+    assert_eq!(
+        (::http::StatusCode::OK, "GET HTTP/2 /echo ".to_string()),
+        read_body(fast).await
+    );
+    assert!(start.elapsed() < Duration::from_millis(300));
+    assert_eq!(
+        (
+            ::http::StatusCode::OK,
+            "POST HTTP/2 /echo hello".to_string()
+        ),
+        read_body(post).await
+    );
+    assert_eq!(
+        (::http::StatusCode::OK, "slow".to_string()),
+        read_body(slow).await
+    );
+
+    let mut client = client.ready().await.unwrap();
+    let (missing, _) = client.send_request(request("GET", "/nope"), true).unwrap();
+    assert_eq!(::http::StatusCode::NOT_FOUND, read_body(missing).await.0);
+}
+
+#[tokio::test]
+async fn h2c_prior_knowledge() {
+    let tcp_port = port();
+    let listener_ip = format!("0.0.0.0:{}", tcp_port);
+    let server = Server::bind(&listener_ip, router().await, "./")
+        .await
+        .unwrap();
+    tokio::spawn(async move { server.serve().await.expect("Server Shutdown") });
+
+    let stream = tokio::net::TcpStream::connect(format!("127.0.0.1:{tcp_port}"))
+        .await
+        .unwrap();
+    multiplexed(stream, "http").await;
+}
+
+#[tokio::test]
+async fn h2_over_tls() {
+    let cert = rcgen::generate_simple_self_signed(vec!["localhost".to_string()]).unwrap();
+    let dir = test_support::TempDir::new("h2");
+    std::fs::write(dir.join("cert.pem"), cert.serialize_pem().unwrap()).unwrap();
+    std::fs::write(dir.join("key.pem"), cert.serialize_private_key_pem()).unwrap();
+
+    let tcp_port = port();
+    let listener_ip = format!("0.0.0.0:{}", tcp_port);
+    let server = Server::bind_tls(
+        &listener_ip,
+        &dir.join("cert.pem"),
+        &dir.join("key.pem"),
+        router().await,
+        "./",
+    )
+    .await
+    .unwrap();
+    tokio::spawn(async move { server.serve().await.expect("Server Shutdown") });
+
+    let mut roots = RootCertStore::empty();
+    roots
+        .add(&Certificate(cert.serialize_der().unwrap()))
+        .unwrap();
+    let mut config = rustls::ClientConfig::builder()
+        .with_safe_defaults()
+        .with_root_certificates(roots)
+        .with_no_client_auth();
+    config.alpn_protocols = vec![b"h2".to_vec(), b"http/1.1".to_vec()];
+    let connector = tokio_rustls::TlsConnector::from(Arc::new(config));
+    let stream = tokio::net::TcpStream::connect(format!("127.0.0.1:{tcp_port}"))
+        .await
+        .unwrap();
+    let tls = connector
+        .connect(ServerName::try_from("localhost").unwrap(), stream)
+        .await
+        .unwrap();
+    assert_eq!(Some(&b"h2"[..]), tls.get_ref().1.alpn_protocol());
+    multiplexed(tls, "https").await;
+}
+
+#[tokio::test]
+async fn h2c_multipart() {
+    let tcp_port = port();
+    let listener_ip = format!("0.0.0.0:{}", tcp_port);
+    let server = Server::bind(&listener_ip, router().await, "./")
+        .await
+        .unwrap();
+    tokio::spawn(async move { server.serve().await.expect("Server Shutdown") });
+
+    let stream = tokio::net::TcpStream::connect(format!("127.0.0.1:{tcp_port}"))
+        .await
+        .unwrap();
+    let (client, connection) = h2::client::handshake(stream).await.unwrap();
+    tokio::spawn(async move {
+        let _ = connection.await;
+    });
+    let request = || {
+        ::http::Request::builder()
+            .method("POST")
+            .uri("http://localhost/upload")
+            .header("Content-Type", "multipart/form-data; boundary=xyz")
+            .body(())
+            .unwrap()
+    };
+
+    // no Content-Length, the body is parsed as the chunks arrive and the file is spooled
+    let mut client = client.ready().await.unwrap();
+    let (response, mut body) = client.send_request(request(), false).unwrap();
+    let data = form(20 * 1024);
+    let chunks: Vec<_> = data.chunks(3000).collect();
+    for (i, chunk) in chunks.iter().enumerate() {
+        body.reserve_capacity(chunk.len());
+        body.send_data(chunk.to_vec().into(), i == chunks.len() - 1)
+            .unwrap();
+    }
+    assert_eq!(
+        (
+            ::http::StatusCode::OK,
+            "title 5 false,file 20480 true".to_string()
+        ),
+        read_body(response).await
+    );
+
+    // a field over the limit is refused before the whole body was sent
+    let mut client = client.ready().await.unwrap();
+    let (response, mut body) = client.send_request(request(), false).unwrap();
+    let data = form(100 * 1024);
+    body.send_data(data[..80 * 1024].to_vec().into(), false)
+        .unwrap();
+    // the unread rest of the stream is reset after the response so only its head is checked
+    let response = tokio::time::timeout(Duration::from_secs(5), response)
+        .await
+        .expect("missing response")
+        .unwrap();
+    assert_eq!(::http::StatusCode::PAYLOAD_TOO_LARGE, response.status());
+}
+
+#[tokio::test]
+async fn h2c_timeouts() {
+    let tcp_port = port();
+    let listener_ip = format!("0.0.0.0:{}", tcp_port);
+    let mut server = Server::bind(&listener_ip, router().await, "./")
+        .await
+        .unwrap();
+    server.set_read_timeout(Duration::from_millis(200));
+    tokio::spawn(async move { server.serve().await.expect("Server Shutdown") });
+
+    let stream = tokio::net::TcpStream::connect(format!("127.0.0.1:{tcp_port}"))
+        .await
+        .unwrap();
+    let (client, connection) = h2::client::handshake(stream).await.unwrap();
+    let connection = tokio::spawn(connection);
+
+    // a body that stalls is answered with 408
+    let mut client = client.ready().await.unwrap();
+    let request = ::http::Request::builder()
+        .method("POST")
+        .uri("http://localhost/echo")
+        .body(())
+        .unwrap();
+    let (response, _body) = client.send_request(request, false).unwrap();
+    let response = tokio::time::timeout(Duration::from_secs(5), response)
+        .await
+        .expect("missing response")
+        .unwrap();
+    assert_eq!(::http::StatusCode::REQUEST_TIMEOUT, response.status());
+
+    // once no streams are open the connection is closed after the timeout
+    let closed = tokio::time::timeout(Duration::from_secs(5), connection).await;
+    assert!(closed.is_ok(), "idle connection wasn't closed");
+}
@@ -0,0 +1,164 @@
+use get_port::tcp::TcpPort;
+use get_port::{Ops, Range};
+use nucleus_http::{
+    multipart::{FieldData, MultipartConfig},
+    request::Request,
+    routes::{Route, Router},
+    Server,
+};
+use std::{format, time::Duration};
+use tokio::{
+    io::{AsyncReadExt, AsyncWriteExt},
+    net::TcpStream,
+};
+
+/// Name, size and whether it was spooled of every field in order
+async fn upload(_: (), request: Request) -> Result<String, String> {
+    let multipart = request.multipart().ok_or("not multipart")?;
+    let mut fields = vec![];
+    for field in multipart {
+        let bytes = field.bytes().await.map_err(|e| e.to_string())?;
+        assert_eq!(field.len(), bytes.len() as u64);
+        let spooled = matches!(field.data(), FieldData::Spooled(_));
+        fields.push(format!("{} {} {spooled}", field.name(), bytes.len()));
+    }
+    Ok(fields.join(","))
+}
+
+async fn connect() -> TcpStream {
+    let tcp_port = TcpPort::in_range(
+        "127.0.0.1",
+        Range {
+            min: 6000,
+            max: 8000,
+        },
+    )
+    .unwrap();
+    let listener_ip = format!("0.0.0.0:{}", tcp_port);
+    let mut router = Router::new(());
+    let mut config = MultipartConfig::new();
+    config.set_spool_threshold(1024);
+    config.set_max_field_size(64 * 1024);
+    router.set_multipart_config(config);
+    router.add_route(Route::post("/upload", upload)).await;
+    let server = Server::bind(&listener_ip, router, "./").await.unwrap();
+    tokio::spawn(async move { server.serve().await.expect("Server Shutdown") });
+    TcpStream::connect(format!("127.0.0.1:{tcp_port}"))
+        .await
+        .unwrap()
+}
+
+fn form(file_size: usize) -> Vec<u8> {
+    let mut body = b"--xyz\r\n\
+        Content-Disposition: form-data; name=\"title\"\r\n\
+        \r\n\
+        hello\r\n\
+        --xyz\r\n\
+        Content-Disposition: form-data; name=\"file\"; filename=\"data.bin\"\r\n\
+        Content-Type: application/octet-stream\r\n\
+        \r\n"
+        .to_vec();
+    body.extend((0..file_size).map(|i| (i % 251) as u8));
+    body.extend_from_slice(b"\r\n--xyz--\r\n");
+    body
+}
+
+fn head(length: usize) -> String {
+    format!(
+        "POST /upload HTTP/1.1\r\nHost: localhost\r\n\
+        Content-Type: multipart/form-data; boundary=xyz\r\nContent-Length: {length}\r\n\r\n"
+    )
+}
+
+async fn read_response(stream: &mut TcpStream) -> String {
+    let mut received = vec![];
+    loop {
+        let mut buffer = [0; 1024];
+        let n = tokio::time::timeout(Duration::from_secs(5), stream.read(&mut buffer))
+            .await
+            .expect("missing response")
+            .unwrap();
+        received.extend_from_slice(&buffer[..n]);
+        let response = String::from_utf8_lossy(&received).to_string();
+        let Some((head, body)) = response.split_once("\r\n\r\n") else {
+            assert_ne!(0, n, "{response}");
+            continue;
+        };
+        let length = head
+            .lines()
+            .find_map(|line| line.strip_prefix("Content-Length: "))
+            .map_or(0, |length| length.parse().unwrap());
+        if body.len() >= length || n == 0 {
+            return response;
+        }
+    }
+}
+
+#[tokio::test]
+async fn streamed_upload() {
+    let mut stream = connect().await;
+    let body = form(20 * 1024);
+    stream.write_all(head(body.len()).as_bytes()).await.unwrap();
+    for chunk in body.chunks(3000) {
+        stream.write_all(chunk).await.unwrap();
+        tokio::time::sleep(Duration::from_millis(5)).await;
+    }
+    let response = read_response(&mut stream).await;
+    assert!(response.starts_with("HTTP/1.1 200 OK\r\n"), "{response}");
+    assert!(
+        response.ends_with("\r\n\r\ntitle 5 false,file 20480 true"),
+        "{response}"
+    );
+
+    // the connection is still usable, small files stay in memory
+    let body = form(10);
+    let mut request = head(body.len()).into_bytes();
+    request.extend(body);
+    stream.write_all(&request).await.unwrap();
+    let response = read_response(&mut stream).await;
+    assert!(
+        response.ends_with("\r\n\r\ntitle 5 false,file 10 false"),
+        "{response}"
+    );
+}
+
+#[tokio::test]
+async fn malformed() {
+    let mut stream = connect().await;
+    let body = b"--xyz\r\nno disposition here\r\n\r\nvalue\r\n--xyz--\r\n";
+    stream.write_all(head(body.len()).as_bytes()).await.unwrap();
+    stream.write_all(body).await.unwrap();
+    let response = read_response(&mut stream).await;
+    assert!(
+        response.starts_with("HTTP/1.1 400 Bad Request\r\n"),
+        "{response}"
+    );
+}
+
+#[tokio::test]
+async fn field_too_large() {
+    let mut stream = connect().await;
+    let body = form(100 * 1024);
+    stream.write_all(head(body.len()).as_bytes()).await.unwrap();
+    // the server answers once the field is over the limit without reading the rest
+    let mut received = vec![];
+    for chunk in body.chunks(4096) {
+        stream.write_all(chunk).await.unwrap();
+        let mut buffer = [0; 1024];
+        if let Ok(read) =
+            tokio::time::timeout(Duration::from_millis(10), stream.read(&mut buffer)).await
+        {
+            received.extend_from_slice(&buffer[..read.unwrap()]);
+            break;
+        }
+    }
+    // the rest of the body is unread so closing can reset the connection
+    let _ = tokio::time::timeout(Duration::from_secs(5), stream.read_to_end(&mut received))
+        .await
+        .expect("connection wasn't closed");
+    let response = String::from_utf8(received).unwrap();
+    assert!(
+        response.starts_with("HTTP/1.1 413 Payload Too Large\r\n"),
+        "{response}"
+    );
+}
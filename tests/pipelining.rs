@@ -0,0 +1,214 @@
+use get_port::tcp::TcpPort;
+use get_port::{Ops, Range};
+use nucleus_http::{
+    request::Request,
+    routes::{Route, Router},
+    Server,
+};
+use std::{format, time::Duration};
+use tokio::{
+    io::{AsyncReadExt, AsyncWriteExt},
+    net::TcpStream,
+};
+
+async fn echo(_: (), request: Request) -> Result<String, String> {
+    let body = String::from_utf8_lossy(request.body()).to_string();
+    Ok(format!("{} {body}", request.path()))
+}
+
+async fn connect(max_pipelined: usize) -> TcpStream {
+    let tcp_port = TcpPort::in_range(
+        "127.0.0.1",
+        Range {
+            min: 6000,
+            max: 8000,
+        },
+    )
+    .unwrap();
+    let listener_ip = format!("0.0.0.0:{}", tcp_port);
+    let mut router = Router::new(());
+    router.add_route(Route::get("/*", echo)).await;
+    router.add_route(Route::post("/*", echo)).await;
+    let mut server = Server::bind(&listener_ip, router, "./").await.unwrap();
+    server.set_max_pipelined(max_pipelined);
+    tokio::spawn(async move { server.serve().await.expect("Server Shutdown") });
+    TcpStream::connect(format!("127.0.0.1:{tcp_port}"))
+        .await
+        .unwrap()
+}
+
+fn get(path: &str) -> String {
+    format!("GET {path} HTTP/1.1\r\nHost: localhost\r\n\r\n")
+}
+
+/// Read until count responses with their bodies arrived, just the bodies in order
+async fn read_bodies(stream: &mut TcpStream, count: usize) -> Vec<String> {
+    let mut received = String::new();
+    let mut bodies = vec![];
+    while bodies.len() < count {
+        let mut buffer = [0; 1024];
+        let n = tokio::time::timeout(Duration::from_secs(5), stream.read(&mut buffer))
+            .await
+            .expect("missing response")
+            .unwrap();
+        assert_ne!(0, n, "closed after {bodies:?}");
+        received.push_str(std::str::from_utf8(&buffer[..n]).unwrap());
+        while let Some((head, rest)) = received.split_once("\r\n\r\n") {
+            let length: usize = head
+                .lines()
+                .find_map(|line| line.strip_prefix("Content-Length: "))
+                .unwrap()
+                .parse()
+                .unwrap();
+            if rest.len() < length {
+                break;
+            }
+            bodies.push(rest[..length].to_string());
+            received = rest[length..].to_string();
+        }
+    }
+    assert!(received.is_empty(), "{received}");
+    bodies
+}
+
+#[tokio::test]
+async fn pipelined_requests() {
+    let mut stream = connect(16).await;
+    let post = "POST /two HTTP/1.1\r\nHost: localhost\r\nContent-Length: 4\r\n\r\nbody";
+    let requests = format!("{}{post}{}", get("/one"), get("/three"));
+    stream.write_all(requests.as_bytes()).await.unwrap();
+    assert_eq!(
+        vec!["/one ", "/two body", "/three "],
+        read_bodies(&mut stream, 3).await
+    );
+
+    // a request split across writes still waits for the rest of it
+    let requests = format!("{}{}", get("/four"), get("/five"));
+    let (first, second) = requests.split_at(requests.len() - 10);
+    stream.write_all(first.as_bytes()).await.unwrap();
+    assert_eq!(vec!["/four "], read_bodies(&mut stream, 1).await);
+    stream.write_all(second.as_bytes()).await.unwrap();
+    assert_eq!(vec!["/five "], read_bodies(&mut stream, 1).await);
+}
+
+#[tokio::test]
+async fn max_pipelined() {
+    let mut stream = connect(2).await;
+    let requests: String = ["/1", "/2", "/3", "/4"].iter().map(|p| get(p)).collect();
+    stream.write_all(requests.as_bytes()).await.unwrap();
+    let mut received = vec![];
+    tokio::time::timeout(Duration::from_secs(5), stream.read_to_end(&mut received))
+        .await
+        .expect("connection wasn't closed")
+        .unwrap();
+    let received = String::from_utf8(received).unwrap();
+    // the rest is left for the client to send again
+    assert_eq!(2, received.matches("HTTP/1.1 200 OK").count(), "{received}");
+    assert!(received.ends_with("\r\n\r\n/2 "), "{received}");
+    assert!(received.contains("connection: close\r\n"), "{received}");
+}
+
+#[tokio::test]
+#[should_panic(expected = "max_pipelined can't be zero")]
+async fn zero_max_pipelined() {
+    connect(0).await;
+}
+
+/// Everything the server sends before closing the connection
+async fn read_all(stream: &mut TcpStream) -> String {
+    let mut received = vec![];
+    tokio::time::timeout(Duration::from_secs(5), stream.read_to_end(&mut received))
+        .await
+        .expect("connection wasn't closed")
+        .unwrap();
+    String::from_utf8(received).unwrap()
+}
+
+#[tokio::test]
+async fn chunked_requests() {
+    let mut stream = connect(16).await;
+    let chunked = "POST /two HTTP/1.1\r\nHost: localhost\r\nTransfer-Encoding: chunked\r\n\r\n\
+        2\r\nbo\r\n2;last\r\ndy\r\n0\r\n\r\n";
+    let requests = format!("{}{chunked}{}", get("/one"), get("/three"));
+    stream.write_all(requests.as_bytes()).await.unwrap();
+    assert_eq!(
+        vec!["/one ", "/two body", "/three "],
+        read_bodies(&mut stream, 3).await
+    );
+}
+
+#[tokio::test]
+async fn transfer_encoding() {
+    // a body in a coding that can't be decoded isn't taken for the next request
+    let mut stream = connect(16).await;
+    let smuggled = "POST /two HTTP/1.1\r\nHost: localhost\r\nTransfer-Encoding: gzip\r\n\r\n\
+        GET /smuggled HTTP/1.1\r\nHost: localhost\r\n\r\n";
+    let requests = format!("{}{smuggled}", get("/one"));
+    stream.write_all(requests.as_bytes()).await.unwrap();
+    let received = read_all(&mut stream).await;
+    assert!(received.starts_with("HTTP/1.1 200 OK\r\n"), "{received}");
+    assert!(received.contains("\r\n\r\n/one "), "{received}");
+    assert!(
+        received.contains("HTTP/1.1 501 Not Implemented\r\n"),
+        "{received}"
+    );
+    assert!(!received.contains("/smuggled"), "{received}");
+}
+
+#[tokio::test]
+async fn conflicting_content_length() {
+    let mut stream = connect(16).await;
+    let conflicting = "POST /two HTTP/1.1\r\nHost: localhost\r\n\
+        Content-Length: 0\r\nContent-Length: 44\r\n\r\n\
+        GET /smuggled HTTP/1.1\r\nHost: localhost\r\n\r\n";
+    let requests = format!("{}{conflicting}", get("/one"));
+    stream.write_all(requests.as_bytes()).await.unwrap();
+    let received = read_all(&mut stream).await;
+    assert!(received.contains("\r\n\r\n/one "), "{received}");
+    assert!(
+        received.contains("HTTP/1.1 400 Bad Request\r\n"),
+        "{received}"
+    );
+    assert!(!received.contains("/smuggled"), "{received}");
+
+    // nor is a length next to a chunked body
+    let mut stream = connect(16).await;
+    let chunked = "POST /two HTTP/1.1\r\nHost: localhost\r\n\
+        Content-Length: 5\r\nTransfer-Encoding: chunked\r\n\r\n0\r\n\r\n\
+        GET /smuggled HTTP/1.1\r\nHost: localhost\r\n\r\n";
+    stream.write_all(chunked.as_bytes()).await.unwrap();
+    let received = read_all(&mut stream).await;
+    assert!(
+        received.starts_with("HTTP/1.1 400 Bad Request\r\n"),
+        "{received}"
+    );
+    assert!(!received.contains("/smuggled"), "{received}");
+
+    // the same length repeated is fine
+    let mut stream = connect(16).await;
+    let repeated = "POST /two HTTP/1.1\r\nHost: localhost\r\n\
+        Content-Length: 4\r\nContent-Length: 4\r\n\r\nbody";
+    let requests = format!("{repeated}{}", get("/three"));
+    stream.write_all(requests.as_bytes()).await.unwrap();
+    assert_eq!(
+        vec!["/two body", "/three "],
+        read_bodies(&mut stream, 2).await
+    );
+}
+
+#[tokio::test]
+async fn preface_after_request() {
+    // h2c prior knowledge only starts a connection, later it's just a bad request
+    let mut stream = connect(16).await;
+    stream.write_all(get("/one").as_bytes()).await.unwrap();
+    assert_eq!(vec!["/one "], read_bodies(&mut stream, 1).await);
+    stream
+        .write_all(b"PRI * HTTP/2.0\r\n\r\nSM\r\n\r\n")
+        .await
+        .unwrap();
+    let received = read_all(&mut stream).await;
+    assert!(
+        received.is_empty() || received.starts_with("HTTP/1.1 4"),
+        "{received}"
+    );
+}
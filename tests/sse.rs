@@ -0,0 +1,101 @@
+use futures::Stream;
+use get_port::tcp::TcpPort;
+use get_port::{Ops, Range};
+use nucleus_http::{
+    request::Request,
+    routes::{Route, Router},
+    sse::{Event, Sse},
+    Server,
+};
+use std::{
+    format,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+    time::Duration,
+};
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+/// Marks the stream as dropped so the test can see the server let go of it
+struct DropFlag(Arc<AtomicBool>);
+
+impl Drop for DropFlag {
+    fn drop(&mut self) {
+        self.0.store(true, Ordering::SeqCst);
+    }
+}
+
+async fn counter(
+    dropped: Arc<AtomicBool>,
+    _: Request,
+) -> Result<Sse<impl Stream<Item = Event>>, String> {
+    let flag = DropFlag(dropped);
+    let events = futures::stream::unfold((0, flag), |(count, flag)| async move {
+        tokio::time::sleep(Duration::from_millis(10)).await;
+        let event = Event::default()
+            .id(count.to_string())
+            .data(format!("{count}"));
+        Some((event, (count + 1, flag)))
+    });
+    Ok(Sse::new(events))
+}
+
+#[tokio::test]
+async fn event_stream() {
+    let tcp_port = TcpPort::in_range(
+        "127.0.0.1",
+        Range {
+            min: 6000,
+            max: 8000,
+        },
+    )
+    .unwrap();
+    let listener_ip = format!("0.0.0.0:{}", tcp_port);
+    let dropped = Arc::new(AtomicBool::new(false));
+    let mut router = Router::new(dropped.clone());
+    router.add_route(Route::get("/events", counter)).await;
+    let server = Server::bind(&listener_ip, router, "./").await.unwrap();
+    tokio::spawn(async move { server.serve().await.expect("Server Shutdown") });
+
+    let mut stream = tokio::net::TcpStream::connect(format!("127.0.0.1:{tcp_port}"))
+        .await
+        .unwrap();
+    stream
+        .write_all(b"GET /events HTTP/1.1\r\nHost: localhost\r\n\r\n")
+        .await
+        .unwrap();
+    // the stream never ends, read until a few events have arrived
+    let mut received = vec![];
+    while !String::from_utf8_lossy(&received).contains("data: 2\n\n") {
+        let mut buffer = [0; 256];
+        let n = stream.read(&mut buffer).await.unwrap();
+        assert_ne!(0, n);
+        received.extend_from_slice(&buffer[..n]);
+    }
+    let received = String::from_utf8(received).unwrap();
+    assert!(received.starts_with("HTTP/1.1 200"), "{received}");
+    assert!(
+        received.contains("Content-Type: text/event-stream"),
+        "{received}"
+    );
+    assert!(
+        received.contains("Transfer-Encoding: chunked"),
+        "{received}"
+    );
+    assert!(
+        received.contains("\r\n\r\nF\r\nid: 0\ndata: 0\n\n\r\n"),
+        "{received}"
+    );
+    assert!(!dropped.load(Ordering::SeqCst));
+
+    // the next write fails once the client is gone and the stream is dropped
+    drop(stream);
+    for _ in 0..100 {
+        if dropped.load(Ordering::SeqCst) {
+            break;
+        }
+        tokio::time::sleep(Duration::from_millis(10)).await;
+    }
+    assert!(dropped.load(Ordering::SeqCst));
+}
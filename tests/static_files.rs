@@ -0,0 +1,52 @@
+use get_port::tcp::TcpPort;
+use get_port::{Ops, Range};
+use nucleus_http::{routes::Router, Server};
+use std::format;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+/// send raw request so the path isnt normalized by the client
+async fn raw_get(port: u16, path: &str) -> String {
+    let mut stream = tokio::net::TcpStream::connect(format!("127.0.0.1:{port}"))
+        .await
+        .unwrap();
+    let request = format!("GET {path} HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n\r\n");
+    stream.write_all(request.as_bytes()).await.unwrap();
+    let mut response = String::new();
+    stream.read_to_string(&mut response).await.unwrap();
+    response
+}
+
+#[tokio::test]
+async fn static_traversal() {
+    let tcp_port = TcpPort::in_range(
+        "127.0.0.1",
+        Range {
+            min: 6000,
+            max: 8000,
+        },
+    )
+    .unwrap();
+    let listener_ip = format!("0.0.0.0:{}", tcp_port);
+    let router = Router::new(());
+    let server = Server::bind(&listener_ip, router, "./src").await.unwrap();
+    tokio::spawn(async move { server.serve().await.expect("Server Shutdown") });
+
+    let response = raw_get(tcp_port, "/lib.rs").await;
+    assert!(response.starts_with("HTTP/1.1 200"), "{response}");
+
+    for (path, status) in [
+        ("/../Cargo.toml", "403 Forbidden"),
+        ("/%2e%2e/Cargo.toml", "403 Forbidden"),
+        ("/..%2fCargo.toml", "403 Forbidden"),
+        ("/%2E%2E%5CCargo.toml", "400 Bad Request"),
+        ("/lib.rs%00.html", "400 Bad Request"),
+        ("/../.git/config", "403 Forbidden"),
+    ] {
+        let response = raw_get(tcp_port, path).await;
+        assert!(
+            response.starts_with(&format!("HTTP/1.1 {status}\r\n")),
+            "{path}: {response}"
+        );
+        assert!(!response.contains("[package]"), "{path} leaked Cargo.toml");
+    }
+}
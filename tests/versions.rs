@@ -0,0 +1,147 @@
+use futures::Stream;
+use get_port::tcp::TcpPort;
+use get_port::{Ops, Range};
+use nucleus_http::{
+    request::Request,
+    routes::{Route, Router},
+    sse::{Event, Sse},
+    Server,
+};
+use std::{format, time::Duration};
+use tokio::{
+    io::{AsyncReadExt, AsyncWriteExt},
+    net::TcpStream,
+};
+
+async fn hello(_: (), _: Request) -> Result<String, String> {
+    Ok("hello".to_string())
+}
+
+async fn events(_: (), _: Request) -> Result<Sse<impl Stream<Item = Event>>, String> {
+    let events = futures::stream::iter([Event::default().data("a"), Event::default().data("b")]);
+    Ok(Sse::new(events).keep_alive(None))
+}
+
+async fn connect() -> TcpStream {
+    let tcp_port = TcpPort::in_range(
+        "127.0.0.1",
+        Range {
+            min: 6000,
+            max: 8000,
+        },
+    )
+    .unwrap();
+    let listener_ip = format!("0.0.0.0:{}", tcp_port);
+    let mut router = Router::new(());
+    router.add_route(Route::get("/hello", hello)).await;
+    router.add_route(Route::get("/events", events)).await;
+    let server = Server::bind(&listener_ip, router, "./").await.unwrap();
+    tokio::spawn(async move { server.serve().await.expect("Server Shutdown") });
+    TcpStream::connect(format!("127.0.0.1:{tcp_port}"))
+        .await
+        .unwrap()
+}
+
+/// Everything until the server closes the connection
+async fn read_to_close(stream: &mut TcpStream) -> String {
+    let mut received = vec![];
+    tokio::time::timeout(Duration::from_secs(5), stream.read_to_end(&mut received))
+        .await
+        .expect("connection wasn't closed")
+        .unwrap();
+    String::from_utf8(received).unwrap()
+}
+
+#[tokio::test]
+async fn http_1_0() {
+    let mut stream = connect().await;
+    // no Host header needed before 1.1
+    stream
+        .write_all(b"GET /hello HTTP/1.0\r\n\r\n")
+        .await
+        .unwrap();
+    let response = read_to_close(&mut stream).await;
+    assert!(response.starts_with("HTTP/1.0 200 OK\r\n"), "{response}");
+    assert!(response.ends_with("\r\n\r\nhello"), "{response}");
+}
+
+#[tokio::test]
+async fn http_1_0_keep_alive() {
+    let mut stream = connect().await;
+    stream
+        .write_all(b"GET /hello HTTP/1.0\r\nConnection: keep-alive\r\n\r\n")
+        .await
+        .unwrap();
+    let mut received = vec![];
+    while !received.ends_with(b"hello") {
+        let mut buffer = [0; 256];
+        let n = stream.read(&mut buffer).await.unwrap();
+        assert_ne!(0, n);
+        received.extend_from_slice(&buffer[..n]);
+    }
+    let response = String::from_utf8(received).unwrap();
+    assert!(response.starts_with("HTTP/1.0 200 OK\r\n"), "{response}");
+    assert!(
+        response.contains("connection: keep-alive\r\n"),
+        "{response}"
+    );
+
+    stream
+        .write_all(b"GET /hello HTTP/1.0\r\n\r\n")
+        .await
+        .unwrap();
+    let response = read_to_close(&mut stream).await;
+    assert!(response.starts_with("HTTP/1.0 200 OK\r\n"), "{response}");
+    assert!(!response.contains("keep-alive"), "{response}");
+}
+
+#[tokio::test]
+async fn http_1_0_stream() {
+    let mut stream = connect().await;
+    stream
+        .write_all(b"GET /events HTTP/1.0\r\nConnection: keep-alive\r\n\r\n")
+        .await
+        .unwrap();
+    // no chunked encoding, the body ends when the connection does
+    let response = read_to_close(&mut stream).await;
+    assert!(response.starts_with("HTTP/1.0 200 OK\r\n"), "{response}");
+    assert!(!response.contains("Transfer-Encoding"), "{response}");
+    assert!(!response.contains("Content-Length"), "{response}");
+    assert!(
+        response.ends_with("\r\n\r\ndata: a\n\ndata: b\n\n"),
+        "{response}"
+    );
+}
+
+#[tokio::test]
+async fn http_0_9() {
+    let mut stream = connect().await;
+    stream.write_all(b"GET /hello\r\n").await.unwrap();
+    assert_eq!("hello", read_to_close(&mut stream).await);
+}
+
+#[tokio::test]
+async fn http_1_1_close() {
+    let mut stream = connect().await;
+    stream
+        .write_all(b"GET /hello HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n\r\n")
+        .await
+        .unwrap();
+    let response = read_to_close(&mut stream).await;
+    assert!(response.starts_with("HTTP/1.1 200 OK\r\n"), "{response}");
+    assert!(response.contains("connection: close\r\n"), "{response}");
+}
+
+#[tokio::test]
+async fn unsupported_version() {
+    let mut stream = connect().await;
+    stream
+        .write_all(b"GET /hello HTTP/3.0\r\nHost: localhost\r\n\r\n")
+        .await
+        .unwrap();
+    let response = read_to_close(&mut stream).await;
+    assert!(
+        response.starts_with("HTTP/1.1 505 HTTP Version Not Supported\r\n"),
+        "{response}"
+    );
+}
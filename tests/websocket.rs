@@ -0,0 +1,109 @@
+use get_port::tcp::TcpPort;
+use get_port::{Ops, Range};
+use nucleus_http::{
+    request::Request,
+    routes::{Route, Router},
+    websocket::{Message, WebSocket},
+    Server,
+};
+use std::format;
+use tokio::{
+    io::{AsyncReadExt, AsyncWriteExt},
+    net::TcpStream,
+};
+
+async fn echo(_: (), _: Request, mut socket: WebSocket) {
+    let protocol = socket.protocol().unwrap_or("none").to_string();
+    while let Some(Ok(message)) = socket.recv().await {
+        let reply = match message {
+            Message::Text(text) => Message::Text(format!("{protocol}: {text}")),
+            Message::Binary(data) => Message::Binary(data),
+            _ => continue,
+        };
+        if socket.send(reply).await.is_err() {
+            return;
+        }
+    }
+}
+
+/// Masked frame as a client sends it
+fn client_frame(opcode: u8, payload: &[u8]) -> Vec<u8> {
+    let mask = [1, 2, 3, 4];
+    let mut frame = vec![0x80 | opcode, 0x80 | payload.len() as u8];
+    frame.extend_from_slice(&mask);
+    frame.extend(payload.iter().enumerate().map(|(i, b)| b ^ mask[i % 4]));
+    frame
+}
+
+async fn server_frame(stream: &mut TcpStream) -> (u8, Vec<u8>) {
+    let mut head = [0; 2];
+    stream.read_exact(&mut head).await.unwrap();
+    let mut payload = vec![0; (head[1] & 0x7F) as usize];
+    stream.read_exact(&mut payload).await.unwrap();
+    (head[0] & 0x0F, payload)
+}
+
+#[tokio::test]
+async fn websocket_echo() {
+    let tcp_port = TcpPort::in_range(
+        "127.0.0.1",
+        Range {
+            min: 6000,
+            max: 8000,
+        },
+    )
+    .unwrap();
+    let listener_ip = format!("0.0.0.0:{}", tcp_port);
+    let mut router = Router::new(());
+    router
+        .add_route(Route::websocket("/echo", echo).with_protocols(&["echo"]))
+        .await;
+    let server = Server::bind(&listener_ip, router, "./").await.unwrap();
+    tokio::spawn(async move { server.serve().await.expect("Server Shutdown") });
+
+    let mut stream = TcpStream::connect(format!("127.0.0.1:{tcp_port}"))
+        .await
+        .unwrap();
+    let handshake = "GET /echo HTTP/1.1\r\nHost: localhost\r\nUpgrade: websocket\r\n\
+        Connection: Upgrade\r\nSec-WebSocket-Key: dGhlIHNhbXBsZSBub25jZQ==\r\n\
+        Sec-WebSocket-Version: 13\r\nSec-WebSocket-Protocol: echo\r\n\r\n";
+    // the first frame is sent along with the handshake
+    let mut bytes = handshake.as_bytes().to_vec();
+    bytes.extend(client_frame(0x1, b"early"));
+    stream.write_all(&bytes).await.unwrap();
+
+    let mut head = vec![];
+    while !head.ends_with(b"\r\n\r\n") {
+        head.push(stream.read_u8().await.unwrap());
+    }
+    let head = String::from_utf8(head).unwrap();
+    assert!(head.starts_with("HTTP/1.1 101"), "{head}");
+    assert!(
+        head.contains("sec-websocket-accept: s3pPLMBiTxaQ9kYGzzhZRbK+xOo="),
+        "{head}"
+    );
+    assert!(head.contains("sec-websocket-protocol: echo"), "{head}");
+
+    assert_eq!(
+        (0x1, b"echo: early".to_vec()),
+        server_frame(&mut stream).await
+    );
+    stream
+        .write_all(&client_frame(0x2, &[1, 2, 3]))
+        .await
+        .unwrap();
+    assert_eq!((0x2, vec![1, 2, 3]), server_frame(&mut stream).await);
+    stream.write_all(&client_frame(0x9, b"ping")).await.unwrap();
+    assert_eq!((0xA, b"ping".to_vec()), server_frame(&mut stream).await);
+
+    stream
+        .write_all(&client_frame(0x8, &1000u16.to_be_bytes()))
+        .await
+        .unwrap();
+    assert_eq!(
+        (0x8, 1000u16.to_be_bytes().to_vec()),
+        server_frame(&mut stream).await
+    );
+    // handler returned so the server closes the connection
+    assert_eq!(0, stream.read(&mut [0; 16]).await.unwrap());
+}